@@ -0,0 +1,46 @@
+/* The C ABI of btree-rs, a B+Tree of uint64_t keys and values. See src/ffi.rs. */
+#ifndef BTREE_RS_H
+#define BTREE_RS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* An opaque tree. */
+typedef struct btree btree_t;
+
+/* The callback of btree_range, which returns false to stop the scan. */
+typedef bool (*btree_range_cb)(uint64_t key, uint64_t value, void *ctx);
+
+/* Creates an empty tree, which must be freed by btree_free. */
+btree_t *btree_new(void);
+
+/* Frees the tree. NULL is ignored. */
+void btree_free(btree_t *t);
+
+/* Returns the number of the entries. */
+size_t btree_len(const btree_t *t);
+
+/* Inserts or updates the key value pair. Returns true if the key existed, and writes its old value into old unless it
+ * is NULL. */
+bool btree_insert(btree_t *t, uint64_t key, uint64_t value, uint64_t *old);
+
+/* Looks up the key. Returns true if it exists, and writes its value into value unless it is NULL. */
+bool btree_lookup(const btree_t *t, uint64_t key, uint64_t *value);
+
+/* Removes the key. Returns true if it existed, and writes its value into value unless it is NULL. */
+bool btree_remove(btree_t *t, uint64_t key, uint64_t *value);
+
+/* Calls cb with the entries whose keys are in [start, end] in the order of the keys, passing ctx through, until cb
+ * returns false. Returns the number of the calls. cb must not modify or free the tree. */
+size_t btree_range(const btree_t *t, uint64_t start, uint64_t end, btree_range_cb cb, void *ctx);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BTREE_RS_H */
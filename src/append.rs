@@ -0,0 +1,215 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+use core::ops::RangeBounds;
+
+use crate::metrics::Counter;
+use crate::observe::NodeEvent;
+use crate::range::{Iter, Range};
+use crate::{BTree, InternalNode, LeafNode, NodeIndex};
+
+/// The error of `AppendOnlyBTree::insert`. The tree is not modified.
+#[derive(Debug, PartialEq)]
+pub enum AppendError<K> {
+    /// The key exists, and the written entries are never overwritten.
+    Overwrite { key: K },
+    /// The key is less than the last key.
+    NotMonotonic { key: K, last: K },
+}
+
+impl<K: fmt::Debug> fmt::Display for AppendError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppendError::Overwrite { key } => write!(f, "the key {:?} already exists", key),
+            AppendError::NotMonotonic { key, last } => write!(f, "the key {:?} is less than the last key {:?}", key, last),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: fmt::Debug> std::error::Error for AppendError<K> {}
+
+/// AppendOnlyBTree is a write-once B+Tree whose keys are inserted in the strictly increasing order, e.g. an immutable
+/// event log indexed by the sequence numbers.
+///
+/// Since every key is larger than all of the existing ones, an insertion always appends to the rightmost leaf, following
+/// the rightmost sons without searching the nodes. A full node splits 100/0: it keeps all of its entries and the new
+/// entry starts an empty right node, so that all nodes except the rightmost ones are full.
+pub struct AppendOnlyBTree<K, V> {
+    t: BTree<K, V>,
+    last: Option<K>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for AppendOnlyBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> AppendOnlyBTree<K, V> {
+    pub fn new() -> Self {
+        AppendOnlyBTree { t: BTree::new(), last: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// Returns the largest key, None if the tree is empty.
+    pub fn last_key(&self) -> Option<&K> {
+        self.last.as_ref()
+    }
+
+    /// Appends the key value pair. Fails if `k` is not larger than the last key.
+    pub fn insert(&mut self, k: &K, v: &V) -> Result<(), AppendError<K>> {
+        if let Some(last) = self.last {
+            match last.partial_cmp(k) {
+                Some(Ordering::Less) => {}
+                Some(Ordering::Equal) => return Err(AppendError::Overwrite { key: *k }),
+                _ => return Err(AppendError::NotMonotonic { key: *k, last }),
+            }
+        }
+        let appended = self.t.push_back(k, v);
+        debug_assert!(appended);
+        self.last = Some(*k);
+        Ok(())
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k)
+    }
+
+    pub fn get_key_value(&self, k: &K) -> Option<(&K, &V)> {
+        self.t.get_key_value(k)
+    }
+
+    /// Returns an iterator over the entries whose keys are in the `range`.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        self.t.range(range)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.t.iter()
+    }
+
+    /// Converts it into a `BTree`, which accepts any writes.
+    pub fn into_inner(self) -> BTree<K, V> {
+        self.t
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Appends the key value pair to the rightmost leaf if `k` is larger than all of the keys, otherwise returns false
+    /// without modifying the tree. The full nodes on the rightmost path split 100/0 bottom-up.
+    pub(crate) fn push_back(&mut self, k: &K, v: &V) -> bool {
+        let mut cur = self.root;
+        while let NodeIndex::Internal(id) = cur {
+            let node = &self.i[id];
+            cur = node.sons[node.cnt - 1];
+        }
+        let id = match cur {
+            NodeIndex::Leaf(id) => id,
+            NodeIndex::Internal(_) => unreachable!(),
+        };
+
+        let appendable = match self.l[id].cnt {
+            0 => self.len == 0,
+            cnt => self.l[id].keys[cnt - 1] < *k,
+        };
+        if !appendable {
+            return false;
+        }
+        self.metrics.add(Counter::Inserts, 1);
+        self.len += 1;
+
+        let leaf = &mut self.l[id];
+        if !leaf.full() {
+            leaf.keys[leaf.cnt] = *k;
+            leaf.values[leaf.cnt] = *v;
+            leaf.cnt += 1;
+            return true;
+        }
+
+        // the internal nodes on the rightmost path, from the root, only collected for the splits
+        let mut path = Vec::new();
+        let mut cur = self.root;
+        while let NodeIndex::Internal(fa) = cur {
+            path.push(fa);
+            let node = &self.i[fa];
+            cur = node.sons[node.cnt - 1];
+        }
+
+        // the max key of the full leaf is also the max key of every full node above it
+        let leaf = &self.l[id];
+        let left_max = leaf.keys[leaf.cnt - 1];
+        let mut right = LeafNode::new();
+        right.keys[0] = *k;
+        right.values[0] = *v;
+        right.cnt = 1;
+        let mut node = NodeIndex::Leaf(id);
+        let mut right = NodeIndex::Leaf(self.alloc_leaf(right));
+        loop {
+            self.metrics.add(Counter::Splits, 1);
+            self.notify(NodeEvent::Split { node: node.into(), right: right.into(), left_max });
+            match path.pop() {
+                Some(fa) if !self.i[fa].full() => {
+                    let pos = self.i[fa].cnt;
+                    self.i[fa].insert(pos, &left_max, right);
+                    return true;
+                }
+                Some(fa) => {
+                    node = NodeIndex::Internal(fa);
+                    right = NodeIndex::Internal(self.alloc_internal(InternalNode::new(right)));
+                }
+                None => {
+                    let root = self.make_new_root(node);
+                    self.i[root].insert(1, &left_max, right);
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_append_only() {
+    use crate::NODE_DEG;
+
+    let mut t = AppendOnlyBTree::<u64, u64>::new();
+    let n = 100000u64;
+    for i in 0..n {
+        t.insert(&(i * 2), &i).unwrap();
+    }
+    assert_eq!(t.len(), n as usize);
+    assert_eq!(t.last_key(), Some(&(n * 2 - 2)));
+
+    let e = t.insert(&10, &0).unwrap_err();
+    assert_eq!(e, AppendError::NotMonotonic { key: 10, last: n * 2 - 2 });
+    assert_eq!(e.to_string(), format!("the key 10 is less than the last key {}", n * 2 - 2));
+    assert_eq!(t.insert(&(n * 2 - 2), &0), Err(AppendError::Overwrite { key: n * 2 - 2 }));
+    assert_eq!(t.len(), n as usize);
+
+    for i in 0..n {
+        assert_eq!(t.lookup(&(i * 2)), Some(&i));
+        assert_eq!(t.lookup(&(i * 2 + 1)), None);
+    }
+    assert!(t.iter().map(|(k, v)| (*k, *v)).eq((0..n).map(|i| (i * 2, i))));
+    assert!(t.range(100..=200).map(|(k, _)| *k).eq((50..=100).map(|i| i * 2)));
+
+    // all leaves but the rightmost one are full
+    let t = t.into_inner();
+    let leaves = t.leaf_ids();
+    assert_eq!(leaves.len(), (n as usize).div_ceil(NODE_DEG));
+    assert!(leaves[..leaves.len() - 1].iter().all(|&id| t.l[id].cnt == NODE_DEG));
+    // and so are the internal nodes, the tree has the fewest nodes possible
+    let (mut level, mut internals) = (leaves.len(), 0);
+    while level > 1 {
+        level = level.div_ceil(NODE_DEG);
+        internals += level;
+    }
+    assert_eq!(t.i.len() - t.free_i.len(), internals);
+}
@@ -0,0 +1,91 @@
+use crate::BTree;
+
+/// ArrivalBTree is a B+Tree which also iterates its entries in the order they are inserted, e.g. to replay them in the
+/// order of their arrival.
+///
+/// Every new key gets the next sequence number, and the keys are also indexed by their sequence numbers. Updating an
+/// existing key keeps its place in the arrival order, while removing and inserting it again moves it to the end.
+pub struct ArrivalBTree<K, V> {
+    t: BTree<K, (V, u64)>,
+    // the keys by their sequence numbers
+    arrivals: BTree<u64, K>,
+    next_seq: u64,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for ArrivalBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> ArrivalBTree<K, V> {
+    pub fn new() -> Self {
+        ArrivalBTree {
+            t: BTree::new(),
+            arrivals: BTree::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        if let Some([(old, _)]) = self.t.get_many_mut(&[k]) {
+            return Some(core::mem::replace(old, *v));
+        }
+        self.t.insert(k, &(*v, self.next_seq));
+        self.arrivals.insert(&self.next_seq, k);
+        self.next_seq += 1;
+        None
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let (v, seq) = self.t.remove(k)?;
+        self.arrivals.remove(&seq);
+        Some(v)
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k).map(|(v, _)| v)
+    }
+
+    /// Returns an iterator over the entries in the order of the keys.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.t.iter().map(|(k, (v, _))| (k, v))
+    }
+
+    /// Returns an iterator over the entries in the order they are inserted.
+    pub fn iter_arrival(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.arrivals.iter().map(move |(_, k)| (k, self.lookup(k).unwrap()))
+    }
+}
+
+#[test]
+fn test_arrival() {
+    let mut t = ArrivalBTree::<u32, u32>::new();
+    let keys: alloc::vec::Vec<u32> = (0..10000).map(|i| i * 7919 % 10007).collect();
+    for &k in keys.iter() {
+        assert_eq!(t.insert(&k, &k), None);
+    }
+    assert!(t.iter().map(|(k, _)| *k).eq((0..10007).filter(|k| keys.contains(k))));
+    assert!(t.iter_arrival().map(|(k, v)| (*k, *v)).eq(keys.iter().map(|&k| (k, k))));
+
+    // updates keep the places, and the keys inserted again move to the end
+    assert_eq!(t.insert(&keys[0], &42), Some(keys[0]));
+    assert_eq!(t.remove(&keys[1]), Some(keys[1]));
+    assert_eq!(t.remove(&keys[1]), None);
+    assert_eq!(t.insert(&keys[1], &43), None);
+    assert_eq!(t.len(), 10000);
+    let mut expected: alloc::vec::Vec<(u32, u32)> = keys[2..].iter().map(|&k| (k, k)).collect();
+    expected.insert(0, (keys[0], 42));
+    expected.push((keys[1], 43));
+    assert!(t.iter_arrival().map(|(k, v)| (*k, *v)).eq(expected));
+}
@@ -0,0 +1,546 @@
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::ops::{Add, Bound, RangeBounds};
+
+use crate::cursor::Change;
+use crate::range::Iter;
+use crate::{BTree, NodeIndex};
+
+/// Monoid describes an aggregate over values: `combine` is associative, and `identity` is its identity element.
+pub trait Monoid<V> {
+    type Agg: Copy;
+
+    fn identity() -> Self::Agg;
+    /// Returns the aggregate of a single value.
+    fn lift(v: &V) -> Self::Agg;
+    fn combine(a: &Self::Agg, b: &Self::Agg) -> Self::Agg;
+}
+
+/// The sum of the values.
+pub struct Sum;
+
+impl<V: Copy + Default + Add<Output = V>> Monoid<V> for Sum {
+    type Agg = V;
+
+    fn identity() -> V {
+        V::default()
+    }
+
+    fn lift(v: &V) -> V {
+        *v
+    }
+
+    fn combine(a: &V, b: &V) -> V {
+        *a + *b
+    }
+}
+
+/// The number of entries.
+pub struct Count;
+
+impl<V> Monoid<V> for Count {
+    type Agg = usize;
+
+    fn identity() -> usize {
+        0
+    }
+
+    fn lift(_: &V) -> usize {
+        1
+    }
+
+    fn combine(a: &usize, b: &usize) -> usize {
+        a + b
+    }
+}
+
+/// The minimum value, None if there are no entries.
+pub struct Min;
+
+impl<V: Copy + PartialOrd> Monoid<V> for Min {
+    type Agg = Option<V>;
+
+    fn identity() -> Option<V> {
+        None
+    }
+
+    fn lift(v: &V) -> Option<V> {
+        Some(*v)
+    }
+
+    fn combine(a: &Option<V>, b: &Option<V>) -> Option<V> {
+        match (a, b) {
+            (Some(x), Some(y)) => Some(if y < x { *y } else { *x }),
+            _ => a.or(*b),
+        }
+    }
+}
+
+/// The maximum value, None if there are no entries.
+pub struct Max;
+
+impl<V: Copy + PartialOrd> Monoid<V> for Max {
+    type Agg = Option<V>;
+
+    fn identity() -> Option<V> {
+        None
+    }
+
+    fn lift(v: &V) -> Option<V> {
+        Some(*v)
+    }
+
+    fn combine(a: &Option<V>, b: &Option<V>) -> Option<V> {
+        match (a, b) {
+            (Some(x), Some(y)) => Some(if y > x { *y } else { *x }),
+            _ => a.or(*b),
+        }
+    }
+}
+
+/// AugBTree is a B+Tree maintaining the aggregate `M` of the values in every subtree, which answers the aggregate over
+/// any range of keys in O(log n).
+///
+/// The aggregates are kept beside the nodes, indexed by the node ids. Every update goes through a cursor recording the
+/// nodes it writes, and the aggregates of these nodes are recomputed from the bottom up.
+pub struct AugBTree<K, V, M: Monoid<V>> {
+    t: BTree<K, V>,
+    internal_aggs: Vec<M::Agg>,
+    leaf_aggs: Vec<M::Agg>,
+    _marker: PhantomData<M>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy, M: Monoid<V>> Default for AugBTree<K, V, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> AugBTree<K, V, Count> {
+    /// Returns an iterator over the entries whose keys are in the `range`, whose length is counted in O(log n).
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K, V> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        Iter::new(self.t.range(bounds), self.range_aggregate(bounds))
+    }
+
+    /// Returns the entry with `n` smaller keys, found by the counts of the subtrees in O(log n).
+    pub fn nth(&self, mut n: usize) -> Option<(&K, &V)> {
+        if n >= self.aggregate() {
+            return None;
+        }
+        let mut cur = self.t.root;
+        loop {
+            match cur {
+                NodeIndex::Internal(id) => {
+                    let node = &self.t.i[id];
+                    for &son in node.sons[0..node.cnt].iter() {
+                        let cnt = self.agg(son);
+                        if n < cnt {
+                            cur = son;
+                            break;
+                        }
+                        n -= cnt;
+                    }
+                }
+                NodeIndex::Leaf(id) => {
+                    let l = &self.t.l[id];
+                    return Some((&l.keys[n], &l.values[n]));
+                }
+            }
+        }
+    }
+
+    /// Returns an iterator over at most `limit` entries whose keys are in the `range`, after skipping the first
+    /// `offset` of them. The skipped entries are counted by the subtrees in O(log n) rather than visited, so a deep
+    /// page costs the same as the first one.
+    pub fn range_limited<R: RangeBounds<K>>(&self, range: R, offset: usize, limit: usize) -> Iter<'_, K, V> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        let len = self.range_aggregate(bounds).saturating_sub(offset).min(limit);
+        if len == 0 {
+            let k = K::default();
+            return Iter::new(self.t.range((Bound::Excluded(k), Bound::Excluded(k))), 0);
+        }
+        let before = match bounds.0 {
+            Bound::Included(s) => self.range_aggregate(..s),
+            Bound::Excluded(s) => self.range_aggregate(..=s),
+            Bound::Unbounded => 0,
+        };
+        let first = *self.nth(before + offset).unwrap().0;
+        let last = *self.nth(before + offset + len - 1).unwrap().0;
+        Iter::new(self.t.range(first..=last), len)
+    }
+
+    /// Returns `k` entries chosen uniformly at random without replacement, sorted by the keys, or all entries if there
+    /// are no more than `k`. `rng` returns uniformly random u64s, e.g. `|| rng.gen()` of the `rand` crate.
+    ///
+    /// The ranks of the entries are drawn by Floyd's algorithm, and every entry is found by the counts of the subtrees,
+    /// so it takes O(k log n) without scanning the tree.
+    pub fn sample<R: FnMut() -> u64>(&self, k: usize, rng: &mut R) -> Vec<(&K, &V)> {
+        let n = self.aggregate();
+        if k >= n {
+            return self.t.iter().collect();
+        }
+        let mut ranks = BTreeSet::new();
+        for j in n - k..n {
+            // a uniform number in [0, j], by the high bits of the product to avoid the bias of the modulo
+            let r = ((rng() as u128 * (j as u128 + 1)) >> 64) as usize;
+            if !ranks.insert(r) {
+                ranks.insert(j);
+            }
+        }
+        ranks.into_iter().map(|r| self.nth(r).unwrap()).collect()
+    }
+
+    /// Splits the tree after the first `n` entries, and returns the rest as a new tree, see `split_off`.
+    pub fn split_by_rank(&mut self, n: usize) -> Self {
+        match self.nth(n).map(|(k, _)| *k) {
+            Some(k) => self.split_off(&k),
+            None => Self::new(),
+        }
+    }
+}
+
+/// Returns true if every key greater than `x` satisfies the lower bound.
+fn above_start<K: PartialOrd>(x: &K, start: Bound<&K>) -> bool {
+    match start {
+        Bound::Included(s) | Bound::Excluded(s) => x >= s,
+        Bound::Unbounded => true,
+    }
+}
+
+/// Returns true if every key not greater than `x` satisfies the upper bound.
+fn below_end<K: PartialOrd>(x: &K, end: Bound<&K>) -> bool {
+    match end {
+        Bound::Included(e) => x <= e,
+        Bound::Excluded(e) => x < e,
+        Bound::Unbounded => true,
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy, M: Monoid<V>> AugBTree<K, V, M> {
+    pub fn new() -> Self {
+        AugBTree {
+            t: BTree::new(),
+            internal_aggs: Vec::new(),
+            leaf_aggs: vec![M::identity()],
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k)
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        let mut c = self.t.cursor_mut();
+        c.track_changes();
+        c.seek(k);
+        let ret = match c.peek_mut() {
+            Some((key, value)) if key == k => Some(core::mem::replace(value, *v)),
+            _ => {
+                c.insert_after(k, v);
+                None
+            }
+        };
+        let changes = c.take_changes();
+        self.update(changes);
+        ret
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let mut c = self.t.cursor_mut();
+        c.track_changes();
+        c.seek(k);
+        let ret = match c.peek() {
+            Some((key, _)) if key == k => c.remove_next().map(|(_, v)| v),
+            _ => None,
+        };
+        let changes = c.take_changes();
+        self.update(changes);
+        ret
+    }
+
+    /// Splits the tree at `k`, and returns the entries whose keys are not less than `k` as a new tree, see
+    /// `BTree::split_off`.
+    pub fn split_off(&mut self, k: &K) -> Self {
+        let tail: Vec<(K, V)> = self.t.range(k..).map(|(k, v)| (*k, *v)).collect();
+        let mut c = self.t.cursor_mut();
+        c.track_changes();
+        c.seek(k);
+        while c.remove_next().is_some() {}
+        let changes = c.take_changes();
+        self.update(changes);
+
+        let mut rest = AugBTree {
+            t: BTree::from_sorted_vec(tail),
+            internal_aggs: Vec::new(),
+            leaf_aggs: Vec::new(),
+            _marker: PhantomData,
+        };
+        rest.internal_aggs.resize(rest.t.i.len(), M::identity());
+        rest.leaf_aggs.resize(rest.t.l.len(), M::identity());
+        rest.build(rest.t.root);
+        rest
+    }
+
+    /// Computes the aggregates of the subtree `node` from scratch.
+    fn build(&mut self, node: NodeIndex) -> M::Agg {
+        let agg = match node {
+            NodeIndex::Leaf(id) => {
+                let l = &self.t.l[id];
+                l.values[0..l.cnt].iter().fold(M::identity(), |a, v| M::combine(&a, &M::lift(v)))
+            }
+            NodeIndex::Internal(id) => {
+                let mut agg = M::identity();
+                for i in 0..self.t.i[id].cnt {
+                    agg = M::combine(&agg, &self.build(self.t.i[id].sons[i]));
+                }
+                agg
+            }
+        };
+        match node {
+            NodeIndex::Leaf(id) => self.leaf_aggs[id] = agg,
+            NodeIndex::Internal(id) => self.internal_aggs[id] = agg,
+        }
+        agg
+    }
+
+    /// Recomputes the aggregates of the changed nodes, from the leaves to the root.
+    fn update(&mut self, changes: Vec<Change>) {
+        self.internal_aggs.resize(self.t.i.len(), M::identity());
+        self.leaf_aggs.resize(self.t.l.len(), M::identity());
+
+        let mut changes: Vec<(usize, NodeIndex)> = changes.iter().map(|c| (c.height, c.node)).collect();
+        changes.sort_by_key(|&(h, node)| match node {
+            NodeIndex::Leaf(id) => (h, 0, id),
+            NodeIndex::Internal(id) => (h, 1, id),
+        });
+        changes.dedup();
+        for (_, node) in changes {
+            match node {
+                NodeIndex::Leaf(id) => {
+                    let l = &self.t.l[id];
+                    self.leaf_aggs[id] = l.values[0..l.cnt].iter().fold(M::identity(), |a, v| M::combine(&a, &M::lift(v)));
+                }
+                NodeIndex::Internal(id) => {
+                    let n = &self.t.i[id];
+                    self.internal_aggs[id] = n.sons[0..n.cnt].iter().fold(M::identity(), |a, son| M::combine(&a, &self.agg(*son)));
+                }
+            }
+        }
+    }
+
+    fn agg(&self, node: NodeIndex) -> M::Agg {
+        match node {
+            NodeIndex::Leaf(id) => self.leaf_aggs[id],
+            NodeIndex::Internal(id) => self.internal_aggs[id],
+        }
+    }
+
+    /// Returns the aggregate of the whole tree.
+    pub fn aggregate(&self) -> M::Agg {
+        self.agg(self.t.root)
+    }
+
+    /// Returns the aggregate of the values whose keys are in the `range`.
+    pub fn range_aggregate<R: RangeBounds<K>>(&self, range: R) -> M::Agg {
+        let (start, end) = (range.start_bound(), range.end_bound());
+        let start_in = matches!(start, Bound::Unbounded);
+        let end_in = matches!(end, Bound::Unbounded);
+        self.range_agg(self.t.root, start, end, start_in, end_in)
+    }
+
+    /// Returns the aggregate of the subtree `node` within the bounds. `start_in` and `end_in` tell whether all keys in
+    /// the subtree satisfy the lower and the upper bound, then only the subtrees on the boundaries are visited.
+    fn range_agg(&self, node: NodeIndex, start: Bound<&K>, end: Bound<&K>, start_in: bool, end_in: bool) -> M::Agg {
+        if start_in && end_in {
+            return self.agg(node);
+        }
+        match node {
+            NodeIndex::Leaf(id) => {
+                let l = &self.t.l[id];
+                (0..l.cnt)
+                    .filter(|&i| (start, end).contains(&l.keys[i]))
+                    .fold(M::identity(), |a, i| M::combine(&a, &M::lift(&l.values[i])))
+            }
+            NodeIndex::Internal(id) => {
+                // the keys of `sons[i]` are in (keys[i-1], keys[i]]
+                let n = &self.t.i[id];
+                let mut ret = M::identity();
+                for i in 0..n.cnt {
+                    let low = if i > 0 { Some(&n.keys[i - 1]) } else { None };
+                    let high = if i + 1 < n.cnt { Some(&n.keys[i]) } else { None };
+                    // skip the sons out of the range
+                    let below = high.is_some_and(|h| match start {
+                        Bound::Included(s) => h < s,
+                        Bound::Excluded(s) => h <= s,
+                        Bound::Unbounded => false,
+                    });
+                    if below {
+                        continue;
+                    }
+                    let above = low.is_some_and(|l| match end {
+                        Bound::Included(e) | Bound::Excluded(e) => l >= e,
+                        Bound::Unbounded => false,
+                    });
+                    if above {
+                        break;
+                    }
+                    let son_start_in = start_in || low.is_some_and(|l| above_start(l, start));
+                    let son_end_in = end_in || high.is_some_and(|h| below_end(h, end));
+                    ret = M::combine(&ret, &self.range_agg(n.sons[i], start, end, son_start_in, son_end_in));
+                }
+                ret
+            }
+        }
+    }
+}
+
+#[test]
+fn test_split_by_rank() {
+    let mut t = AugBTree::<u32, u64, Count>::new();
+    let n = 20000u32;
+    for i in 0..n {
+        t.insert(&(i * 7919 % n * 3), &(i as u64));
+    }
+    assert_eq!(t.nth(0).map(|(k, _)| *k), Some(0));
+    assert_eq!(t.nth(1234).map(|(k, _)| *k), Some(1234 * 3));
+    assert_eq!(t.nth(n as usize), None);
+
+    let mut rest = t.split_by_rank(5000);
+    assert_eq!((t.aggregate(), rest.aggregate()), (5000, 15000));
+    assert_eq!(t.range(..).len(), 5000);
+    assert_eq!(rest.nth(0).map(|(k, _)| *k), Some(15000));
+    assert_eq!(t.nth(4999).map(|(k, _)| *k), Some(14997));
+    assert_eq!(rest.range(15000..15100).len(), 34);
+
+    // both halves stay usable
+    t.insert(&1, &1);
+    assert_eq!(t.aggregate(), 5001);
+    rest.remove(&15000);
+    assert_eq!(rest.nth(0).map(|(k, _)| *k), Some(15003));
+    let empty = rest.split_by_rank(100000);
+    assert_eq!((rest.aggregate(), empty.aggregate()), (14999, 0));
+
+    let mut sums = AugBTree::<u32, u64, Sum>::new();
+    for i in 0..1000 {
+        sums.insert(&i, &(i as u64));
+    }
+    let upper = sums.split_off(&500);
+    assert_eq!((sums.aggregate(), upper.aggregate()), ((0..500).sum(), (500..1000).sum()));
+}
+
+#[test]
+fn test_sample() {
+    // xorshift64*
+    let mut state = 0x2545f4914f6cdd1du64;
+    let mut rng = || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        state.wrapping_mul(0x2545f4914f6cdd1d)
+    };
+
+    let mut t = AugBTree::<u32, u32, Count>::new();
+    assert!(t.sample(10, &mut rng).is_empty());
+    for i in 0..100 {
+        t.insert(&i, &(i * 2));
+    }
+    assert_eq!(t.sample(1000, &mut rng).len(), 100);
+    assert!(t.sample(0, &mut rng).is_empty());
+
+    let mut hits = [0; 100];
+    for _ in 0..10000 {
+        let s = t.sample(10, &mut rng);
+        assert_eq!(s.len(), 10);
+        assert!(s.windows(2).all(|w| w[0].0 < w[1].0));
+        for (k, v) in s {
+            assert_eq!(*v, k * 2);
+            hits[*k as usize] += 1;
+        }
+    }
+    // every entry is expected to be chosen 1000 times
+    assert!(hits.iter().all(|&h| (850..1150).contains(&h)));
+}
+
+#[test]
+fn test_range_limited() {
+    let mut t = AugBTree::<u32, u32, Count>::new();
+    for i in 0..10000 {
+        t.insert(&(i * 2), &i);
+    }
+    let keys = |it: Iter<u32, u32>| it.map(|(k, _)| *k).collect::<Vec<_>>();
+    assert_eq!(keys(t.range_limited(.., 0, 3)), [0, 2, 4]);
+    assert_eq!(keys(t.range_limited(.., 9000, 2)), [18000, 18002]);
+    assert_eq!(keys(t.range_limited(99..110, 2, 10)), [104, 106, 108]);
+    assert_eq!(keys(t.range_limited((Bound::Excluded(100), Bound::Included(110)), 1, 2)), [104, 106]);
+    assert_eq!(t.range_limited(.., 9999, 5).len(), 1);
+    assert_eq!(t.range_limited(.., 10000, 5).len(), 0);
+    assert_eq!(t.range_limited(5..5, 0, 5).count(), 0);
+    assert_eq!(t.range_limited(.., 0, 0).count(), 0);
+    for (a, b, offset, limit) in [(0, 20000, 123, 456), (1001, 5000, 1000, 1000), (17, 19, 0, 10)].iter() {
+        let it = t.range_limited(*a..*b, *offset, *limit);
+        let expected: Vec<u32> = t.t.range(*a..*b).skip(*offset).take(*limit).map(|(k, _)| *k).collect();
+        assert_eq!(it.len(), expected.len());
+        assert_eq!(keys(it), expected);
+    }
+}
+
+#[test]
+fn test_range_aggregate() {
+    let mut t = AugBTree::<u32, u64, Sum>::new();
+    let n = 20000u32;
+    for i in 0..n {
+        let k = i * 7919 % n;
+        t.insert(&k, &(k as u64));
+    }
+    let sum = |a: u64, b: u64| if a < b { (a..b).sum::<u64>() } else { 0 };
+    assert_eq!(t.aggregate(), sum(0, n as u64));
+    assert_eq!(t.range_aggregate(100..200), sum(100, 200));
+    assert_eq!(t.range_aggregate(100..=200), sum(100, 201));
+    assert_eq!(t.range_aggregate((Bound::Excluded(100), Bound::Unbounded)), sum(101, n as u64));
+    assert_eq!(t.range_aggregate(..5000), sum(0, 5000));
+    assert_eq!(t.range_aggregate(n..), 0);
+
+    // updates and removals, which split and merge nodes, keep the aggregates up to date
+    assert_eq!(t.insert(&10, &1000), Some(10));
+    assert_eq!(t.range_aggregate(9..=10), 1009);
+    assert_eq!(t.insert(&10, &10), Some(1000));
+    for k in (0..n).filter(|k| k % 3 != 0) {
+        assert_eq!(t.remove(&k), Some(k as u64));
+    }
+    assert_eq!(t.remove(&1), None);
+    let expected = |a: u32, b: u32| (a..b).filter(|k| k % 3 == 0).map(|k| k as u64).sum::<u64>();
+    for (a, b) in [(0, n), (5, 17), (999, 12345), (0, 1)].iter() {
+        assert_eq!(t.range_aggregate(*a..*b), expected(*a, *b));
+    }
+    for k in (0..n).filter(|k| k % 3 != 0) {
+        t.insert(&k, &(k as u64));
+    }
+    assert_eq!(t.aggregate(), sum(0, n as u64));
+    assert_eq!(t.range_aggregate(3000..4000), sum(3000, 4000));
+
+    let mut m = AugBTree::<u32, i32, Max>::new();
+    assert_eq!(m.aggregate(), None);
+    for i in 0..1000 {
+        m.insert(&i, &((i as i32 - 500).abs()));
+    }
+    assert_eq!(m.range_aggregate(400..600), Some(100));
+    assert_eq!(AugBTree::<u32, i32, Count>::new().aggregate(), 0);
+
+    let mut c = AugBTree::<u32, u32, Count>::new();
+    for i in 0..1000 {
+        c.insert(&(i * 2), &i);
+    }
+    let mut r = c.range(100..200);
+    assert_eq!(r.len(), 50);
+    assert_eq!(r.next(), Some((&100, &50)));
+    assert_eq!(r.len(), 49);
+    assert_eq!(r.count(), 49);
+    assert_eq!(c.range(..).len(), 1000);
+    assert_eq!(c.range(5000..).len(), 0);
+}
@@ -0,0 +1,187 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::BTree;
+
+/// WriteBatch is a group of insertions and removals, which are applied to a tree in one call.
+///
+/// The operations are applied in the order of the keys rather than the order they are added, so every affected leaf
+/// is visited once. If a key is written multiple times, the last operation wins.
+pub struct WriteBatch<K, V> {
+    // None removes the key
+    ops: Vec<(K, Option<V>)>,
+}
+
+impl<K: PartialOrd + Copy, V: Copy> Default for WriteBatch<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + Copy, V: Copy> WriteBatch<K, V> {
+    pub fn new() -> Self {
+        WriteBatch { ops: Vec::new() }
+    }
+
+    pub fn insert(&mut self, k: &K, v: &V) {
+        self.ops.push((*k, Some(*v)));
+    }
+
+    pub fn remove(&mut self, k: &K) {
+        self.ops.push((*k, None));
+    }
+
+    /// Returns the number of operations, counting the overwritten ones.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+
+    /// Returns the last operation of every key, sorted by the keys.
+    pub(crate) fn sorted(&self) -> Vec<(K, Option<V>)> {
+        let mut ops = self.ops.clone();
+        // the stable sort keeps the operations of a key in order
+        ops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let mut ret: Vec<(K, Option<V>)> = Vec::with_capacity(ops.len());
+        for op in ops {
+            match ret.last_mut() {
+                Some(last) if last.0 == op.0 => *last = op,
+                _ => ret.push(op),
+            }
+        }
+        ret
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Applies the operations of the batch. A cursor walks the keys in order, so the leaves are not searched from the
+    /// root for every key.
+    pub fn apply_batch(&mut self, batch: &WriteBatch<K, V>) {
+        let mut c = self.cursor_mut();
+        for (k, v) in batch.sorted() {
+            c.seek_forward(&k);
+            let exists = c.peek().is_some_and(|(key, _)| key == &k);
+            match (exists, v) {
+                (true, Some(v)) => *c.peek_mut().unwrap().1 = v,
+                (false, Some(v)) => c.insert_after(&k, &v),
+                (true, None) => {
+                    c.remove_next();
+                }
+                (false, None) => {}
+            }
+        }
+    }
+
+    /// Inserts or updates the key value pairs, and returns the old value of every pair in the order of `entries`.
+    ///
+    /// The pairs are applied in the order of the keys, walking a cursor over the tree, so every affected leaf is
+    /// searched from the root at most once. The pairs of the same key are applied in their order, as if they were
+    /// inserted one by one.
+    pub fn insert_batch(&mut self, entries: &[(K, V)]) -> Vec<Option<V>> {
+        let mut ret = vec![None; entries.len()];
+        let mut c = self.cursor_mut();
+        for i in sorted_order(entries.len(), |i| &entries[i].0) {
+            let (k, v) = &entries[i];
+            c.seek_forward(k);
+            match c.peek_mut() {
+                Some((key, value)) if key == k => ret[i] = Some(core::mem::replace(value, *v)),
+                _ => c.insert_after(k, v),
+            }
+        }
+        ret
+    }
+
+    /// Removes the keys, and returns the value of every key in the order of `ks`. See `insert_batch`.
+    pub fn remove_batch(&mut self, ks: &[K]) -> Vec<Option<V>> {
+        let mut ret = vec![None; ks.len()];
+        let mut c = self.cursor_mut();
+        for i in sorted_order(ks.len(), |i| &ks[i]) {
+            c.seek_forward(&ks[i]);
+            if c.peek().is_some_and(|(key, _)| key == &ks[i]) {
+                ret[i] = c.remove_next().map(|(_, v)| v);
+            }
+        }
+        ret
+    }
+}
+
+/// Returns the indexes `0..n` sorted by their keys, the indexes of the same key in order.
+fn sorted_order<'a, K: PartialOrd + 'a>(n: usize, key: impl Fn(usize) -> &'a K) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..n).collect();
+    // the stable sort keeps the indexes of a key in order
+    order.sort_by(|&a, &b| key(a).partial_cmp(key(b)).unwrap());
+    order
+}
+
+#[test]
+fn test_write_batch() {
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..10000 {
+        t.insert(&(i * 2), &i);
+    }
+
+    let mut b = WriteBatch::new();
+    for i in (0..10000).rev() {
+        // overwrite the even keys, insert the odd ones, and remove every tenth even key
+        b.insert(&(i * 2), &(i + 1));
+        b.insert(&(i * 2 + 1), &i);
+        if i % 10 == 0 {
+            b.remove(&(i * 2));
+        }
+    }
+    // the last operation of a key wins
+    b.remove(&7);
+    b.insert(&7, &42);
+    b.insert(&100000, &1);
+    b.remove(&100000);
+    b.remove(&100001);
+    assert_eq!(b.len(), 21005);
+
+    t.apply_batch(&b);
+    for i in 0..10000 {
+        let even = if i % 10 == 0 { None } else { Some(i + 1) };
+        assert_eq!(t.lookup(&(i * 2)).copied(), even);
+        let odd = if i == 3 { 42 } else { i };
+        assert_eq!(t.lookup(&(i * 2 + 1)), Some(&odd));
+    }
+    assert_eq!(t.lookup(&100000), None);
+    assert_eq!(t.range(..).count(), 19000);
+
+    b.clear();
+    assert!(b.is_empty());
+    for i in 0..20000 {
+        b.remove(&i);
+    }
+    t.apply_batch(&b);
+    assert_eq!(t.range(..).count(), 0);
+}
+
+#[test]
+fn test_insert_remove_batch() {
+    let mut t = BTree::<u32, u32>::new();
+    assert_eq!(t.insert_batch(&[]), []);
+    let entries: Vec<(u32, u32)> = (0..10000).map(|i| (i * 7919 % 10007, i)).collect();
+    assert!(t.insert_batch(&entries).iter().all(|v| v.is_none()));
+    assert_eq!(t.len(), 10000);
+    assert!(entries.iter().all(|(k, v)| t.lookup(k) == Some(v)));
+
+    // the results are in the order of the input, and the pairs of a key are applied in order
+    assert_eq!(t.insert_batch(&[(20000, 1), (7919, 2), (20000, 3), (30000, 4)]), [None, Some(1), Some(1), None]);
+    assert_eq!(t.lookup(&20000), Some(&3));
+    assert_eq!(t.len(), 10002);
+
+    let mut ks: Vec<u32> = (0..10007).rev().collect();
+    ks.extend_from_slice(&[20000, 30000, 20000]);
+    let removed = t.remove_batch(&ks);
+    assert_eq!(removed[10007 - 1 - 7919], Some(2));
+    assert_eq!(&removed[10007..], [Some(3), Some(4), None]);
+    assert_eq!(removed.iter().filter(|v| v.is_some()).count(), 10002);
+    assert!(t.is_empty());
+}
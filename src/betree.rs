@@ -0,0 +1,357 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::take;
+
+use crate::cursor::CursorMut;
+use crate::{BTree, NodeIndex, NODE_DEG};
+
+/// The number of messages an internal node holds before they are flushed down.
+const BUFFER_CAP: usize = 4 * NODE_DEG;
+
+/// A pending update of a key.
+#[derive(Clone, Copy)]
+pub enum Message<V> {
+    Insert(V),
+    Delete,
+    /// Computes the new value from the current one, None removes the key.
+    Upsert(fn(Option<V>) -> Option<V>),
+    /// Combines the current value with the operand by the merge operator, or sets the operand if there is no value.
+    Merge(V, fn(&V, &V) -> V),
+}
+
+impl<V: Copy> Message<V> {
+    fn apply(&self, old: Option<V>) -> Option<V> {
+        match self {
+            Message::Insert(v) => Some(*v),
+            Message::Delete => None,
+            Message::Upsert(f) => f(old),
+            Message::Merge(operand, f) => Some(match old {
+                Some(v) => f(&v, operand),
+                None => *operand,
+            }),
+        }
+    }
+}
+
+/// BeTree is a write optimized B+Tree (a Bε-tree). The updates are buffered as messages at the internal nodes, and a
+/// full buffer is flushed to the son receiving the most of its messages. The messages only reach the leaves in
+/// batches, so a write costs much less than descending to a leaf.
+///
+/// The buffers are kept beside the nodes, indexed by the node ids. A lookup merges the messages on its path with the
+/// leaf, and the messages nearer to the root are newer.
+pub struct BeTree<K, V> {
+    t: BTree<K, V>,
+    // the messages of every internal node, from the oldest to the newest
+    buffers: Vec<Vec<(K, Message<V>)>>,
+    merge_op: Option<fn(&V, &V) -> V>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for BeTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Applies the message to the tree.
+fn apply<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy>(c: &mut CursorMut<K, V>, k: &K, msg: &Message<V>) {
+    c.seek(k);
+    let old = match c.peek() {
+        Some((key, v)) if key == k => Some(*v),
+        _ => None,
+    };
+    match (old, msg.apply(old)) {
+        (Some(_), Some(v)) => *c.peek_mut().unwrap().1 = v,
+        (None, Some(v)) => c.insert_after(k, &v),
+        (Some(_), None) => {
+            c.remove_next();
+        }
+        (None, None) => {}
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BeTree<K, V> {
+    pub fn new() -> Self {
+        BeTree {
+            t: BTree::new(),
+            buffers: Vec::new(),
+            merge_op: None,
+        }
+    }
+
+    /// News a tree with the merge operator used by `merge`, which combines a value with an operand into the new value.
+    /// The operator should be associative, e.g. the addition of the counters.
+    pub fn with_merge_operator(op: fn(&V, &V) -> V) -> Self {
+        let mut t = Self::new();
+        t.merge_op = Some(op);
+        t
+    }
+
+    /// Inserts or updates the key value pair. Unlike `BTree::insert`, the old value is not returned, which would
+    /// need a lookup.
+    pub fn insert(&mut self, k: &K, v: &V) {
+        self.put(k, Message::Insert(*v));
+    }
+
+    /// Removes `k` if it exists.
+    pub fn remove(&mut self, k: &K) {
+        self.put(k, Message::Delete);
+    }
+
+    /// Combines the value of `k` with `operand` by the merge operator, or sets it to `operand` if `k` does not exist.
+    /// It is a blind write like `insert`, the operand is applied when the message reaches the leaf, and a lookup applies
+    /// the pending operands to the value it finds.
+    ///
+    /// Panics if the tree has no merge operator, see `with_merge_operator`.
+    pub fn merge(&mut self, k: &K, operand: &V) {
+        let op = self.merge_op.expect("the tree has no merge operator");
+        self.put(k, Message::Merge(*operand, op));
+    }
+
+    /// Updates the value of `k` by `f` when the message reaches the leaf. `f` gets None if `k` does not exist, and
+    /// removes `k` by returning None.
+    pub fn upsert(&mut self, k: &K, f: fn(Option<V>) -> Option<V>) {
+        self.put(k, Message::Upsert(f));
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<V> {
+        let mut buffers = Vec::new();
+        let mut cur = self.t.root;
+        while let NodeIndex::Internal(id) = cur {
+            buffers.extend(self.buffers.get(id));
+            cur = self.t.i[id].lookup(k).1;
+        }
+        let leaf = match cur {
+            NodeIndex::Leaf(id) => id,
+            NodeIndex::Internal(_) => unreachable!(),
+        };
+        // apply the messages from the deepest (oldest) one
+        let mut v = self.t.l[leaf].lookup(k).copied();
+        for b in buffers.iter().rev() {
+            for (_, msg) in b.iter().filter(|(key, _)| key == k) {
+                v = msg.apply(v);
+            }
+        }
+        v
+    }
+
+    /// Applies all buffered messages to the leaves, and returns the tree, e.g. for the range scans.
+    pub fn flush(&mut self) -> &BTree<K, V> {
+        let mut nodes = Vec::new();
+        let mut stack = vec![(self.t.root, 0)];
+        while let Some((cur, depth)) = stack.pop() {
+            if let NodeIndex::Internal(id) = cur {
+                let n = &self.t.i[id];
+                nodes.push((depth, id));
+                stack.extend(n.sons[0..n.cnt].iter().map(|&son| (son, depth + 1)));
+            }
+        }
+        // the deeper messages are older
+        nodes.sort_by_key(|&(depth, _)| core::cmp::Reverse(depth));
+        let mut msgs = Vec::new();
+        for (_, id) in nodes {
+            if let Some(b) = self.buffers.get_mut(id) {
+                msgs.append(b);
+            }
+        }
+        self.apply_to_leaves(msgs);
+        &self.t
+    }
+
+    fn buffer_mut(&mut self, id: usize) -> &mut Vec<(K, Message<V>)> {
+        if self.buffers.len() <= id {
+            self.buffers.resize_with(self.t.i.len(), Vec::new);
+        }
+        &mut self.buffers[id]
+    }
+
+    fn put(&mut self, k: &K, msg: Message<V>) {
+        match self.t.root {
+            NodeIndex::Internal(root) => {
+                self.buffer_mut(root).push((*k, msg));
+                if self.buffers[root].len() > BUFFER_CAP {
+                    self.flush_from(root);
+                }
+            }
+            // a single leaf has no buffer
+            NodeIndex::Leaf(_) => apply(&mut self.t.cursor_mut(), k, &msg),
+        }
+    }
+
+    /// Moves the messages of the full node `id` to the son receiving the most of them, and goes on with the son if it
+    /// becomes full. The messages of a full father of the leaves are applied to the leaves.
+    fn flush_from(&mut self, mut id: usize) {
+        loop {
+            let msgs = take(&mut self.buffers[id]);
+            let n = &self.t.i[id];
+            if let NodeIndex::Leaf(_) = n.sons[0] {
+                self.apply_to_leaves(msgs);
+                return;
+            }
+
+            let idx: Vec<usize> = msgs.iter().map(|(k, _)| n.lookup(k).0).collect();
+            let mut cnt = [0; NODE_DEG];
+            for &i in idx.iter() {
+                cnt[i] += 1;
+            }
+            let best = (0..n.cnt).max_by_key(|&i| cnt[i]).unwrap();
+            let son = match n.sons[best] {
+                NodeIndex::Internal(son) => son,
+                NodeIndex::Leaf(_) => unreachable!(),
+            };
+
+            let (mut down, mut keep) = (Vec::new(), Vec::new());
+            for (msg, i) in msgs.into_iter().zip(idx) {
+                if i == best {
+                    down.push(msg);
+                } else {
+                    keep.push(msg);
+                }
+            }
+            self.buffers[id] = keep;
+            self.buffer_mut(son).append(&mut down);
+            if self.buffers[son].len() <= BUFFER_CAP {
+                return;
+            }
+            id = son;
+        }
+    }
+
+    /// Applies the messages, which are from the oldest to the newest, to the leaves.
+    ///
+    /// The splits and merges change the ranges of the keys of some internal nodes, so the messages buffered at these
+    /// nodes are moved to the nodes at the same heights on the paths of their keys afterwards. The messages of a
+    /// collapsed root go to the new root, or to the leaves if the root is a leaf.
+    fn apply_to_leaves(&mut self, mut msgs: Vec<(K, Message<V>)>) {
+        // the stable sort keeps the order of the messages of a key, and the leaves are visited from left to right
+        msgs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let mut c = self.t.cursor_mut();
+        c.track_changes();
+        for (k, msg) in msgs.iter() {
+            apply(&mut c, k, msg);
+        }
+        let changes = c.take_changes();
+
+        let mut moved = Vec::new();
+        for change in changes.iter().filter(|c| c.resized) {
+            if let NodeIndex::Internal(id) = change.node {
+                if let Some(b) = self.buffers.get_mut(id) {
+                    moved.extend(b.drain(..).map(|(k, msg)| (change.height, k, msg)));
+                }
+            }
+        }
+        // the higher messages are newer
+        moved.sort_by_key(|&(height, _, _)| height);
+        let top = self.t.height() - 1;
+        if top == 0 {
+            if !moved.is_empty() {
+                self.apply_to_leaves(moved.into_iter().map(|(_, k, msg)| (k, msg)).collect());
+            }
+            return;
+        }
+        for (height, k, msg) in moved {
+            let id = self.node_at(height.min(top), &k);
+            self.buffer_mut(id).push((k, msg));
+        }
+    }
+
+    /// Returns the internal node at `height` on the path of `k`.
+    fn node_at(&self, height: usize, k: &K) -> usize {
+        let mut path = Vec::new();
+        let mut cur = self.t.root;
+        while let NodeIndex::Internal(id) = cur {
+            path.push(id);
+            cur = self.t.i[id].lookup(k).1;
+        }
+        path[path.len() - height]
+    }
+}
+
+#[test]
+fn test_betree() {
+    use std::collections::BTreeMap;
+
+    let mut t = BeTree::<u32, u32>::new();
+    let mut truth = BTreeMap::new();
+    let n = 30000u32;
+    for i in 0..n {
+        let k = i * 7919 % n;
+        t.insert(&k, &i);
+        truth.insert(k, i);
+        if i % 3 == 0 {
+            let k = i * 104729 % n;
+            t.remove(&k);
+            truth.remove(&k);
+        }
+        if i % 5 == 0 {
+            let k = i * 31 % n;
+            t.upsert(&k, |v| Some(v.unwrap_or(0) + 1));
+            *truth.entry(k).or_insert(0) += 1;
+        }
+    }
+    // the recent updates are still buffered
+    assert!(t.buffers.iter().any(|b| !b.is_empty()));
+    for k in 0..n {
+        assert_eq!(t.lookup(&k), truth.get(&k).copied());
+    }
+
+    // the merges move the buffered messages as well
+    for k in (0..n).filter(|k| k % 4 != 0) {
+        t.remove(&k);
+        truth.remove(&k);
+    }
+    for k in 0..n {
+        assert_eq!(t.lookup(&k), truth.get(&k).copied());
+    }
+
+    t.upsert(&7, |_| None);
+    truth.remove(&7);
+    let tree = t.flush();
+    assert!(tree.range(..).map(|(k, v)| (*k, *v)).eq(truth.iter().map(|(k, v)| (*k, *v))));
+    assert!(t.buffers.iter().all(|b| b.is_empty()));
+    assert_eq!(t.lookup(&7), None);
+
+    // the root collapses as the tree shrinks, with the messages buffered at it
+    let high = t.t.height();
+    for k in (0..n).filter(|k| k % 1000 != 0) {
+        t.remove(&k);
+        truth.remove(&k);
+    }
+    for k in 0..n {
+        assert_eq!(t.lookup(&k), truth.get(&k).copied());
+    }
+    let tree = t.flush();
+    assert!(tree.height() < high);
+    assert!(tree.range(..).map(|(k, v)| (*k, *v)).eq(truth.iter().map(|(k, v)| (*k, *v))));
+}
+
+#[test]
+fn test_betree_merge() {
+    use std::collections::BTreeMap;
+
+    let mut t = BeTree::<u32, u64>::with_merge_operator(|a, b| a + b);
+    let mut truth = BTreeMap::new();
+    let n = 1000u32;
+    for i in 0..50000u32 {
+        let k = i * 7919 % n;
+        t.merge(&k, &(i as u64));
+        *truth.entry(k).or_insert(0) += i as u64;
+        if i % 1000 == 0 {
+            t.insert(&k, &1);
+            truth.insert(k, 1);
+        }
+        if i % 777 == 0 {
+            t.remove(&(k + 1));
+            truth.remove(&(k + 1));
+        }
+    }
+    // the recent operands are still buffered
+    assert!(t.buffers.iter().any(|b| b.iter().any(|(_, m)| matches!(m, Message::Merge(..)))));
+    for k in 0..n + 1 {
+        assert_eq!(t.lookup(&k), truth.get(&k).copied());
+    }
+    let tree = t.flush();
+    assert!(tree.iter().map(|(k, v)| (*k, *v)).eq(truth.iter().map(|(k, v)| (*k, *v))));
+
+    let mut t = BeTree::<u32, u64>::new();
+    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| t.merge(&1, &1))).is_err());
+}
@@ -0,0 +1,188 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::Bound;
+
+use crate::BTree;
+
+/// A range of keys as a pair of bounds.
+pub type KeyRange<K> = (Bound<K>, Bound<K>);
+
+/// The policy choosing the entry to evict from a full `BoundedBTree`.
+pub enum Eviction<K, V> {
+    Smallest,
+    Largest,
+    /// Returns the key of the entry to evict, which must be in the tree.
+    Custom(fn(&BTree<K, V>) -> K),
+    /// Returns the range of the keys to evict at once, e.g. the entries of the lowest priority, which must hold an
+    /// entry.
+    Range(fn(&BTree<K, V>) -> KeyRange<K>),
+}
+
+type Callback<K, V> = Box<dyn FnMut(&K, &V)>;
+
+/// BoundedBTree is a B+Tree with a budget, which evicts entries after an insertion exceeds the budget.
+///
+/// Every entry costs its size given by the caller, so the budget is a byte budget if the sizes are the bytes of the
+/// entries, or a maximum number of entries if every entry costs 1. The inserted entry may be evicted itself, e.g. the
+/// smallest key under `Eviction::Smallest`. The evicted entries are passed to the callback set by `on_evict`, e.g. to
+/// write them back to a slower tier.
+pub struct BoundedBTree<K, V> {
+    t: BTree<K, V>,
+    len: usize,
+    used: usize,
+    budget: usize,
+    size: fn(&K, &V) -> usize,
+    eviction: Eviction<K, V>,
+    on_evict: Option<Callback<K, V>>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BoundedBTree<K, V> {
+    /// News a tree holding at most `max_len` entries.
+    pub fn new(max_len: usize, eviction: Eviction<K, V>) -> Self {
+        Self::with_budget(max_len, |_, _| 1, eviction)
+    }
+
+    /// News a tree whose entries cost at most `budget` in total, an entry costs `size(k, v)`.
+    pub fn with_budget(budget: usize, size: fn(&K, &V) -> usize, eviction: Eviction<K, V>) -> Self {
+        BoundedBTree {
+            t: BTree::new(),
+            len: 0,
+            used: 0,
+            budget,
+            size,
+            eviction,
+            on_evict: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the total cost of the entries.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k)
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value. Then the entries are evicted until the
+    /// budget is met.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        let old = self.t.insert(k, v);
+        match old {
+            Some(old) => self.used -= (self.size)(k, &old),
+            None => self.len += 1,
+        }
+        self.used += (self.size)(k, v);
+
+        while self.used > self.budget {
+            let victim = match self.eviction {
+                Eviction::Smallest => *self.t.range(..).next().unwrap().0,
+                Eviction::Largest => {
+                    let mut c = self.t.cursor();
+                    c.seek_last();
+                    *c.prev().unwrap().0
+                }
+                Eviction::Custom(f) => f(&self.t),
+                Eviction::Range(f) => {
+                    let victims: Vec<K> = self.t.range(f(&self.t)).map(|(k, _)| *k).collect();
+                    assert!(!victims.is_empty(), "the evicted range is empty");
+                    for k in victims.iter() {
+                        self.evict(k);
+                    }
+                    continue;
+                }
+            };
+            self.evict(&victim);
+        }
+        old
+    }
+
+    /// Sets the callback invoked with every evicted entry after it is removed.
+    pub fn on_evict<F: FnMut(&K, &V) + 'static>(&mut self, f: F) {
+        self.on_evict = Some(Box::new(f));
+    }
+
+    fn evict(&mut self, k: &K) {
+        let v = self.remove(k).expect("the evicted key is not in the tree");
+        if let Some(f) = self.on_evict.as_mut() {
+            f(k, &v);
+        }
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let v = self.t.remove(k)?;
+        self.len -= 1;
+        self.used -= (self.size)(k, &v);
+        Some(v)
+    }
+}
+
+#[test]
+fn test_bounded() {
+    let mut t = BoundedBTree::<u32, u32>::new(100, Eviction::Smallest);
+    for i in 0..1000 {
+        t.insert(&i, &i);
+    }
+    assert_eq!(t.len(), 100);
+    assert_eq!(t.lookup(&899), None);
+    assert_eq!(t.lookup(&900), Some(&900));
+    // a new smallest key is evicted at once, and updates do not evict
+    assert_eq!(t.insert(&1, &1), None);
+    assert_eq!(t.lookup(&1), None);
+    assert_eq!(t.insert(&950, &0), Some(950));
+    assert_eq!(t.len(), 100);
+
+    let mut t = BoundedBTree::<u32, u32>::new(10, Eviction::Largest);
+    for i in (0..100).rev() {
+        t.insert(&i, &i);
+    }
+    assert_eq!(t.lookup(&9), Some(&9));
+    assert_eq!(t.lookup(&10), None);
+
+    // evict the entry of the smallest value
+    let min_value = |t: &BTree<u32, u32>| *t.range(..).min_by_key(|(_, v)| **v).unwrap().0;
+    let mut t = BoundedBTree::<u32, u32>::new(3, Eviction::Custom(min_value));
+    for (k, v) in [(1, 50), (2, 10), (3, 30), (4, 40)].iter() {
+        t.insert(k, v);
+    }
+    assert_eq!(t.lookup(&2), None);
+    assert_eq!(t.len(), 3);
+
+    // the byte budget counts the strings
+    let mut t = BoundedBTree::<u32, &str>::with_budget(10, |_, v| v.len(), Eviction::Smallest);
+    t.insert(&1, &"abcd");
+    t.insert(&2, &"efgh");
+    assert_eq!(t.used(), 8);
+    t.insert(&3, &"ij");
+    assert_eq!(t.len(), 3);
+    t.insert(&4, &"k");
+    assert_eq!(t.lookup(&1), None);
+    assert_eq!((t.len(), t.used()), (3, 7));
+    t.insert(&5, &"a very long value");
+    assert!(t.is_empty());
+    assert_eq!(t.used(), 0);
+
+    // evict the lowest priority, i.e. the keys below 1000, at once, and collect the evicted entries
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    let low = |_: &BTree<u32, u32>| (Bound::Unbounded, Bound::Excluded(1000));
+    let mut t = BoundedBTree::<u32, u32>::with_budget(100, |_, v| *v as usize, Eviction::Range(low));
+    let evicted = Rc::new(RefCell::new(Vec::new()));
+    let sink = evicted.clone();
+    t.on_evict(move |k, v| sink.borrow_mut().push((*k, *v)));
+    for k in [1, 2000, 3, 4000].iter() {
+        t.insert(k, &30);
+    }
+    assert_eq!(t.len(), 2);
+    assert_eq!(t.used(), 60);
+    assert_eq!(*evicted.borrow(), [(1, 30), (3, 30)]);
+}
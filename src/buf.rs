@@ -0,0 +1,94 @@
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+
+#[cfg(all(feature = "std", unix))]
+use crate::mmap::MmapVec;
+
+/// The buffer holding the nodes of a tree. It is either a plain `Vec` or a file mapped into the memory.
+/// Both dereference to a slice of nodes, so the tree indexes nodes the same way no matter where they live.
+pub(crate) enum NodeBuf<T> {
+    Heap(Vec<T>),
+    #[cfg(all(feature = "std", unix))]
+    Mapped(MmapVec<T>),
+}
+
+impl<T> NodeBuf<T> {
+    pub(crate) fn with_capacity(cap: usize) -> Self {
+        NodeBuf::Heap(Vec::with_capacity(cap))
+    }
+
+    /// Reserves the space for at least `additional` more nodes.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        match self {
+            NodeBuf::Heap(v) => v.reserve(additional),
+            #[cfg(all(feature = "std", unix))]
+            NodeBuf::Mapped(m) => m.reserve(additional),
+        }
+    }
+
+    /// Reserves the space for at least `additional` more nodes, failing instead of panicking if the buffer can not grow.
+    #[cfg(feature = "std")]
+    pub(crate) fn try_reserve(&mut self, additional: usize) -> std::io::Result<()> {
+        match self {
+            NodeBuf::Heap(v) => v.try_reserve(additional).map_err(|_| std::io::ErrorKind::OutOfMemory.into()),
+            #[cfg(unix)]
+            NodeBuf::Mapped(m) => m.try_reserve(additional),
+        }
+    }
+
+    /// Replaces the nodes with the copies of `src`, reusing the space of the buffer.
+    pub(crate) fn assign(&mut self, src: &[T])
+    where
+        T: Clone,
+    {
+        self.clear();
+        match self {
+            NodeBuf::Heap(v) => v.extend_from_slice(src),
+            #[cfg(all(feature = "std", unix))]
+            NodeBuf::Mapped(m) => {
+                m.reserve(src.len());
+                for t in src {
+                    m.push(t.clone());
+                }
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match self {
+            NodeBuf::Heap(v) => v.clear(),
+            #[cfg(all(feature = "std", unix))]
+            NodeBuf::Mapped(m) => m.clear(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, t: T) {
+        match self {
+            NodeBuf::Heap(v) => v.push(t),
+            #[cfg(all(feature = "std", unix))]
+            NodeBuf::Mapped(m) => m.push(t),
+        }
+    }
+}
+
+impl<T> Deref for NodeBuf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            NodeBuf::Heap(v) => v,
+            #[cfg(all(feature = "std", unix))]
+            NodeBuf::Mapped(m) => m,
+        }
+    }
+}
+
+impl<T> DerefMut for NodeBuf<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self {
+            NodeBuf::Heap(v) => v,
+            #[cfg(all(feature = "std", unix))]
+            NodeBuf::Mapped(m) => m,
+        }
+    }
+}
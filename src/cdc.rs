@@ -0,0 +1,196 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::batch::WriteBatch;
+use crate::BTree;
+
+/// The sequence number of a change. The first change is 1, so 0 stands for "before any change".
+pub type Seq = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Insert,
+    Remove,
+}
+
+/// A committed mutation. `value` is the new value of an insertion, or the removed value of a removal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Change<K, V> {
+    pub seq: Seq,
+    pub op: Op,
+    pub key: K,
+    pub value: V,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Change<K, V> {
+    /// Applies the change to `t`, which replicates the tree on a follower when the changes are applied in order.
+    pub fn apply(&self, t: &mut BTree<K, V>) {
+        match self.op {
+            Op::Insert => t.insert(&self.key, &self.value),
+            Op::Remove => t.remove(&self.key),
+        };
+    }
+}
+
+/// CdcBTree is a B+Tree recording every committed mutation as a change with an increasing sequence number.
+///
+/// The changes are kept until they are trimmed, so a consumer reads `changes_since` the last change it has seen, and
+/// trims the ones every consumer has seen. With the `std` feature, the changes are also sent to the subscribed
+/// channels as they are committed.
+///
+/// Removing an absent key is not a mutation, so it is not recorded. The operations of a batch are recorded in the
+/// order of the keys, after the whole batch is applied.
+pub struct CdcBTree<K, V> {
+    t: BTree<K, V>,
+    changes: VecDeque<Change<K, V>>,
+    last_seq: Seq,
+    #[cfg(feature = "std")]
+    subscribers: Vec<Sender<Change<K, V>>>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for CdcBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> CdcBTree<K, V> {
+    pub fn new() -> Self {
+        CdcBTree {
+            t: BTree::new(),
+            changes: VecDeque::new(),
+            last_seq: 0,
+            #[cfg(feature = "std")]
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Returns the sequence number of the last change, 0 if nothing has changed.
+    pub fn last_seq(&self) -> Seq {
+        self.last_seq
+    }
+
+    fn record(&mut self, op: Op, key: K, value: V) {
+        self.last_seq += 1;
+        let change = Change {
+            seq: self.last_seq,
+            op,
+            key,
+            value,
+        };
+        // the receivers which are dropped unsubscribe
+        #[cfg(feature = "std")]
+        self.subscribers.retain(|tx| tx.send(change).is_ok());
+        self.changes.push_back(change);
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k)
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        let old = self.t.insert(k, v);
+        self.record(Op::Insert, *k, *v);
+        old
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let old = self.t.remove(k)?;
+        self.record(Op::Remove, *k, old);
+        Some(old)
+    }
+
+    /// Applies the operations of the batch, see `BTree::apply_batch`.
+    pub fn apply_batch(&mut self, batch: &WriteBatch<K, V>) {
+        let mut changes = Vec::new();
+        for (k, v) in batch.sorted() {
+            match (v, self.t.lookup(&k)) {
+                (Some(v), _) => changes.push((Op::Insert, k, v)),
+                (None, Some(old)) => changes.push((Op::Remove, k, *old)),
+                (None, None) => {}
+            }
+        }
+        self.t.apply_batch(batch);
+        for (op, k, v) in changes {
+            self.record(op, k, v);
+        }
+    }
+
+    /// Returns the changes after `seq` which are not trimmed yet, in order.
+    ///
+    /// Panics if some changes after `seq` are trimmed already.
+    pub fn changes_since(&self, seq: Seq) -> impl Iterator<Item = &Change<K, V>> + '_ {
+        let first = self.last_seq + 1 - self.changes.len() as Seq;
+        assert!(seq + 1 >= first, "the changes after {} are trimmed", seq);
+        self.changes.range((seq + 1 - first).min(self.changes.len() as Seq) as usize..)
+    }
+
+    /// Drops the changes up to `seq`, which every consumer has seen.
+    pub fn trim(&mut self, seq: Seq) {
+        while self.changes.front().is_some_and(|c| c.seq <= seq) {
+            self.changes.pop_front();
+        }
+    }
+
+    /// Returns a channel receiving the changes committed from now on.
+    #[cfg(feature = "std")]
+    pub fn subscribe(&mut self) -> Receiver<Change<K, V>> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        rx
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_cdc() {
+    let mut t = CdcBTree::<u32, u32>::new();
+    let rx = t.subscribe();
+    for i in 0..100 {
+        t.insert(&i, &i);
+    }
+    t.insert(&5, &50);
+    assert_eq!(t.remove(&6), Some(6));
+    assert_eq!(t.remove(&1000), None);
+    let mut b = WriteBatch::new();
+    b.remove(&8);
+    b.remove(&7);
+    b.remove(&1000);
+    b.insert(&200, &2);
+    t.apply_batch(&b);
+    assert_eq!(t.last_seq(), 105);
+
+    let changes: Vec<_> = t.changes_since(100).copied().collect();
+    assert_eq!(
+        changes,
+        [
+            Change { seq: 101, op: Op::Insert, key: 5, value: 50 },
+            Change { seq: 102, op: Op::Remove, key: 6, value: 6 },
+            Change { seq: 103, op: Op::Remove, key: 7, value: 7 },
+            Change { seq: 104, op: Op::Remove, key: 8, value: 8 },
+            Change { seq: 105, op: Op::Insert, key: 200, value: 2 },
+        ]
+    );
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), t.changes_since(0).copied().collect::<Vec<_>>());
+    assert_eq!(t.changes_since(105).count(), 0);
+
+    // a follower replays the changes in order
+    let mut follower = BTree::new();
+    for c in t.changes_since(0) {
+        c.apply(&mut follower);
+    }
+    assert_eq!(follower.len(), 98);
+    assert!((0..1000).all(|k| follower.lookup(&k) == t.lookup(&k)));
+
+    t.trim(103);
+    assert_eq!(t.changes_since(103).map(|c| c.seq).collect::<Vec<_>>(), [104, 105]);
+    assert!(std::panic::catch_unwind(|| t.changes_since(102).count()).is_err());
+
+    drop(rx);
+    t.insert(&1, &1);
+    assert_eq!(t.changes_since(105).count(), 1);
+}
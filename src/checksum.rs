@@ -0,0 +1,332 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+
+use crate::cursor::Change;
+use crate::observe::NodeId;
+use crate::{BTree, NodeIndex, NODE_DEG};
+
+/// The error of a node whose checksum does not match its content, e.g. by a bad memory or a stray write.
+#[derive(Debug, PartialEq)]
+pub struct CorruptedNode {
+    pub node: NodeId,
+}
+
+impl fmt::Display for CorruptedNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the checksum of node {:?} does not match", self.node)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CorruptedNode {}
+
+/// The 64-bit FNV-1a hash.
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// CheckedBTree is a B+Tree keeping a checksum of every node, which catches the corrupted memory in long-running
+/// processes instead of returning wrong results.
+///
+/// The checksums are kept beside the nodes, indexed by the node ids, like the aggregates of `AugBTree`. Every update
+/// goes through a cursor recording the nodes it writes, and the checksums of these nodes are recomputed. The lookups
+/// verify the nodes on their paths, the updates also verify the siblings and the neighbouring leaves they may touch,
+/// before reading or writing them, and `verify` checks the whole tree.
+///
+/// The checksums cover the entries of the nodes, hashed by `Hash`, so keys and values equal by `Hash` are not told
+/// apart.
+pub struct CheckedBTree<K, V> {
+    t: BTree<K, V>,
+    internal_sums: Vec<u64>,
+    leaf_sums: Vec<u64>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy + Hash, V: Default + Copy + Hash> Default for CheckedBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy + Hash, V: Default + Copy + Hash> CheckedBTree<K, V> {
+    pub fn new() -> Self {
+        let mut t = CheckedBTree {
+            t: BTree::new(),
+            internal_sums: Vec::new(),
+            leaf_sums: vec![0],
+        };
+        t.leaf_sums[0] = t.checksum(t.t.root);
+        t
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// Computes the checksum of the node from its content.
+    fn checksum(&self, node: NodeIndex) -> u64 {
+        let mut h = Fnv(0xcbf29ce484222325);
+        match node {
+            NodeIndex::Leaf(id) => {
+                let l = &self.t.l[id];
+                l.cnt.hash(&mut h);
+                l.keys[..l.cnt].hash(&mut h);
+                l.values[..l.cnt].hash(&mut h);
+            }
+            NodeIndex::Internal(id) => {
+                let n = &self.t.i[id];
+                n.cnt.hash(&mut h);
+                n.keys[..n.cnt.saturating_sub(1)].hash(&mut h);
+                for son in n.sons[..n.cnt].iter() {
+                    NodeId::from(*son).hash(&mut h);
+                }
+            }
+        }
+        h.finish()
+    }
+
+    /// Checks the node against its checksum. A node id out of range, e.g. read from a corrupted son pointer, or a count
+    /// out of range fails before the node is hashed.
+    fn check(&self, node: NodeIndex) -> Result<(), CorruptedNode> {
+        let sum = match node {
+            NodeIndex::Leaf(id) if id < self.t.l.len() && self.t.l[id].cnt <= NODE_DEG => self.leaf_sums.get(id),
+            NodeIndex::Internal(id) if id < self.t.i.len() && (1..=NODE_DEG).contains(&self.t.i[id].cnt) => {
+                self.internal_sums.get(id)
+            }
+            _ => None,
+        };
+        match sum {
+            Some(&sum) if sum == self.checksum(node) => Ok(()),
+            _ => Err(CorruptedNode { node: node.into() }),
+        }
+    }
+
+    /// Checks the nodes on the path to `k`, and returns the leaf.
+    fn check_path(&self, k: &K) -> Result<usize, CorruptedNode> {
+        let mut cur = self.t.root;
+        loop {
+            self.check(cur)?;
+            match cur {
+                NodeIndex::Internal(id) => cur = self.t.i[id].lookup(k).1,
+                NodeIndex::Leaf(id) => return Ok(id),
+            }
+        }
+    }
+
+    /// Checks the nodes an update of `k` may read or write: the nodes on the path to `k`, their siblings, which the
+    /// rebalancing borrows from or merges with, and the paths to the leaves beside the path, which the cursor steps to.
+    fn check_update(&self, k: &K) -> Result<(), CorruptedNode> {
+        let mut cur = self.t.root;
+        // the deepest subtrees on the left and on the right of the path
+        let (mut left, mut right) = (None, None);
+        while let NodeIndex::Internal(id) = cur {
+            self.check(cur)?;
+            let n = &self.t.i[id];
+            let (i, son) = n.lookup(k);
+            if i > 0 {
+                self.check(n.sons[i - 1])?;
+                left = Some(n.sons[i - 1]);
+            }
+            if i + 1 < n.cnt {
+                self.check(n.sons[i + 1])?;
+                right = Some(n.sons[i + 1]);
+            }
+            cur = son;
+        }
+        self.check(cur)?;
+        for (mut cur, last) in [(left, true), (right, false)] {
+            while let Some(NodeIndex::Internal(id)) = cur {
+                let n = &self.t.i[id];
+                let son = if last { n.sons[n.cnt - 1] } else { n.sons[0] };
+                self.check(son)?;
+                cur = Some(son);
+            }
+        }
+        Ok(())
+    }
+
+    /// Recomputes the checksums of the changed nodes.
+    fn update(&mut self, changes: Vec<Change>) {
+        self.internal_sums.resize(self.t.i.len(), 0);
+        self.leaf_sums.resize(self.t.l.len(), 0);
+        for c in changes {
+            let sum = self.checksum(c.node);
+            match c.node {
+                NodeIndex::Leaf(id) => self.leaf_sums[id] = sum,
+                NodeIndex::Internal(id) => self.internal_sums[id] = sum,
+            }
+        }
+    }
+
+    /// Looks up the value of `k`, verifying the nodes on the path.
+    pub fn lookup(&self, k: &K) -> Result<Option<&V>, CorruptedNode> {
+        let leaf = self.check_path(k)?;
+        Ok(self.t.l[leaf].lookup(k))
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value. Fails without writing if a node it may read or
+    /// write is corrupted.
+    pub fn insert(&mut self, k: &K, v: &V) -> Result<Option<V>, CorruptedNode> {
+        self.check_update(k)?;
+        let mut c = self.t.cursor_mut();
+        c.track_changes();
+        c.seek(k);
+        let ret = match c.peek_mut() {
+            Some((key, value)) if key == k => Some(core::mem::replace(value, *v)),
+            _ => {
+                c.insert_after(k, v);
+                None
+            }
+        };
+        let changes = c.take_changes();
+        self.update(changes);
+        Ok(ret)
+    }
+
+    /// Removes `k`, and returns its value if it exists. Fails without writing if a node it may read or write, e.g. the
+    /// sibling it merges with, is corrupted.
+    pub fn remove(&mut self, k: &K) -> Result<Option<V>, CorruptedNode> {
+        self.check_update(k)?;
+        let mut c = self.t.cursor_mut();
+        c.track_changes();
+        c.seek(k);
+        let ret = match c.peek() {
+            Some((key, _)) if key == k => c.remove_next().map(|(_, v)| v),
+            _ => None,
+        };
+        let changes = c.take_changes();
+        self.update(changes);
+        Ok(ret)
+    }
+
+    /// Verifies all nodes of the tree.
+    pub fn verify(&self) -> Result<(), CorruptedNode> {
+        let mut stack = vec![self.t.root];
+        while let Some(node) = stack.pop() {
+            self.check(node)?;
+            if let NodeIndex::Internal(id) = node {
+                let n = &self.t.i[id];
+                stack.extend_from_slice(&n.sons[..n.cnt]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies all nodes of the tree, and returns an iterator over the entries.
+    pub fn iter(&self) -> Result<impl Iterator<Item = (&K, &V)> + '_, CorruptedNode> {
+        self.verify()?;
+        Ok(self.t.iter())
+    }
+}
+
+#[test]
+fn test_checked() {
+    let mut t = CheckedBTree::<u64, u64>::new();
+    assert_eq!(t.lookup(&0), Ok(None));
+    for i in 0..10000u64 {
+        assert_eq!(t.insert(&(i * 7919 % 10007), &i), Ok(None));
+    }
+    for i in 0..5000u64 {
+        assert_eq!(t.remove(&(i * 7919 % 10007)), Ok(Some(i)));
+    }
+    assert_eq!(t.insert(&(5000 * 7919 % 10007), &0), Ok(Some(5000)));
+    assert_eq!(t.verify(), Ok(()));
+    assert!(t.iter().unwrap().map(|(k, _)| *k).eq(t.t.iter().map(|(k, _)| *k)));
+    assert_eq!(t.len(), 5000);
+
+    // a stray write to a leaf
+    let (leaf, pos) = t.t.locate(&(6000 * 7919 % 10007)).unwrap();
+    t.t.l[leaf].values[pos] ^= 1 << 40;
+    let e = CorruptedNode { node: NodeId::Leaf(leaf) };
+    assert_eq!(t.lookup(&(6000 * 7919 % 10007)).unwrap_err(), e);
+    assert_eq!(t.insert(&(6000 * 7919 % 10007), &0).unwrap_err(), e);
+    assert_eq!(t.verify(), Err(e));
+    assert!(t.iter().is_err());
+    // the other leaves are still readable
+    assert_eq!(t.lookup(&(9000 * 7919 % 10007)).map(|v| v.copied()).ok(), Some(Some(9000)));
+    t.t.l[leaf].values[pos] ^= 1 << 40;
+    assert_eq!(t.verify(), Ok(()));
+
+    // a bit flip in the root
+    let root = match t.t.root {
+        NodeIndex::Internal(id) => id,
+        NodeIndex::Leaf(_) => unreachable!(),
+    };
+    t.t.i[root].keys[0] += 1;
+    let e = CorruptedNode { node: NodeId::Internal(root) };
+    assert_eq!(t.lookup(&0), Err(e));
+    assert_eq!(
+        t.remove(&0).unwrap_err().to_string(),
+        format!("the checksum of node Internal({}) does not match", root)
+    );
+    t.t.i[root].keys[0] -= 1;
+
+    // a stray write to the count of a node, out of its capacity
+    let (leaf, _) = t.t.locate(&(9000 * 7919 % 10007)).unwrap();
+    t.t.l[leaf].cnt = NODE_DEG + 1000;
+    let e = CorruptedNode { node: NodeId::Leaf(leaf) };
+    assert_eq!(t.lookup(&(9000 * 7919 % 10007)).unwrap_err(), e);
+    assert_eq!(t.verify(), Err(e));
+    t.t.l[leaf].cnt = 0;
+    t.t.i[root].cnt = 0;
+    assert_eq!(t.lookup(&0), Err(CorruptedNode { node: NodeId::Internal(root) }));
+
+    // a son pointing out of the tree
+    let mut t = CheckedBTree::<u64, u64>::new();
+    for i in 0..10000u64 {
+        assert_eq!(t.insert(&i, &i), Ok(None));
+    }
+    let root = match t.t.root {
+        NodeIndex::Internal(id) => id,
+        NodeIndex::Leaf(_) => unreachable!(),
+    };
+    let bad = match t.t.i[root].sons[0] {
+        NodeIndex::Internal(_) => NodeIndex::Internal(t.t.i.len() + 5),
+        NodeIndex::Leaf(_) => NodeIndex::Leaf(t.t.l.len() + 5),
+    };
+    t.t.i[root].sons[0] = bad;
+    assert_eq!(t.lookup(&0), Err(CorruptedNode { node: NodeId::Internal(root) }));
+    // even if the checksum of the root is corrupted the same way
+    t.internal_sums[root] = t.checksum(t.t.root);
+    assert_eq!(t.lookup(&0), Err(CorruptedNode { node: bad.into() }));
+    assert_eq!(t.insert(&0, &1), Err(CorruptedNode { node: bad.into() }));
+    assert_eq!(t.verify(), Err(CorruptedNode { node: bad.into() }));
+
+    // a stray write to the sibling a leaf merges with
+    let mut t = CheckedBTree::<u64, u64>::new();
+    for i in 0..10000u64 {
+        assert_eq!(t.insert(&i, &i), Ok(None));
+    }
+    let (leaf, _) = t.t.locate(&0).unwrap();
+    let (sibling, _) = t.t.locate(&(t.t.l[leaf].cnt as u64)).unwrap();
+    let mut k = 0;
+    while t.t.l[leaf].cnt > NODE_DEG / 2 {
+        assert_eq!(t.remove(&k), Ok(Some(k)));
+        k += 1;
+    }
+    t.t.l[sibling].values[0] ^= 1 << 40;
+    let e = CorruptedNode { node: NodeId::Leaf(sibling) };
+    assert_eq!(t.remove(&k).unwrap_err(), e);
+    assert_eq!(t.insert(&0, &0).unwrap_err(), e);
+    // the sibling is not written, and keeps its checksum
+    assert_eq!(t.len(), 10000 - k as usize);
+    assert_eq!(t.verify(), Err(e));
+    t.t.l[sibling].values[0] ^= 1 << 40;
+    assert_eq!(t.remove(&k), Ok(Some(k)));
+    assert_eq!(t.verify(), Ok(()));
+}
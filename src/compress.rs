@@ -0,0 +1,230 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::RangeBounds;
+
+use crate::overflow::OverflowBTree;
+
+/// ValueCodec compresses the values of a `CompressedBTree`, e.g. by LZ4, Zstd or Snappy.
+pub trait ValueCodec {
+    /// Compresses the value.
+    fn compress(&self, value: &[u8]) -> Vec<u8>;
+    /// Decompresses `data` into `out`, whose length is the length of the value. Returns None if `data` is corrupted.
+    fn decompress(&self, data: &[u8], out: &mut [u8]) -> Option<()>;
+}
+
+/// The LZ4 block codec.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lz4;
+
+#[cfg(feature = "std")]
+impl ValueCodec for Lz4 {
+    fn compress(&self, value: &[u8]) -> Vec<u8> {
+        crate::lz4::compress(value)
+    }
+
+    fn decompress(&self, data: &[u8], out: &mut [u8]) -> Option<()> {
+        crate::lz4::decompress(data, out)
+    }
+}
+
+/// The statistics of the compression of a `CompressedBTree`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// The bytes of the values.
+    pub raw_bytes: usize,
+    /// The bytes stored for the values.
+    pub stored_bytes: usize,
+    /// The values stored compressed.
+    pub compressed: usize,
+}
+
+impl CompressionStats {
+    /// Returns the bytes of the values per stored byte, 1 if there are none.
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            1.0
+        } else {
+            self.raw_bytes as f64 / self.stored_bytes as f64
+        }
+    }
+}
+
+/// A value as it is stored.
+struct Blob {
+    // the length of the value, which is stored as it is if the data is as long
+    len: usize,
+    data: Vec<u8>,
+}
+
+/// CompressedBTree is a B+Tree of byte values, which are stored compressed by a `ValueCodec` out of line, and
+/// decompressed on every access.
+///
+/// The values shorter than the minimum size, 64 bytes by default, and the values which do not compress are stored as
+/// they are.
+pub struct CompressedBTree<K, C> {
+    t: OverflowBTree<K, Blob>,
+    codec: C,
+    min_size: usize,
+    stats: CompressionStats,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, C: ValueCodec> CompressedBTree<K, C> {
+    pub fn new(codec: C) -> Self {
+        CompressedBTree {
+            t: OverflowBTree::new(),
+            codec,
+            min_size: 64,
+            stats: CompressionStats::default(),
+        }
+    }
+
+    /// Sets the size of the shortest value to compress.
+    pub fn set_min_size(&mut self, min_size: usize) {
+        self.min_size = min_size;
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    pub fn stats(&self) -> CompressionStats {
+        self.stats
+    }
+
+    fn encode(&mut self, value: &[u8]) -> Blob {
+        let mut data = Vec::new();
+        if value.len() >= self.min_size {
+            data = self.codec.compress(value);
+        }
+        if data.is_empty() || data.len() >= value.len() {
+            data = value.to_vec();
+        }
+        let blob = Blob {
+            len: value.len(),
+            data,
+        };
+        self.account(&blob, true);
+        blob
+    }
+
+    fn decode(&self, blob: &Blob) -> Vec<u8> {
+        if blob.data.len() == blob.len {
+            return blob.data.clone();
+        }
+        let mut value = vec![0; blob.len];
+        self.codec
+            .decompress(&blob.data, &mut value)
+            .expect("the codec failed to decompress a value it compressed");
+        value
+    }
+
+    /// Adds the blob to the statistics, or subtracts it.
+    fn account(&mut self, blob: &Blob, add: bool) {
+        let compressed = (blob.data.len() != blob.len) as usize;
+        let s = &mut self.stats;
+        if add {
+            s.raw_bytes += blob.len;
+            s.stored_bytes += blob.data.len();
+            s.compressed += compressed;
+        } else {
+            s.raw_bytes -= blob.len;
+            s.stored_bytes -= blob.data.len();
+            s.compressed -= compressed;
+        }
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<Vec<u8>> {
+        self.t.lookup(k).map(|b| self.decode(b))
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value.
+    pub fn insert(&mut self, k: &K, value: &[u8]) -> Option<Vec<u8>> {
+        let blob = self.encode(value);
+        let old = self.t.insert(k, blob)?;
+        self.account(&old, false);
+        Some(self.decode(&old))
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<Vec<u8>> {
+        let old = self.t.remove(k)?;
+        self.account(&old, false);
+        Some(self.decode(&old))
+    }
+
+    /// Returns an iterator over the entries whose keys are in the `range`, decompressing the values.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (&K, Vec<u8>)> + '_ {
+        self.t.range(range).map(move |(k, b)| (k, self.decode(b)))
+    }
+}
+
+#[test]
+fn test_compressed() {
+    // stores the runs of the same byte as (byte, count) pairs
+    struct Rle;
+    impl ValueCodec for Rle {
+        fn compress(&self, value: &[u8]) -> Vec<u8> {
+            let mut out = Vec::new();
+            for &b in value {
+                match out.len() {
+                    n if n >= 2 && out[n - 2] == b && out[n - 1] < 255 => out[n - 1] += 1,
+                    _ => out.extend_from_slice(&[b, 1]),
+                }
+            }
+            out
+        }
+
+        fn decompress(&self, data: &[u8], out: &mut [u8]) -> Option<()> {
+            let mut pos = 0;
+            for run in data.chunks(2) {
+                out.get_mut(pos..pos + run[1] as usize)?.fill(run[0]);
+                pos += run[1] as usize;
+            }
+            (pos == out.len()).then_some(())
+        }
+    }
+
+    let mut t = CompressedBTree::<u32, _>::new(Rle);
+    for i in 0..1000u32 {
+        assert_eq!(t.insert(&i, &vec![i as u8; i as usize]), None);
+    }
+    assert_eq!(t.len(), 1000);
+    assert_eq!(t.lookup(&10), Some(vec![10; 10]));
+    assert_eq!(t.lookup(&999), Some(vec![231; 999]));
+    assert_eq!(t.lookup(&1000), None);
+    let s = t.stats();
+    assert_eq!(s.raw_bytes, 999 * 1000 / 2);
+    assert_eq!(s.compressed, 1000 - 64);
+    assert!(s.ratio() > 50.0);
+
+    // the values which do not compress are stored as they are
+    let bytes: Vec<u8> = (0..200).collect();
+    assert_eq!(t.insert(&999, &bytes), Some(vec![231; 999]));
+    assert_eq!(t.lookup(&999), Some(bytes.clone()));
+    assert_eq!(t.stats().compressed, 1000 - 65);
+    assert_eq!(t.remove(&999), Some(bytes));
+    for i in 0..999 {
+        assert_eq!(t.remove(&i).map(|v| v.len()), Some(i as usize));
+    }
+    assert_eq!(t.stats(), CompressionStats::default());
+    assert_eq!(t.stats().ratio(), 1.0);
+
+    #[cfg(feature = "std")]
+    {
+        use alloc::format;
+
+        let mut t = CompressedBTree::<u32, _>::new(Lz4);
+        let doc = |i: u32| format!(r#"{{"id":{},"status":"active","tags":["a","b","c"],"owner":"user-{}"}}"#, i, i % 7);
+        for i in 0..1000u32 {
+            t.insert(&i, doc(i).repeat(4).as_bytes());
+        }
+        assert!(t.range(10..20).map(|(k, v)| (*k, v)).eq((10..20).map(|i| (i, doc(i).repeat(4).into_bytes()))));
+        assert_eq!(t.stats().compressed, 1000);
+        assert!(t.stats().ratio() > 3.0);
+    }
+}
@@ -0,0 +1,200 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::{BTree, LeafNode, NodeIndex, NODE_DEG};
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Builds a tree from the entries sorted by the keys. The leaves are filled from left to right, and the internal
+    /// nodes are built on top of them level by level, without searching the tree for every key.
+    ///
+    /// Panics if the keys are not strictly increasing.
+    pub fn from_sorted_vec(entries: Vec<(K, V)>) -> Self {
+        assert!(
+            entries.windows(2).all(|w| w[0].0 < w[1].0),
+            "the keys are not strictly increasing"
+        );
+        let mut t = BTree::with_capacity(entries.len());
+        t.load_sorted(&entries, NODE_DEG, NODE_DEG);
+        t
+    }
+
+    /// Rewrites the tree so that its leaves are `target_fill` full, e.g. after many removals left them half empty.
+    /// The entries are packed into as few leaves as the fill allows, and the internal nodes are built on top of them
+    /// as full as the fill allows too, so the scans read fewer leaves and the tree is as low as it can be. A fill less
+    /// than 1 leaves room for the insertions before the nodes split again.
+    ///
+    /// The nodes are rebuilt from the start of the node buffers without the freed ones, and the leaves are laid out in
+    /// the order of the keys, so the scans read the memory sequentially. The buffers keep their space. The keys are of
+    /// a fixed size, so the separators in the internal nodes are not truncated.
+    ///
+    /// Panics if `target_fill` is not in (0, 1].
+    pub fn optimize(&mut self, target_fill: f64) {
+        assert!(target_fill > 0.0 && target_fill <= 1.0, "the target fill is not in (0, 1]");
+        let leaf_size = ((NODE_DEG as f64 * target_fill + 0.5) as usize).max(1);
+        let fanout = leaf_size.max(2);
+        let mut entries = Vec::with_capacity(self.len);
+        for id in self.leaf_ids() {
+            let l = &self.l[id];
+            entries.extend(l.keys[0..l.cnt].iter().copied().zip(l.values[0..l.cnt].iter().copied()));
+        }
+        self.load_sorted(&entries, leaf_size, fanout);
+    }
+
+    /// Replaces all nodes with the sorted entries, filling the leaves with `leaf_size` entries and the internal nodes
+    /// with `fanout` sons at most.
+    fn load_sorted(&mut self, entries: &[(K, V)], leaf_size: usize, fanout: usize) {
+        self.i.clear();
+        self.l.clear();
+        self.free_i.clear();
+        self.free_l.clear();
+        self.len = entries.len();
+        if entries.is_empty() {
+            self.l.push(LeafNode::new());
+            self.root = NodeIndex::Leaf(0);
+            return;
+        }
+
+        // split the entries into leaves of even sizes
+        let groups = entries.len().div_ceil(leaf_size);
+        let (base, extra) = (entries.len() / groups, entries.len() % groups);
+        let mut leaves = Vec::with_capacity(groups);
+        let mut rest = entries;
+        for g in 0..groups {
+            let size = base + (g < extra) as usize;
+            let mut l = LeafNode::new();
+            for (i, (k, v)) in rest[..size].iter().enumerate() {
+                l.keys[i] = *k;
+                l.values[i] = *v;
+            }
+            l.cnt = size;
+            rest = &rest[size..];
+            leaves.push(self.alloc_leaf(l));
+        }
+        self.build_internal_levels(&leaves, fanout);
+    }
+
+    /// Splits the tree at `k`, and returns the entries whose keys are not less than `k` as a new tree. The moved
+    /// entries are bulk loaded into the new tree, and removed from this one by a cursor, so it takes O(m) for m moved
+    /// entries.
+    pub fn split_off(&mut self, k: &K) -> Self {
+        let tail: Vec<(K, V)> = self.range(k..).map(|(k, v)| (*k, *v)).collect();
+        let mut c = self.cursor_mut();
+        c.seek(k);
+        while c.remove_next().is_some() {}
+        BTree::from_sorted_vec(tail)
+    }
+
+    /// Returns the entries sorted by the keys, copied from the leaves in order.
+    pub fn into_sorted_vec(self) -> Vec<(K, V)> {
+        let mut entries = Vec::new();
+        for id in self.leaf_ids() {
+            let l = &self.l[id];
+            entries.extend(l.keys[0..l.cnt].iter().copied().zip(l.values[0..l.cnt].iter().copied()));
+        }
+        entries
+    }
+}
+
+impl<K: Ord + Default + Copy, V: Default + Copy> From<BTreeMap<K, V>> for BTree<K, V> {
+    /// The entries of the map are in order, so the tree is bulk loaded by `from_sorted_vec`.
+    fn from(m: BTreeMap<K, V>) -> Self {
+        BTree::from_sorted_vec(m.into_iter().collect())
+    }
+}
+
+impl<K: Ord + Default + Copy, V: Default + Copy> From<BTree<K, V>> for BTreeMap<K, V> {
+    /// `BTreeMap` builds itself in bulk from the sorted entries.
+    fn from(t: BTree<K, V>) -> Self {
+        t.into_sorted_vec().into_iter().collect()
+    }
+}
+
+#[test]
+fn test_sorted_vec() {
+    let entries: Vec<(u32, u32)> = (0..10000).map(|i| (i * 3, i)).collect();
+    let mut t = BTree::from_sorted_vec(entries.clone());
+    assert_eq!(t.len(), 10000);
+    for (k, v) in entries.iter() {
+        assert_eq!(t.lookup(k), Some(v));
+        assert_eq!(t.lookup(&(k + 1)), None);
+    }
+
+    // the tree is a normal tree
+    t.insert(&1, &1);
+    assert_eq!(t.remove(&3), Some(1));
+    let mut expected = entries.clone();
+    expected[1] = (1, 1);
+    assert_eq!(t.into_sorted_vec(), expected);
+
+    assert!(BTree::<u32, u32>::from_sorted_vec(Vec::new()).into_sorted_vec().is_empty());
+    assert_eq!(BTree::from_sorted_vec(vec![(1, 2)]).into_sorted_vec(), [(1, 2)]);
+}
+
+#[test]
+#[should_panic(expected = "the keys are not strictly increasing")]
+fn test_sorted_vec_unsorted() {
+    BTree::from_sorted_vec(vec![(1, 1), (1, 2)]);
+}
+
+#[test]
+fn test_optimize() {
+    let n = 100000u32;
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..n {
+        t.insert(&(i * 7919 % n), &i);
+    }
+    for k in (0..n).filter(|k| k % 3 != 0) {
+        t.remove(&k);
+    }
+    let entries: Vec<(u32, u32)> = t.iter().map(|(k, v)| (*k, *v)).collect();
+    let leaves = t.leaf_ids().len();
+
+    t.optimize(1.0);
+    assert!(t.iter().map(|(k, v)| (*k, *v)).eq(entries.iter().copied()));
+    assert_eq!(t.leaf_ids(), (0..entries.len().div_ceil(NODE_DEG)).collect::<Vec<_>>());
+    assert!(t.leaf_ids().len() * 3 < leaves * 2);
+    assert!(t.free_l.is_empty() && t.free_i.is_empty());
+    assert_eq!(t.l.len(), t.leaf_ids().len());
+
+    // room for the insertions
+    t.optimize(0.75);
+    let l = &t.l[0];
+    assert_eq!(l.cnt, 24);
+    assert!(t.lookup(&3).is_some() && t.lookup(&4).is_none());
+    for i in 0..n {
+        t.insert(&i, &i);
+    }
+    assert_eq!(t.len(), n as usize);
+    assert!(t.iter().map(|(k, _)| *k).eq(0..n));
+
+    let mut t = BTree::<u32, u32>::new();
+    t.optimize(0.5);
+    assert!(t.is_empty());
+    t.insert(&1, &1);
+    assert_eq!(t.lookup(&1), Some(&1));
+}
+
+#[test]
+fn test_split_off() {
+    let mut t = BTree::from_sorted_vec((0..10000u32).map(|i| (i * 2, i)).collect());
+    let tail = t.split_off(&5001);
+    assert_eq!((t.len(), tail.len()), (2501, 7499));
+    assert_eq!(t.iter().last(), Some((&5000, &2500)));
+    assert_eq!(tail.iter().next(), Some((&5002, &2501)));
+    assert!(t.split_off(&100000).is_empty());
+    let all = t.split_off(&0);
+    assert!(t.is_empty());
+    assert_eq!(all.len(), 2501);
+}
+
+#[test]
+fn test_btreemap() {
+    let m: BTreeMap<u32, u32> = (0..10000).map(|i| (i * 7919 % 10000, i)).collect();
+    let t = BTree::from(m.clone());
+    for (k, v) in m.iter() {
+        assert_eq!(t.lookup(k), Some(v));
+    }
+    let back: BTreeMap<u32, u32> = t.into();
+    assert_eq!(back, m);
+    assert!(BTreeMap::from(BTree::<u32, u32>::from(BTreeMap::new())).is_empty());
+}
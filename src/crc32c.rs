@@ -0,0 +1,38 @@
+//! CRC-32C (Castagnoli), the checksum of the pages.
+
+const POLY: u32 = 0x82f6_3b78; // the reversed polynomial
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static TABLE: [u32; 256] = make_table();
+
+/// Continues the checksum `crc` of the previous bytes with `data`, the checksum of no bytes is 0.
+pub(crate) fn update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in data {
+        crc = TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[test]
+fn test_crc32c() {
+    assert_eq!(update(0, b""), 0);
+    assert_eq!(update(0, b"123456789"), 0xe306_9283);
+    assert_eq!(update(update(0, b"1234"), b"56789"), 0xe306_9283);
+    assert_eq!(update(0, &[0u8; 32]), 0x8a91_36aa);
+}
@@ -0,0 +1,810 @@
+use alloc::vec::Vec;
+
+use crate::metrics::Counter;
+use crate::observe::NodeEvent;
+use crate::{lower_bound, BTree, InternalNode, NodeIndex, NODE_DEG};
+
+/// The nodes a cursor walks on. It is the tree itself, or a view which never references the values of the leaves,
+/// so that the values can be lent out mutably during the walk.
+pub(crate) trait Nodes {
+    type K;
+
+    fn root(&self) -> NodeIndex;
+    fn internal(&self, id: usize) -> &InternalNode<Self::K>;
+    /// Returns the valid keys of the leaf.
+    fn leaf_keys(&self, id: usize) -> &[Self::K];
+}
+
+impl<K, V> Nodes for BTree<K, V> {
+    type K = K;
+
+    fn root(&self) -> NodeIndex {
+        self.root
+    }
+
+    fn internal(&self, id: usize) -> &InternalNode<K> {
+        &self.i[id]
+    }
+
+    fn leaf_keys(&self, id: usize) -> &[K] {
+        let l = &self.l[id];
+        &l.keys[0..l.cnt]
+    }
+}
+
+/// The position of a cursor, which is a gap between two adjacent entries of a tree.
+#[derive(Clone)]
+pub(crate) struct Path {
+    stack: Vec<(usize, usize)>, // (internal node id, son index) from the root to the father of the leaf
+    leaf: usize,
+    // the gap is before `keys[pos]` of the leaf. `pos` only equals the leaf size when there is no leaf on the right.
+    pos: usize,
+}
+
+impl Path {
+    pub(crate) fn new() -> Self {
+        Path {
+            stack: Vec::new(),
+            leaf: 0,
+            pos: 0,
+        }
+    }
+
+    /// Descends from `cur` to its leftmost or rightmost leaf, and points to the first or the last gap of the leaf.
+    fn descend<T: Nodes>(&mut self, t: &T, mut cur: NodeIndex, leftmost: bool) {
+        loop {
+            match cur {
+                NodeIndex::Internal(id) => {
+                    let node = t.internal(id);
+                    let i = if leftmost { 0 } else { node.cnt - 1 };
+                    self.stack.push((id, i));
+                    cur = node.sons[i];
+                }
+                NodeIndex::Leaf(id) => {
+                    self.leaf = id;
+                    self.pos = if leftmost { 0 } else { t.leaf_keys(id).len() };
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Moves to the next (or the previous) leaf. Returns false and stays if there is no such leaf.
+    fn step_leaf<T: Nodes>(&mut self, t: &T, forward: bool) -> bool {
+        // find the deepest ancestor which has a son on that side
+        let depth = self.stack.iter().rposition(|&(id, i)| if forward { i + 1 < t.internal(id).cnt } else { i > 0 });
+        let depth = match depth {
+            Some(d) => d,
+            None => return false,
+        };
+
+        self.stack.truncate(depth + 1);
+        let (id, i) = self.stack.last_mut().unwrap();
+        *i = if forward { *i + 1 } else { *i - 1 };
+        let son = t.internal(*id).sons[*i];
+        self.descend(t, son, forward);
+        true
+    }
+
+    /// Keeps the invariant that `pos` is in the leaf, unless the cursor is at the end.
+    fn normalize<T: Nodes>(&mut self, t: &T) {
+        while self.pos == t.leaf_keys(self.leaf).len() && self.step_leaf(t, true) {}
+    }
+
+    pub(crate) fn seek_first<T: Nodes>(&mut self, t: &T) {
+        self.stack.clear();
+        self.descend(t, t.root(), true);
+        self.normalize(t);
+    }
+
+    pub(crate) fn seek_last<T: Nodes>(&mut self, t: &T) {
+        self.stack.clear();
+        self.descend(t, t.root(), false);
+    }
+
+    pub(crate) fn seek<T: Nodes>(&mut self, t: &T, k: &T::K)
+    where
+        T::K: PartialOrd + Copy + Default,
+    {
+        self.stack.clear();
+        self.seek_from(t, t.root(), k);
+    }
+
+    /// Descends from `cur`, which is the node after the stack, to the gap of `k`.
+    fn seek_from<T: Nodes>(&mut self, t: &T, mut cur: NodeIndex, k: &T::K)
+    where
+        T::K: PartialOrd + Copy + Default,
+    {
+        loop {
+            match cur {
+                NodeIndex::Internal(id) => {
+                    let (i, son) = t.internal(id).lookup(k);
+                    self.stack.push((id, i));
+                    cur = son;
+                }
+                NodeIndex::Leaf(id) => {
+                    self.leaf = id;
+                    self.pos = lower_bound(t.leaf_keys(id), k);
+                    break;
+                }
+            }
+        }
+        self.normalize(t);
+    }
+
+    /// The same as `seek`, but `k` must not be less than the key before the gap. If the gap of `k` is in the current
+    /// leaf, the path stays in it without descending from the root. Otherwise it only goes up to the deepest ancestor
+    /// whose subtree holds `k`, and descends from there.
+    pub(crate) fn seek_forward<T: Nodes>(&mut self, t: &T, k: &T::K)
+    where
+        T::K: PartialOrd + Copy + Default,
+    {
+        let keys = t.leaf_keys(self.leaf);
+        if keys.last().is_some_and(|last| k <= last) {
+            self.pos += lower_bound(&keys[self.pos..], k);
+            return;
+        }
+        // `sons[i]` holds the keys up to `keys[i]` after `keys[i - 1]`, and `k` is after `keys[i - 1]` as it is not
+        // less than the keys before the gap, so only the upper bound is checked
+        while let Some(&(id, i)) = self.stack.last() {
+            let node = t.internal(id);
+            if i + 1 == node.cnt || k <= &node.keys[i] {
+                break;
+            }
+            self.stack.pop();
+        }
+        match self.stack.pop() {
+            Some((id, _)) => self.seek_from(t, NodeIndex::Internal(id), k),
+            None => self.seek(t, k),
+        }
+    }
+
+    /// Returns the (leaf id, position) of the entry after the gap.
+    pub(crate) fn peek<T: Nodes>(&self, t: &T) -> Option<(usize, usize)> {
+        if self.pos < t.leaf_keys(self.leaf).len() {
+            Some((self.leaf, self.pos))
+        } else {
+            None
+        }
+    }
+
+    /// Moves over the entry after the gap, and returns its (leaf id, position).
+    pub(crate) fn next<T: Nodes>(&mut self, t: &T) -> Option<(usize, usize)> {
+        let ret = self.peek(t)?;
+        self.pos += 1;
+        self.normalize(t);
+        Some(ret)
+    }
+
+    /// Moves over the rest of the leaf after the gap to the next leaf, and returns (leaf id, start, end) of the entries
+    /// moved over. Returns None at the end.
+    pub(crate) fn next_chunk<T: Nodes>(&mut self, t: &T) -> Option<(usize, usize, usize)> {
+        let end = t.leaf_keys(self.leaf).len();
+        if self.pos == end {
+            return None;
+        }
+        let ret = (self.leaf, self.pos, end);
+        self.pos = end;
+        self.normalize(t);
+        Some(ret)
+    }
+
+    /// Moves over the entry before the gap, and returns its (leaf id, position).
+    pub(crate) fn prev<T: Nodes>(&mut self, t: &T) -> Option<(usize, usize)> {
+        while self.pos == 0 {
+            if !self.step_leaf(t, false) {
+                return None;
+            }
+        }
+        self.pos -= 1;
+        Some((self.leaf, self.pos))
+    }
+
+    /// Returns the (leaf id, position) of the entry before the gap.
+    fn peek_prev<T: Nodes>(&self, t: &T) -> Option<(usize, usize)> {
+        if self.pos > 0 {
+            return Some((self.leaf, self.pos - 1));
+        }
+        self.clone().prev(t)
+    }
+}
+
+/// Cursor points to a gap between two adjacent entries of a tree, or the gap before the first entry or after the last.
+/// `next` returns the entry after the gap and moves over it, `prev` does the same backward.
+///
+/// The cursor remembers the path from the root, so stepping to the neighbouring leaf does not descend from the root.
+pub struct Cursor<'a, K, V> {
+    t: &'a BTree<K, V>,
+    path: Path,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Returns a cursor pointing to the gap before the first entry.
+    pub fn cursor(&self) -> Cursor<'_, K, V> {
+        let mut path = Path::new();
+        path.seek_first(self);
+        Cursor { t: self, path }
+    }
+
+    /// Returns a mutable cursor pointing to the gap before the first entry.
+    pub fn cursor_mut(&mut self) -> CursorMut<'_, K, V> {
+        let mut path = Path::new();
+        path.seek_first(self);
+        CursorMut {
+            t: self,
+            path,
+            changes: None,
+        }
+    }
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Cursor<'a, K, V> {
+    fn entry(&self, (leaf, pos): (usize, usize)) -> (&'a K, &'a V) {
+        let l = &self.t.l[leaf];
+        (&l.keys[pos], &l.values[pos])
+    }
+
+    /// Points to the gap before the first entry.
+    pub fn seek_first(&mut self) {
+        self.path.seek_first(self.t);
+    }
+
+    /// Points to the gap after the last entry.
+    pub fn seek_last(&mut self) {
+        self.path.seek_last(self.t);
+    }
+
+    /// Points to the gap before the first entry whose key is not less than `k`.
+    pub fn seek(&mut self, k: &K) {
+        self.path.seek(self.t, k);
+        self.t.metrics.add(Counter::Searches, self.path.stack.len() as u64 + 1);
+    }
+
+    /// Returns the entry after the gap without moving.
+    pub fn peek(&self) -> Option<(&'a K, &'a V)> {
+        self.path.peek(self.t).map(|e| self.entry(e))
+    }
+
+    /// Returns the entry after the gap and moves over it. Returns None at the end.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.path.next(self.t).map(|e| self.entry(e))
+    }
+
+    /// Returns the entry before the gap and moves over it. Returns None at the beginning.
+    pub fn prev(&mut self) -> Option<(&'a K, &'a V)> {
+        self.path.prev(self.t).map(|e| self.entry(e))
+    }
+}
+
+/// A node other than the root is rebalanced when it is less than half full.
+const MIN_CNT: usize = NODE_DEG / 2;
+
+/// Returns the mutable references to two different elements of a slice.
+fn two_mut<T>(s: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    assert!(a != b);
+    if a < b {
+        let (left, right) = s.split_at_mut(b);
+        (&mut left[a], &mut right[0])
+    } else {
+        let (left, right) = s.split_at_mut(a);
+        (&mut right[0], &mut left[b])
+    }
+}
+
+/// A node written by a `CursorMut`, the leaves are at the height 0.
+#[derive(Clone, Copy)]
+pub(crate) struct Change {
+    pub height: usize,
+    pub node: NodeIndex,
+    // the node is split, merged, or moves entries with a sibling, so the range of its keys changes
+    pub resized: bool,
+}
+
+/// CursorMut is a cursor which can also update the values, and insert or remove entries at the gap.
+///
+/// Inserting and removing work on the path of the cursor: a full leaf is split, and a leaf less than half full is
+/// merged with or borrows from its sibling, both may propagate to the ancestors on the path.
+pub struct CursorMut<'a, K, V> {
+    t: &'a mut BTree<K, V>,
+    path: Path,
+    // the written nodes, only recorded if it is not None
+    changes: Option<Vec<Change>>,
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> CursorMut<'a, K, V> {
+    fn entry_mut(&mut self, (leaf, pos): (usize, usize)) -> (&K, &mut V) {
+        let l = &mut self.t.l[leaf];
+        (&l.keys[pos], &mut l.values[pos])
+    }
+
+    /// Starts recording the nodes written by the cursor, for the trees maintaining data derived from the nodes.
+    pub(crate) fn track_changes(&mut self) {
+        self.changes = Some(Vec::new());
+    }
+
+    /// Returns the recorded changes, a node may appear multiple times.
+    pub(crate) fn take_changes(&mut self) -> Vec<Change> {
+        self.changes.as_mut().map(core::mem::take).unwrap_or_default()
+    }
+
+    /// Records a node which is split or rebalanced.
+    fn touch(&mut self, height: usize, node: NodeIndex) {
+        if let Some(changes) = self.changes.as_mut() {
+            changes.push(Change { height, node, resized: true });
+        }
+    }
+
+    /// Records the leaf of the cursor and all its ancestors, whose subtrees change with the leaf.
+    fn touch_path(&mut self) {
+        if let Some(changes) = self.changes.as_mut() {
+            let h = self.path.stack.len();
+            let path = self.path.stack.iter().enumerate().map(|(d, &(id, _))| (h - d, NodeIndex::Internal(id)));
+            let nodes = core::iter::once((0, NodeIndex::Leaf(self.path.leaf))).chain(path);
+            changes.extend(nodes.map(|(height, node)| Change { height, node, resized: false }));
+        }
+    }
+
+    /// Points to the gap before the first entry.
+    pub fn seek_first(&mut self) {
+        self.path.seek_first(self.t);
+    }
+
+    /// Points to the gap after the last entry.
+    pub fn seek_last(&mut self) {
+        self.path.seek_last(self.t);
+    }
+
+    /// Points to the gap before the first entry whose key is not less than `k`.
+    pub fn seek(&mut self, k: &K) {
+        self.path.seek(self.t, k);
+        self.t.metrics.add(Counter::Searches, self.path.stack.len() as u64 + 1);
+    }
+
+    /// The same as `seek`, but cheaper when `k` is near after the gap. `k` must not be less than the key before the gap.
+    pub(crate) fn seek_forward(&mut self, k: &K) {
+        self.path.seek_forward(self.t, k);
+    }
+
+    /// Returns the id of the leaf of the gap, which holds the entry just inserted by `insert_after`.
+    #[cfg(feature = "std")]
+    pub(crate) fn leaf(&self) -> usize {
+        self.path.leaf
+    }
+
+    /// Returns the entry after the gap without moving.
+    pub fn peek(&self) -> Option<(&K, &V)> {
+        let (leaf, pos) = self.path.peek(self.t)?;
+        let l = &self.t.l[leaf];
+        Some((&l.keys[pos], &l.values[pos]))
+    }
+
+    /// Returns the entry after the gap without moving, the value can be updated in place.
+    pub fn peek_mut(&mut self) -> Option<(&K, &mut V)> {
+        let e = self.path.peek(self.t)?;
+        self.touch_path();
+        Some(self.entry_mut(e))
+    }
+
+    /// Consumes the cursor, and returns the entry after the gap borrowed for as long as the tree.
+    pub(crate) fn into_peek_mut(self) -> Option<(&'a K, &'a mut V)> {
+        let (leaf, pos) = self.path.peek(self.t)?;
+        let l = &mut self.t.l[leaf];
+        Some((&l.keys[pos], &mut l.values[pos]))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&K, &mut V)> {
+        self.path.peek(self.t)?;
+        self.touch_path();
+        let e = self.path.next(self.t)?;
+        Some(self.entry_mut(e))
+    }
+
+    /// Returns the entry before the gap and moves over it. Returns None at the beginning.
+    pub fn prev(&mut self) -> Option<(&K, &mut V)> {
+        let e = self.path.prev(self.t)?;
+        self.touch_path();
+        Some(self.entry_mut(e))
+    }
+
+    /// Inserts the entry into the gap. The cursor stays before it, so `next` returns it.
+    ///
+    /// Panics if `k` is not greater than the key before the gap and less than the key after the gap.
+    pub fn insert_after(&mut self, k: &K, v: &V) {
+        self.insert_at_gap(k, v);
+    }
+
+    /// Inserts the entry into the gap. The cursor moves after it, so `prev` returns it.
+    ///
+    /// Panics if `k` is not greater than the key before the gap and less than the key after the gap.
+    pub fn insert_before(&mut self, k: &K, v: &V) {
+        self.insert_at_gap(k, v);
+        self.path.pos += 1;
+        self.path.normalize(self.t);
+    }
+
+    /// Removes the entry after the gap, and returns it.
+    pub fn remove_next(&mut self) -> Option<(K, V)> {
+        let (leaf, pos) = self.path.peek(self.t)?;
+        let ret = self.t.l[leaf].remove_at(pos);
+        self.t.len -= 1;
+        self.t.metrics.add(Counter::Removes, 1);
+        self.touch_path();
+        self.rebalance();
+        self.collapse_root();
+        self.path.normalize(self.t);
+        Some(ret)
+    }
+
+    /// Removes the entry before the gap, and returns it.
+    pub fn remove_prev(&mut self) -> Option<(K, V)> {
+        self.path.prev(self.t)?;
+        self.remove_next()
+    }
+
+    fn insert_at_gap(&mut self, k: &K, v: &V) {
+        self.t.metrics.add(Counter::Inserts, 1);
+        if let Some((leaf, pos)) = self.path.peek(self.t) {
+            assert!(k < &self.t.l[leaf].keys[pos], "the key is not less than the key after the cursor");
+        }
+        if let Some((leaf, pos)) = self.path.peek_prev(self.t) {
+            let prev = self.t.l[leaf].keys[pos];
+            assert!(&prev < k, "the key is not greater than the key before the cursor");
+
+            // The separator between this leaf and the left one may be stale, i.e. not less than `k`. Tighten it to the
+            // maximum of the left leaf before putting `k` at the front of this leaf.
+            if self.path.pos == 0 {
+                let d = self.path.stack.iter().rposition(|&(_, i)| i > 0).unwrap();
+                let (id, i) = self.path.stack[d];
+                self.t.i[id].keys[i - 1] = prev;
+            }
+        }
+
+        if self.t.l[self.path.leaf].full() {
+            let (left_max, right) = self.t.l[self.path.leaf].split_at(self.t.split_at);
+            let left_cnt = self.t.l[self.path.leaf].cnt;
+            let right_id = self.t.alloc_leaf(right);
+            self.t.metrics.add(Counter::Splits, 1);
+            self.t.notify(NodeEvent::Split {
+                node: NodeIndex::Leaf(self.path.leaf).into(),
+                right: NodeIndex::Leaf(right_id).into(),
+                left_max,
+            });
+            self.touch(0, NodeIndex::Leaf(self.path.leaf));
+            self.touch(0, NodeIndex::Leaf(right_id));
+            self.insert_son(0, &left_max, NodeIndex::Leaf(right_id));
+            // the new key goes to the front of the right rather than the end of the left, so no maximum changes
+            if self.path.pos >= left_cnt {
+                self.path.leaf = right_id;
+                self.path.pos -= left_cnt;
+                self.path.stack.last_mut().unwrap().1 += 1;
+            }
+        }
+        self.t.l[self.path.leaf].insert_at(self.path.pos, k, v);
+        self.t.len += 1;
+        self.touch_path();
+    }
+
+    /// Inserts `right` after the node at the level `up` on the path, which is just split. The leaf is at the level 0.
+    /// The father is split first if it is full, and the path follows the half holding the left node.
+    fn insert_son(&mut self, up: usize, left_max: &K, right: NodeIndex) {
+        if self.path.stack.len() == up {
+            // the split node is the root
+            let root = self.t.root;
+            let id = self.t.make_new_root(root);
+            self.path.stack.insert(0, (id, 0));
+        }
+
+        let (fid, i) = self.path.stack[self.path.stack.len() - 1 - up];
+        if self.t.i[fid].full() {
+            let (fmax, fright) = self.t.i[fid].split();
+            let left_cnt = self.t.i[fid].cnt;
+            let fright_id = self.t.alloc_internal(fright);
+            self.t.metrics.add(Counter::Splits, 1);
+            self.t.notify(NodeEvent::Split {
+                node: NodeIndex::Internal(fid).into(),
+                right: NodeIndex::Internal(fright_id).into(),
+                left_max: fmax,
+            });
+            self.touch(up + 1, NodeIndex::Internal(fid));
+            self.touch(up + 1, NodeIndex::Internal(fright_id));
+            self.insert_son(up + 1, &fmax, NodeIndex::Internal(fright_id));
+            if i >= left_cnt {
+                let d = self.path.stack.len() - 1 - up;
+                self.path.stack[d] = (fright_id, i - left_cnt);
+                self.path.stack[d - 1].1 += 1;
+            }
+        }
+
+        let (fid, i) = self.path.stack[self.path.stack.len() - 1 - up];
+        self.t.i[fid].insert(i + 1, left_max, right);
+    }
+
+    /// Rebalances the nodes on the path from the leaf, until a node is not less than half full.
+    fn rebalance(&mut self) {
+        let mut up = 0;
+        while up < self.path.stack.len() {
+            let d = self.path.stack.len() - 1 - up;
+            let (fid, i) = self.path.stack[d];
+            let cnt = if up == 0 {
+                self.t.l[self.path.leaf].cnt
+            } else {
+                self.t.i[self.path.stack[d + 1].0].cnt
+            };
+            let fcnt = self.t.i[fid].cnt;
+            // the only son of the root has no sibling to rebalance with
+            if cnt >= MIN_CNT || fcnt == 1 {
+                return;
+            }
+
+            // rebalance the son with its right sibling, or the left one if it is the last son
+            let a = if i + 1 < fcnt { i } else { i - 1 };
+            let merged = if up == 0 { self.rebalance_leaves(d, a) } else { self.rebalance_internals(d, a, up) };
+            if !merged {
+                return;
+            }
+            self.t.metrics.add(Counter::Merges, 1);
+            up += 1;
+        }
+    }
+
+    /// Replaces the root with its only son while it has one, so the tree gets lower as it shrinks.
+    fn collapse_root(&mut self) {
+        while let NodeIndex::Internal(id) = self.t.root {
+            if self.t.i[id].cnt > 1 {
+                return;
+            }
+            self.touch(self.path.stack.len(), NodeIndex::Internal(id));
+            self.t.root = self.t.i[id].sons[0];
+            self.t.free_node(NodeIndex::Internal(id));
+            self.t.notify(NodeEvent::RootCollapsed { root: self.t.root.into(), freed: NodeIndex::Internal(id).into() });
+            self.path.stack.remove(0);
+        }
+    }
+
+    /// Merges the leaves `a` and `a+1` of the father at `stack[d]`, or moves entries between them if they do not fit
+    /// into one leaf. Returns true if they are merged, and the father loses a son.
+    fn rebalance_leaves(&mut self, d: usize, a: usize) -> bool {
+        let (fid, i) = self.path.stack[d];
+        let (la, lb) = match (self.t.i[fid].sons[a], self.t.i[fid].sons[a + 1]) {
+            (NodeIndex::Leaf(la), NodeIndex::Leaf(lb)) => (la, lb),
+            _ => unreachable!(),
+        };
+        self.touch(0, NodeIndex::Leaf(la));
+        self.touch(0, NodeIndex::Leaf(lb));
+        let (x, y) = two_mut(&mut self.t.l, la, lb);
+        let (ca, cb) = (x.cnt, y.cnt);
+        // the offset of the cursor in the concatenation of the two leaves
+        let off = if i == a { self.path.pos } else { ca + self.path.pos };
+
+        let mut keys = [K::default(); 2 * NODE_DEG];
+        let mut values = [V::default(); 2 * NODE_DEG];
+        keys[..ca].copy_from_slice(&x.keys[..ca]);
+        keys[ca..ca + cb].copy_from_slice(&y.keys[..cb]);
+        values[..ca].copy_from_slice(&x.values[..ca]);
+        values[ca..ca + cb].copy_from_slice(&y.values[..cb]);
+
+        let n = ca + cb;
+        if n <= NODE_DEG {
+            x.keys[..n].copy_from_slice(&keys[..n]);
+            x.values[..n].copy_from_slice(&values[..n]);
+            x.cnt = n;
+            y.cnt = 0;
+            self.t.i[fid].remove(a + 1);
+            self.t.free_node(NodeIndex::Leaf(lb));
+            self.t.notify(NodeEvent::Merge { node: NodeIndex::Leaf(la).into(), from: NodeIndex::Leaf(lb).into() });
+            self.path.stack[d].1 = a;
+            self.path.leaf = la;
+            self.path.pos = off;
+            return true;
+        }
+
+        let left = n / 2;
+        x.keys[..left].copy_from_slice(&keys[..left]);
+        x.values[..left].copy_from_slice(&values[..left]);
+        x.cnt = left;
+        y.keys[..n - left].copy_from_slice(&keys[left..n]);
+        y.values[..n - left].copy_from_slice(&values[left..n]);
+        y.cnt = n - left;
+        self.t.i[fid].keys[a] = keys[left - 1];
+        self.t.notify(NodeEvent::Moved {
+            left: NodeIndex::Leaf(la).into(),
+            right: NodeIndex::Leaf(lb).into(),
+            left_max: keys[left - 1],
+        });
+        if off < left {
+            self.path.stack[d].1 = a;
+            self.path.leaf = la;
+            self.path.pos = off;
+        } else {
+            self.path.stack[d].1 = a + 1;
+            self.path.leaf = lb;
+            self.path.pos = off - left;
+        }
+        false
+    }
+
+    /// The same as `rebalance_leaves`, but for the internal nodes `a` and `a+1` of the father at `stack[d]`, which are
+    /// at the height `up`.
+    fn rebalance_internals(&mut self, d: usize, a: usize, up: usize) -> bool {
+        let (fid, i) = self.path.stack[d];
+        let (ia, ib) = match (self.t.i[fid].sons[a], self.t.i[fid].sons[a + 1]) {
+            (NodeIndex::Internal(ia), NodeIndex::Internal(ib)) => (ia, ib),
+            _ => unreachable!(),
+        };
+        let sep = self.t.i[fid].keys[a];
+        self.touch(up, NodeIndex::Internal(ia));
+        self.touch(up, NodeIndex::Internal(ib));
+        let (x, y) = two_mut(&mut self.t.i, ia, ib);
+        let (ca, cb) = (x.cnt, y.cnt);
+        let j = self.path.stack[d + 1].1;
+        let off = if i == a { j } else { ca + j };
+
+        // the separator of the father goes between the keys of the two nodes
+        let mut keys = [K::default(); 2 * NODE_DEG];
+        let mut sons = [NodeIndex::default(); 2 * NODE_DEG];
+        keys[..ca - 1].copy_from_slice(&x.keys[..ca - 1]);
+        keys[ca - 1] = sep;
+        keys[ca..ca + cb - 1].copy_from_slice(&y.keys[..cb - 1]);
+        sons[..ca].copy_from_slice(&x.sons[..ca]);
+        sons[ca..ca + cb].copy_from_slice(&y.sons[..cb]);
+
+        let n = ca + cb;
+        if n <= NODE_DEG {
+            x.keys[..n - 1].copy_from_slice(&keys[..n - 1]);
+            x.sons[..n].copy_from_slice(&sons[..n]);
+            x.cnt = n;
+            y.cnt = 0;
+            self.t.i[fid].remove(a + 1);
+            self.t.free_node(NodeIndex::Internal(ib));
+            self.t.notify(NodeEvent::Merge {
+                node: NodeIndex::Internal(ia).into(),
+                from: NodeIndex::Internal(ib).into(),
+            });
+            self.path.stack[d].1 = a;
+            self.path.stack[d + 1] = (ia, off);
+            return true;
+        }
+
+        let left = n / 2;
+        x.keys[..left - 1].copy_from_slice(&keys[..left - 1]);
+        x.sons[..left].copy_from_slice(&sons[..left]);
+        x.cnt = left;
+        y.keys[..n - left - 1].copy_from_slice(&keys[left..n - 1]);
+        y.sons[..n - left].copy_from_slice(&sons[left..n]);
+        y.cnt = n - left;
+        self.t.i[fid].keys[a] = keys[left - 1];
+        self.t.notify(NodeEvent::Moved {
+            left: NodeIndex::Internal(ia).into(),
+            right: NodeIndex::Internal(ib).into(),
+            left_max: keys[left - 1],
+        });
+        if off < left {
+            self.path.stack[d] = (fid, a);
+            self.path.stack[d + 1] = (ia, off);
+        } else {
+            self.path.stack[d] = (fid, a + 1);
+            self.path.stack[d + 1] = (ib, off - left);
+        }
+        false
+    }
+}
+
+#[test]
+fn test_cursor() {
+    let mut t = BTree::<u32, u32>::new();
+    let mut c = t.cursor();
+    assert_eq!(c.peek(), None);
+    assert_eq!(c.next(), None);
+    assert_eq!(c.prev(), None);
+
+    let n = 10000;
+    for i in 0..n {
+        let k = (i * 7919) % n;
+        t.insert(&(k * 2), &k);
+    }
+
+    // forward and backward scans
+    let mut c = t.cursor();
+    for i in 0..n {
+        assert_eq!(c.peek(), Some((&(i * 2), &i)));
+        assert_eq!(c.next(), Some((&(i * 2), &i)));
+    }
+    assert_eq!(c.next(), None);
+    for i in (0..n).rev() {
+        assert_eq!(c.prev(), Some((&(i * 2), &i)));
+    }
+    assert_eq!(c.prev(), None);
+    assert_eq!(c.peek(), Some((&0, &0)));
+
+    // seek to existing keys and to the gaps between them
+    c.seek(&500);
+    assert_eq!(c.peek(), Some((&500, &250)));
+    c.seek(&501);
+    assert_eq!(c.next(), Some((&502, &251)));
+    assert_eq!(c.prev(), Some((&502, &251)));
+    assert_eq!(c.prev(), Some((&500, &250)));
+    c.seek(&(n * 2));
+    assert_eq!(c.peek(), None);
+    assert_eq!(c.prev(), Some((&(n * 2 - 2), &(n - 1))));
+
+    c.seek_last();
+    assert_eq!(c.prev(), Some((&(n * 2 - 2), &(n - 1))));
+    c.seek_first();
+    assert_eq!(c.next(), Some((&0, &0)));
+}
+
+#[test]
+fn test_cursor_mut() {
+    let mut t = BTree::<u32, u32>::new();
+    let n = 20000;
+
+    // build the tree from an empty one only by the cursor, it splits nodes bottom-up
+    {
+        let mut c = t.cursor_mut();
+        for i in 0..n / 2 {
+            c.insert_before(&(i * 2), &i);
+        }
+        // fill the gaps backward
+        c.seek_last();
+        for i in (0..n / 2).rev() {
+            c.insert_after(&(i * 2 + 1), &(i + n));
+            assert_eq!(c.prev().map(|(k, v)| (*k, *v)), Some((i * 2, i)));
+        }
+    }
+    for i in 0..n {
+        let expected = if i % 2 == 0 { i / 2 } else { i / 2 + n };
+        assert_eq!(t.lookup(&i), Some(&expected));
+    }
+
+    // update values in place
+    {
+        let mut c = t.cursor_mut();
+        while let Some((k, v)) = c.next() {
+            *v = *k;
+        }
+        c.seek(&100);
+        *c.peek_mut().unwrap().1 = 42;
+    }
+    assert_eq!(t.lookup(&100), Some(&42));
+    assert_eq!(t.lookup(&101), Some(&101));
+
+    // remove most entries, the nodes are merged and reused by later insertions
+    {
+        let mut c = t.cursor_mut();
+        c.seek(&100);
+        assert_eq!(c.remove_next(), Some((100, 42)));
+        assert_eq!(c.remove_prev(), Some((99, 99)));
+        assert_eq!(c.peek(), Some((&101, &101)));
+    }
+    for i in 0..n {
+        if i % 7 != 0 && i != 99 && i != 100 {
+            assert_eq!(t.remove(&i), Some(i));
+        }
+    }
+    assert_eq!(t.remove(&1), None);
+    let mut c = t.cursor();
+    for i in (0..n).filter(|i| i % 7 == 0) {
+        assert_eq!(c.next(), Some((&i, &i)));
+    }
+    assert_eq!(c.next(), None);
+
+    // every leaf is either in the tree or free
+    assert!(!t.free_l.is_empty());
+    assert_eq!(t.leaf_ids().len() + t.free_l.len(), t.l.len());
+    // the separators left by the removals do not misplace the keys inserted by the cursor
+    for i in 0..n {
+        if i % 7 != 0 && i % 2 == 0 {
+            let mut c = t.cursor_mut();
+            c.seek(&i);
+            c.insert_after(&i, &i);
+        } else if i % 7 != 0 {
+            t.insert(&i, &i);
+        }
+    }
+    assert_eq!(t.leaf_ids().len() + t.free_l.len(), t.l.len());
+    for i in 0..n {
+        assert_eq!(t.lookup(&i), Some(&i));
+    }
+}
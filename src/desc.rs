@@ -0,0 +1,107 @@
+use core::cmp::Reverse;
+use core::ops::{Bound, RangeBounds};
+
+use crate::range::Range;
+use crate::BTree;
+
+/// DescBTree is a B+Tree sorted in the descending order of the keys, so the iterations go from the largest key to the
+/// smallest one, e.g. newest first for timestamps.
+///
+/// The keys are stored as `Reverse<K>`, and the API takes and returns the plain keys.
+pub struct DescBTree<K, V> {
+    t: BTree<Reverse<K>, V>,
+}
+
+/// An iterator over the entries of a `DescBTree` within a range of keys, from the largest key to the smallest.
+pub struct DescRange<'a, K, V> {
+    range: Range<'a, Reverse<K>, V>,
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Iterator for DescRange<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range.next().map(|(k, v)| (&k.0, v))
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for DescBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the bound of the reversed keys.
+fn reverse<K: Copy>(b: Bound<&K>) -> Bound<Reverse<K>> {
+    match b {
+        Bound::Included(k) => Bound::Included(Reverse(*k)),
+        Bound::Excluded(k) => Bound::Excluded(Reverse(*k)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> DescBTree<K, V> {
+    pub fn new() -> Self {
+        DescBTree { t: BTree::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(&Reverse(*k))
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        self.t.insert(&Reverse(*k), v)
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.t.remove(&Reverse(*k))
+    }
+
+    /// Returns an iterator over the entries whose keys are in the `range`, from the largest key to the smallest. The
+    /// `range` is given in the natural order, e.g. `10..20` yields the keys from 19 down to 10.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> DescRange<'_, K, V> {
+        // the end of the range is the first key in the descending order
+        let bounds = (reverse(range.end_bound()), reverse(range.start_bound()));
+        DescRange {
+            range: self.t.range(bounds),
+        }
+    }
+
+    /// Returns an iterator over all entries, from the largest key to the smallest.
+    pub fn iter(&self) -> DescRange<'_, K, V> {
+        self.range(..)
+    }
+}
+
+#[test]
+fn test_desc() {
+    let mut t = DescBTree::<u64, u32>::new();
+    for i in 0..10000 {
+        assert_eq!(t.insert(&(i * 2), &(i as u32)), None);
+    }
+    assert_eq!(t.len(), 10000);
+    assert_eq!(t.lookup(&20), Some(&10));
+    assert_eq!(t.lookup(&21), None);
+    assert!(t.iter().map(|(k, _)| *k).eq((0..10000).rev().map(|i| i * 2)));
+
+    let keys = |r: DescRange<u64, u32>| r.map(|(k, _)| *k).collect::<Vec<_>>();
+    assert_eq!(keys(t.range(10..16)), [14, 12, 10]);
+    assert_eq!(keys(t.range(10..=16)), [16, 14, 12, 10]);
+    assert_eq!(keys(t.range((Bound::Excluded(10), Bound::Included(14)))), [14, 12]);
+    assert_eq!(keys(t.range(19995..)), [19998, 19996]);
+    assert_eq!(keys(t.range(..3)), [2, 0]);
+    assert!(keys(t.range((Bound::Included(16), Bound::Excluded(10)))).is_empty());
+
+    assert_eq!(t.remove(&19998), Some(9999));
+    assert_eq!(t.iter().next(), Some((&19996, &9998)));
+}
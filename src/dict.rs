@@ -0,0 +1,151 @@
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::ops::RangeBounds;
+
+use crate::BTree;
+
+/// DictBTree is a B+Tree for the values repeated by many entries, e.g. enums or status strings. The leaves only hold
+/// the 4-byte ids of the values in a dictionary, which stores every distinct value once.
+///
+/// The dictionary counts the entries of every value, and drops a value with its last entry, so its id is reused by the
+/// next new value.
+pub struct DictBTree<K, V> {
+    t: BTree<K, u32>,
+    // the values by their ids, with the number of the entries holding them
+    dict: Vec<(V, usize)>,
+    // the ids by the values
+    ids: BTree<V, u32>,
+    free: Vec<u32>, // the ids of the dropped values
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: PartialOrd + PartialEq + Default + Copy> Default for DictBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: PartialOrd + PartialEq + Default + Copy> DictBTree<K, V> {
+    pub fn new() -> Self {
+        DictBTree {
+            t: BTree::new(),
+            dict: Vec::new(),
+            ids: BTree::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// Returns the number of the distinct values.
+    pub fn distinct_values(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns the id of `v` with its count incremented, adding `v` to the dictionary if it is new.
+    ///
+    /// Panics if there are more than `u32::MAX` distinct values.
+    fn acquire(&mut self, v: &V) -> u32 {
+        if let Some(&id) = self.ids.lookup(v) {
+            self.dict[id as usize].1 += 1;
+            return id;
+        }
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.dict[id as usize] = (*v, 1);
+                id
+            }
+            None => {
+                let id = u32::try_from(self.dict.len()).expect("too many distinct values");
+                self.dict.push((*v, 1));
+                id
+            }
+        };
+        self.ids.insert(v, &id);
+        id
+    }
+
+    /// Decrements the count of the value `id`, and returns the value. Drops it if no entry holds it any more.
+    fn release(&mut self, id: u32) -> V {
+        let (v, cnt) = &mut self.dict[id as usize];
+        *cnt -= 1;
+        let v = *v;
+        if *cnt == 0 {
+            self.ids.remove(&v);
+            self.free.push(id);
+        }
+        v
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        let id = self.acquire(v);
+        let old = self.t.insert(k, &id)?;
+        Some(self.release(old))
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let id = self.t.remove(k)?;
+        Some(self.release(id))
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k).map(|&id| &self.dict[id as usize].0)
+    }
+
+    /// Returns the number of the entries holding `v`.
+    pub fn count(&self, v: &V) -> usize {
+        self.ids.lookup(v).map_or(0, |&id| self.dict[id as usize].1)
+    }
+
+    /// Returns an iterator over the entries whose keys are in the `range`.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.t.range(range).map(move |(k, &id)| (k, &self.dict[id as usize].0))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.range(..)
+    }
+}
+
+#[test]
+fn test_dict() {
+    let statuses = [*b"pending         ", *b"active          ", *b"suspended       ", *b"deleted         "];
+    let mut t = DictBTree::<u64, [u8; 16]>::new();
+    for i in 0..10000u64 {
+        assert_eq!(t.insert(&i, &statuses[(i % 3) as usize]), None);
+    }
+    assert_eq!(t.len(), 10000);
+    assert_eq!(t.distinct_values(), 3);
+    assert_eq!(t.count(&statuses[0]), 3334);
+    assert_eq!(t.lookup(&5), Some(&statuses[2]));
+    assert!(t.iter().all(|(k, v)| *v == statuses[(k % 3) as usize]));
+
+    // the value of the last entry is dropped
+    for i in (2..10000u64).step_by(3) {
+        assert_eq!(t.insert(&i, &statuses[3]), Some(statuses[2]));
+    }
+    assert_eq!(t.count(&statuses[2]), 0);
+    assert_eq!((t.distinct_values(), t.dict.len()), (3, 4));
+    for i in (0..10000u64).step_by(3) {
+        assert_eq!(t.remove(&i), Some(statuses[0]));
+    }
+    assert_eq!(t.remove(&0), None);
+    assert_eq!(t.len(), 6666);
+    assert_eq!(t.distinct_values(), 2);
+
+    // and the ids of the dropped values are reused
+    t.insert(&10000, &[0; 16]);
+    t.insert(&10001, &[1; 16]);
+    assert_eq!((t.distinct_values(), t.dict.len()), (4, 4));
+    t.remove(&10000);
+    t.remove(&10001);
+    let expected = [1, 2, 4, 5, 7, 8].iter().map(|&k| (k, if k % 3 == 1 { statuses[1] } else { statuses[3] }));
+    assert!(t.range(..10).map(|(k, v)| (*k, *v)).eq(expected));
+}
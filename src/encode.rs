@@ -0,0 +1,136 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// KeyEncode encodes a key into bytes whose lexicographic order is the order of the keys, so multi-column keys can be
+/// stored as byte keys, e.g. `[u8; N]` or `&[u8]`.
+///
+/// The encodings of a type are prefix-free, i.e. no encoding is a prefix of another one. Thus the encoding of a tuple
+/// is the concatenation of the encodings of its fields, the encoding of its leading fields is a prefix for
+/// `prefix_iter`, and padding the encodings with zeros to a fixed size keeps the order.
+pub trait KeyEncode {
+    /// Appends the encoding to `out`.
+    fn encode_key(&self, out: &mut Vec<u8>);
+
+    fn to_key(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_key(&mut out);
+        out
+    }
+
+    /// Returns the encoding padded with zeros to `N` bytes, None if it is longer.
+    fn to_fixed_key<const N: usize>(&self) -> Option<[u8; N]> {
+        let key = self.to_key();
+        if key.len() > N {
+            return None;
+        }
+        let mut k = [0u8; N];
+        k[..key.len()].copy_from_slice(&key);
+        Some(k)
+    }
+}
+
+/// The unsigned integers are big-endian.
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {
+        $(impl KeyEncode for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+        })*
+    };
+}
+
+/// The signed integers are big-endian with the sign bit flipped, so the negative numbers go first.
+macro_rules! impl_signed {
+    ($($t:ty => $u:ty),*) => {
+        $(impl KeyEncode for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let flipped = (*self as $u) ^ (1 << (<$u>::BITS - 1));
+                out.extend_from_slice(&flipped.to_be_bytes());
+            }
+        })*
+    };
+}
+
+impl_unsigned!(u8, u16, u32, u64, u128);
+impl_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+/// The bytes are terminated by `00 00`, and every `00` in them is escaped to `00 ff`.
+impl KeyEncode for [u8] {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        for &b in self {
+            out.push(b);
+            if b == 0 {
+                out.push(0xff);
+            }
+        }
+        out.extend_from_slice(&[0, 0]);
+    }
+}
+
+impl KeyEncode for str {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_key(out);
+    }
+}
+
+impl KeyEncode for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_key(out);
+    }
+}
+
+impl<T: KeyEncode + ?Sized> KeyEncode for &T {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        (**self).encode_key(out);
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<$($name: KeyEncode),*> KeyEncode for ($($name,)*) {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let ($($name,)*) = self;
+                $($name.encode_key(out);)*
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+
+#[test]
+fn test_key_encode() {
+    use crate::BTree;
+
+    // (tenant_id, timestamp, seq)
+    let mut t = BTree::<[u8; 14], (u32, i64, u16)>::new();
+    let mut keys = Vec::new();
+    for i in 0..3000u32 {
+        let k = (i % 3, (i as i64 * 7919 % 3001) - 1500, (i % 5) as u16);
+        keys.push(k);
+        t.insert(&k.to_fixed_key().unwrap(), &k);
+    }
+    keys.sort();
+    assert!(t.range(..).map(|(_, k)| *k).eq(keys.into_iter()));
+
+    // the entries of a tenant
+    let tenant = 1u32.to_key();
+    assert_eq!(t.prefix_iter(&tenant[..]).count(), 1000);
+    assert!(t.prefix_iter(&tenant[..]).all(|(_, k)| k.0 == 1));
+
+    let mut strs = [("b", 0u8), ("a\0", 0), ("a", 1), ("", 9), ("a", 0), ("\0", 0), ("ab", 0)];
+    let mut encoded: Vec<Vec<u8>> = strs.iter().map(|k| k.to_key()).collect();
+    strs.sort();
+    encoded.sort();
+    assert_eq!(encoded, strs.iter().map(|k| k.to_key()).collect::<Vec<_>>());
+
+    assert!((-5i8).to_key() < 3i8.to_key() && i64::MIN.to_key() < (-1i64).to_key());
+    assert_eq!(String::from("x").to_key(), "x".to_key());
+    assert_eq!((1u64, "long string").to_fixed_key::<8>(), None);
+}
@@ -0,0 +1,270 @@
+use core::fmt;
+
+use crate::cursor::CursorMut;
+use crate::BTree;
+
+/// The error of `try_insert` when the key exists. The existing entry is kept.
+#[derive(Debug, PartialEq)]
+pub struct OccupiedError<K, V> {
+    /// The stored key.
+    pub key: K,
+    /// The existing value.
+    pub value: V,
+    /// The value not inserted.
+    pub new: V,
+}
+
+impl<K: fmt::Debug, V> fmt::Display for OccupiedError<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the key {:?} already exists", self.key)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: fmt::Debug, V: fmt::Debug> std::error::Error for OccupiedError<K, V> {}
+
+/// The error of `compare_and_swap` when the current value is not the expected one. The tree is not modified.
+#[derive(Debug, PartialEq)]
+pub struct CompareAndSwapError<V> {
+    /// The current value, None if the key does not exist.
+    pub current: Option<V>,
+    /// The value not written.
+    pub proposed: Option<V>,
+}
+
+impl<V> fmt::Display for CompareAndSwapError<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the current value is not the expected one")
+    }
+}
+
+#[cfg(feature = "std")]
+impl<V: fmt::Debug> std::error::Error for CompareAndSwapError<V> {}
+
+/// A handle to an existing entry, which reads, updates or removes it without searching the tree again.
+pub struct OccupiedEntry<'a, K, V> {
+    // the cursor is right before the entry
+    c: CursorMut<'a, K, V>,
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> OccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        self.c.peek().unwrap().0
+    }
+
+    pub fn get(&self) -> &V {
+        self.c.peek().unwrap().1
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.c.peek_mut().unwrap().1
+    }
+
+    /// Converts the entry into the reference to the value borrowed for as long as the tree.
+    pub fn into_mut(self) -> &'a mut V {
+        self.c.into_peek_mut().unwrap().1
+    }
+
+    /// Updates the value, and returns the old one.
+    pub fn insert(&mut self, v: V) -> V {
+        core::mem::replace(self.get_mut(), v)
+    }
+
+    /// Removes the entry, and returns it.
+    pub fn remove_entry(mut self) -> (K, V) {
+        self.c.remove_next().unwrap()
+    }
+
+    /// Removes the entry, and returns the value.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Returns the entry of the smallest key, None if the tree is empty.
+    pub fn first_entry(&mut self) -> Option<OccupiedEntry<'_, K, V>> {
+        let mut c = self.cursor_mut();
+        c.seek_first();
+        c.peek()?;
+        Some(OccupiedEntry { c })
+    }
+
+    /// Returns the entry of the largest key, None if the tree is empty.
+    pub fn last_entry(&mut self) -> Option<OccupiedEntry<'_, K, V>> {
+        let mut c = self.cursor_mut();
+        c.seek_last();
+        c.prev()?;
+        Some(OccupiedEntry { c })
+    }
+
+    /// Inserts the key value pair if the key does not exist, and returns the inserted value. Never overwrites an
+    /// existing value, which is reported in the error instead.
+    pub fn try_insert(&mut self, k: &K, v: &V) -> Result<&mut V, OccupiedError<K, V>> {
+        let mut c = self.cursor_mut();
+        c.seek(k);
+        if let Some((key, value)) = c.peek() {
+            if key == k {
+                return Err(OccupiedError {
+                    key: *key,
+                    value: *value,
+                    new: *v,
+                });
+            }
+        }
+        c.insert_after(k, v);
+        Ok(c.into_peek_mut().unwrap().1)
+    }
+
+    /// Updates the value of `k` to `f` of the current one, in a single descent. `f` gets None if `k` does not exist,
+    /// and removes `k` by returning None. Returns the previous value.
+    pub fn fetch_update<F: FnOnce(Option<&V>) -> Option<V>>(&mut self, k: &K, f: F) -> Option<V> {
+        let mut c = self.cursor_mut();
+        c.seek(k);
+        let current = match c.peek() {
+            Some((key, value)) if key == k => Some(*value),
+            _ => None,
+        };
+        match (current, f(current.as_ref())) {
+            (Some(_), Some(v)) => *c.peek_mut().unwrap().1 = v,
+            (Some(_), None) => {
+                c.remove_next();
+            }
+            (None, Some(v)) => c.insert_after(k, &v),
+            (None, None) => {}
+        }
+        current
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy + PartialEq> BTree<K, V> {
+    /// Sets the value of `k` to `new` if its current value is `expected`, in a single descent. None as `expected`
+    /// means that `k` must not exist, and None as `new` removes `k`. Returns the current value in the error if it is
+    /// not the expected one.
+    pub fn compare_and_swap(
+        &mut self,
+        k: &K,
+        expected: Option<&V>,
+        new: Option<&V>,
+    ) -> Result<(), CompareAndSwapError<V>> {
+        let mut c = self.cursor_mut();
+        c.seek(k);
+        let current = match c.peek() {
+            Some((key, value)) if key == k => Some(*value),
+            _ => None,
+        };
+        if current.as_ref() != expected {
+            return Err(CompareAndSwapError {
+                current,
+                proposed: new.copied(),
+            });
+        }
+        match (current, new) {
+            (Some(_), Some(v)) => *c.peek_mut().unwrap().1 = *v,
+            (Some(_), None) => {
+                c.remove_next();
+            }
+            (None, Some(v)) => c.insert_after(k, v),
+            (None, None) => {}
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_try_insert() {
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..1000 {
+        *t.try_insert(&i, &i).unwrap() += 1;
+    }
+    assert_eq!(t.len(), 1000);
+    assert_eq!(t.lookup(&999), Some(&1000));
+
+    let e = t.try_insert(&500, &0).unwrap_err();
+    assert_eq!(e, OccupiedError { key: 500, value: 501, new: 0 });
+    assert_eq!(e.to_string(), "the key 500 already exists");
+    assert_eq!(t.lookup(&500), Some(&501));
+    assert_eq!(t.len(), 1000);
+}
+
+#[test]
+fn test_fetch_update() {
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..1000 {
+        let prev = t.fetch_update(&(i % 100), |v| Some(v.map_or(1, |v| v + 1)));
+        assert_eq!(prev, if i < 100 { None } else { Some(i / 100) });
+    }
+    assert_eq!(t.len(), 100);
+    assert!(t.iter().all(|(_, v)| *v == 10));
+
+    // remove the odd keys, keep the others untouched
+    for i in 0..200 {
+        let prev = t.fetch_update(&i, |v| if i % 2 == 1 { None } else { v.copied() });
+        assert_eq!(prev, if i < 100 { Some(10) } else { None });
+    }
+    assert_eq!(t.len(), 50);
+    assert!(t.iter().all(|(k, v)| k % 2 == 0 && *v == 10));
+}
+
+#[test]
+fn test_compare_and_swap() {
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..1000 {
+        t.compare_and_swap(&i, None, Some(&i)).unwrap();
+    }
+    assert_eq!(t.len(), 1000);
+
+    let e = t.compare_and_swap(&10, None, Some(&0)).unwrap_err();
+    assert_eq!(e, CompareAndSwapError { current: Some(10), proposed: Some(0) });
+    assert_eq!(e.to_string(), "the current value is not the expected one");
+    assert!(t.compare_and_swap(&10, Some(&11), Some(&0)).is_err());
+    assert!(t.compare_and_swap(&1000, Some(&0), None).unwrap_err().current.is_none());
+    assert_eq!(t.lookup(&10), Some(&10));
+
+    // increment every value optimistically, retrying with the value reported in the error
+    for i in 0..1000 {
+        let mut cur = 0;
+        while let Err(e) = t.compare_and_swap(&i, Some(&cur), Some(&(cur + 1))) {
+            cur = e.current.unwrap();
+        }
+    }
+    assert!(t.iter().all(|(k, v)| *v == k + 1));
+
+    for i in 0..1000 {
+        t.compare_and_swap(&i, Some(&(i + 1)), None).unwrap();
+    }
+    assert!(t.is_empty());
+    t.compare_and_swap(&0, None, None).unwrap();
+    assert!(t.is_empty());
+}
+
+#[test]
+fn test_first_last_entry() {
+    let mut t = BTree::<u32, u32>::new();
+    assert!(t.first_entry().is_none());
+    assert!(t.last_entry().is_none());
+    for i in 0..1000 {
+        t.insert(&i, &i);
+    }
+
+    let mut e = t.first_entry().unwrap();
+    assert_eq!((*e.key(), *e.get()), (0, 0));
+    *e.get_mut() += 10;
+    assert_eq!(e.insert(42), 10);
+    *e.into_mut() += 1;
+    assert_eq!(t.lookup(&0), Some(&43));
+    assert_eq!(t.last_entry().unwrap().remove_entry(), (999, 999));
+
+    // consume the tree like a priority queue from both ends
+    let mut popped = Vec::new();
+    while let Some(e) = t.first_entry() {
+        popped.push(*e.key());
+        e.remove();
+        if let Some(e) = t.last_entry() {
+            popped.push(e.remove());
+        }
+    }
+    assert_eq!(popped.len(), 999);
+    assert_eq!(popped[..4], [0, 998, 1, 997]);
+    assert!(t.is_empty());
+}
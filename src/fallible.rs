@@ -0,0 +1,161 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::RangeBounds;
+
+use crate::range::Range;
+use crate::BTree;
+
+/// The error of the fallible operations of the trees backed by files or mappings, see `BTree::try_reserve` and
+/// `PagedBTree::try_lookup`.
+#[derive(Debug)]
+pub enum BTreeError {
+    /// Reading or writing the storage failed.
+    Io(io::Error),
+    /// The page read from the file does not match its checksum, or can not be decoded.
+    Corrupted { page: Option<u64> },
+    /// The storage is full or over its quota, or the memory can not be allocated.
+    QuotaExceeded(io::Error),
+}
+
+impl fmt::Display for BTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BTreeError::Io(e) => write!(f, "the storage failed: {}", e),
+            BTreeError::Corrupted { page: Some(page) } => {
+                write!(f, "the page {} is corrupted", page)
+            }
+            BTreeError::Corrupted { page: None } => write!(f, "the storage is corrupted"),
+            BTreeError::QuotaExceeded(e) => write!(f, "the storage is out of space: {}", e),
+        }
+    }
+}
+
+impl Error for BTreeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BTreeError::Io(e) | BTreeError::QuotaExceeded(e) => Some(e),
+            BTreeError::Corrupted { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for BTreeError {
+    /// Classifies the error: the invalid data is corruption, and running out of the space is over the quota.
+    fn from(e: io::Error) -> Self {
+        #[cfg(unix)]
+        {
+            if let Some(c) = e
+                .get_ref()
+                .and_then(|c| c.downcast_ref::<crate::paged::CorruptedPage>())
+            {
+                return BTreeError::Corrupted { page: Some(c.page) };
+            }
+            if let Some(libc::ENOSPC) | Some(libc::EDQUOT) | Some(libc::EFBIG) = e.raw_os_error() {
+                return BTreeError::QuotaExceeded(e);
+            }
+        }
+        match e.kind() {
+            io::ErrorKind::InvalidData => BTreeError::Corrupted { page: None },
+            io::ErrorKind::OutOfMemory | io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => {
+                BTreeError::QuotaExceeded(e)
+            }
+            _ => BTreeError::Io(e),
+        }
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// The same as `lookup`. The lookups never fail, even if the nodes are mapped from files, it is here for the
+    /// symmetry with `PagedBTree::try_lookup`.
+    pub fn try_lookup(&self, k: &K) -> Result<Option<&V>, BTreeError> {
+        Ok(self.lookup(k))
+    }
+
+    /// Reserves the nodes for `additional` more insertions, so that they do not panic, and fails instead if the node
+    /// buffers can not grow, e.g. the file the nodes are mapped from hits the quota. `try_insert` is the entry API, see
+    /// `PagedBTree::try_insert` for the fallible insertion of the paged trees.
+    ///
+    /// Every insertion may split a node on every level and add a new root, so this reserves one leaf and `height`
+    /// internal nodes per insertion.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), BTreeError> {
+        self.i.try_reserve(additional.saturating_mul(self.height()))?;
+        self.l.try_reserve(additional)?;
+        Ok(())
+    }
+
+    /// The same as `range`, see `try_lookup`.
+    pub fn try_range<R: RangeBounds<K>>(&self, range: R) -> Result<Range<'_, K, V>, BTreeError> {
+        Ok(self.range(range))
+    }
+}
+
+#[cfg(unix)]
+mod paged {
+    use super::*;
+    use crate::mmap::Pod;
+    use crate::paged::PagedBTree;
+
+    impl<K: Pod + PartialOrd + Default, V: Pod + Default> PagedBTree<K, V> {
+        /// The same as `lookup`, with the errors classified.
+        pub fn try_lookup(&mut self, k: &K) -> Result<Option<V>, BTreeError> {
+            Ok(self.lookup(k)?)
+        }
+
+        /// The same as `insert`, with the errors classified.
+        pub fn try_insert(&mut self, k: &K, v: &V) -> Result<Option<V>, BTreeError> {
+            Ok(self.insert(k, v)?)
+        }
+
+        /// The same as `range`, with the errors classified.
+        pub fn try_range<R: RangeBounds<K>>(
+            &mut self,
+            range: R,
+        ) -> impl Iterator<Item = Result<(K, V), BTreeError>> + '_ {
+            self.range(range).map(|e| e.map_err(BTreeError::from))
+        }
+    }
+}
+
+#[test]
+fn test_btree_error() {
+    let e = BTreeError::from(io::Error::new(io::ErrorKind::InvalidData, "bad"));
+    assert!(matches!(e, BTreeError::Corrupted { page: None }));
+    assert_eq!(e.to_string(), "the storage is corrupted");
+    let e = BTreeError::from(io::Error::from(io::ErrorKind::StorageFull));
+    assert!(matches!(e, BTreeError::QuotaExceeded(_)));
+    #[cfg(unix)]
+    assert!(matches!(
+        BTreeError::from(io::Error::from_raw_os_error(libc::EDQUOT)),
+        BTreeError::QuotaExceeded(_)
+    ));
+    let e = BTreeError::from(io::Error::from(io::ErrorKind::NotFound));
+    assert!(matches!(e, BTreeError::Io(_)));
+    assert!(e.source().is_some());
+
+    let mut t = BTree::<u64, u64>::new();
+    for i in 0..10000 {
+        t.try_reserve(1).unwrap();
+        assert_eq!(t.insert(&i, &i), None);
+    }
+    t.try_reserve(1).unwrap();
+    assert_eq!(t.insert(&5, &0), Some(5));
+    assert_eq!(t.try_lookup(&5).unwrap(), Some(&0));
+    assert_eq!(t.try_range(10..20).unwrap().count(), 10);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_try_mapped() {
+    let dir = std::env::temp_dir().join(format!("btree-rs-test-try-mmap-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    {
+        let mut t = unsafe { BTree::<u64, u64>::open(&dir) }.unwrap();
+        for i in 0..100000 {
+            t.try_reserve(1).unwrap();
+            assert_eq!(t.insert(&i, &i), None);
+        }
+        assert_eq!(t.try_lookup(&99999).unwrap(), Some(&99999));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,167 @@
+//! The C ABI of a tree of u64 keys and values, declared by `include/btree_rs.h`.
+//!
+//! Build the library with `cargo rustc --release --features ffi --crate-type staticlib` (or `cdylib`), and link it with
+//! the C code including the header. A tree is an opaque pointer created by `btree_new` and freed by `btree_free`. The
+//! functions never panic on valid arguments, so no Rust panic unwinds into the C code.
+
+use alloc::boxed::Box;
+use core::ffi::c_void;
+
+use crate::BTree;
+
+/// The tree behind the opaque `btree_t *` of C.
+pub type FfiBTree = BTree<u64, u64>;
+
+/// The callback of `btree_range`, which returns false to stop the scan.
+pub type RangeCallback = extern "C" fn(key: u64, value: u64, ctx: *mut c_void) -> bool;
+
+/// Writes `v` into `out` if `out` is not null, and returns true if `v` exists.
+unsafe fn write_out(v: Option<u64>, out: *mut u64) -> bool {
+    match v {
+        Some(v) => {
+            if !out.is_null() {
+                *out = v;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Creates an empty tree, which must be freed by `btree_free`.
+#[no_mangle]
+pub extern "C" fn btree_new() -> *mut FfiBTree {
+    Box::into_raw(Box::new(BTree::new()))
+}
+
+/// Frees the tree. Null is ignored.
+///
+/// # Safety
+///
+/// `t` must be created by `btree_new`, and must not be used after.
+#[no_mangle]
+pub unsafe extern "C" fn btree_free(t: *mut FfiBTree) {
+    if !t.is_null() {
+        drop(Box::from_raw(t));
+    }
+}
+
+/// Returns the number of the entries.
+///
+/// # Safety
+///
+/// `t` must be a live tree created by `btree_new`.
+#[no_mangle]
+pub unsafe extern "C" fn btree_len(t: *const FfiBTree) -> usize {
+    (*t).len()
+}
+
+/// Inserts or updates the key value pair. Returns true if the key existed, and writes its old value into `old` unless
+/// it is null.
+///
+/// # Safety
+///
+/// `t` must be a live tree created by `btree_new`, and `old` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btree_insert(t: *mut FfiBTree, key: u64, value: u64, old: *mut u64) -> bool {
+    write_out((*t).insert(&key, &value), old)
+}
+
+/// Looks up the key. Returns true if it exists, and writes its value into `value` unless it is null.
+///
+/// # Safety
+///
+/// `t` must be a live tree created by `btree_new`, and `value` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btree_lookup(t: *const FfiBTree, key: u64, value: *mut u64) -> bool {
+    write_out((*t).lookup(&key).copied(), value)
+}
+
+/// Removes the key. Returns true if it existed, and writes its value into `value` unless it is null.
+///
+/// # Safety
+///
+/// `t` must be a live tree created by `btree_new`, and `value` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btree_remove(t: *mut FfiBTree, key: u64, value: *mut u64) -> bool {
+    write_out((*t).remove(&key), value)
+}
+
+/// Calls `cb` with the entries whose keys are in [start, end] in the order of the keys, passing `ctx` through, until
+/// `cb` returns false. Returns the number of the calls.
+///
+/// # Safety
+///
+/// `t` must be a live tree created by `btree_new`, and `cb` must not modify or free the tree.
+#[no_mangle]
+pub unsafe extern "C" fn btree_range(
+    t: *const FfiBTree,
+    start: u64,
+    end: u64,
+    cb: RangeCallback,
+    ctx: *mut c_void,
+) -> usize {
+    let mut calls = 0;
+    for (k, v) in (*t).range(start..=end) {
+        calls += 1;
+        if !cb(*k, *v, ctx) {
+            break;
+        }
+    }
+    calls
+}
+
+#[test]
+fn test_ffi() {
+    extern "C" fn collect(key: u64, value: u64, ctx: *mut c_void) -> bool {
+        let out = unsafe { &mut *(ctx as *mut alloc::vec::Vec<(u64, u64)>) };
+        out.push((key, value));
+        out.len() < 5
+    }
+
+    unsafe {
+        let t = btree_new();
+        for i in 0..1000 {
+            assert!(!btree_insert(t, i * 2, i, core::ptr::null_mut()));
+        }
+        let mut v = 0;
+        assert!(btree_insert(t, 10, 42, &mut v));
+        assert_eq!(v, 5);
+        assert_eq!(btree_len(t), 1000);
+        assert!(btree_lookup(t, 10, &mut v) && v == 42);
+        assert!(!btree_lookup(t, 11, &mut v));
+        assert!(btree_lookup(t, 12, core::ptr::null_mut()));
+        assert!(btree_remove(t, 12, &mut v) && v == 6);
+        assert!(!btree_remove(t, 12, &mut v));
+
+        let mut out: alloc::vec::Vec<(u64, u64)> = alloc::vec::Vec::new();
+        let ctx = &mut out as *mut _ as *mut c_void;
+        assert_eq!(btree_range(t, 9, 14, collect, ctx), 2);
+        assert_eq!(out, [(10, 42), (14, 7)]);
+        out.clear();
+        // the callback stops the scan
+        assert_eq!(btree_range(t, 0, u64::MAX, collect, ctx), 5);
+        assert_eq!(out.len(), 5);
+        btree_free(t);
+        btree_free(core::ptr::null_mut());
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_ffi_header() {
+    // every exported function is declared by the header
+    let header = include_str!("../include/btree_rs.h");
+    let source = include_str!("ffi.rs");
+    let mut exported = 0;
+    for line in source.lines() {
+        if let Some(rest) = line.split("extern \"C\" fn ").nth(1) {
+            if line.starts_with("pub") {
+                let name = &rest[..rest.find('(').unwrap()];
+                assert!(header.contains(&format!("{}(", name)), "{} is not in the header", name);
+                exported += 1;
+            }
+        }
+    }
+    assert_eq!(exported, 7);
+}
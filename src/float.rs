@@ -0,0 +1,93 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use crate::encode::KeyEncode;
+
+macro_rules! float_key {
+    ($name:ident, $f:ty, $u:ty) => {
+        /// A float key ordered by `total_cmp`, so every value including NaN has a place in the tree.
+        ///
+        /// The order is: the negative NaNs, -inf, the negative numbers, -0.0, 0.0, the positive numbers, inf, and the
+        /// positive NaNs. -0.0 and 0.0 are different keys, so are the NaNs of different bits.
+        #[derive(Debug, Default, Clone, Copy)]
+        #[repr(transparent)]
+        pub struct $name(pub $f);
+
+        impl PartialEq for $name {
+            fn eq(&self, other: &Self) -> bool {
+                self.cmp(other) == Ordering::Equal
+            }
+        }
+
+        impl Eq for $name {}
+
+        impl PartialOrd for $name {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for $name {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.0.total_cmp(&other.0)
+            }
+        }
+
+        impl From<$f> for $name {
+            fn from(f: $f) -> Self {
+                $name(f)
+            }
+        }
+
+        impl From<$name> for $f {
+            fn from(k: $name) -> Self {
+                k.0
+            }
+        }
+
+        /// The bits are flipped so the unsigned big-endian order is the `total_cmp` order.
+        impl KeyEncode for $name {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let bits = self.0.to_bits();
+                let sign = 1 << (<$u>::BITS - 1);
+                let ordered = if bits & sign != 0 { !bits } else { bits | sign };
+                out.extend_from_slice(&ordered.to_be_bytes());
+            }
+        }
+
+        #[cfg(all(feature = "std", unix))]
+        unsafe impl crate::mmap::Pod for $name {}
+    };
+}
+
+float_key!(F64Key, f64, u64);
+float_key!(F32Key, f32, u32);
+
+#[test]
+fn test_float_key() {
+    use crate::BTree;
+
+    let values = [f64::NAN, 1.5, f64::NEG_INFINITY, -0.0, 0.0, -f64::NAN, f64::INFINITY, -2.0, f64::MIN_POSITIVE];
+    let mut t = BTree::<F64Key, u32>::new();
+    for (i, v) in values.iter().enumerate() {
+        assert_eq!(t.insert(&F64Key(*v), &(i as u32)), None);
+    }
+    assert_eq!(t.len(), values.len());
+    assert_eq!(t.lookup(&F64Key(f64::NAN)), Some(&0));
+    assert_eq!(t.lookup(&F64Key(-0.0)), Some(&3));
+    assert_eq!(t.lookup(&F64Key(0.0)), Some(&4));
+
+    let order: Vec<u32> = t.range(..).map(|(_, i)| *i).collect();
+    assert_eq!(order, [5, 2, 7, 3, 4, 8, 1, 6, 0]);
+    let order: Vec<u32> = t.range(F64Key(-1.0)..F64Key(f64::INFINITY)).map(|(_, i)| *i).collect();
+    assert_eq!(order, [3, 4, 8, 1]);
+
+    // the encodings are in the same order
+    let mut keys: Vec<F64Key> = values.iter().map(|v| F64Key(*v)).collect();
+    keys.sort();
+    let mut encoded: Vec<Vec<u8>> = keys.iter().map(|k| k.to_key()).collect();
+    encoded.sort();
+    assert_eq!(encoded, keys.iter().map(|k| k.to_key()).collect::<Vec<_>>());
+
+    assert!(F32Key(-1.0) < F32Key(f32::NAN) && f32::from(F32Key::from(2.5)) == 2.5);
+}
@@ -0,0 +1,168 @@
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::mmap::Pod;
+use crate::paged::PagedBTree;
+
+/// When the background flusher makes a checkpoint.
+#[derive(Debug, Clone, Copy)]
+pub struct FlushPolicy {
+    /// The longest time between two checkpoints.
+    pub interval: Duration,
+    /// Makes a checkpoint earlier once the dirty pages reach this fraction of the buffer pool.
+    pub dirty_ratio: f64,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        FlushPolicy {
+            interval: Duration::from_secs(1),
+            dirty_ratio: 0.5,
+        }
+    }
+}
+
+/// The shortest time between two checks of the dirty ratio, so a zero `interval` does not spin.
+const MIN_POLL: Duration = Duration::from_millis(1);
+
+/// The state shared with the thread.
+struct Shared {
+    stop: Mutex<bool>,
+    wake: Condvar,
+    // the error of the last failed checkpoint in the background, reported by `flush`
+    error: Mutex<Option<io::Error>>,
+}
+
+/// BackgroundFlusher makes the checkpoints of a shared `PagedBTree` in a background thread, so the writers rarely pay
+/// for writing the dirty pages and syncing the log themselves.
+///
+/// The tree is locked for every checkpoint, so the writers wait for it. A checkpoint happens after every `interval`,
+/// or earlier if the dirty pages reach `dirty_ratio`; the dirty ratio is checked at least 10 times per interval and
+/// at most every 100ms, but not more often than every 1ms. The thread stops when the flusher is dropped.
+pub struct BackgroundFlusher<K, V> {
+    t: Arc<Mutex<PagedBTree<K, V>>>,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<K: Pod + PartialOrd + Default + Send, V: Pod + Default + Send> BackgroundFlusher<K, V> {
+    pub fn new(t: Arc<Mutex<PagedBTree<K, V>>>, policy: FlushPolicy) -> Self {
+        let shared = Arc::new(Shared {
+            stop: Mutex::new(false),
+            wake: Condvar::new(),
+            error: Mutex::new(None),
+        });
+        let thread = {
+            let (t, shared) = (t.clone(), shared.clone());
+            thread::spawn(move || run(&t, &shared, policy))
+        };
+        BackgroundFlusher {
+            t,
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Makes a checkpoint now, so the operations before it survive a power failure. Returns the error of a failed
+    /// checkpoint in the background since the last call, if any.
+    pub fn flush(&self) -> io::Result<()> {
+        if let Some(e) = self.shared.error.lock().unwrap().take() {
+            return Err(e);
+        }
+        self.t.lock().unwrap().flush()
+    }
+}
+
+fn run<K: Pod + PartialOrd + Default, V: Pod + Default>(
+    t: &Mutex<PagedBTree<K, V>>,
+    shared: &Shared,
+    policy: FlushPolicy,
+) {
+    let poll = (policy.interval / 10).clamp(MIN_POLL, Duration::from_millis(100));
+    let mut last = Instant::now();
+    loop {
+        // the guard is released before locking the tree, so the drop does not wait for a checkpoint to stop the thread
+        {
+            let stop = shared.stop.lock().unwrap();
+            let (stop, _) = shared.wake.wait_timeout_while(stop, poll, |stop| !*stop).unwrap();
+            if *stop {
+                break;
+            }
+        }
+        let mut t = t.lock().unwrap();
+        if last.elapsed() >= policy.interval || t.dirty_ratio() >= policy.dirty_ratio {
+            if let Err(e) = t.flush() {
+                *shared.error.lock().unwrap() = Some(e);
+            }
+            last = Instant::now();
+        }
+    }
+}
+
+impl<K, V> Drop for BackgroundFlusher<K, V> {
+    fn drop(&mut self) {
+        *self.shared.stop.lock().unwrap() = true;
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+fn test_background_flusher() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-flusher-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut wal_path = path.as_os_str().to_owned();
+    wal_path.push(".wal");
+
+    let t = Arc::new(Mutex::new(unsafe { PagedBTree::<u64, u64>::open(&path, 64) }.unwrap()));
+    let policy = FlushPolicy {
+        interval: Duration::from_millis(20),
+        dirty_ratio: 0.5,
+    };
+    let flusher = BackgroundFlusher::new(t.clone(), policy);
+    for i in 0..10000 {
+        t.lock().unwrap().insert(&i, &i).unwrap();
+    }
+
+    // the writes are checkpointed in the background without calling flush
+    let start = Instant::now();
+    while t.lock().unwrap().dirty_ratio() > 0.0 {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+
+    t.lock().unwrap().insert(&10000, &10000).unwrap();
+    flusher.flush().unwrap();
+    assert_eq!(t.lock().unwrap().dirty_ratio(), 0.0);
+    drop(flusher);
+
+    // a zero interval checkpoints as often as the dirty ratio is checked, and the flusher still stops
+    let flusher = BackgroundFlusher::new(
+        t.clone(),
+        FlushPolicy {
+            interval: Duration::ZERO,
+            dirty_ratio: 0.5,
+        },
+    );
+    t.lock().unwrap().insert(&10000, &10000).unwrap();
+    let start = Instant::now();
+    while t.lock().unwrap().dirty_ratio() > 0.0 {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(5));
+    }
+    drop(flusher);
+    drop(t);
+
+    let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 64) }.unwrap();
+    for i in 0..=10000 {
+        assert_eq!(t.lookup(&i).unwrap(), Some(i));
+    }
+    drop(t);
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&wal_path);
+}
@@ -0,0 +1,268 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::{Bound, RangeBounds};
+
+use crate::{lower_bound, BTree, NODE_DEG};
+
+// marks the missing son of a leaf
+const NIL: u32 = u32::MAX;
+
+/// The layout of the index of a `FrozenBTree` over its sorted keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrozenLayout {
+    /// Full nodes of `NODE_DEG` keys without pointers. Every level holds the maximum keys of the nodes of the level
+    /// below it, the same as the internal nodes of a `BTree`.
+    Packed,
+    /// A binary search tree over the keys in the van Emde Boas order. The tree is split at the half of its height into
+    /// a top tree and the bottom trees under it, each of them is stored contiguously and laid out the same way
+    /// recursively. A lookup thus touches O(log_B n) cache lines or pages for any block size B, so it suits every level
+    /// of the memory hierarchy without tuning the node size, at the cost of a copy of the keys.
+    VanEmdeBoas,
+}
+
+struct Node<K> {
+    key: K,
+    // the index of the key in the sorted order, the padding nodes are at `len` or after
+    rank: u32,
+    sons: [u32; 2],
+}
+
+enum Index<K> {
+    // the levels from the one above the keys to the root
+    Packed(Vec<Vec<K>>),
+    VanEmdeBoas(Vec<Node<K>>),
+}
+
+/// FrozenBTree is a read-only tree built from the sorted entries, for the trees built once and queried forever.
+///
+/// The keys and the values are stored in two sorted arrays without any slack, and the index over the keys is laid out
+/// as `FrozenLayout`.
+pub struct FrozenBTree<K, V> {
+    keys: Vec<K>,
+    values: Vec<V>,
+    index: Index<K>,
+}
+
+/// Appends the BFS indexes of the perfect tree of `height` under `root` in the van Emde Boas order.
+fn veb_order(root: usize, height: u32, out: &mut Vec<usize>) {
+    if height == 1 {
+        out.push(root);
+        return;
+    }
+    let top = height / 2;
+    veb_order(root, top, out);
+    for j in 0..1 << top {
+        veb_order((root << top) + j, height - top, out);
+    }
+}
+
+/// Builds the van Emde Boas layout of the perfect binary search tree over the `keys`, the nodes after them are the
+/// padding.
+fn build_veb<K: Default + Copy>(keys: &[K]) -> Vec<Node<K>> {
+    let height = usize::BITS - keys.len().leading_zeros();
+    let mut order = Vec::with_capacity((1 << height) - 1);
+    if height > 0 {
+        veb_order(1, height, &mut order);
+    }
+    let mut pos = vec![0u32; 1 << height];
+    for (i, &b) in order.iter().enumerate() {
+        pos[b] = i as u32;
+    }
+
+    order
+        .iter()
+        .map(|&b| {
+            let depth = usize::BITS - 1 - b.leading_zeros();
+            let offset = b - (1 << depth);
+            // the in-order rank of the node in the perfect tree
+            let rank = ((2 * offset + 1) << (height - 1 - depth)) - 1;
+            Node {
+                key: keys.get(rank).copied().unwrap_or_default(),
+                rank: rank as u32,
+                sons: if depth + 1 < height {
+                    [pos[2 * b], pos[2 * b + 1]]
+                } else {
+                    [NIL, NIL]
+                },
+            }
+        })
+        .collect()
+}
+
+/// Builds the levels of full nodes above the `keys`.
+fn build_packed<K: Copy>(keys: &[K]) -> Vec<Vec<K>> {
+    let mut levels: Vec<Vec<K>> = Vec::new();
+    loop {
+        let below = levels.last().map_or(keys, |l| &l[..]);
+        if below.len() <= NODE_DEG {
+            return levels;
+        }
+        let mut level: Vec<K> = below.chunks(NODE_DEG).map(|c| c[c.len() - 1]).collect();
+        level.shrink_to_fit();
+        levels.push(level);
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> FrozenBTree<K, V> {
+    /// Builds the tree from the entries sorted by the keys, with the packed layout.
+    ///
+    /// Panics if the keys are not strictly increasing.
+    pub fn from_sorted_vec(entries: Vec<(K, V)>) -> Self {
+        Self::with_layout(entries, FrozenLayout::Packed)
+    }
+
+    /// Builds the tree from the entries sorted by the keys, with the index in `layout`.
+    ///
+    /// Panics if the keys are not strictly increasing, or there are 2^31 entries or more for the van Emde Boas layout.
+    pub fn with_layout(entries: Vec<(K, V)>, layout: FrozenLayout) -> Self {
+        assert!(
+            entries.windows(2).all(|w| w[0].0 < w[1].0),
+            "the keys are not strictly increasing"
+        );
+        let (keys, values): (Vec<K>, Vec<V>) = entries.into_iter().unzip();
+        let index = match layout {
+            FrozenLayout::Packed => Index::Packed(build_packed(&keys)),
+            FrozenLayout::VanEmdeBoas => {
+                assert!(keys.len() < 1 << 31, "too many entries");
+                Index::VanEmdeBoas(build_veb(&keys))
+            }
+        };
+        FrozenBTree { keys, values, index }
+    }
+
+    pub fn layout(&self) -> FrozenLayout {
+        match self.index {
+            Index::Packed(_) => FrozenLayout::Packed,
+            Index::VanEmdeBoas(_) => FrozenLayout::VanEmdeBoas,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the number of the keys less than `k`.
+    fn rank(&self, k: &K) -> usize {
+        match &self.index {
+            Index::Packed(levels) => {
+                // the first node whose maximum key is not less than `k` holds the answer
+                let mut start = 0;
+                for level in levels.iter().rev().chain(core::iter::once(&self.keys)) {
+                    let node = &level[start..level.len().min(start + NODE_DEG)];
+                    let i = lower_bound(node, k);
+                    if i == node.len() {
+                        return self.keys.len();
+                    }
+                    start = (start + i) * NODE_DEG;
+                }
+                start / NODE_DEG
+            }
+            Index::VanEmdeBoas(nodes) => {
+                let mut cur = if nodes.is_empty() { NIL } else { 0 };
+                let mut ret = self.keys.len();
+                while cur != NIL {
+                    let node = &nodes[cur as usize];
+                    // the padding nodes are greater than any key
+                    if node.rank as usize >= self.keys.len() || k <= &node.key {
+                        ret = ret.min(node.rank as usize);
+                        cur = node.sons[0];
+                    } else {
+                        cur = node.sons[1];
+                    }
+                }
+                ret
+            }
+        }
+    }
+
+    /// Returns the stored key equal to `k` with its value.
+    pub fn get_key_value(&self, k: &K) -> Option<(&K, &V)> {
+        let i = self.rank(k);
+        (i < self.keys.len() && &self.keys[i] == k).then(|| (&self.keys[i], &self.values[i]))
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.get_key_value(k).map(|(_, v)| v)
+    }
+
+    /// Returns an iterator over the entries in the order of the keys.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> + ExactSizeIterator + '_ {
+        self.keys.iter().zip(self.values.iter())
+    }
+
+    /// Returns an iterator over the entries whose keys are in the `range`.
+    pub fn range<R: RangeBounds<K>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = (&K, &V)> + ExactSizeIterator + '_ {
+        let after = |k: &K| {
+            let i = self.rank(k);
+            i + (i < self.keys.len() && &self.keys[i] == k) as usize
+        };
+        let start = match range.start_bound() {
+            Bound::Included(s) => self.rank(s),
+            Bound::Excluded(s) => after(s),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(e) => after(e),
+            Bound::Excluded(e) => self.rank(e),
+            Bound::Unbounded => self.keys.len(),
+        };
+        let end = end.max(start);
+        self.keys[start..end].iter().zip(self.values[start..end].iter())
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Copies the entries into a read-only `FrozenBTree` with the packed layout.
+    pub fn freeze(&self) -> FrozenBTree<K, V> {
+        self.freeze_with(FrozenLayout::Packed)
+    }
+
+    /// Copies the entries into a read-only `FrozenBTree` with the index in `layout`.
+    pub fn freeze_with(&self, layout: FrozenLayout) -> FrozenBTree<K, V> {
+        let mut entries = Vec::with_capacity(self.len());
+        entries.extend(self.iter().map(|(k, v)| (*k, *v)));
+        FrozenBTree::with_layout(entries, layout)
+    }
+}
+
+#[test]
+fn test_frozen_btree() {
+    let mut order = Vec::new();
+    veb_order(1, 4, &mut order);
+    assert_eq!(order, [1, 2, 3, 4, 8, 9, 5, 10, 11, 6, 12, 13, 7, 14, 15]);
+
+    for layout in [FrozenLayout::Packed, FrozenLayout::VanEmdeBoas] {
+        for n in [0u32, 1, 2, 3, 4, 7, 8, 32, 33, 1024, 1025, 5000] {
+            let f = FrozenBTree::with_layout((0..n).map(|i| (i * 2, i)).collect(), layout);
+            assert_eq!((f.len(), f.is_empty(), f.layout()), (n as usize, n == 0, layout));
+            for k in 0..2 * n + 2 {
+                assert_eq!(f.lookup(&k).copied(), if k % 2 == 0 && k < 2 * n { Some(k / 2) } else { None });
+                assert_eq!(f.rank(&k), (k.min(2 * n) as usize).div_ceil(2));
+            }
+        }
+    }
+
+    let mut t = BTree::<u64, u64>::new();
+    for i in 0..100000u64 {
+        t.insert(&(i * 2654435761 % 1000003), &i);
+    }
+    for f in [t.freeze(), t.freeze_with(FrozenLayout::VanEmdeBoas)] {
+        assert_eq!(f.len(), t.len());
+        for k in 0..1000003 {
+            assert_eq!(f.lookup(&k), t.lookup(&k));
+        }
+        assert!(f.iter().eq(t.iter()));
+        assert!(f.range(1000..=5000).eq(t.range(1000..=5000)));
+        let r = (Bound::Excluded(5000), Bound::Excluded(9000));
+        assert!(f.range(r).eq(t.range(r)));
+        assert_eq!(f.range((Bound::Included(7), Bound::Excluded(3))).len(), 0);
+        assert_eq!(f.iter().next_back(), t.iter().last());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::{lower_bound, BTree};
+
+/// HashIndexedBTree is a B+Tree with a hash table mapping the keys to their leaves, so a point lookup searches one
+/// leaf instead of descending from the root. Range scans and the other ordered operations go through the tree.
+///
+/// The table holds hints rather than the truth: a hint is checked by searching the key in its leaf, and the lookup
+/// falls back to the descent if the key is not there. Insertions keep the hints exact, including the keys moved to
+/// the new leaf by a split. Removals may move the keys between the leaves when they merge or rebalance them, so the
+/// hints of the moved keys go stale until `rebuild_index`.
+pub struct HashIndexedBTree<K, V> {
+    t: BTree<K, V>,
+    hints: HashMap<K, usize>, // key -> leaf id
+}
+
+impl<K: PartialOrd + Default + Copy + Hash + Eq, V: Default + Copy> Default for HashIndexedBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + Default + Copy + Hash + Eq, V: Default + Copy> HashIndexedBTree<K, V> {
+    pub fn new() -> Self {
+        HashIndexedBTree {
+            t: BTree::new(),
+            hints: HashMap::new(),
+        }
+    }
+
+    /// Returns the tree for the ordered operations.
+    pub fn tree(&self) -> &BTree<K, V> {
+        &self.t
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        if let Some(&leaf) = self.hints.get(k) {
+            let l = &self.t.l[leaf];
+            let pos = lower_bound(&l.keys[0..l.cnt], k);
+            if pos < l.cnt && &l.keys[pos] == k {
+                return Some(&l.values[pos]);
+            }
+        }
+        self.t.lookup(k)
+    }
+
+    /// Inserts the key value pair, and returns the old value if the key already exists.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        // a split moves the upper half of the leaf to a new one, which is either reused or pushed
+        let (leaves, reused) = (self.t.l.len(), self.t.free_l.last().copied());
+        let free = self.t.free_l.len();
+        // the cursor tells the leaf the key goes to, so the hint is taken from the insertion path
+        let mut c = self.t.cursor_mut();
+        c.seek(k);
+        let (old, leaf) = match c.peek_mut() {
+            Some((key, value)) if key == k => (Some(core::mem::replace(value, *v)), None),
+            _ => {
+                c.insert_after(k, v);
+                (None, Some(c.leaf()))
+            }
+        };
+        let split = if self.t.l.len() > leaves {
+            Some(leaves)
+        } else if self.t.free_l.len() < free {
+            reused
+        } else {
+            None
+        };
+        if let Some(id) = split {
+            let l = &self.t.l[id];
+            for key in l.keys[0..l.cnt].iter() {
+                self.hints.insert(*key, id);
+            }
+        }
+        if let Some(leaf) = leaf {
+            self.hints.insert(*k, leaf);
+        }
+        old
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.hints.remove(k);
+        self.t.remove(k)
+    }
+
+    /// Rebuilds the hints of all keys from the leaves.
+    pub fn rebuild_index(&mut self) {
+        self.hints.clear();
+        for id in self.t.leaf_ids() {
+            let l = &self.t.l[id];
+            self.hints.extend(l.keys[0..l.cnt].iter().map(|k| (*k, id)));
+        }
+    }
+
+    /// Returns the number of hints pointing to the leaves not holding their keys.
+    #[cfg(test)]
+    fn stale_hints(&self) -> usize {
+        self.hints.iter().filter(|&(k, &leaf)| self.t.locate(k).map(|(l, _)| l) != Some(leaf)).count()
+    }
+}
+
+#[test]
+fn test_hash_indexed_btree() {
+    let mut t = HashIndexedBTree::<u32, u32>::new();
+    for i in 0..10000 {
+        assert_eq!(t.insert(&(i * 7919 % 10000), &i), None);
+    }
+    assert_eq!(t.insert(&0, &42), Some(0));
+    // the splits keep the hints exact
+    assert_eq!(t.stale_hints(), 0);
+    assert_eq!(t.hints.len(), 10000);
+    for i in 0..10000 {
+        assert_eq!(t.lookup(&(i * 7919 % 10000)), Some(&if i == 0 { 42 } else { i }));
+    }
+    assert_eq!(t.lookup(&10000), None);
+
+    // the merges leave stale hints, which still find the keys
+    for k in (0..10000).filter(|k| k % 3 != 0) {
+        assert!(t.remove(&k).is_some());
+    }
+    assert_eq!(t.len(), 3334);
+    // the hints of the removed keys are dropped
+    assert_eq!(t.hints.len(), 3334);
+    assert!(t.stale_hints() > 0);
+    for k in 0..10000 {
+        assert_eq!(t.lookup(&k).is_some(), k % 3 == 0);
+    }
+    t.rebuild_index();
+    assert_eq!(t.stale_hints(), 0);
+    assert_eq!(t.hints.len(), 3334);
+
+    // the freed leaves are reused by the splits
+    for k in (0..10000).filter(|k| k % 3 != 0) {
+        t.insert(&k, &k);
+    }
+    assert_eq!(t.stale_hints(), 0);
+    assert_eq!(t.tree().range(..).count(), 10000);
+}
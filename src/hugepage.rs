@@ -0,0 +1,52 @@
+use std::io;
+
+use crate::buf::NodeBuf;
+use crate::mmap::MmapVec;
+use crate::{BTree, LeafNode};
+
+/// The kind of the pages holding the nodes, from the weakest to the strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HugePages {
+    /// The regular pages, e.g. 4KB.
+    Regular,
+    /// The memory is advised to be backed by the transparent huge pages, which the kernel may or may not do.
+    Transparent,
+    /// The huge pages reserved by the system, e.g. `vm.nr_hugepages`, which are guaranteed.
+    Explicit,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// News a tree whose nodes are allocated from the 2MB huge pages, which reduces the TLB misses of a large tree. The
+    /// reserved huge pages are used if there are, otherwise the transparent huge pages are asked for. Check
+    /// `huge_pages` for what is actually obtained.
+    pub fn with_huge_pages() -> io::Result<Self> {
+        let mut t = BTree::new();
+        t.i = NodeBuf::Mapped(MmapVec::anonymous(1024, None, true)?);
+        t.l = NodeBuf::Mapped(MmapVec::anonymous(1024, None, true)?);
+        // push the root node
+        t.l.push(LeafNode::new());
+        Ok(t)
+    }
+
+    /// Returns the kind of the pages holding the nodes. It is the weaker one of the internal nodes and the leaves, and
+    /// may change as the node buffers grow.
+    pub fn huge_pages(&self) -> HugePages {
+        match (&self.i, &self.l) {
+            (NodeBuf::Mapped(i), NodeBuf::Mapped(l)) => i.huge_pages().min(l.huge_pages()),
+            _ => HugePages::Regular,
+        }
+    }
+}
+
+#[test]
+fn test_huge_pages() {
+    let mut t = BTree::<u64, u64>::with_huge_pages().unwrap();
+    assert!(t.huge_pages() > HugePages::Regular);
+    for i in 0..200000 {
+        t.insert(&i, &i);
+    }
+    assert!(t.huge_pages() > HugePages::Regular);
+    assert!(t.range(..).map(|(k, v)| (*k, *v)).eq((0..200000).map(|i| (i, i))));
+
+    assert_eq!(BTree::<u64, u64>::new().huge_pages(), HugePages::Regular);
+}
@@ -0,0 +1,168 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+use std::slice;
+
+// the length of an interned key before its bytes
+const LEN_SIZE: usize = 4;
+
+// the empty key, for `Sym::default`
+static EMPTY: [u8; LEN_SIZE] = [0; LEN_SIZE];
+
+/// An interned key, which is a thin pointer to the bytes stored once in an `Interner`. Symbols are ordered and
+/// compared by their bytes, so they are keys of a tree just like `&[u8]`, at half of the size.
+#[derive(Clone, Copy)]
+pub struct Sym<'a> {
+    // points to the length followed by the bytes
+    p: NonNull<u8>,
+    _marker: PhantomData<&'a [u8]>,
+}
+
+impl<'a> Sym<'a> {
+    pub fn as_bytes(&self) -> &'a [u8] {
+        // the key is alive as long as the interner is borrowed, see `Interner::intern`
+        unsafe {
+            let len = u32::from_le_bytes(*(self.p.as_ptr() as *const [u8; LEN_SIZE])) as usize;
+            slice::from_raw_parts(self.p.as_ptr().add(LEN_SIZE), len)
+        }
+    }
+}
+
+impl Default for Sym<'_> {
+    /// Returns the empty key.
+    fn default() -> Self {
+        Sym {
+            p: NonNull::from(&EMPTY).cast(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl PartialEq for Sym<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        // the keys of an interner are equal only if they are the same
+        self.p == other.p || self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for Sym<'_> {}
+
+impl PartialOrd for Sym<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Sym<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self.p == other.p {
+            return Ordering::Equal;
+        }
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl fmt::Debug for Sym<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(self.as_bytes()))
+    }
+}
+
+/// Interner stores every distinct key once, and hands out the symbols pointing to them. A tree keyed by the symbols
+/// keeps one copy of every repeated key across all its entries, and across all the trees sharing the interner.
+///
+/// The keys sharing only a prefix are still stored separately. The keys are never freed until the interner is dropped,
+/// which the symbols borrowing it prevent.
+#[derive(Default)]
+pub struct Interner {
+    // the keys by their bytes, which point into `keys`
+    index: RefCell<HashMap<&'static [u8], NonNull<u8>>>,
+    // every key is allocated as its length followed by the bytes, the allocations never move
+    keys: RefCell<Vec<Box<[u8]>>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the symbol of `key`, storing it if it is new.
+    ///
+    /// Panics if the key is 4GB or longer.
+    pub fn intern(&self, key: &[u8]) -> Sym<'_> {
+        if let Some(sym) = self.get(key) {
+            return sym;
+        }
+        assert!(key.len() <= u32::MAX as usize, "the key is too long");
+        let mut data = Vec::with_capacity(LEN_SIZE + key.len());
+        data.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        data.extend_from_slice(key);
+        let data = data.into_boxed_slice();
+        let p = NonNull::from(&data[0]);
+        // the bytes stay at the same address until the interner is dropped
+        let bytes: &'static [u8] = unsafe { slice::from_raw_parts(p.as_ptr().add(LEN_SIZE), key.len()) };
+        self.keys.borrow_mut().push(data);
+        self.index.borrow_mut().insert(bytes, p);
+        Sym {
+            p,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the symbol of `key` if it is interned. A key which is not interned is in no tree keyed by the symbols
+    /// of this interner.
+    pub fn get(&self, key: &[u8]) -> Option<Sym<'_>> {
+        self.index.borrow().get(key).map(|&p| Sym {
+            p,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Returns the number of the distinct keys.
+    pub fn len(&self) -> usize {
+        self.keys.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the bytes taken by the keys.
+    pub fn bytes(&self) -> usize {
+        self.keys.borrow().iter().map(|k| k.len()).sum()
+    }
+}
+
+#[test]
+fn test_interner() {
+    use crate::BTree;
+
+    let pool = Interner::new();
+    let mut t = BTree::<Sym, u64>::new();
+    for i in 0..100000u64 {
+        let key = format!("tenant-{}/user-{}", i % 10, i % 1000);
+        let sym = pool.intern(key.as_bytes());
+        let cnt = t.lookup(&sym).copied().unwrap_or(0);
+        t.insert(&sym, &(cnt + 1));
+    }
+    assert_eq!(std::mem::size_of::<Sym>(), 8);
+    assert_eq!((pool.len(), t.len()), (1000, 1000));
+    assert!(pool.bytes() < 1000 * 24);
+    assert_eq!(t.lookup(&pool.get(b"tenant-3/user-123").unwrap()), Some(&100));
+    assert!(pool.get(b"tenant-3/user-124").is_none());
+
+    // the symbols are ordered by their bytes
+    let keys: Vec<&[u8]> = t.iter().map(|(k, _)| k.as_bytes()).collect();
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(keys[0], b"tenant-0/user-0");
+
+    // the default symbol is the empty key
+    let empty = pool.intern(b"");
+    assert_eq!(empty, Sym::default());
+    assert_eq!(Sym::default().as_bytes(), b"");
+    assert!(Sym::default() < pool.intern(b"a"));
+    assert_eq!(format!("{:?}", pool.intern(b"a")), "\"a\"");
+}
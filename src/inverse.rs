@@ -0,0 +1,122 @@
+use crate::BTree;
+
+/// InverseBTree is a B+Tree which also indexes the keys by a projection of their values, e.g. a field of the values,
+/// so it finds the keys whose values project to a given one. The index is kept consistent by every insertion, update
+/// and removal.
+///
+/// Every entry is indexed under its projection paired with a sequence number, so the keys with the same projection are
+/// adjacent in the index and kept in the order they got the projection.
+pub struct InverseBTree<K, V, P> {
+    t: BTree<K, (V, u64)>,
+    // the keys by the projections of their values and the sequence numbers
+    inverse: BTree<(P, u64), K>,
+    project: fn(&V) -> P,
+    seq: u64,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: PartialOrd + Default + Copy> InverseBTree<K, V, V> {
+    /// News a tree indexing the keys by their values.
+    pub fn by_value() -> Self {
+        Self::new(|v| *v)
+    }
+}
+
+impl<K, V, P> InverseBTree<K, V, P>
+where
+    K: PartialOrd + PartialEq + Default + Copy,
+    V: Default + Copy,
+    P: PartialOrd + PartialEq + Default + Copy,
+{
+    /// News a tree indexing the keys by `project` of their values.
+    pub fn new(project: fn(&V) -> P) -> Self {
+        InverseBTree {
+            t: BTree::new(),
+            inverse: BTree::new(),
+            project,
+            seq: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        let p = (self.project)(v);
+        if let Some([(old, seq)]) = self.t.get_many_mut(&[k]) {
+            let old_p = (self.project)(old);
+            let ret = core::mem::replace(old, *v);
+            // the key stays in place if the projection does not change
+            if old_p != p {
+                self.inverse.remove(&(old_p, *seq));
+                *seq = self.seq;
+                self.inverse.insert(&(p, self.seq), k);
+                self.seq += 1;
+            }
+            return Some(ret);
+        }
+        self.t.insert(k, &(*v, self.seq));
+        self.inverse.insert(&(p, self.seq), k);
+        self.seq += 1;
+        None
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let (v, seq) = self.t.remove(k)?;
+        self.inverse.remove(&((self.project)(&v), seq));
+        Some(v)
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k).map(|(v, _)| v)
+    }
+
+    /// Returns an iterator over the entries in the order of the keys.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.t.iter().map(|(k, (v, _))| (k, v))
+    }
+
+    /// Returns the keys whose values project to `p`, in the order they got the projection.
+    pub fn keys_with_value(&self, p: &P) -> impl Iterator<Item = &K> + '_ {
+        self.inverse.range((*p, 0)..=(*p, u64::MAX)).map(|(_, k)| k)
+    }
+}
+
+#[test]
+fn test_inverse() {
+    use alloc::vec::Vec;
+
+    // index the keys by the tens of the values
+    let mut t = InverseBTree::<u32, u32, u32>::new(|v| v / 10);
+    for i in 0..1000 {
+        assert_eq!(t.insert(&i, &(i % 100)), None);
+    }
+    let with = |t: &InverseBTree<u32, u32, u32>, p| t.keys_with_value(&p).copied().collect::<Vec<_>>();
+    let expected: Vec<u32> = (0..1000).filter(|i| i % 100 / 10 == 3).collect();
+    assert_eq!(with(&t, 3), expected);
+    assert_eq!(with(&t, 10), []);
+
+    // the same projection keeps the place, and a different one moves the key
+    assert_eq!(t.insert(&30, &35), Some(30));
+    assert_eq!(with(&t, 3), expected);
+    assert_eq!(t.insert(&30, &42), Some(35));
+    assert_eq!(with(&t, 3), expected[1..]);
+    assert_eq!(with(&t, 4).last(), Some(&30));
+    assert_eq!(t.remove(&31), Some(31));
+    assert_eq!(t.remove(&31), None);
+    assert_eq!(with(&t, 3), expected[2..]);
+    assert_eq!(t.len(), 999);
+
+    let mut t = InverseBTree::<u32, u32, u32>::by_value();
+    for i in 0..100 {
+        t.insert(&i, &(i % 7));
+    }
+    assert!(t.keys_with_value(&3).copied().eq((3..100).step_by(7)));
+    assert_eq!(t.lookup(&10), Some(&3));
+}
@@ -0,0 +1,158 @@
+use crate::range::Range;
+use crate::BTree;
+
+/// An entry of `MergeJoin`: the key is only in the left tree, only in the right tree, or in both.
+#[derive(Debug, PartialEq)]
+pub enum Joined<'a, K, V, W> {
+    Left(&'a K, &'a V),
+    Right(&'a K, &'a W),
+    Both(&'a K, &'a V, &'a W),
+}
+
+/// An iterator over the keys of two trees in order, which walks both trees once side by side.
+pub struct MergeJoin<'a, K, V, W> {
+    left: Range<'a, K, V>,
+    right: Range<'a, K, W>,
+    // the next entries of both sides
+    l: Option<(&'a K, &'a V)>,
+    r: Option<(&'a K, &'a W)>,
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy, W: Default + Copy> Iterator
+    for MergeJoin<'a, K, V, W>
+{
+    type Item = Joined<'a, K, V, W>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ret = match (self.l, self.r) {
+            (Some((lk, v)), Some((rk, w))) if lk == rk => Joined::Both(lk, v, w),
+            (Some((lk, v)), Some((rk, _))) if lk < rk => Joined::Left(lk, v),
+            (Some(_), Some((rk, w))) => Joined::Right(rk, w),
+            (Some((k, v)), None) => Joined::Left(k, v),
+            (None, Some((k, w))) => Joined::Right(k, w),
+            (None, None) => return None,
+        };
+        if !matches!(ret, Joined::Right(..)) {
+            self.l = self.left.next();
+        }
+        if !matches!(ret, Joined::Left(..)) {
+            self.r = self.right.next();
+        }
+        Some(ret)
+    }
+}
+
+/// An entry of `Diff`, from the older tree to the newer one.
+#[derive(Debug, PartialEq)]
+pub enum Delta<'a, K, V> {
+    Added(&'a K, &'a V),
+    Removed(&'a K, &'a V),
+    /// The key with the old value and the new value.
+    Changed(&'a K, &'a V, &'a V),
+}
+
+/// An iterator over the differences between two trees in the order of the keys.
+pub struct Diff<'a, K, V> {
+    join: MergeJoin<'a, K, V, V>,
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: PartialEq + Default + Copy> Iterator for Diff<'a, K, V> {
+    type Item = Delta<'a, K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            return Some(match self.join.next()? {
+                Joined::Left(k, v) => Delta::Removed(k, v),
+                Joined::Right(k, v) => Delta::Added(k, v),
+                Joined::Both(k, old, new) if old != new => Delta::Changed(k, old, new),
+                Joined::Both(..) => continue,
+            });
+        }
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Returns the entries added, removed and changed from this tree to `newer`.
+    ///
+    /// The trees never share nodes, so both are walked entirely by a merge join. `PersistentBTree::diff` skips the
+    /// subtrees shared by the versions of a persistent tree instead.
+    pub fn diff<'a>(&'a self, newer: &'a BTree<K, V>) -> Diff<'a, K, V>
+    where
+        V: PartialEq,
+    {
+        Diff {
+            join: self.merge_join(newer),
+        }
+    }
+
+    /// Returns an iterator over the keys of this tree and `other` in order, pairing the values of the keys in both.
+    pub fn merge_join<'a, W: Default + Copy>(&'a self, other: &'a BTree<K, W>) -> MergeJoin<'a, K, V, W> {
+        let (mut left, mut right) = (self.range(..), other.range(..));
+        MergeJoin {
+            l: left.next(),
+            r: right.next(),
+            left,
+            right,
+        }
+    }
+}
+
+#[test]
+fn test_merge_join() {
+    let mut a = BTree::<u32, u32>::new();
+    let mut b = BTree::<u32, i64>::new();
+    for i in 0..10000 {
+        a.insert(&(i * 2), &i);
+        b.insert(&(i * 3), &-(i as i64));
+    }
+
+    let mut cnt = [0; 3];
+    let mut last = None;
+    for j in a.merge_join(&b) {
+        let k = match j {
+            Joined::Left(k, v) => {
+                assert!(k % 2 == 0 && (k % 3 != 0 || *k >= 30000) && *v == k / 2);
+                cnt[0] += 1;
+                k
+            }
+            Joined::Right(k, w) => {
+                assert!(k % 3 == 0 && (k % 2 != 0 || *k >= 20000) && *w == -((k / 3) as i64));
+                cnt[1] += 1;
+                k
+            }
+            Joined::Both(k, v, w) => {
+                assert!(k % 6 == 0 && *v == k / 2 && *w == -((k / 3) as i64));
+                cnt[2] += 1;
+                k
+            }
+        };
+        assert!(last < Some(*k));
+        last = Some(*k);
+    }
+    // the multiples of 6 below 20000 are in both
+    assert_eq!(cnt, [10000 - 3334, 10000 - 3334, 3334]);
+
+    let empty = BTree::<u32, u32>::new();
+    assert_eq!(empty.merge_join(&empty).next(), None);
+    assert_eq!(a.merge_join(&empty).count(), 10000);
+    assert_eq!(empty.merge_join(&a).next(), Some(Joined::Right(&0, &0)));
+}
+
+#[test]
+fn test_diff() {
+    let mut a = BTree::<u32, u32>::new();
+    for i in 0..1000 {
+        a.insert(&i, &i);
+    }
+    let mut b = BTree::<u32, u32>::new();
+    for i in 0..1000 {
+        b.insert(&i, &i);
+    }
+    assert_eq!(a.diff(&b).next(), None);
+
+    b.remove(&10);
+    b.insert(&500, &0);
+    b.insert(&1000, &1000);
+    let d: Vec<_> = a.diff(&b).collect();
+    assert_eq!(d, [Delta::Removed(&10, &10), Delta::Changed(&500, &500, &0), Delta::Added(&1000, &1000)]);
+}
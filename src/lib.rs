@@ -1,9 +1,94 @@
-#![feature(test)]
+#![cfg_attr(test, feature(test))]
+// without the `std` feature, the crate only depends on `core` and `alloc`, and the file backed trees are only built
+// on unix, so the in-memory trees work on targets without files too, e.g. wasm32-unknown-unknown
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+extern crate alloc;
 
-use std::ptr::copy;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr::copy;
+
+use buf::NodeBuf;
+use metrics::{Counter, Counters};
+use observe::{NodeEvent, NodeObserver};
+
+pub mod append;
+pub mod arrival;
+pub mod augment;
+pub mod batch;
+pub mod betree;
+pub mod bounded;
+mod buf;
+pub mod cdc;
+pub mod checksum;
+pub mod compress;
+mod convert;
+#[cfg(all(feature = "std", unix))]
+mod crc32c;
+pub mod cursor;
+pub mod desc;
+pub mod dict;
+pub mod encode;
+pub mod entry;
+#[cfg(feature = "std")]
+pub mod fallible;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod float;
+#[cfg(all(feature = "std", unix))]
+pub mod flusher;
+pub mod frozen;
+#[cfg(feature = "std")]
+pub mod hashindex;
+#[cfg(all(feature = "std", unix))]
+pub mod hugepage;
+#[cfg(feature = "std")]
+pub mod intern;
+pub mod inverse;
+pub mod join;
+pub mod lww;
+#[cfg(feature = "std")]
+mod lz4;
+pub mod memtable;
+#[cfg(feature = "meta")]
+pub mod meta;
+pub mod metrics;
+#[cfg(all(feature = "std", unix))]
+pub mod mmap;
+pub mod multimap;
+pub mod mvcc;
+pub mod observe;
+pub mod options;
+#[cfg(all(feature = "std", unix))]
+mod numa;
+pub mod overflow;
+#[cfg(all(feature = "std", unix))]
+pub mod paged;
+#[cfg(all(feature = "std", unix))]
+mod pager;
+pub mod persistent;
+pub mod prefix;
+mod quantile;
+pub mod range;
+pub mod search;
+#[cfg(all(feature = "std", unix))]
+pub mod serialize;
+#[cfg(all(feature = "std", unix))]
+pub mod spill;
+#[cfg(all(feature = "std", unix))]
+pub mod static_tree;
+pub mod tombstone;
+pub mod ttl;
+pub mod txn;
+pub mod visit;
+pub mod warmup;
+pub mod watch;
+#[cfg(all(feature = "std", unix))]
+mod wal;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
 enum NodeIndex {
     Leaf(usize),
     Internal(usize),
@@ -18,6 +103,8 @@ impl Default for NodeIndex {
 // TODO: pad node structs to 4kB by atomatically choosing node degrees
 const NODE_DEG: usize = 32;
 
+#[repr(C)]
+#[derive(Clone)]
 struct InternalNode<K> {
     keys: [K; NODE_DEG - 1],
     sons: [NodeIndex; NODE_DEG],
@@ -48,6 +135,9 @@ impl<K: PartialOrd + Copy + Default> InternalNode<K> {
         // sons[0]            sons[1]            ...                sons[1024]
         //
         // Thus, `k` in the sub-tree `sons[lower_bound(keys, k)]`
+        //
+        // Removing keys may leave `keys[i]` greater than the actual maximum in `sons[i]`, but it is still less than the
+        // keys in `sons[i+1]`, which is enough for finding the sub-tree.
 
         let i = lower_bound(&self.keys[0..self.cnt-1], k);
         (i, self.sons[i])
@@ -71,6 +161,17 @@ impl<K: PartialOrd + Copy + Default> InternalNode<K> {
         self.cnt += 1;
     }
 
+    /// Removes the son at the position `pos`, and the key separating it from the left son.
+    /// It is the reverse of `insert`, the left son takes over the range of the removed one.
+    fn remove(&mut self, pos: usize) {
+        assert!(0 < pos && pos < self.cnt);
+        unsafe {
+            copy(self.keys.as_ptr().add(pos), self.keys.as_mut_ptr().add(pos - 1), self.cnt - pos - 1);
+            copy(self.sons.as_ptr().add(pos + 1), self.sons.as_mut_ptr().add(pos), self.cnt - pos - 1);
+        }
+        self.cnt -= 1;
+    }
+
     /// Splits the node to two nodes. The current node turns into the left node.
     /// Returns the max key in the left, and the right node,
     fn split(&mut self) -> (K, Self) {
@@ -113,9 +214,16 @@ fn test_internal_node() {
     // now insert the leaf(5)
     i.insert(3, &15, NodeIndex::Leaf(5));
     assert_eq!(i.keys[0..i.cnt-1], [1, 10, 15, 20, 30]);
-    assert_eq!(i.sons[0..i.cnt], [NodeIndex::Leaf(0), NodeIndex::Leaf(1), NodeIndex::Leaf(2), NodeIndex::Leaf(5), NodeIndex::Leaf(3), NodeIndex::Leaf(4)])
+    assert_eq!(i.sons[0..i.cnt], [NodeIndex::Leaf(0), NodeIndex::Leaf(1), NodeIndex::Leaf(2), NodeIndex::Leaf(5), NodeIndex::Leaf(3), NodeIndex::Leaf(4)]);
+
+    // and merge it back
+    i.remove(3);
+    assert_eq!(i.keys[0..i.cnt-1], [1, 10, 20, 30]);
+    assert_eq!(i.sons[0..i.cnt], [NodeIndex::Leaf(0), NodeIndex::Leaf(1), NodeIndex::Leaf(2), NodeIndex::Leaf(3), NodeIndex::Leaf(4)]);
 }
 
+#[repr(C)]
+#[derive(Clone)]
 struct LeafNode<K, V> {
     keys: [K; NODE_DEG],
     values: [V; NODE_DEG],
@@ -156,16 +264,53 @@ impl<K: PartialOrd + Copy + Default, V: Copy + Default> LeafNode<K, V> {
             return ret;
         }
 
+        self.insert_at(i, k, v);
+        None
+    }
+
+    /// Inserts the key value pair, the caller makes sure that `k` does not exist.
+    fn insert_unique(&mut self, k: &K, v: &V) {
+        let i = lower_bound(&self.keys[0..self.cnt], k);
+        self.insert_at(i, k, v);
+    }
+
+    /// Inserts the key value pair at the position `i`. The caller keeps the keys sorted.
+    fn insert_at(&mut self, i: usize, k: &K, v: &V) {
+        assert!(!self.full() && i <= self.cnt);
+
         // shift the data to the right, to empty one slot
         unsafe {
-            copy(&self.keys[i], &mut self.keys[i + 1], self.cnt - i);
-            copy(&self.values[i], &mut self.values[i + 1], self.cnt - i);
+            copy(self.keys.as_ptr().add(i), self.keys.as_mut_ptr().add(i + 1), self.cnt - i);
+            copy(self.values.as_ptr().add(i), self.values.as_mut_ptr().add(i + 1), self.cnt - i);
         };
 
         self.keys[i] = *k;
         self.values[i] = *v;
         self.cnt += 1;
-        None
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    #[cfg_attr(not(all(feature = "std", unix)), allow(dead_code))] // only used by the file backed trees for now
+    fn remove(&mut self, k: &K) -> Option<V> {
+        let i = lower_bound(&self.keys[0..self.cnt], k);
+        if i == self.cnt || &self.keys[i] != k {
+            return None;
+        }
+        Some(self.remove_at(i).1)
+    }
+
+    /// Removes the key value pair at the position `i`, and returns it.
+    fn remove_at(&mut self, i: usize) -> (K, V) {
+        assert!(i < self.cnt);
+
+        // shift the data to the left, to fill the slot
+        let ret = (self.keys[i], self.values[i]);
+        unsafe {
+            copy(self.keys.as_ptr().add(i + 1), self.keys.as_mut_ptr().add(i), self.cnt - i - 1);
+            copy(self.values.as_ptr().add(i + 1), self.values.as_mut_ptr().add(i), self.cnt - i - 1);
+        };
+        self.cnt -= 1;
+        ret
     }
 
     fn lookup(&self, k: &K) -> Option<&V> {
@@ -179,10 +324,10 @@ impl<K: PartialOrd + Copy + Default, V: Copy + Default> LeafNode<K, V> {
         }
     }
 
-    /// Splits the node to two nodes. The current node turns into the left node.
+    /// Splits the node to two nodes, keeping `left_cnt` entries in the left. The current node turns into the left node.
     /// Returns the max key in the left, and the right node,
-    fn split(&mut self) -> (K, Self) {
-        let left_cnt = self.cnt / 2;
+    fn split_at(&mut self, left_cnt: usize) -> (K, Self) {
+        assert!(0 < left_cnt && left_cnt < self.cnt);
         let mut right = Self::new();
         // updates data
         unsafe {
@@ -222,7 +367,7 @@ fn test_leaf_node() {
     assert_eq!(l.lookup(&"def"), Some(&7));
 
     // test split
-    let (left_max, right) = l.split();
+    let (left_max, right) = l.split_at(l.cnt / 2);
     assert_eq!(left_max, "def");
     assert_eq!(l.cnt, 2);
     assert_eq!(l.lookup(&"abc"), Some(&6));
@@ -236,12 +381,20 @@ fn test_leaf_node() {
     assert_eq!(right.lookup(&"hello"), Some(&4));
     assert_eq!(right.lookup(&"hi"), Some(&3));
     assert_eq!(right.lookup(&"world"), Some(&5));
+
+    // test remove
+    let mut right = right;
+    assert_eq!(right.remove(&"hi"), Some(3));
+    assert_eq!(right.remove(&"hi"), None);
+    assert_eq!(right.remove(&"world"), Some(5));
+    assert_eq!(right.cnt, 1);
+    assert_eq!(right.lookup(&"hello"), Some(&4));
 }
 
 /// Returns the index pointing to the first element in the range [0,a.len()) which does not compare less than val.
 /// If such element does not exist, then return a.len()
 fn lower_bound<T: PartialOrd>(a: &[T], val: &T) -> usize {
-    if a.len() == 0 {
+    if a.is_empty() {
         return 0;
     }
     if &a[a.len()-1] < val {
@@ -278,19 +431,104 @@ fn test_lower_bound() {
     assert_eq!(lower_bound(&[], &42), 0);
 }
 
+/// Returns the numbers of the internal nodes and the leaves holding `n` entries in the worst case, where every node is
+/// half full after a split.
+fn nodes_for(n: usize) -> (usize, usize) {
+    let half = NODE_DEG / 2;
+    let leaves = n.div_ceil(half).max(1);
+    let (mut internals, mut level) = (0, leaves);
+    while level > 1 {
+        level = level.div_ceil(half);
+        internals += level;
+    }
+    (internals, leaves)
+}
+
 pub struct BTree<K, V> {
-    i: Vec<InternalNode<K>>, // internal nodes buf
-    l: Vec<LeafNode<K, V>>,  // leaf nodes buf
+    i: NodeBuf<InternalNode<K>>, // internal nodes buf
+    l: NodeBuf<LeafNode<K, V>>,  // leaf nodes buf
     root: NodeIndex,
+    free_i: Vec<usize>, // the ids of the freed internal nodes
+    free_l: Vec<usize>, // the ids of the freed leaf nodes
+    len: usize,         // the number of entries
+    metrics: Counters,
+    interpolate: Option<fn(&K) -> f64>, // maps the keys to numbers for the interpolation search
+    split_at: usize,                    // the number of entries the left leaf keeps when a full leaf splits
+    observer: Option<alloc::boxed::Box<dyn NodeObserver<K>>>, // notified of the splits, merges and root changes
+    #[cfg(all(feature = "std", unix))]
+    meta_file: Option<std::fs::File>, // the meta file if the nodes are mapped from files
+}
+
+#[cfg(all(feature = "std", unix))]
+impl<K, V> Drop for BTree<K, V> {
+    fn drop(&mut self) {
+        // there is no way to report the error here, call `flush` explicitly to check it
+        let _ = self.flush();
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for BTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The clone lives in the memory even if the tree is mapped from files, and it has no observer.
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Clone for BTree<K, V> {
+    fn clone(&self) -> Self {
+        let mut t = Self::with_node_capacity(self.i.len(), self.l.len());
+        t.clone_from(self);
+        t
+    }
+
+    /// Copies the nodes of `source` into the node buffers of this tree, which are only grown if they are too small,
+    /// so that rebuilding a tree into a spare one and swapping them does not reallocate every time. The observer and
+    /// the metrics of this tree are kept.
+    fn clone_from(&mut self, source: &Self) {
+        self.i.assign(&source.i);
+        self.l.assign(&source.l);
+        self.root = source.root;
+        self.free_i.clone_from(&source.free_i);
+        self.free_l.clone_from(&source.free_l);
+        self.len = source.len;
+        self.interpolate = source.interpolate;
+        self.split_at = source.split_at;
+    }
 }
 
 /// Btree is a balanced tree optimized for reducing the number of memory accesses.
 impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
     pub fn new() -> Self {
+        Self::with_node_capacity(1024, 1024)
+    }
+
+    /// News a tree with the space for `n` entries, so that inserting them does not grow the node buffers.
+    pub fn with_capacity(n: usize) -> Self {
+        let (internals, leaves) = nodes_for(n);
+        Self::with_node_capacity(internals, leaves)
+    }
+
+    /// Reserves the space for at least `additional` more entries.
+    pub fn reserve(&mut self, additional: usize) {
+        let (internals, leaves) = nodes_for(additional);
+        self.i.reserve(internals);
+        self.l.reserve(leaves);
+    }
+
+    fn with_node_capacity(internals: usize, leaves: usize) -> Self {
         let mut t = BTree {
-            i: Vec::with_capacity(1024),
-            l: Vec::with_capacity(1024),
+            i: NodeBuf::with_capacity(internals),
+            l: NodeBuf::with_capacity(leaves),
             root: NodeIndex::Leaf(0),
+            free_i: Vec::new(),
+            free_l: Vec::new(),
+            len: 0,
+            metrics: Counters::default(),
+            interpolate: None,
+            split_at: NODE_DEG / 2,
+            observer: None,
+            #[cfg(all(feature = "std", unix))]
+            meta_file: None,
         };
         // push the root node
         t.l.push(LeafNode::new());
@@ -300,6 +538,11 @@ impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V>
     /// Allocates a leaf node, and initializes it to `leaf`
     /// Then returns the index of the new leaf node.
     fn alloc_leaf(&mut self, leaf: LeafNode<K, V>) -> usize {
+        self.metrics.add(Counter::NodeAllocs, 1);
+        if let Some(id) = self.free_l.pop() {
+            self.l[id] = leaf;
+            return id;
+        }
         self.l.push(leaf);
         self.l.len() - 1
     }
@@ -307,19 +550,121 @@ impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V>
     /// Allocates an internal node, and initializes it to `internal`
     /// Returns the indexe of the new internal node.
     fn alloc_internal(&mut self, internal: InternalNode<K>) -> usize {
+        self.metrics.add(Counter::NodeAllocs, 1);
+        if let Some(id) = self.free_i.pop() {
+            self.i[id] = internal;
+            return id;
+        }
         self.i.push(internal);
         self.i.len() - 1
     }
 
+    /// Frees the node, it will be reused by the next allocation. A freed leaf is emptied, so it never holds a key of
+    /// the tree.
+    fn free_node(&mut self, node: NodeIndex) {
+        match node {
+            NodeIndex::Internal(id) => self.free_i.push(id),
+            NodeIndex::Leaf(id) => {
+                self.l[id].cnt = 0;
+                self.free_l.push(id);
+            }
+        }
+    }
+
     /// Makes the new root, which must be the internal node. `first` is the first child of the new root.
     /// Returns the new root id.
     fn make_new_root(&mut self, first: NodeIndex) -> usize {
         let new_root_id = self.alloc_internal(InternalNode::new(first));
         self.root = NodeIndex::Internal(new_root_id);
+        self.notify(NodeEvent::RootGrown { root: self.root.into() });
         new_root_id
     }
 
+    /// Passes the event to the observer if there is one.
+    fn notify(&mut self, e: NodeEvent<K>) {
+        if let Some(o) = self.observer.as_mut() {
+            o.on_event(&e);
+        }
+    }
+
+    /// Returns the leaf ids from the leftmost leaf to the rightmost one.
+    fn leaf_ids(&self) -> Vec<usize> {
+        let mut ids = Vec::new();
+        let mut stack = vec![self.root];
+        while let Some(cur) = stack.pop() {
+            match cur {
+                NodeIndex::Internal(id) => {
+                    let node = &self.i[id];
+                    // push the sons reversely, so that the leftmost son is popped first
+                    stack.extend(node.sons[0..node.cnt].iter().rev());
+                }
+                NodeIndex::Leaf(id) => ids.push(id),
+            }
+        }
+        ids
+    }
+
+    /// Builds the internal nodes on top of the `leaves`, which are sorted from the leftmost to the rightmost.
+    /// Every level is split into nodes of even sizes, with at most `fanout` sons each.
+    fn build_internal_levels(&mut self, leaves: &[usize], fanout: usize) {
+        let mut level: Vec<(K, NodeIndex)> = leaves
+            .iter()
+            .map(|&id| {
+                let l = &self.l[id];
+                // only the root leaf can be empty
+                let max = if l.cnt == 0 { K::default() } else { l.keys[l.cnt - 1] };
+                (max, NodeIndex::Leaf(id))
+            })
+            .collect();
+
+        while level.len() > 1 {
+            let groups = level.len().div_ceil(fanout);
+            let (base, extra) = (level.len() / groups, level.len() % groups);
+            let mut next = Vec::with_capacity(groups);
+            let mut sons = level.into_iter();
+            for g in 0..groups {
+                let size = base + (g < extra) as usize;
+                let (mut max, first) = sons.next().unwrap();
+                let mut node = InternalNode::new(first);
+                for _ in 1..size {
+                    let (son_max, son) = sons.next().unwrap();
+                    node.keys[node.cnt - 1] = max;
+                    node.sons[node.cnt] = son;
+                    node.cnt += 1;
+                    max = son_max;
+                }
+                next.push((max, NodeIndex::Internal(self.alloc_internal(node))));
+            }
+            level = next;
+        }
+        self.root = level[0].1;
+    }
+
     pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        self.insert_impl(k, v, false)
+    }
+
+    /// Inserts the key value pair without checking if the key exists, which saves the comparison for the equal key in
+    /// the leaf. It suits the bulk loading of the keys known to be new.
+    ///
+    /// A key larger than all of the keys is appended to the rightmost leaf without searching the nodes, as
+    /// `AppendOnlyBTree` does, and the full nodes on the way split 100/0, so loading the keys in the increasing order
+    /// packs the nodes full.
+    ///
+    /// # Safety
+    ///
+    /// `k` must not exist in the tree. Otherwise the tree holds the key twice, and the lookups, iterations and removals
+    /// of the key are unspecified.
+    pub unsafe fn insert_unique_unchecked(&mut self, k: &K, v: &V) {
+        if !self.push_back(k, v) {
+            self.insert_impl(k, v, true);
+        }
+    }
+
+    /// Inserts the key value pair top-down, splitting the full nodes on the way. Skips checking the existing key if
+    /// `unique` is true.
+    fn insert_impl(&mut self, k: &K, v: &V, unique: bool) -> Option<V> {
+        self.metrics.add(Counter::Inserts, 1);
         let mut cur = self.root;
         let mut father_id: Option<usize> = None; // the node id of the father node of the current node
         let mut father_son_index: usize = 0; // the current node `father_son_index`-th son of the father node
@@ -329,9 +674,15 @@ impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V>
                     if self.i[id].full() {
                         let (left_max, right) = self.i[id].split();
                         let right_id = self.alloc_internal(right);
+                        self.metrics.add(Counter::Splits, 1);
+                        self.notify(NodeEvent::Split {
+                            node: NodeIndex::Internal(id).into(),
+                            right: NodeIndex::Internal(right_id).into(),
+                            left_max,
+                        });
 
                         // make a new root node if the current node is the root
-                        if father_id == None {
+                        if father_id.is_none() {
                             father_id = Some(self.make_new_root(NodeIndex::Internal(id))); 
                             father_son_index = 0;
                         }
@@ -347,6 +698,7 @@ impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V>
                     }
                     
                     father_id = Some(id);
+                    self.metrics.add(Counter::Searches, 1);
                     let tmp = self.i[id].lookup(k);
                     father_son_index = tmp.0;
                     cur = tmp.1;
@@ -354,11 +706,17 @@ impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V>
                 NodeIndex::Leaf(mut id) => {
                     if self.l[id].full() {
                         // split
-                        let (left_max, right) = self.l[id].split();
+                        let (left_max, right) = self.l[id].split_at(self.split_at);
                         let right_id = self.alloc_leaf(right);
+                        self.metrics.add(Counter::Splits, 1);
+                        self.notify(NodeEvent::Split {
+                            node: NodeIndex::Leaf(id).into(),
+                            right: NodeIndex::Leaf(right_id).into(),
+                            left_max,
+                        });
 
                         // make a new root node if the current node is the root
-                        if father_id == None {
+                        if father_id.is_none() {
                             father_id = Some(self.make_new_root(NodeIndex::Leaf(id))); 
                             father_son_index = 0;
                         }
@@ -373,25 +731,128 @@ impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V>
                         }
                     }
 
-                    return self.l[id].insert(k, v);
+                    self.metrics.add(Counter::Searches, 1);
+                    if unique {
+                        self.l[id].insert_unique(k, v);
+                        self.len += 1;
+                        return None;
+                    }
+                    let old = self.l[id].insert(k, v);
+                    if old.is_none() {
+                        self.len += 1;
+                    }
+                    return old;
                 }
             }
         }
     }
 
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of the levels of the nodes, a tree whose root is a leaf has the height 1.
+    pub fn height(&self) -> usize {
+        let mut cur = self.root;
+        let mut h = 1;
+        while let NodeIndex::Internal(id) = cur {
+            cur = self.i[id].sons[0];
+            h += 1;
+        }
+        h
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let mut c = self.cursor_mut();
+        c.seek(k);
+        match c.peek() {
+            Some((key, _)) if key == k => c.remove_next().map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
     pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.get_key_value(k).map(|(_, v)| v)
+    }
+
+    /// Looks up the keys, which should be sorted. The lookups share the path from the root: the keys in the same leaf
+    /// are answered from it, and the next key only goes up as far as the subtree holding it. A key less than the one
+    /// before it is looked up from the root.
+    pub fn lookup_batch(&self, ks: &[K]) -> Vec<Option<&V>> {
+        let mut ret = Vec::with_capacity(ks.len());
+        let mut path = cursor::Path::new();
+        for (i, k) in ks.iter().enumerate() {
+            self.metrics.add(Counter::Lookups, 1);
+            if i > 0 && &ks[i - 1] <= k {
+                path.seek_forward(self, k);
+            } else {
+                path.seek(self, k);
+            }
+            ret.push(path.peek(self).and_then(|(leaf, pos)| {
+                let l = &self.l[leaf];
+                (&l.keys[pos] == k).then_some(&l.values[pos])
+            }));
+        }
+        ret
+    }
+
+    /// Returns the mutable references to the values of `ks`, None if a key does not exist or the keys are not distinct.
+    pub fn get_many_mut<const N: usize>(&mut self, ks: &[&K; N]) -> Option<[&mut V; N]> {
+        for i in 0..N {
+            if ks[i + 1..].iter().any(|k| *k == ks[i]) {
+                return None;
+            }
+        }
+        let mut found = [(0, 0); N];
+        for (f, k) in found.iter_mut().zip(ks.iter()) {
+            *f = self.locate(k)?;
+        }
+        // the keys are distinct, so are the slots of the values
+        let l = self.l.as_mut_ptr();
+        Some(found.map(|(leaf, pos)| unsafe { &mut (*l.add(leaf)).values[pos] }))
+    }
+
+    /// Returns the (leaf id, position) of `k` if it exists.
+    fn locate(&self, k: &K) -> Option<(usize, usize)> {
         let mut cur = self.root;
         loop {
+            self.metrics.add(Counter::Searches, 1);
             match cur {
                 NodeIndex::Internal(id) => {
-                    cur = self.i[id].lookup(k).1;
+                    let node = &self.i[id];
+                    cur = node.sons[self.search_keys(&node.keys[0..node.cnt - 1], k)];
                 }
                 NodeIndex::Leaf(id) => {
-                    return self.l[id].lookup(k);
+                    let l = &self.l[id];
+                    let pos = self.search_keys(&l.keys[0..l.cnt], k);
+                    return (pos < l.cnt && &l.keys[pos] == k).then_some((id, pos));
                 }
             }
         }
     }
+
+    /// Returns `lower_bound(keys, k)`, found by the interpolation search if it is enabled.
+    fn search_keys(&self, keys: &[K], k: &K) -> usize {
+        match self.interpolate {
+            Some(f) => search::interpolation_search(keys, k, f),
+            None => lower_bound(keys, k),
+        }
+    }
+
+    /// Returns the stored key equal to `k` with its value. The stored key may differ from `k` if the keys are equal
+    /// without being identical, e.g. normalized keys.
+    pub fn get_key_value(&self, k: &K) -> Option<(&K, &V)> {
+        self.metrics.add(Counter::Lookups, 1);
+        let (leaf, pos) = self.locate(k)?;
+        let l = &self.l[leaf];
+        Some((&l.keys[pos], &l.values[pos]))
+    }
 }
 
 #[test]
@@ -403,6 +864,163 @@ fn test_btree_smoke() {
     assert_eq!(btree.lookup(&"theanswer"), Some(&43));
 }
 
+#[test]
+fn test_get_key_value() {
+    let mut t = BTree::<f64, u32>::new();
+    for i in 0..1000 {
+        t.insert(&(i as f64 - 500.0), &i);
+    }
+    // -0.0 equals 0.0, but the stored key is returned
+    let (k, v) = t.get_key_value(&-0.0).unwrap();
+    assert!(k.is_sign_positive());
+    assert_eq!(*v, 500);
+    assert_eq!(t.get_key_value(&0.5), None);
+    assert_eq!(t.get_key_value(&499.0), Some((&499.0, &999)));
+}
+
+#[test]
+fn test_insert_unique_unchecked() {
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..10000 {
+        unsafe { t.insert_unique_unchecked(&(i * 7919 % 10000), &i) };
+    }
+    assert_eq!(t.len(), 10000);
+    for i in 0..10000 {
+        assert_eq!(t.lookup(&(i * 7919 % 10000)), Some(&i));
+    }
+    assert!(t.iter().map(|(k, _)| *k).eq(0..10000));
+
+    // the increasing keys are appended, and mixed with the smaller ones
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..10000 {
+        unsafe { t.insert_unique_unchecked(&(i * 2 + 1), &i) };
+        if i % 10 == 0 {
+            unsafe { t.insert_unique_unchecked(&(i * 2), &i) };
+        }
+    }
+    assert_eq!(t.len(), 11000);
+    assert_eq!(t.lookup(&19999), Some(&9999));
+    assert_eq!(t.lookup(&500), Some(&250));
+    assert_eq!(t.lookup(&502), None);
+    assert!(t.iter().map(|(k, _)| *k).eq((0..20000).filter(|k| k % 2 == 1 || k % 20 == 0)));
+
+    // the appended leaves are full, except the rightmost one
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..10000 {
+        unsafe { t.insert_unique_unchecked(&i, &i) };
+    }
+    assert_eq!(t.l.len(), 10000usize.div_ceil(NODE_DEG));
+    assert!(t.iter().map(|(k, _)| *k).eq(0..10000));
+}
+
+#[test]
+fn test_get_many_mut() {
+    let mut t = BTree::<u32, u64>::new();
+    for i in 0..1000 {
+        t.insert(&i, &100);
+    }
+    // transfer between two accounts
+    let [a, b] = t.get_many_mut(&[&3, &900]).unwrap();
+    *a -= 30;
+    *b += 30;
+    assert_eq!(t.lookup(&3), Some(&70));
+    assert_eq!(t.lookup(&900), Some(&130));
+
+    assert!(t.get_many_mut(&[&3, &1000]).is_none());
+    assert!(t.get_many_mut(&[&3, &4, &3]).is_none());
+    assert_eq!(t.get_many_mut::<0>(&[]), Some([]));
+    let vs = t.get_many_mut(&[&0, &1, &2, &999]).unwrap();
+    assert_eq!(vs.map(|v| *v), [100, 100, 100, 100]);
+}
+
+#[test]
+fn test_with_capacity() {
+    let n = 100000;
+    let mut t = BTree::<u32, u32>::with_capacity(n);
+    let (i, l) = (t.i.as_ptr(), t.l.as_ptr());
+    for k in 0..n as u32 {
+        t.insert(&k, &k);
+    }
+    // the buffers are never reallocated
+    assert_eq!((i, l), (t.i.as_ptr(), t.l.as_ptr()));
+
+    t.reserve(n);
+    let (i, l) = (t.i.as_ptr(), t.l.as_ptr());
+    for k in 0..n as u32 {
+        t.insert(&(k + n as u32), &k);
+    }
+    assert_eq!((i, l), (t.i.as_ptr(), t.l.as_ptr()));
+    assert_eq!(nodes_for(0), (0, 1));
+}
+
+#[test]
+fn test_clone_from() {
+    let mut a = BTree::<u64, u64>::new();
+    for i in 0..100000 {
+        a.insert(&i, &i);
+    }
+    let mut b = a.clone();
+    assert!(b.iter().eq(a.iter()));
+    b.insert(&100000, &0);
+    assert_eq!(a.lookup(&100000), None);
+
+    // rebuild `a` from a smaller tree in place
+    let mut c = BTree::<u64, u64>::new();
+    for i in 0..50000 {
+        c.insert(&(i * 3), &i);
+    }
+    let (i, l) = (a.i.as_ptr(), a.l.as_ptr());
+    a.clone_from(&c);
+    assert_eq!((i, l), (a.i.as_ptr(), a.l.as_ptr()));
+    assert_eq!(a.len(), 50000);
+    assert!(a.iter().eq(c.iter()));
+    a.insert(&1, &1);
+    assert_eq!(a.remove(&3), Some(1));
+    assert_eq!(c.lookup(&3), Some(&1));
+
+    // and back to the larger one
+    a.clone_from(&b);
+    assert!(a.iter().eq(b.iter()));
+}
+
+#[test]
+fn test_root_collapse() {
+    let n = 2_000_000u64;
+    let mut t = BTree::<u64, u64>::new();
+    for i in 0..n {
+        t.insert(&(i * 2654435761 % n), &i);
+    }
+    let high = t.height();
+    assert!(high >= 5);
+
+    // remove all but a few entries, spread over the key space
+    for k in 0..n {
+        if k % 500_000 != 0 {
+            assert!(t.remove(&k).is_some());
+        }
+    }
+    assert_eq!(t.len(), 4);
+    assert_eq!(t.height(), 1);
+    assert_eq!(t.i.len(), t.free_i.len());
+    t.reset_metrics();
+    for k in (0..n).step_by(500_000) {
+        assert!(t.lookup(&k).is_some());
+    }
+    #[cfg(feature = "metrics")]
+    assert_eq!(t.metrics().searches, 4);
+
+    // the tree grows back
+    for i in 0..n {
+        t.insert(&i, &i);
+    }
+    assert_eq!(t.height(), high);
+    for k in 0..n {
+        t.remove(&k);
+    }
+    assert!(t.is_empty());
+    assert_eq!(t.height(), 1);
+}
+
 #[cfg(test)]
 mod tests {
     extern crate rand;
@@ -423,7 +1041,7 @@ mod tests {
         for _ in 0..300000 {
             let lookup: bool = rng.gen();
 
-            if lookup && keys.len() != 0 {
+            if lookup && !keys.is_empty() {
                 let mut i: usize = rng.gen();
                 i %= keys.len();
                 // println!("lookup key: {}", keys[i]);
@@ -440,6 +1058,50 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_lookup_batch() {
+        let mut t = BTree::<u32, u32>::new();
+        assert_eq!(t.lookup_batch(&[0, 1]), [None, None]);
+        for i in 0..100000 {
+            t.insert(&(i * 2), &i);
+        }
+        let ks: Vec<u32> = (0..300000).step_by(3).collect();
+        let expected: Vec<Option<&u32>> = ks.iter().map(|k| t.lookup(k)).collect();
+        assert_eq!(t.lookup_batch(&ks), expected);
+        // the duplicated and the unsorted keys are answered too
+        assert_eq!(t.lookup_batch(&[4, 4, 199998, 7, 2, 200000, 0]), [
+            Some(&2), Some(&2), Some(&99999), None, Some(&1), None, Some(&0)
+        ]);
+    }
+
+    #[test]
+    fn test_btree_remove() {
+        let mut rng = rand::thread_rng();
+
+        let mut truth = BTreeMap::new();
+        let mut t = BTree::new();
+
+        // a small key space makes the tree grow and shrink repeatedly
+        for _ in 0..300000 {
+            let k: u16 = rng.gen::<u16>() % 4096;
+            match rng.gen::<u8>() % 3 {
+                0 => assert_eq!(t.lookup(&k), truth.get(&k)),
+                1 => {
+                    let v: i32 = rng.gen();
+                    assert_eq!(t.insert(&k, &v), truth.insert(k, v));
+                }
+                _ => assert_eq!(t.remove(&k), truth.remove(&k)),
+            }
+        }
+        assert_eq!(t.len(), truth.len());
+
+        let mut c = t.cursor();
+        for (k, v) in truth.iter() {
+            assert_eq!(c.next(), Some((k, v)));
+        }
+        assert_eq!(c.next(), None);
+    }
+
     #[bench]
     fn bench_insert_dense_keys(b: &mut Bencher) {
         let n = 100000;
@@ -452,6 +1114,175 @@ mod tests {
         b.bytes = n as u64;
     }
 
+    #[bench]
+    fn bench_insert_unique_unchecked_dense_keys(b: &mut Bencher) {
+        let n = 100000;
+        b.iter(||{
+            let mut t = BTree::<usize, usize>::new();
+            for i in 0..n {
+                unsafe { t.insert_unique_unchecked(&i, &i) };
+            }
+        });
+        b.bytes = n as u64;
+    }
+
+    #[bench]
+    fn bench_insert_random_keys(b: &mut Bencher) {
+        let n = 100000u64;
+        b.iter(||{
+            let mut t = BTree::<u64, u64>::new();
+            for i in 0..n {
+                t.insert(&(i * 2654435761 % n), &i);
+            }
+        });
+        b.bytes = n;
+    }
+
+    #[bench]
+    fn bench_insert_unique_unchecked_random_keys(b: &mut Bencher) {
+        let n = 100000u64;
+        b.iter(||{
+            let mut t = BTree::<u64, u64>::new();
+            for i in 0..n {
+                unsafe { t.insert_unique_unchecked(&(i * 2654435761 % n), &i) };
+            }
+        });
+        b.bytes = n;
+    }
+
+    #[bench]
+    fn bench_lookup_random_keys(b: &mut Bencher) {
+        let n = 100000u64;
+        let mut t = BTree::<u64, u64>::new();
+        for i in 0..n {
+            t.insert(&(i * 2654435761 % n), &i);
+        }
+        b.iter(|| {
+            for i in 0..n {
+                test::black_box(t.lookup(&(i * 7919 % n)));
+            }
+        });
+        b.bytes = n;
+    }
+
+    #[bench]
+    fn bench_lookup_sorted_keys(b: &mut Bencher) {
+        let n = 100000u64;
+        let mut t = BTree::<u64, u64>::new();
+        for i in 0..n {
+            t.insert(&(i * 2654435761 % n), &i);
+        }
+        let ks: Vec<u64> = (0..n).map(|i| i * 3).collect();
+        b.iter(|| {
+            for k in ks.iter() {
+                test::black_box(t.lookup(k));
+            }
+        });
+        b.bytes = n;
+    }
+
+    #[bench]
+    fn bench_lookup_batch_sorted_keys(b: &mut Bencher) {
+        let n = 100000u64;
+        let mut t = BTree::<u64, u64>::new();
+        for i in 0..n {
+            t.insert(&(i * 2654435761 % n), &i);
+        }
+        let ks: Vec<u64> = (0..n).map(|i| i * 3).collect();
+        b.iter(|| test::black_box(t.lookup_batch(&ks)));
+        b.bytes = n;
+    }
+
+    #[bench]
+    fn bench_frozen_lookup_random_keys(b: &mut Bencher) {
+        let n = 100000u64;
+        let mut t = BTree::<u64, u64>::new();
+        for i in 0..n {
+            t.insert(&(i * 2654435761 % n), &i);
+        }
+        let f = t.freeze();
+        b.iter(|| {
+            for i in 0..n {
+                test::black_box(f.lookup(&(i * 7919 % n)));
+            }
+        });
+        b.bytes = n;
+    }
+
+    #[bench]
+    fn bench_frozen_veb_lookup_random_keys(b: &mut Bencher) {
+        let n = 100000u64;
+        let mut t = BTree::<u64, u64>::new();
+        for i in 0..n {
+            t.insert(&(i * 2654435761 % n), &i);
+        }
+        let f = t.freeze_with(crate::frozen::FrozenLayout::VanEmdeBoas);
+        b.iter(|| {
+            for i in 0..n {
+                test::black_box(f.lookup(&(i * 7919 % n)));
+            }
+        });
+        b.bytes = n;
+    }
+
+    #[bench]
+    fn bench_interpolation_lookup_random_keys(b: &mut Bencher) {
+        let n = 100000u64;
+        let mut t = BTree::<u64, u64>::new();
+        t.set_interpolation_search(true);
+        for i in 0..n {
+            t.insert(&(i * 2654435761 % n), &i);
+        }
+        b.iter(|| {
+            for i in 0..n {
+                test::black_box(t.lookup(&(i * 7919 % n)));
+            }
+        });
+        b.bytes = n;
+    }
+
+    #[cfg(feature = "std")]
+    #[bench]
+    fn bench_hash_indexed_lookup_random_keys(b: &mut Bencher) {
+        let n = 100000u64;
+        let mut t = crate::hashindex::HashIndexedBTree::<u64, u64>::new();
+        for i in 0..n {
+            t.insert(&(i * 2654435761 % n), &i);
+        }
+        b.iter(|| {
+            for i in 0..n {
+                test::black_box(t.lookup(&(i * 7919 % n)));
+            }
+        });
+        b.bytes = n;
+    }
+
+    #[bench]
+    fn bench_range_sum(b: &mut Bencher) {
+        let n = 100000u64;
+        let mut t = BTree::<u64, u64>::new();
+        for i in 0..n {
+            t.insert(&i, &i);
+        }
+        b.iter(|| test::black_box(t.range(..).map(|(_, v)| *v).sum::<u64>()));
+        b.bytes = n;
+    }
+
+    #[bench]
+    fn bench_for_each_in_range_sum(b: &mut Bencher) {
+        let n = 100000u64;
+        let mut t = BTree::<u64, u64>::new();
+        for i in 0..n {
+            t.insert(&i, &i);
+        }
+        b.iter(|| {
+            let mut sum = 0;
+            t.for_each_in_range(.., |_, v| sum += *v);
+            test::black_box(sum)
+        });
+        b.bytes = n;
+    }
+
     #[bench]
     fn bench_std_insert_dense_keys(b: &mut Bencher) {
         let n = 100000;
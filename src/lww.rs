@@ -0,0 +1,178 @@
+use alloc::vec::Vec;
+
+use crate::batch::WriteBatch;
+use crate::BTree;
+
+/// The timestamp of a write, ordered by the time and then by the replica making it, so the writes of two replicas
+/// never tie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp {
+    pub time: u64,
+    pub replica: u32,
+}
+
+/// The last write of a key, `value` is None if the key was removed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Versioned<V> {
+    pub ts: Timestamp,
+    pub value: Option<V>,
+}
+
+/// LwwBTree is a B+Tree replicated on multiple devices, where every replica is written independently, and the replicas
+/// converge by merging each other.
+///
+/// Every key keeps the timestamp of its last write, and a removal leaves a tombstone, so that a merge tells a removed
+/// key from one never written. By default the write with the larger timestamp wins, which makes the merge commutative
+/// and idempotent. The tombstones are kept until `purge` drops them.
+pub struct LwwBTree<K, V> {
+    t: BTree<K, Versioned<V>>,
+    replica: u32,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy + PartialEq> LwwBTree<K, V> {
+    /// Creates an empty replica. Every replica of a tree must have a different id.
+    pub fn new(replica: u32) -> Self {
+        LwwBTree {
+            t: BTree::new(),
+            replica,
+        }
+    }
+
+    pub fn replica(&self) -> u32 {
+        self.replica
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k).and_then(|e| e.value.as_ref())
+    }
+
+    /// Returns the last write of `k`, including the removal.
+    pub fn lookup_versioned(&self, k: &K) -> Option<&Versioned<V>> {
+        self.t.lookup(k)
+    }
+
+    /// Writes `v` to `k` at `time`. The write is ignored if the key has a later write, e.g. merged from a replica
+    /// whose clock is ahead, and false is returned.
+    pub fn insert(&mut self, k: &K, v: &V, time: u64) -> bool {
+        self.write(k, Some(*v), time)
+    }
+
+    /// Removes `k` at `time` by leaving a tombstone, see `insert`.
+    pub fn remove(&mut self, k: &K, time: u64) -> bool {
+        self.write(k, None, time)
+    }
+
+    fn write(&mut self, k: &K, value: Option<V>, time: u64) -> bool {
+        let ts = Timestamp {
+            time,
+            replica: self.replica,
+        };
+        if self.t.lookup(k).is_some_and(|e| e.ts >= ts) {
+            return false;
+        }
+        self.t.insert(k, &Versioned { ts, value });
+        true
+    }
+
+    /// Returns an iterator over the keys and the values which are not removed, in the order of the keys.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.t.iter().filter_map(|(k, e)| e.value.as_ref().map(|v| (k, v)))
+    }
+
+    /// Merges the writes of `other` with the last writer wins.
+    pub fn merge(&mut self, other: &LwwBTree<K, V>) {
+        self.merge_with(other, |_, ours, theirs| if theirs.ts > ours.ts { *theirs } else { *ours });
+    }
+
+    /// Merges the writes of `other`. The keys written only by `other` are copied, and `resolver` decides the result of
+    /// every key written differently by both, given the key, our write and their write.
+    ///
+    /// The replicas converge as long as the resolver is commutative, e.g. picks by the timestamps or merges the
+    /// values. The result should carry the larger timestamp, otherwise a later local write may be ignored.
+    pub fn merge_with<F>(&mut self, other: &LwwBTree<K, V>, mut resolver: F)
+    where
+        F: FnMut(&K, &Versioned<V>, &Versioned<V>) -> Versioned<V>,
+    {
+        let mut batch = WriteBatch::new();
+        for (k, theirs) in other.t.iter() {
+            match self.t.lookup(k) {
+                None => batch.insert(k, theirs),
+                Some(ours) if ours != theirs => batch.insert(k, &resolver(k, ours, theirs)),
+                Some(_) => {}
+            }
+        }
+        self.t.apply_batch(&batch);
+    }
+
+    /// Drops the tombstones written before `time`, which returns the number of them. It must only drop the removals
+    /// every replica has merged, otherwise a merge brings the removed keys back.
+    pub fn purge(&mut self, time: u64) -> usize {
+        let dead: Vec<K> = self
+            .t
+            .iter()
+            .filter(|(_, e)| e.value.is_none() && e.ts.time < time)
+            .map(|(k, _)| *k)
+            .collect();
+        for k in dead.iter() {
+            self.t.remove(k);
+        }
+        dead.len()
+    }
+}
+
+#[test]
+fn test_lww_merge() {
+    let mut a = LwwBTree::<u32, u32>::new(1);
+    let mut b = LwwBTree::<u32, u32>::new(2);
+    for i in 0..100 {
+        a.insert(&i, &i, 1);
+    }
+    // b starts from the same state, then both diverge
+    b.merge(&a);
+    assert_eq!(b.iter().count(), 100);
+
+    a.insert(&1, &10, 2);
+    b.insert(&1, &20, 3);
+    a.insert(&2, &10, 3);
+    b.insert(&2, &20, 3);
+    a.remove(&3, 2);
+    b.insert(&3, &30, 4);
+    b.remove(&4, 2);
+    a.insert(&200, &1, 2);
+    b.insert(&300, &2, 2);
+    // a write older than the existing one is ignored
+    assert!(!a.insert(&200, &0, 1));
+
+    let mut ab = LwwBTree::new(1);
+    ab.merge(&a);
+    ab.merge(&b);
+    let mut ba = LwwBTree::new(2);
+    ba.merge(&b);
+    ba.merge(&a);
+    a.merge(&b);
+    b.merge(&a);
+    for t in [&a, &b, &ab, &ba] {
+        assert_eq!(t.iter().collect::<Vec<_>>(), a.iter().collect::<Vec<_>>());
+    }
+    assert_eq!(a.lookup(&1), Some(&20));
+    // the replica breaks the tie
+    assert_eq!(a.lookup(&2), Some(&20));
+    assert_eq!(a.lookup(&3), Some(&30));
+    assert_eq!(a.lookup(&4), None);
+    assert_eq!((a.lookup(&200), a.lookup(&300)), (Some(&1), Some(&2)));
+    assert_eq!(a.lookup_versioned(&4).unwrap().ts, Timestamp { time: 2, replica: 2 });
+
+    // a custom resolver, e.g. adding up the concurrent values
+    let mut c = LwwBTree::<u32, u32>::new(3);
+    c.insert(&1, &5, 10);
+    c.merge_with(&a, |_, ours, theirs| Versioned {
+        ts: ours.ts.max(theirs.ts),
+        value: Some(ours.value.unwrap_or(0) + theirs.value.unwrap_or(0)),
+    });
+    assert_eq!(c.lookup(&1), Some(&25));
+    assert_eq!(c.lookup(&0), Some(&0));
+
+    assert_eq!(a.purge(3), 1);
+    assert_eq!(a.lookup_versioned(&4), None);
+    assert_eq!(a.iter().count(), b.iter().count());
+}
@@ -0,0 +1,167 @@
+//! A minimal LZ4 block codec, compatible with the LZ4 block format.
+
+const MIN_MATCH: usize = 4;
+// the last 5 bytes are always literals
+const LAST_LITERALS: usize = 5;
+// the last match must start at least 12 bytes before the end
+const MF_LIMIT: usize = 12;
+const HASH_BITS: usize = 12;
+
+fn read_u32(a: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([a[i], a[i + 1], a[i + 2], a[i + 3]])
+}
+
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Writes the length beyond the 4-bit field of the token, as a run of 255s and the remainder.
+fn write_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// Emits a sequence, i.e. the literals and the match after them. `match_len` is 0 for the last sequence.
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], offset: usize, match_len: usize) {
+    let lit = literals.len();
+    let ml = match_len.saturating_sub(MIN_MATCH);
+    out.push(((lit.min(15) as u8) << 4) | ml.min(15) as u8);
+    if lit >= 15 {
+        write_len(out, lit - 15);
+    }
+    out.extend_from_slice(literals);
+    if match_len > 0 {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if ml >= 15 {
+            write_len(out, ml - 15);
+        }
+    }
+}
+
+/// Compresses `input` greedily, with a hash table of the last positions of 4-byte sequences.
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let n = input.len();
+    let mut out = Vec::with_capacity(n / 2);
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut i = 0;
+    while n >= MF_LIMIT && i <= n - MF_LIMIT {
+        let seq = read_u32(input, i);
+        let h = hash(seq);
+        let cand = table[h];
+        table[h] = i;
+        if cand != usize::MAX && i - cand <= u16::MAX as usize && read_u32(input, cand) == seq {
+            let mut len = MIN_MATCH;
+            while i + len < n - LAST_LITERALS && input[cand + len] == input[i + len] {
+                len += 1;
+            }
+            write_sequence(&mut out, &input[anchor..i], i - cand, len);
+            i += len;
+            anchor = i;
+        } else {
+            i += 1;
+        }
+    }
+    write_sequence(&mut out, &input[anchor..], 0, 0);
+    out
+}
+
+/// Reads the length beyond the 4-bit field of the token.
+fn read_len(input: &[u8], ip: &mut usize) -> Option<usize> {
+    let mut len = 0;
+    loop {
+        let b = *input.get(*ip)?;
+        *ip += 1;
+        len += b as usize;
+        if b != 255 {
+            return Some(len);
+        }
+    }
+}
+
+/// Decompresses `input` into `out`, which must be exactly the size of the original data.
+/// Returns None if `input` is malformed.
+pub(crate) fn decompress(input: &[u8], out: &mut [u8]) -> Option<()> {
+    let mut ip = 0;
+    let mut op = 0;
+    loop {
+        let token = *input.get(ip)?;
+        ip += 1;
+
+        let mut lit = (token >> 4) as usize;
+        if lit == 15 {
+            lit += read_len(input, &mut ip)?;
+        }
+        out.get_mut(op..op + lit)?.copy_from_slice(input.get(ip..ip + lit)?);
+        ip += lit;
+        op += lit;
+        if ip == input.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([*input.get(ip)?, *input.get(ip + 1)?]) as usize;
+        ip += 2;
+        if offset == 0 || offset > op {
+            return None;
+        }
+        let mut len = (token & 15) as usize;
+        if len == 15 {
+            len += read_len(input, &mut ip)?;
+        }
+        len += MIN_MATCH;
+        if op + len > out.len() {
+            return None;
+        }
+        // the match may overlap with itself, so copy byte by byte
+        for j in op..op + len {
+            out[j] = out[j - offset];
+        }
+        op += len;
+    }
+    if op == out.len() {
+        Some(())
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_lz4_roundtrip() {
+    let mut inputs: Vec<Vec<u8>> = vec![
+        vec![],
+        b"abc".to_vec(),
+        b"hello hello hello hello hello hello".to_vec(),
+        vec![0; 4096],
+        (0..5000u32).map(|i| (i % 251) as u8).collect(),
+    ];
+    // pseudo random bytes are incompressible
+    let mut x = 42u64;
+    inputs.push(
+        (0..3000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect(),
+    );
+
+    for input in inputs.iter() {
+        let c = compress(input);
+        let mut out = vec![0u8; input.len()];
+        assert_eq!(decompress(&c, &mut out), Some(()));
+        assert_eq!(&out, input);
+    }
+    assert!(compress(&[0; 4096]).len() < 64);
+
+    // a literal-only block, as produced by the reference implementation for "abc"
+    assert_eq!(compress(b"abc"), b"\x30abc");
+    // malformed blocks are rejected
+    let mut out = [0u8; 16];
+    assert_eq!(decompress(b"\x30ab", &mut out[..3]), None);
+    assert_eq!(decompress(b"\x10a\x05\x00", &mut out), None);
+}
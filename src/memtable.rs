@@ -0,0 +1,134 @@
+use crate::range::Range;
+use crate::BTree;
+
+/// The sequence number of a write to a `MemTable`, the later writes have the larger numbers.
+pub type SeqNo = u64;
+
+/// MemTable is a B+Tree buffering the writes of an LSM engine before they are flushed into an SSTable.
+///
+/// Every write is kept as a version of its key with the sequence number chosen by the engine, and a removal is a
+/// tombstone, so the reads at an older sequence number and the flush see the writes they should. The versions are keyed
+/// by the key and the inverted sequence number, so the versions of a key are adjacent, the latest first.
+pub struct MemTable<K, V> {
+    t: BTree<(K, u64), Option<V>>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for MemTable<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> MemTable<K, V> {
+    pub fn new() -> Self {
+        MemTable { t: BTree::new() }
+    }
+
+    /// Returns the number of the versions, including the tombstones.
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// Writes `v` to `k` at `seq`. A write at the sequence number of an existing version of `k` replaces it.
+    pub fn insert(&mut self, k: &K, seq: SeqNo, v: &V) {
+        self.t.insert(&(*k, !seq), &Some(*v));
+    }
+
+    /// Removes `k` at `seq` by writing a tombstone.
+    pub fn remove(&mut self, k: &K, seq: SeqNo) {
+        self.t.insert(&(*k, !seq), &None);
+    }
+
+    /// Returns the latest version of `k` not later than `seq`, which is `Some(None)` if it is a tombstone, and None if
+    /// this table has no such version, so the older tables and the SSTables must be searched.
+    pub fn lookup(&self, k: &K, seq: SeqNo) -> Option<Option<&V>> {
+        match self.t.range((*k, !seq)..).next() {
+            Some(((key, _), v)) if key == k => Some(v.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over the latest version of every key not later than `seq`, without the tombstones, in the
+    /// order of the keys. The versions later than `seq` are skipped, e.g. the writes after the flush started.
+    ///
+    /// The stream is what an SSTable of the last level holds. The tombstones must be kept while the older SSTables may
+    /// still hold their keys, see `compact_with_tombstones`.
+    pub fn compact(&self, seq: SeqNo) -> impl Iterator<Item = (&K, SeqNo, &V)> + '_ {
+        self.compact_with_tombstones(seq)
+            .filter_map(|(k, seq, v)| v.map(|v| (k, seq, v)))
+    }
+
+    /// The same as `compact`, but yields the tombstones as None.
+    pub fn compact_with_tombstones(&self, seq: SeqNo) -> Compaction<'_, K, V> {
+        Compaction {
+            range: self.t.range(..),
+            seq,
+            last: None,
+        }
+    }
+}
+
+/// Compaction is an iterator over the latest versions of the keys of a `MemTable`, see `MemTable::compact`.
+pub struct Compaction<'a, K, V> {
+    range: Range<'a, (K, u64), Option<V>>,
+    seq: SeqNo,
+    // the key of the last version yielded, whose older versions are skipped
+    last: Option<K>,
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Iterator for Compaction<'a, K, V> {
+    type Item = (&'a K, SeqNo, Option<&'a V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for ((k, inv), v) in self.range.by_ref() {
+            if !*inv > self.seq || self.last.as_ref() == Some(k) {
+                continue;
+            }
+            self.last = Some(*k);
+            return Some((k, !*inv, v.as_ref()));
+        }
+        None
+    }
+}
+
+#[test]
+fn test_memtable() {
+    let mut t = MemTable::<u64, u64>::new();
+    assert_eq!(t.lookup(&1, 100), None);
+    let mut seq = 0;
+    for round in 0..3 {
+        for i in 0..1000 {
+            seq += 1;
+            t.insert(&i, seq, &(i + round * 1000));
+        }
+    }
+    for i in (0..1000).step_by(3) {
+        seq += 1;
+        t.remove(&i, seq);
+    }
+    assert_eq!(t.len(), 3334);
+
+    assert_eq!(t.lookup(&1, seq), Some(Some(&2001)));
+    assert_eq!(t.lookup(&3, seq), Some(None));
+    // before the removal and the last round
+    assert_eq!(t.lookup(&3, 2000), Some(Some(&1003)));
+    assert_eq!(t.lookup(&3, 0), None);
+
+    let compacted: alloc::vec::Vec<_> = t.compact(seq).map(|(k, _, v)| (*k, *v)).collect();
+    let expected: alloc::vec::Vec<_> = (0..1000).filter(|i| i % 3 != 0).map(|i| (i, i + 2000)).collect();
+    assert_eq!(compacted, expected);
+    assert_eq!(
+        t.compact_with_tombstones(seq).filter(|(_, _, v)| v.is_none()).count(),
+        334
+    );
+
+    // the writes after the flush started are skipped
+    let mut c = t.compact(1500);
+    assert_eq!(c.next(), Some((&0, 1001, &1000)));
+    assert_eq!(t.compact(1500).count(), 1000);
+    assert_eq!(t.compact(1500).last(), Some((&999, 1000, &999)));
+}
@@ -0,0 +1,126 @@
+use core::ops::RangeBounds;
+
+use crate::BTree;
+
+/// The metadata kept with every entry of `MetaBTree`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EntryMeta {
+    /// The time of the insertion creating the entry.
+    pub created: u64,
+    /// The time of the last write of the entry.
+    pub updated: u64,
+    /// The number of the writes of the entry, 1 after it is created.
+    pub version: u64,
+}
+
+/// MetaBTree is a B+Tree keeping the creation time, the last modification time and the version of every entry beside
+/// its value, e.g. to find the entries changed since the last sync.
+///
+/// The metadata is maintained by the writes, the time is any u64 clock chosen by the caller like `TtlBTree`. Removing
+/// a key drops its metadata, and inserting it again creates a new entry of version 1.
+pub struct MetaBTree<K, V> {
+    t: BTree<K, (V, EntryMeta)>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for MetaBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> MetaBTree<K, V> {
+    pub fn new() -> Self {
+        MetaBTree { t: BTree::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// Inserts or updates the key value pair at `now`, and returns the old value. An update keeps the creation time
+    /// and bumps the version.
+    pub fn insert(&mut self, k: &K, v: &V, now: u64) -> Option<V> {
+        self.t
+            .fetch_update(k, |old| {
+                let meta = match old {
+                    Some((_, m)) => EntryMeta {
+                        updated: now,
+                        version: m.version + 1,
+                        ..*m
+                    },
+                    None => EntryMeta {
+                        created: now,
+                        updated: now,
+                        version: 1,
+                    },
+                };
+                Some((*v, meta))
+            })
+            .map(|(v, _)| v)
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k).map(|(v, _)| v)
+    }
+
+    /// Returns the value of `k` and its metadata.
+    pub fn get_with_meta(&self, k: &K) -> Option<(&V, &EntryMeta)> {
+        self.t.lookup(k).map(|(v, m)| (v, m))
+    }
+
+    /// Removes `k`, and returns its value and metadata if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<(V, EntryMeta)> {
+        self.t.remove(k)
+    }
+
+    /// Returns an iterator over the entries with the keys in the `range` and their metadata, in the order of the keys.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (&K, &V, &EntryMeta)> + '_ {
+        self.t.range(range).map(|(k, (v, m))| (k, v, m))
+    }
+
+    /// Returns an iterator over the entries written after `since`, in the order of the keys. It scans all entries.
+    pub fn modified_since(&self, since: u64) -> impl Iterator<Item = (&K, &V, &EntryMeta)> + '_ {
+        self.range(..).filter(move |(_, _, m)| m.updated > since)
+    }
+}
+
+#[test]
+fn test_meta() {
+    let mut t = MetaBTree::<u64, u64>::new();
+    assert!(t.is_empty());
+    for i in 0..1000 {
+        assert_eq!(t.insert(&i, &i, 10), None);
+    }
+    assert_eq!(t.insert(&7, &70, 20), Some(7));
+    assert_eq!(t.insert(&7, &700, 30), Some(70));
+    assert_eq!(t.lookup(&7), Some(&700));
+    let meta = EntryMeta {
+        created: 10,
+        updated: 30,
+        version: 3,
+    };
+    assert_eq!(t.get_with_meta(&7), Some((&700, &meta)));
+    assert_eq!(t.get_with_meta(&8).unwrap().1.version, 1);
+    assert_eq!(t.get_with_meta(&1000), None);
+
+    assert_eq!(t.insert(&500, &0, 25), Some(500));
+    assert!(t.modified_since(20).map(|(k, _, _)| *k).eq([7, 500].iter().copied()));
+    assert_eq!(t.range(5..10).count(), 5);
+
+    // a removed key starts over
+    assert_eq!(t.remove(&7), Some((700, meta)));
+    assert_eq!(t.insert(&7, &1, 40), None);
+    assert_eq!(
+        t.get_with_meta(&7).unwrap().1,
+        &EntryMeta {
+            created: 40,
+            updated: 40,
+            version: 1
+        }
+    );
+    assert_eq!(t.len(), 1000);
+}
@@ -0,0 +1,115 @@
+#[cfg(feature = "metrics")]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::BTree;
+
+/// A snapshot of the operation counters of a tree. The counters are only maintained with the `metrics` feature,
+/// otherwise they are all 0.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    pub lookups: u64,
+    /// The insertions, including the ones updating an existing key.
+    pub inserts: u64,
+    pub removes: u64,
+    /// The nodes split by the insertions.
+    pub splits: u64,
+    /// The nodes merged into their siblings by the removals.
+    pub merges: u64,
+    pub node_allocs: u64,
+    /// The binary searches in the nodes by the lookups, the insertions and the seeks of the cursors. A search takes at
+    /// most log2(NODE_DEG) + 1 key comparisons.
+    pub searches: u64,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Counter {
+    Lookups,
+    Inserts,
+    Removes,
+    Splits,
+    Merges,
+    NodeAllocs,
+    Searches,
+}
+
+/// The counters of a tree. They are atomic so that the lookups can count through `&self`, and take no space without
+/// the `metrics` feature.
+#[derive(Default)]
+pub(crate) struct Counters {
+    #[cfg(feature = "metrics")]
+    c: [AtomicU64; 7],
+}
+
+impl Counters {
+    #[inline]
+    pub(crate) fn add(&self, c: Counter, n: u64) {
+        #[cfg(feature = "metrics")]
+        self.c[c as usize].fetch_add(n, Ordering::Relaxed);
+        #[cfg(not(feature = "metrics"))]
+        let _ = (c, n);
+    }
+
+    fn get(&self, c: Counter) -> u64 {
+        #[cfg(feature = "metrics")]
+        return self.c[c as usize].load(Ordering::Relaxed);
+        #[cfg(not(feature = "metrics"))]
+        {
+            let _ = c;
+            0
+        }
+    }
+
+    fn reset(&self) {
+        #[cfg(feature = "metrics")]
+        for c in self.c.iter() {
+            c.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<K, V> BTree<K, V> {
+    /// Returns the operation counters since the tree is created or the counters are reset.
+    pub fn metrics(&self) -> Metrics {
+        let m = &self.metrics;
+        Metrics {
+            lookups: m.get(Counter::Lookups),
+            inserts: m.get(Counter::Inserts),
+            removes: m.get(Counter::Removes),
+            splits: m.get(Counter::Splits),
+            merges: m.get(Counter::Merges),
+            node_allocs: m.get(Counter::NodeAllocs),
+            searches: m.get(Counter::Searches),
+        }
+    }
+
+    pub fn reset_metrics(&self) {
+        self.metrics.reset();
+    }
+}
+
+#[test]
+#[cfg(feature = "metrics")]
+fn test_metrics() {
+    use crate::NODE_DEG;
+
+    let mut t = BTree::<u32, u32>::new();
+    assert_eq!(t.metrics(), Metrics::default());
+    for i in 0..10000 {
+        t.insert(&i, &i);
+    }
+    for i in 0..10000 {
+        assert_eq!(t.lookup(&i), Some(&i));
+    }
+    let m = t.metrics();
+    assert_eq!((m.lookups, m.inserts, m.removes, m.merges), (10000, 10000, 0, 0));
+    assert!(m.splits > (10000 / NODE_DEG) as u64 && m.node_allocs >= m.splits);
+    assert!(m.searches > 20000);
+
+    t.reset_metrics();
+    for i in 0..10000 {
+        t.remove(&i);
+    }
+    let m = t.metrics();
+    assert_eq!((m.lookups, m.inserts, m.removes, m.splits), (0, 0, 10000, 0));
+    assert!(m.merges > 0);
+}
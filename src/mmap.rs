@@ -0,0 +1,551 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::ptr::{self, NonNull};
+use std::slice;
+
+use crate::buf::NodeBuf;
+use crate::hugepage::HugePages;
+use crate::{BTree, InternalNode, LeafNode, NodeIndex, NODE_DEG};
+
+/// Plain old data, i.e. the types which can be written into a file and mapped back as they are.
+///
+/// # Safety
+///
+/// The type must not contain pointers, references or anything else depending on the address space, must not have
+/// padding bytes, and every bit pattern must be a valid value of the type.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for () {}
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for u128 {}
+unsafe impl Pod for usize {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
+unsafe impl Pod for i128 {}
+unsafe impl Pod for isize {}
+unsafe impl Pod for f32 {}
+unsafe impl Pod for f64 {}
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+/// Returns the bytes of `t`.
+pub(crate) fn bytes_of<T: Pod>(t: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(t as *const T as *const u8, size_of::<T>()) }
+}
+
+/// Returns the bytes of the slice `s`.
+pub(crate) fn slice_bytes<T: Pod>(s: &[T]) -> &[u8] {
+    unsafe { slice::from_raw_parts(s.as_ptr() as *const u8, std::mem::size_of_val(s)) }
+}
+
+/// Returns the mutable bytes of the slice `s`.
+pub(crate) fn slice_bytes_mut<T: Pod>(s: &mut [T]) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut(s.as_mut_ptr() as *mut u8, std::mem::size_of_val(s)) }
+}
+
+/// Reads a `T` from the beginning of `bytes`.
+pub(crate) fn from_bytes<T: Pod>(bytes: &[u8]) -> T {
+    assert!(bytes.len() >= size_of::<T>());
+    unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+/// A growable array living in a shared mapping of `file`. The file holds exactly `cap` elements.
+///
+/// Without a file, the array lives in an anonymous private mapping instead, which can be bound to a NUMA node and
+/// backed by the huge pages.
+pub(crate) struct MmapVec<T> {
+    file: Option<File>,
+    ptr: NonNull<T>,
+    len: usize,
+    cap: usize,
+    // the NUMA node the anonymous mapping is bound to
+    numa_node: Option<usize>,
+    // whether the anonymous mapping asks for the huge pages, and the pages it gets
+    huge: bool,
+    huge_pages: HugePages,
+}
+
+// MmapVec owns the mapped elements just like a Vec owns its buffer.
+unsafe impl<T: Send> Send for MmapVec<T> {}
+unsafe impl<T: Sync> Sync for MmapVec<T> {}
+
+const HUGE_PAGE_SIZE: usize = 2 << 20;
+
+impl<T> MmapVec<T> {
+    /// Maps `file`, whose first `len` elements are valid.
+    fn open(file: File, len: usize) -> io::Result<Self> {
+        let cap = file.metadata()?.len() as usize / size_of::<T>();
+        if cap < len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the node file is truncated"));
+        }
+        let mut v = MmapVec {
+            file: Some(file),
+            ptr: NonNull::dangling(),
+            len,
+            cap: 0,
+            numa_node: None,
+            huge: false,
+            huge_pages: HugePages::Regular,
+        };
+        v.map(cap)?;
+        Ok(v)
+    }
+
+    /// Creates an empty array in the anonymous memory with the space for `cap` elements.
+    pub(crate) fn anonymous(cap: usize, numa_node: Option<usize>, huge: bool) -> io::Result<Self> {
+        let mut v = MmapVec {
+            file: None,
+            ptr: NonNull::dangling(),
+            len: 0,
+            cap: 0,
+            numa_node,
+            huge,
+            huge_pages: HugePages::Regular,
+        };
+        v.map(cap)?;
+        Ok(v)
+    }
+
+    /// Resizes the file to hold `cap` elements, and maps the whole file. An anonymous array is moved to a new mapping
+    /// of at least `cap` elements.
+    fn map(&mut self, cap: usize) -> io::Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return self.remap_anonymous(cap),
+        };
+        file.set_len((cap * size_of::<T>()) as u64)?;
+        let fd = std::os::unix::io::AsRawFd::as_raw_fd(file);
+        self.unmap();
+        if cap > 0 {
+            self.ptr = mmap(cap * size_of::<T>(), libc::MAP_SHARED, fd)?;
+        }
+        self.cap = cap;
+        Ok(())
+    }
+
+    /// Returns the length of the mapping holding `cap` elements. The anonymous memory asking for the huge pages is
+    /// mapped in whole huge pages.
+    fn mapped_len(&self, cap: usize) -> usize {
+        let len = cap * size_of::<T>();
+        if self.huge {
+            len.div_ceil(HUGE_PAGE_SIZE) * HUGE_PAGE_SIZE
+        } else {
+            len
+        }
+    }
+
+    fn remap_anonymous(&mut self, cap: usize) -> io::Result<()> {
+        assert!(cap >= self.len);
+        let (ptr, huge_pages) = if cap > 0 {
+            let len = self.mapped_len(cap);
+            let (p, huge_pages) = map_anonymous::<T>(len, self.huge)?;
+            if let Some(node) = self.numa_node {
+                if let Err(e) = bind(p.as_ptr() as *mut libc::c_void, len, node) {
+                    unsafe { libc::munmap(p.as_ptr() as *mut libc::c_void, len) };
+                    return Err(e);
+                }
+            }
+            unsafe { ptr::copy_nonoverlapping(self.ptr.as_ptr(), p.as_ptr(), self.len) };
+            (p, huge_pages)
+        } else {
+            (NonNull::dangling(), HugePages::Regular)
+        };
+        self.unmap();
+        self.ptr = ptr;
+        // the rest of the last huge page is usable as well
+        self.cap = self.mapped_len(cap) / size_of::<T>().max(1);
+        self.huge_pages = huge_pages;
+        Ok(())
+    }
+
+    /// Binds the anonymous array to the NUMA `node`, and moves the pages already allocated there.
+    pub(crate) fn set_numa_node(&mut self, node: usize) -> io::Result<()> {
+        assert!(self.file.is_none(), "only the anonymous memory can be bound to a NUMA node");
+        if self.cap > 0 {
+            bind(self.ptr.as_ptr() as *mut libc::c_void, self.mapped_len(self.cap), node)?;
+        }
+        self.numa_node = Some(node);
+        Ok(())
+    }
+
+    pub(crate) fn numa_node(&self) -> Option<usize> {
+        self.numa_node
+    }
+
+    pub(crate) fn huge_pages(&self) -> HugePages {
+        self.huge_pages
+    }
+
+    fn unmap(&mut self) {
+        if self.cap > 0 {
+            unsafe { libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, self.mapped_len(self.cap)) };
+            self.ptr = NonNull::dangling();
+            self.cap = 0;
+        }
+    }
+
+    pub(crate) fn push(&mut self, t: T) {
+        if self.len == self.cap {
+            let cap = std::cmp::max(self.cap * 2, 64);
+            self.map(cap).expect("failed to grow the mapped node file");
+        }
+        unsafe { ptr::write(self.ptr.as_ptr().add(self.len), t) };
+        self.len += 1;
+    }
+
+    /// Empties the array, keeping the mapping and the file size.
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.try_reserve(additional).expect("failed to grow the mapped node file");
+    }
+
+    /// Reserves the space for at least `additional` more elements, growing the file like `push` does.
+    pub(crate) fn try_reserve(&mut self, additional: usize) -> io::Result<()> {
+        if self.len + additional > self.cap {
+            self.map(std::cmp::max(self.len + additional, std::cmp::max(self.cap * 2, 64)))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the dirty pages back to the file.
+    fn sync(&self) -> io::Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        if self.cap > 0 {
+            let ret = unsafe {
+                libc::msync(self.ptr.as_ptr() as *mut libc::c_void, self.cap * size_of::<T>(), libc::MS_SYNC)
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        file.sync_all()
+    }
+}
+
+/// Maps `len` bytes of `fd`, or of the anonymous memory if `fd` is -1.
+fn mmap<T>(len: usize, flags: libc::c_int, fd: libc::c_int) -> io::Result<NonNull<T>> {
+    let p = unsafe { libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, flags, fd, 0) };
+    if p == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(NonNull::new(p as *mut T).unwrap())
+}
+
+/// Maps the first `len` bytes of `file` read-only.
+pub(crate) fn map_read_only(file: &File, len: usize) -> io::Result<NonNull<u8>> {
+    let fd = std::os::unix::io::AsRawFd::as_raw_fd(file);
+    let p = unsafe { libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, fd, 0) };
+    if p == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(NonNull::new(p as *mut u8).unwrap())
+}
+
+/// Maps `len` bytes of the anonymous memory, which is a multiple of the huge page size if `huge` is true. The huge
+/// pages are reserved explicitly if the system has them, otherwise the memory is aligned to the huge pages and advised
+/// to be backed by the transparent huge pages.
+fn map_anonymous<T>(len: usize, huge: bool) -> io::Result<(NonNull<T>, HugePages)> {
+    let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+    if !huge {
+        return Ok((mmap(len, flags, -1)?, HugePages::Regular));
+    }
+    #[cfg(target_os = "linux")]
+    {
+        if let Ok(p) = mmap(len, flags | libc::MAP_HUGETLB, -1) {
+            return Ok((p, HugePages::Explicit));
+        }
+
+        // map one more huge page, and trim both ends to align the mapping
+        let p = mmap::<u8>(len + HUGE_PAGE_SIZE, flags, -1)?.as_ptr();
+        let head = p.align_offset(HUGE_PAGE_SIZE);
+        unsafe {
+            if head > 0 {
+                libc::munmap(p as *mut libc::c_void, head);
+            }
+            libc::munmap(p.add(head + len) as *mut libc::c_void, HUGE_PAGE_SIZE - head);
+            let p = p.add(head);
+            let advised = libc::madvise(p as *mut libc::c_void, len, libc::MADV_HUGEPAGE) == 0;
+            let huge_pages = if advised { HugePages::Transparent } else { HugePages::Regular };
+            Ok((NonNull::new(p as *mut T).unwrap(), huge_pages))
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    Ok((mmap(len, flags, -1)?, HugePages::Regular))
+}
+
+/// Binds the memory to the NUMA `node` by `mbind`, moving the pages already allocated elsewhere.
+#[cfg(target_os = "linux")]
+fn bind(addr: *mut libc::c_void, len: usize, node: usize) -> io::Result<()> {
+    const MPOL_MF_MOVE: libc::c_ulong = 1 << 1;
+    let mut mask = vec![0 as libc::c_ulong; node / libc::c_ulong::BITS as usize + 1];
+    mask[node / libc::c_ulong::BITS as usize] |= 1 << (node % libc::c_ulong::BITS as usize);
+    // the kernel reads `maxnode - 1` bits of the mask
+    let maxnode = mask.len() * libc::c_ulong::BITS as usize + 1;
+    let ret =
+        unsafe { libc::syscall(libc::SYS_mbind, addr, len, libc::MPOL_BIND, mask.as_ptr(), maxnode, MPOL_MF_MOVE) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind(_: *mut libc::c_void, _: usize, _: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "NUMA binding is only supported on Linux"))
+}
+
+impl<T> Drop for MmapVec<T> {
+    fn drop(&mut self) {
+        self.unmap();
+    }
+}
+
+impl<T> Deref for MmapVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for MmapVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+const MAGIC: u64 = u64::from_le_bytes(*b"BTREERS1");
+
+/// The meta file describes the layout of the node files, and where the root is.
+/// All fields are stored as little-endian u64s.
+#[derive(Debug, PartialEq)]
+struct Meta {
+    magic: u64,
+    node_deg: u64,
+    internal_size: u64,
+    leaf_size: u64,
+    root_is_leaf: u64,
+    root: u64,
+    internal_len: u64,
+    leaf_len: u64,
+}
+
+impl Meta {
+    const FIELDS: usize = 8;
+
+    fn read(f: &mut File) -> io::Result<Self> {
+        let mut buf = [0u8; Meta::FIELDS * 8];
+        f.read_exact(&mut buf)?;
+        let field = |i: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&buf[i * 8..i * 8 + 8]);
+            u64::from_le_bytes(b)
+        };
+        Ok(Meta {
+            magic: field(0),
+            node_deg: field(1),
+            internal_size: field(2),
+            leaf_size: field(3),
+            root_is_leaf: field(4),
+            root: field(5),
+            internal_len: field(6),
+            leaf_len: field(7),
+        })
+    }
+
+    fn write(&self, f: &mut File) -> io::Result<()> {
+        let fields = [
+            self.magic,
+            self.node_deg,
+            self.internal_size,
+            self.leaf_size,
+            self.root_is_leaf,
+            self.root,
+            self.internal_len,
+            self.leaf_len,
+        ];
+        let mut buf = Vec::with_capacity(Meta::FIELDS * 8);
+        for x in fields.iter() {
+            buf.extend_from_slice(&x.to_le_bytes());
+        }
+        f.write_all(&buf)
+    }
+}
+
+impl<K, V> BTree<K, V> {
+    fn meta(&self) -> Meta {
+        let (root_is_leaf, root) = match self.root {
+            NodeIndex::Leaf(id) => (1, id),
+            NodeIndex::Internal(id) => (0, id),
+        };
+        Meta {
+            magic: MAGIC,
+            node_deg: NODE_DEG as u64,
+            internal_size: size_of::<InternalNode<K>>() as u64,
+            leaf_size: size_of::<LeafNode<K, V>>() as u64,
+            root_is_leaf,
+            root: root as u64,
+            internal_len: self.i.len() as u64,
+            leaf_len: self.l.len() as u64,
+        }
+    }
+
+    /// Writes the mapped nodes and the meta file back to the disk. It does nothing if the tree lives in the memory.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let meta = self.meta();
+        if let Some(f) = &mut self.meta_file {
+            if let (NodeBuf::Mapped(i), NodeBuf::Mapped(l)) = (&self.i, &self.l) {
+                i.sync()?;
+                l.sync()?;
+            }
+            f.seek(io::SeekFrom::Start(0))?;
+            meta.write(f)?;
+            f.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl<K: Pod + PartialOrd + PartialEq + Default, V: Pod + Default> BTree<K, V> {
+    /// Opens the tree stored in the directory `path`, creating an empty one if the directory does not exist.
+    ///
+    /// The nodes are mapped from the files in the directory instead of being loaded, so opening is instant and the
+    /// tree can be larger than the memory. The changes are written back by `flush`, which also runs when the tree is
+    /// dropped.
+    ///
+    /// # Safety
+    ///
+    /// The directory must be created by this function with the same `K` and `V`, and must not be modified by anyone
+    /// else while the tree is open.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)?;
+        let open = |name: &str| OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path.join(name));
+        let mut meta_file = open("meta")?;
+
+        let mut t;
+        if meta_file.metadata()?.len() == 0 {
+            t = BTree::new();
+            t.i = NodeBuf::Mapped(MmapVec::open(open("internal")?, 0)?);
+            t.l = NodeBuf::Mapped(MmapVec::open(open("leaf")?, 0)?);
+            // push the root node
+            t.l.push(LeafNode::new());
+        } else {
+            let meta = Meta::read(&mut meta_file)?;
+            let mut expected = BTree::<K, V>::new().meta();
+            expected.root_is_leaf = meta.root_is_leaf;
+            expected.root = meta.root;
+            expected.internal_len = meta.internal_len;
+            expected.leaf_len = meta.leaf_len;
+            if meta != expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the tree is not created by this version or with these key and value types",
+                ));
+            }
+
+            t = BTree::new();
+            t.i = NodeBuf::Mapped(MmapVec::open(open("internal")?, meta.internal_len as usize)?);
+            t.l = NodeBuf::Mapped(MmapVec::open(open("leaf")?, meta.leaf_len as usize)?);
+            t.root = if meta.root_is_leaf == 1 {
+                NodeIndex::Leaf(meta.root as usize)
+            } else {
+                NodeIndex::Internal(meta.root as usize)
+            };
+        }
+        t.meta_file = Some(meta_file);
+        t.collect_free_nodes();
+        t.flush()?;
+        Ok(t)
+    }
+
+    /// Rebuilds the free lists and counts the entries, which are not persisted. The nodes not reachable from the root
+    /// are free.
+    fn collect_free_nodes(&mut self) {
+        let mut used_i = vec![false; self.i.len()];
+        let mut used_l = vec![false; self.l.len()];
+        let mut stack = vec![self.root];
+        while let Some(cur) = stack.pop() {
+            match cur {
+                NodeIndex::Internal(id) => {
+                    used_i[id] = true;
+                    let node = &self.i[id];
+                    stack.extend(node.sons[0..node.cnt].iter());
+                }
+                NodeIndex::Leaf(id) => {
+                    used_l[id] = true;
+                    self.len += self.l[id].cnt;
+                }
+            }
+        }
+        self.free_i = (0..used_i.len()).filter(|&id| !used_i[id]).collect();
+        self.free_l = (0..used_l.len()).filter(|&id| !used_l[id]).collect();
+        for &id in self.free_l.iter() {
+            self.l[id].cnt = 0;
+        }
+    }
+}
+
+#[test]
+fn test_mmap_reopen() {
+    let dir = std::env::temp_dir().join(format!("btree-rs-test-mmap-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    {
+        let mut t = unsafe { BTree::<u64, u32>::open(&dir) }.unwrap();
+        for i in 0..10000u64 {
+            t.insert(&(i * 7 % 10007), &(i as u32));
+        }
+        t.flush().unwrap();
+        for i in 10000..20000u64 {
+            t.insert(&(i * 7 % 10007), &(i as u32));
+        }
+        // dropping flushes the tree
+    }
+
+    {
+        let mut t = unsafe { BTree::<u64, u32>::open(&dir) }.unwrap();
+        for i in 0..20000u64 {
+            let k = i * 7 % 10007;
+            // the keys wrapped around after 10007 insertions
+            let v = if i + 10007 < 20000 { i + 10007 } else { i };
+            assert_eq!(t.lookup(&k), Some(&(v as u32)));
+        }
+        assert_eq!(t.insert(&3, &42), Some(1430 + 10007));
+        for k in 0..8000u64 {
+            assert!(t.remove(&k).is_some());
+        }
+    }
+
+    {
+        // the nodes freed by the removals are found again
+        let t = unsafe { BTree::<u64, u32>::open(&dir) }.unwrap();
+        assert!(!t.free_l.is_empty());
+        assert_eq!(t.leaf_ids().len() + t.free_l.len(), t.l.len());
+        assert_eq!(t.len(), 10007 - 8000);
+        for i in 0..20000u64 {
+            let k = i * 7 % 10007;
+            let v = if i + 10007 < 20000 { i + 10007 } else { i };
+            let expected = if k < 8000 { None } else { Some(v as u32) };
+            assert_eq!(t.lookup(&k).copied(), expected);
+        }
+    }
+
+    // the types do not match
+    assert!(unsafe { BTree::<u32, u32>::open(&dir) }.is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,117 @@
+use crate::range::Range;
+use crate::BTree;
+
+/// BTreeMultiMap is a B+Tree which allows duplicate keys.
+///
+/// Every entry is stored under the key paired with a sequence number, so the duplicates of a key are adjacent in the
+/// tree and kept in the order of insertion.
+pub struct BTreeMultiMap<K, V> {
+    t: BTree<(K, u64), V>,
+    seq: u64, // the sequence number of the next insertion
+    len: usize,
+}
+
+/// An iterator over the values of one key, from the oldest to the newest.
+pub struct Values<'a, K, V> {
+    range: Range<'a, (K, u64), V>,
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        self.range.next().map(|(_, v)| v)
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for BTreeMultiMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTreeMultiMap<K, V> {
+    pub fn new() -> Self {
+        BTreeMultiMap {
+            t: BTree::new(),
+            seq: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of entries, counting every duplicate.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends the value after the existing values of `k`.
+    pub fn insert(&mut self, k: &K, v: &V) {
+        self.t.insert(&(*k, self.seq), v);
+        self.seq += 1;
+        self.len += 1;
+    }
+
+    /// Returns the values of `k`, from the oldest to the newest.
+    pub fn lookup(&self, k: &K) -> Values<'_, K, V> {
+        Values {
+            range: self.t.range((*k, 0)..=(*k, u64::MAX)),
+        }
+    }
+
+    /// Removes the oldest value of `k`, and returns it.
+    pub fn remove_one(&mut self, k: &K) -> Option<V> {
+        let mut c = self.t.cursor_mut();
+        c.seek(&(*k, 0));
+        match c.peek() {
+            Some((key, _)) if key.0 == *k => {
+                self.len -= 1;
+                c.remove_next().map(|(_, v)| v)
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes all values of `k`, and returns the number of removed values.
+    pub fn remove_all(&mut self, k: &K) -> usize {
+        let mut c = self.t.cursor_mut();
+        c.seek(&(*k, 0));
+        let mut cnt = 0;
+        while c.peek().is_some_and(|(key, _)| key.0 == *k) {
+            c.remove_next();
+            cnt += 1;
+        }
+        self.len -= cnt;
+        cnt
+    }
+}
+
+#[test]
+fn test_multimap() {
+    let mut m = BTreeMultiMap::<u32, u32>::new();
+    for i in 0..3000 {
+        m.insert(&(i % 100), &i);
+    }
+    assert_eq!(m.len(), 3000);
+
+    let values: Vec<u32> = m.lookup(&42).copied().collect();
+    assert_eq!(values, (0..30).map(|i| i * 100 + 42).collect::<Vec<_>>());
+    assert_eq!(m.lookup(&100).count(), 0);
+
+    assert_eq!(m.remove_one(&42), Some(42));
+    assert_eq!(m.remove_one(&42), Some(142));
+    assert_eq!(m.lookup(&42).next(), Some(&242));
+    assert_eq!(m.remove_all(&42), 28);
+    assert_eq!(m.remove_one(&42), None);
+    assert_eq!(m.lookup(&42).count(), 0);
+    assert_eq!(m.len(), 2970);
+
+    // the neighbours are untouched, and new values go after the old ones
+    assert_eq!(m.lookup(&41).count(), 30);
+    assert_eq!(m.lookup(&43).count(), 30);
+    m.insert(&43, &7);
+    assert_eq!(m.lookup(&43).last(), Some(&7));
+}
@@ -0,0 +1,659 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Bound, RangeBounds};
+
+use crate::entry::CompareAndSwapError;
+use crate::BTree;
+
+/// A version number. Every write to a `MvccBTree` creates a new version, the empty tree is version 0.
+pub type Version = u64;
+
+// marks the end of a version chain
+const NIL: usize = usize::MAX;
+
+/// One version of the value of a key. `value` is `None` if the key was removed in this version.
+struct Entry<V> {
+    version: Version,
+    value: Option<V>,
+    // the next older version of the same key, or NIL
+    prev: usize,
+}
+
+/// Which versions `MvccBTree::gc` keeps besides the latest version and the pinned ones, so that they stay readable by
+/// `lookup_at` and `range_at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    /// Keeps no other versions, the default.
+    Pinned,
+    /// Keeps the versions not older than the latest version minus `n`.
+    Window(u64),
+    /// Keeps all versions, `gc` reclaims nothing.
+    All,
+}
+
+/// The error of reading a version which may have been reclaimed by `gc`.
+#[derive(Debug, PartialEq)]
+pub struct PrunedVersion {
+    /// The version read.
+    pub version: Version,
+    /// The oldest version which is readable without pinning it.
+    pub oldest: Version,
+}
+
+impl fmt::Display for PrunedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the version {} is pruned, the oldest readable version is {}", self.version, self.oldest)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PrunedVersion {}
+
+/// A pinned version of the tree. Reads through a snapshot see the tree exactly as it was when the snapshot was taken.
+/// The snapshot must be returned with `MvccBTree::unpin`, otherwise the versions it can see are never garbage collected.
+#[must_use]
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    version: Version,
+}
+
+impl Snapshot {
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Starts a scan of the keys in `range` as seen by this snapshot.
+    pub fn scan<K: Copy, R: RangeBounds<K>>(&self, range: R) -> Scan<K> {
+        Scan {
+            version: self.version,
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+        }
+    }
+}
+
+/// A scan of a pinned version, which is read in batches by `MvccBTree::scan_next`. The scan does not borrow the tree,
+/// so the tree can be written between the batches, e.g. by other threads sharing it behind a lock.
+///
+/// Every batch sees the tree exactly as the snapshot does: the writes after the snapshot are invisible, and the
+/// versions it sees are kept by `gc`. The snapshot must stay pinned until the scan completes.
+pub struct Scan<K> {
+    version: Version,
+    // the keys left to scan
+    start: Bound<K>,
+    end: Bound<K>,
+}
+
+/// MvccBTree keeps multiple versions of every key, so that readers can pin a version and keep reading it while the tree
+/// is being modified.
+///
+/// The tree maps every key to the head of a chain of versions, sorted from the newest to the oldest one.
+/// The chains live in a separate buffer, and the versions which are not visible from the latest version or any pinned
+/// snapshot are reclaimed by `gc`.
+pub struct MvccBTree<K, V> {
+    index: BTree<K, usize>,
+    entries: Vec<Entry<V>>,
+    free: Vec<usize>, // the free slots in `entries`
+    version: Version,
+    pinned: BTreeMap<Version, usize>, // pinned version -> the number of snapshots pinning it
+    garbage: Vec<K>,                  // the keys which may have unreachable versions
+    retention: Retention,
+    // the versions older than it may have been reclaimed, unless they are pinned
+    oldest: Version,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for MvccBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> MvccBTree<K, V> {
+    pub fn new() -> Self {
+        MvccBTree {
+            index: BTree::new(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            version: 0,
+            pinned: BTreeMap::new(),
+            garbage: Vec::new(),
+            retention: Retention::Pinned,
+            oldest: 0,
+        }
+    }
+
+    /// Returns the latest version.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Sets which versions `gc` keeps. The versions reclaimed before are not readable again by enlarging the retention.
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    /// Returns the oldest version which stays readable by `lookup_at` and `range_at` after `gc`, besides the pinned
+    /// ones. The older versions are readable until the next `gc`.
+    pub fn oldest_version(&self) -> Version {
+        self.oldest.max(self.horizon())
+    }
+
+    /// Returns the oldest version kept by the retention.
+    fn horizon(&self) -> Version {
+        match self.retention {
+            Retention::Pinned => self.version,
+            Retention::Window(n) => self.version.saturating_sub(n),
+            Retention::All => 0,
+        }
+    }
+
+    /// Checks that `version` is readable.
+    ///
+    /// Panics if `version` is newer than the latest version.
+    fn check_version(&self, version: Version) -> Result<(), PrunedVersion> {
+        assert!(version <= self.version, "the version {} does not exist yet", version);
+        if version < self.oldest && !self.pinned.contains_key(&version) {
+            return Err(PrunedVersion {
+                version,
+                oldest: self.oldest_version(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Allocates a slot in `entries` for `e`, and returns its index.
+    fn alloc_entry(&mut self, e: Entry<V>) -> usize {
+        match self.free.pop() {
+            Some(id) => {
+                self.entries[id] = e;
+                id
+            }
+            None => {
+                self.entries.push(e);
+                self.entries.len() - 1
+            }
+        }
+    }
+
+    /// Records `value` as the new version of `k`. Returns the new version.
+    fn write(&mut self, k: &K, value: Option<V>) -> Version {
+        self.version += 1;
+        let id = self.alloc_entry(Entry {
+            version: self.version,
+            value,
+            prev: NIL,
+        });
+        if let Some(prev) = self.index.insert(k, &id) {
+            self.entries[id].prev = prev;
+            self.garbage.push(*k);
+        }
+        self.version
+    }
+
+    /// Inserts `v` as the new value of `k`. Returns the new version.
+    pub fn insert(&mut self, k: &K, v: &V) -> Version {
+        self.write(k, Some(*v))
+    }
+
+    /// Removes `k`. The older versions of `k` are still visible to the snapshots pinned before.
+    /// Returns the new version, or the latest version without writing one if `k` does not exist.
+    pub fn remove(&mut self, k: &K) -> Version {
+        match self.index.lookup(k) {
+            Some(&head) if self.entries[head].value.is_some() => self.write(k, None),
+            _ => self.version,
+        }
+    }
+
+    /// Writes `new` as the new version of `k` if its latest value is `expected`, finding `k` in a single descent.
+    /// None as `expected` means that `k` must not exist, and None as `new` removes `k`. Returns the new version, or the
+    /// latest value in the error if it is not the expected one. Removing a key which does not exist writes no version,
+    /// like `remove`.
+    pub fn compare_and_swap(
+        &mut self,
+        k: &K,
+        expected: Option<&V>,
+        new: Option<&V>,
+    ) -> Result<Version, CompareAndSwapError<V>>
+    where
+        V: PartialEq,
+    {
+        let mut c = self.index.cursor_mut();
+        c.seek(k);
+        let head = match c.peek() {
+            Some((key, head)) if key == k => Some(*head),
+            _ => None,
+        };
+        let entries = &mut self.entries;
+        let current = head.and_then(|id| entries[id].value);
+        if current.as_ref() != expected {
+            return Err(CompareAndSwapError {
+                current,
+                proposed: new.copied(),
+            });
+        }
+        if current.is_none() && new.is_none() {
+            return Ok(self.version);
+        }
+
+        self.version += 1;
+        let e = Entry {
+            version: self.version,
+            value: new.copied(),
+            prev: head.unwrap_or(NIL),
+        };
+        let id = match self.free.pop() {
+            Some(id) => {
+                entries[id] = e;
+                id
+            }
+            None => {
+                entries.push(e);
+                entries.len() - 1
+            }
+        };
+        match head {
+            Some(_) => {
+                *c.peek_mut().unwrap().1 = id;
+                self.garbage.push(*k);
+            }
+            None => c.insert_after(k, &id),
+        }
+        Ok(self.version)
+    }
+
+    /// Returns the value as of `version` in the chain from `head`.
+    fn value_at(&self, head: usize, version: Version) -> Option<&V> {
+        let mut cur = head;
+        while cur != NIL {
+            let e = &self.entries[cur];
+            if e.version <= version {
+                return e.value.as_ref();
+            }
+            cur = e.prev;
+        }
+        None
+    }
+
+    /// Looks up the value of `k` as of `version`.
+    fn lookup_version(&self, k: &K, version: Version) -> Option<&V> {
+        self.value_at(*self.index.lookup(k)?, version)
+    }
+
+    /// Looks up the value of `k` as of `version`, which is readable if it is not older than `oldest_version` or pinned.
+    ///
+    /// Panics if `version` is newer than the latest version.
+    pub fn lookup_at(&self, k: &K, version: Version) -> Result<Option<&V>, PrunedVersion> {
+        self.check_version(version)?;
+        Ok(self.lookup_version(k, version))
+    }
+
+    /// Returns an iterator over the entries whose keys are in the `range` as of `version`, which is readable if it is
+    /// not older than `oldest_version` or pinned. The iterator borrows the tree, use a snapshot and `scan_next` to read
+    /// between the writes.
+    ///
+    /// Panics if `version` is newer than the latest version.
+    pub fn range_at<R: RangeBounds<K>>(
+        &self,
+        range: R,
+        version: Version,
+    ) -> Result<impl Iterator<Item = (&K, &V)> + '_, PrunedVersion> {
+        self.check_version(version)?;
+        Ok(self.index.range(range).filter_map(move |(k, &head)| Some((k, self.value_at(head, version)?))))
+    }
+
+    /// Looks up the latest value of `k`.
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.lookup_version(k, self.version)
+    }
+
+    /// Looks up the value of `k` as seen by `snapshot`.
+    pub fn lookup_pinned(&self, snapshot: &Snapshot, k: &K) -> Option<&V> {
+        self.lookup_version(k, snapshot.version)
+    }
+
+    /// Returns the next at most `n` entries of the scan in the order of the keys, an empty batch when it completes.
+    pub fn scan_next(&self, scan: &mut Scan<K>, n: usize) -> Vec<(K, V)> {
+        let mut batch = Vec::new();
+        for (k, &head) in self.index.range((scan.start, scan.end)) {
+            if batch.len() == n {
+                break;
+            }
+            scan.start = Bound::Excluded(*k);
+            // skip the keys created after the snapshot or removed as of it
+            let mut cur = head;
+            while cur != NIL && self.entries[cur].version > scan.version {
+                cur = self.entries[cur].prev;
+            }
+            if let Some(v) = self.entries.get(cur).and_then(|e| e.value) {
+                batch.push((*k, v));
+            }
+        }
+        batch
+    }
+
+    /// Pins the latest version.
+    pub fn pin(&mut self) -> Snapshot {
+        *self.pinned.entry(self.version).or_insert(0) += 1;
+        Snapshot {
+            version: self.version,
+        }
+    }
+
+    /// Releases the `snapshot`, so that the versions only visible to it can be garbage collected.
+    pub fn unpin(&mut self, snapshot: Snapshot) {
+        let cnt = self
+            .pinned
+            .get_mut(&snapshot.version)
+            .expect("the snapshot is not pinned by this tree");
+        *cnt -= 1;
+        if *cnt == 0 {
+            self.pinned.remove(&snapshot.version);
+        }
+    }
+
+    /// Reclaims the versions which are neither the latest version of a key, nor visible to any pinned snapshot.
+    /// Returns the number of reclaimed versions.
+    pub fn gc(&mut self) -> usize {
+        if self.retention == Retention::All {
+            return 0;
+        }
+        let horizon = self.horizon();
+        self.oldest = self.oldest.max(horizon);
+        let mut reclaimed = 0;
+        let mut garbage = core::mem::take(&mut self.garbage);
+        garbage.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        garbage.dedup();
+        for k in garbage.drain(..) {
+            let head_id = *self.index.lookup(&k).unwrap();
+
+            // The chain is sorted from the newest version to the oldest one. An older entry is still reachable if some
+            // snapshot pinned a version in [entry.version, newer.version), i.e. the newer entry is invisible to it, or
+            // the retention keeps such a version.
+            let mut newer = head_id;
+            let mut cur = self.entries[head_id].prev;
+            while cur != NIL {
+                let prev = self.entries[cur].prev;
+                let reachable = self.entries[newer].version > horizon
+                    || self
+                        .pinned
+                        .range(self.entries[cur].version..self.entries[newer].version)
+                        .next()
+                        .is_some();
+                if reachable {
+                    newer = cur;
+                } else {
+                    self.entries[newer].prev = prev;
+                    self.free.push(cur);
+                    reclaimed += 1;
+                }
+                cur = prev;
+            }
+
+            let head = &self.entries[head_id];
+            if head.prev == NIL && head.value.is_none() && head.version <= horizon {
+                // a lone tombstone reads the same as no key at all
+                self.index.remove(&k);
+                self.free.push(head_id);
+                reclaimed += 1;
+            } else if head.prev != NIL || head.value.is_none() {
+                // the older versions or the tombstone are still retained, revisit them in the next round
+                self.garbage.push(k);
+            }
+        }
+        reclaimed
+    }
+}
+
+#[test]
+fn test_mvcc_pinned_reads() {
+    let mut t = MvccBTree::<u32, u32>::new();
+    assert_eq!(t.insert(&1, &10), 1);
+    assert_eq!(t.insert(&2, &20), 2);
+
+    let s2 = t.pin();
+    assert_eq!(s2.version(), 2);
+    t.insert(&1, &11);
+    t.remove(&2);
+    t.insert(&3, &30);
+
+    // the latest version
+    assert_eq!(t.version(), 5);
+    assert_eq!(t.lookup(&1), Some(&11));
+    assert_eq!(t.lookup(&2), None);
+    assert_eq!(t.lookup(&3), Some(&30));
+
+    // the pinned version
+    assert_eq!(t.lookup_pinned(&s2, &1), Some(&10));
+    assert_eq!(t.lookup_pinned(&s2, &2), Some(&20));
+    assert_eq!(t.lookup_pinned(&s2, &3), None);
+
+    // nothing is reclaimable while s2 is pinned
+    assert_eq!(t.gc(), 0);
+    assert_eq!(t.lookup_pinned(&s2, &1), Some(&10));
+    t.unpin(s2);
+    // the old versions of 1 and 2, and the tombstone of 2 left alone
+    assert_eq!(t.gc(), 3);
+    assert_eq!(t.lookup(&1), Some(&11));
+    assert_eq!(t.lookup(&2), None);
+}
+
+#[test]
+fn test_mvcc_gc_keeps_pinned_versions() {
+    let mut t = MvccBTree::<u32, u32>::new();
+    t.insert(&1, &1);
+    let s1 = t.pin();
+    t.insert(&1, &2);
+    t.insert(&1, &3);
+    let s3 = t.pin();
+    t.insert(&1, &4);
+    t.insert(&1, &5);
+
+    // only the version 2 and 4 are invisible
+    assert_eq!(t.gc(), 2);
+    assert_eq!(t.lookup_pinned(&s1, &1), Some(&1));
+    assert_eq!(t.lookup_pinned(&s3, &1), Some(&3));
+    assert_eq!(t.lookup(&1), Some(&5));
+
+    t.unpin(s1);
+    assert_eq!(t.gc(), 1);
+    assert_eq!(t.lookup_pinned(&s3, &1), Some(&3));
+    t.unpin(s3);
+    assert_eq!(t.gc(), 1);
+    assert_eq!(t.lookup(&1), Some(&5));
+
+    // the reclaimed slots are reused
+    t.insert(&1, &6);
+    assert_eq!(t.entries.len(), 5);
+}
+
+#[test]
+fn test_mvcc_scan_with_writers() {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    let t = Arc::new(Mutex::new(MvccBTree::<u32, u32>::new()));
+    for i in 0..10000 {
+        t.lock().unwrap().insert(&i, &i);
+    }
+    let s = t.lock().unwrap().pin();
+
+    let writer = {
+        let t = t.clone();
+        thread::spawn(move || {
+            for i in 0..20000 {
+                let mut t = t.lock().unwrap();
+                t.insert(&(i * 7 % 20000), &0);
+                t.remove(&(i * 13 % 10000));
+                if i % 1000 == 0 {
+                    t.gc();
+                }
+            }
+        })
+    };
+
+    // back up the keys in [100, 9000) while the writer runs
+    let mut scan = s.scan(100..9000);
+    let mut backup = Vec::new();
+    loop {
+        let batch = t.lock().unwrap().scan_next(&mut scan, 64);
+        if batch.is_empty() {
+            break;
+        }
+        backup.extend(batch);
+    }
+    writer.join().unwrap();
+    assert!(backup.into_iter().eq((100..9000).map(|i| (i, i))));
+
+    let mut t = t.lock().unwrap();
+    assert_eq!(t.scan_next(&mut s.scan(..), usize::MAX).len(), 10000);
+    t.unpin(s);
+}
+
+#[test]
+fn test_mvcc_compare_and_swap() {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    let t = Arc::new(Mutex::new(MvccBTree::<u32, u32>::new()));
+    assert_eq!(t.lock().unwrap().compare_and_swap(&1, None, Some(&0)), Ok(1));
+    let s = t.lock().unwrap().pin();
+
+    // optimistic increments, every thread reads the counter and retries the write if another thread won the race
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let t = t.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    let mut cur = *t.lock().unwrap().lookup(&1).unwrap();
+                    while let Err(e) = t.lock().unwrap().compare_and_swap(&1, Some(&cur), Some(&(cur + 1))) {
+                        cur = e.current.unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for w in workers {
+        w.join().unwrap();
+    }
+
+    let mut t = t.lock().unwrap();
+    assert_eq!(t.lookup(&1), Some(&4000));
+    assert_eq!(t.version(), 4001);
+    assert_eq!(t.lookup_pinned(&s, &1), Some(&0));
+    let e = t.compare_and_swap(&1, Some(&0), None).unwrap_err();
+    assert_eq!(e, CompareAndSwapError { current: Some(4000), proposed: None });
+    assert_eq!(t.compare_and_swap(&1, Some(&4000), None), Ok(4002));
+    assert_eq!(t.lookup(&1), None);
+    assert_eq!(t.lookup_pinned(&s, &1), Some(&0));
+    t.unpin(s);
+    // the removed key is dropped with its tombstone
+    assert_eq!(t.gc(), 4002);
+    assert_eq!(t.index.len(), 0);
+}
+
+#[test]
+fn test_mvcc_time_travel() {
+    let mut t = MvccBTree::<u32, u32>::new();
+    t.set_retention(Retention::Window(100));
+    // the version 10 * i + j writes the key j
+    for i in 0..100 {
+        for j in 0..10 {
+            if i % 3 == 2 && j % 2 == 1 {
+                t.remove(&j);
+            } else {
+                t.insert(&j, &(i * 100 + j));
+            }
+        }
+    }
+    assert_eq!(t.version(), 1000);
+    let expected = |j: u32, version: Version| {
+        // the last write to j as of the version
+        let i = (version as u32 - 1 - j) / 10;
+        if i % 3 == 2 && j % 2 == 1 {
+            None
+        } else {
+            Some(i * 100 + j)
+        }
+    };
+
+    let s = t.pin();
+    let old = t.oldest_version();
+    assert_eq!(old, 900);
+    // every key keeps the 10 versions in the window, and the one before them visible at the version 900
+    assert_eq!(t.gc(), 10 * (100 - 11));
+    for version in old..=1000 {
+        for j in 0..10 {
+            if version > j as Version {
+                assert_eq!(t.lookup_at(&j, version).unwrap().copied(), expected(j, version));
+            }
+        }
+        let entries: Vec<_> = t.range_at(2..7, version).unwrap().map(|(k, v)| (*k, *v)).collect();
+        let truth: Vec<_> = (2..7).filter_map(|j| Some((j, expected(j, version)?))).collect();
+        assert_eq!(entries, truth);
+    }
+    assert_eq!(t.lookup_at(&1, 899), Err(PrunedVersion { version: 899, oldest: 900 }));
+    assert_eq!(
+        t.range_at(.., 10).err().unwrap().to_string(),
+        "the version 10 is pruned, the oldest readable version is 900"
+    );
+
+    // the window moves with the writes, the pinned version stays readable
+    for j in 0..10 {
+        t.insert(&j, &j);
+    }
+    t.gc();
+    assert_eq!(t.oldest_version(), 910);
+    assert!(t.lookup_at(&1, 905).is_err());
+    assert_eq!(t.lookup_at(&1, 1000).unwrap(), Some(&9901));
+
+    // nothing is reclaimed if all versions are kept
+    t.unpin(s);
+    t.set_retention(Retention::All);
+    for j in 0..10 {
+        t.insert(&j, &j);
+    }
+    assert_eq!(t.gc(), 0);
+    assert_eq!(t.oldest_version(), 910);
+    assert_eq!(t.lookup_at(&1, 1000).unwrap(), Some(&9901));
+}
+
+#[test]
+fn test_mvcc_churn() {
+    let mut t = MvccBTree::<u32, u32>::new();
+    // removing a key which does not exist writes nothing
+    assert_eq!(t.remove(&1), 0);
+    assert_eq!(t.compare_and_swap(&1, None, None), Ok(0));
+    assert_eq!(t.index.len(), 0);
+
+    for retention in [Retention::Pinned, Retention::Window(100)].iter() {
+        t.set_retention(*retention);
+        for i in 0..10000 {
+            t.insert(&i, &i);
+            let version = t.remove(&i);
+            assert_eq!(t.remove(&i), version);
+            if i % 100 == 0 {
+                t.gc();
+            }
+            assert!(t.index.len() <= 400 && t.entries.len() <= 800);
+        }
+    }
+
+    // a pinned snapshot keeps the removed keys readable until it is released
+    t.set_retention(Retention::Pinned);
+    t.insert(&1, &10);
+    let s = t.pin();
+    t.remove(&1);
+    t.gc();
+    assert_eq!(t.lookup_pinned(&s, &1), Some(&10));
+    assert_eq!(t.lookup(&1), None);
+    t.unpin(s);
+    t.gc();
+    t.gc();
+    assert_eq!(t.index.len(), 0);
+    assert_eq!(t.entries.len() - t.free.len(), 0);
+}
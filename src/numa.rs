@@ -0,0 +1,82 @@
+use std::io;
+
+use crate::buf::NodeBuf;
+use crate::mmap::MmapVec;
+use crate::{BTree, LeafNode};
+
+/// Moves the nodes in the heap to the anonymous memory bound to the NUMA `node`, or rebinds the anonymous memory.
+fn bind_buf<T>(buf: &mut NodeBuf<T>, node: usize) -> io::Result<()> {
+    match buf {
+        NodeBuf::Heap(v) => {
+            let mut m = MmapVec::anonymous(v.capacity().max(64), Some(node), false)?;
+            for x in v.drain(..) {
+                m.push(x);
+            }
+            *buf = NodeBuf::Mapped(m);
+            Ok(())
+        }
+        NodeBuf::Mapped(m) => m.set_numa_node(node),
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// News a tree whose nodes are allocated on the NUMA `node`, so the threads running on the node access them without
+    /// going through the interconnect. Fails if the node does not exist or the platform is not Linux.
+    pub fn with_numa_node(node: usize) -> io::Result<Self> {
+        let mut t = BTree::new();
+        t.i = NodeBuf::Mapped(MmapVec::anonymous(1024, Some(node), false)?);
+        t.l = NodeBuf::Mapped(MmapVec::anonymous(1024, Some(node), false)?);
+        // push the root node
+        t.l.push(LeafNode::new());
+        Ok(t)
+    }
+
+    /// Binds the nodes to the NUMA `node`, and moves the existing nodes there. The trees mapped from files can not be
+    /// bound.
+    pub fn set_numa_node(&mut self, node: usize) -> io::Result<()> {
+        if self.meta_file.is_some() {
+            let msg = "a tree mapped from files can not be bound to a NUMA node";
+            return Err(io::Error::new(io::ErrorKind::Unsupported, msg));
+        }
+        bind_buf(&mut self.i, node)?;
+        bind_buf(&mut self.l, node)
+    }
+
+    /// Returns the NUMA node the nodes are bound to, None if they are not bound.
+    pub fn numa_node(&self) -> Option<usize> {
+        match &self.l {
+            NodeBuf::Mapped(m) => m.numa_node(),
+            NodeBuf::Heap(_) => None,
+        }
+    }
+}
+
+#[test]
+fn test_numa_node() {
+    let mut t = BTree::<u32, u32>::with_numa_node(0).unwrap();
+    assert_eq!(t.numa_node(), Some(0));
+    for i in 0..100000 {
+        t.insert(&i, &i);
+    }
+    assert!(t.range(..).map(|(k, v)| (*k, *v)).eq((0..100000).map(|i| (i, i))));
+
+    let mut t = BTree::<u32, u32>::new();
+    assert_eq!(t.numa_node(), None);
+    for i in 0..10000 {
+        t.insert(&i, &i);
+    }
+    t.set_numa_node(0).unwrap();
+    assert_eq!(t.numa_node(), Some(0));
+    for i in 10000..20000 {
+        t.insert(&i, &i);
+    }
+    assert!(t.range(..).map(|(k, v)| (*k, *v)).eq((0..20000).map(|i| (i, i))));
+
+    // a node which does not exist
+    assert!(BTree::<u32, u32>::with_numa_node(100000).is_err());
+    let dir = std::env::temp_dir().join(format!("btree-rs-test-numa-{}", std::process::id()));
+    let mut t = unsafe { BTree::<u32, u32>::open(&dir) }.unwrap();
+    assert!(t.set_numa_node(0).is_err());
+    drop(t);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,132 @@
+use alloc::boxed::Box;
+use core::panic::RefUnwindSafe;
+
+use crate::{BTree, NodeIndex};
+
+/// The id of a node in the events, the leaves and the internal nodes are numbered separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeId {
+    Leaf(usize),
+    Internal(usize),
+}
+
+impl From<NodeIndex> for NodeId {
+    fn from(n: NodeIndex) -> Self {
+        match n {
+            NodeIndex::Leaf(id) => NodeId::Leaf(id),
+            NodeIndex::Internal(id) => NodeId::Internal(id),
+        }
+    }
+}
+
+/// A change of the structure of a tree. The key ranges of the nodes are given by the largest keys of the left nodes,
+/// which separate them from their right siblings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeEvent<K> {
+    /// `node` is split, it keeps the keys not greater than `left_max`, and the rest move to the new node `right`.
+    Split { node: NodeId, right: NodeId, left_max: K },
+    /// The right sibling `from` is merged into `node`, and freed.
+    Merge { node: NodeId, from: NodeId },
+    /// The entries are moved between the siblings, `left` now keeps the keys not greater than `left_max`.
+    Moved { left: NodeId, right: NodeId, left_max: K },
+    /// The tree gets higher, the new internal node `root` has the old root as its only son.
+    RootGrown { root: NodeId },
+    /// The tree gets lower, the root `freed` with only one son is freed, and the son `root` is the new root.
+    RootCollapsed { root: NodeId, freed: NodeId },
+}
+
+/// NodeObserver is notified of the changes of the structure of a tree, so external systems can maintain the metadata
+/// derived from the nodes, e.g. the Bloom filters or the statistics of every leaf.
+///
+/// The events come right after the changes. The trees built in bulk, e.g. by `from_sorted_vec`, report nothing. An
+/// observer is `Send` and `Sync` just like the tree, so the tree with an observer is still shared between threads.
+pub trait NodeObserver<K>: Send + Sync + RefUnwindSafe {
+    fn on_event(&mut self, e: &NodeEvent<K>);
+}
+
+impl<K, F: FnMut(&NodeEvent<K>) + Send + Sync + RefUnwindSafe> NodeObserver<K> for F {
+    fn on_event(&mut self, e: &NodeEvent<K>) {
+        self(e)
+    }
+}
+
+impl<K, V> BTree<K, V> {
+    /// Sets the observer notified of the splits, the merges and the root changes, replacing the old one.
+    pub fn set_observer<O: NodeObserver<K> + 'static>(&mut self, o: O) {
+        self.observer = Some(Box::new(o));
+    }
+
+    /// Removes the observer, and returns it.
+    pub fn take_observer(&mut self) -> Option<Box<dyn NodeObserver<K>>> {
+        self.observer.take()
+    }
+}
+
+#[test]
+fn test_observer() {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    // replay the events on the sets of the live nodes
+    #[derive(Default)]
+    struct Live {
+        nodes: HashSet<NodeId>,
+        root: Option<NodeId>,
+        merges: usize,
+    }
+    let live = Arc::new(Mutex::new(Live::default()));
+    let mut t = BTree::<u32, u32>::new();
+    live.lock().unwrap().nodes.insert(NodeId::Leaf(0));
+    let sink = live.clone();
+    t.set_observer(move |e: &NodeEvent<u32>| {
+        let mut live = sink.lock().unwrap();
+        match *e {
+            NodeEvent::Split { node, right, .. } => {
+                assert!(live.nodes.contains(&node));
+                assert!(live.nodes.insert(right));
+            }
+            NodeEvent::Merge { node, from } => {
+                assert!(live.nodes.contains(&node));
+                assert!(live.nodes.remove(&from));
+                live.merges += 1;
+            }
+            NodeEvent::Moved { left, right, .. } => assert!(live.nodes.contains(&left) && live.nodes.contains(&right)),
+            NodeEvent::RootGrown { root } => {
+                assert!(live.nodes.insert(root));
+                live.root = Some(root);
+            }
+            NodeEvent::RootCollapsed { root, freed } => {
+                assert!(live.nodes.remove(&freed));
+                live.root = Some(root);
+            }
+        }
+    });
+
+    let check = |t: &BTree<u32, u32>| {
+        let live = live.lock().unwrap();
+        let mut nodes: HashSet<NodeId> = t.leaf_ids().into_iter().map(NodeId::Leaf).collect();
+        nodes.extend((0..t.i.len()).filter(|id| !t.free_i.contains(id)).map(NodeId::Internal));
+        assert_eq!(live.nodes, nodes);
+        assert_eq!(live.root.unwrap_or(NodeId::Leaf(0)), t.root.into());
+    };
+    for i in 0..20000 {
+        t.insert(&(i * 7919 % 20000), &i);
+    }
+    check(&t);
+    // the cursor splits the nodes as well
+    let mut c = t.cursor_mut();
+    c.seek_last();
+    for i in 20000..30000 {
+        c.insert_before(&i, &i);
+    }
+    check(&t);
+    for k in 100..30000 {
+        t.remove(&k);
+    }
+    check(&t);
+    assert!(live.lock().unwrap().merges > 0);
+
+    assert!(t.take_observer().is_some());
+    t.insert(&100000, &0);
+    assert!(t.take_observer().is_none());
+}
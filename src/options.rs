@@ -0,0 +1,143 @@
+#[cfg(all(feature = "std", unix))]
+use crate::buf::NodeBuf;
+#[cfg(all(feature = "std", unix))]
+use crate::mmap::MmapVec;
+use crate::search::Numeric;
+#[cfg(all(feature = "std", unix))]
+use crate::LeafNode;
+use crate::{nodes_for, BTree, NODE_DEG};
+
+/// Where the nodes of a tree are allocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arena {
+    /// The heap, the default.
+    Heap,
+    /// The 2MB huge pages, see `BTree::with_huge_pages`.
+    #[cfg(all(feature = "std", unix))]
+    HugePages,
+    /// The memory of a NUMA node, see `BTree::with_numa_node`.
+    #[cfg(all(feature = "std", unix))]
+    NumaNode(usize),
+}
+
+/// BTreeOptions collects the tunables of a tree fixed at construction, which is passed to `BTree::with_options`.
+///
+/// The degree of the nodes is not one of them, since the nodes are arrays of `NODE_DEG` entries fixed at compile time.
+#[derive(Clone, Copy)]
+pub struct BTreeOptions<K> {
+    capacity: Option<usize>,
+    split_at: usize,
+    interpolate: Option<fn(&K) -> f64>,
+    arena: Arena,
+}
+
+impl<K> Default for BTreeOptions<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> BTreeOptions<K> {
+    /// The options of `BTree::new`.
+    pub fn new() -> Self {
+        BTreeOptions { capacity: None, split_at: NODE_DEG / 2, interpolate: None, arena: Arena::Heap }
+    }
+
+    /// Reserves the space for `n` entries, so that inserting them does not grow the node buffers.
+    pub fn capacity(mut self, n: usize) -> Self {
+        self.capacity = Some(n);
+        self
+    }
+
+    /// Sets the share of the entries the left leaf keeps when a full leaf splits, 0.5 by default. A larger ratio packs
+    /// the leaves fuller when the keys are mostly inserted in the increasing order, and a smaller one when they are
+    /// mostly decreasing. It is clamped so that both leaves keep at least one entry.
+    pub fn split_ratio(mut self, ratio: f64) -> Self {
+        let left = (ratio * NODE_DEG as f64) as usize;
+        self.split_at = left.clamp(1, NODE_DEG - 1);
+        self
+    }
+
+    /// Sets where the nodes are allocated.
+    pub fn arena(mut self, arena: Arena) -> Self {
+        self.arena = arena;
+        self
+    }
+}
+
+impl<K: Numeric> BTreeOptions<K> {
+    /// Uses the interpolation search within the nodes instead of the binary search, see
+    /// `BTree::set_interpolation_search`.
+    pub fn interpolation_search(mut self, enabled: bool) -> Self {
+        self.interpolate = if enabled { Some(K::to_f64) } else { None };
+        self
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// News a tree with the `options`.
+    ///
+    /// Panics if the arena can not be allocated, e.g. the NUMA node does not exist. `with_huge_pages` and
+    /// `with_numa_node` report it as an error instead.
+    pub fn with_options(options: BTreeOptions<K>) -> Self {
+        let (internals, leaves) = options.capacity.map_or((1024, 1024), nodes_for);
+        let mut t = Self::with_node_capacity(internals, leaves);
+        t.split_at = options.split_at;
+        t.interpolate = options.interpolate;
+
+        match options.arena {
+            Arena::Heap => t,
+            #[cfg(all(feature = "std", unix))]
+            Arena::HugePages => t.in_arena(internals, leaves, None, true),
+            #[cfg(all(feature = "std", unix))]
+            Arena::NumaNode(node) => t.in_arena(internals, leaves, Some(node), false),
+        }
+    }
+
+    /// Moves the empty tree into the anonymous mappings with the space for `internals` internal nodes and `leaves`
+    /// leaves.
+    #[cfg(all(feature = "std", unix))]
+    fn in_arena(mut self, internals: usize, leaves: usize, numa_node: Option<usize>, huge: bool) -> Self {
+        let msg = "failed to allocate the arena";
+        self.i = NodeBuf::Mapped(MmapVec::anonymous(internals, numa_node, huge).expect(msg));
+        self.l = NodeBuf::Mapped(MmapVec::anonymous(leaves, numa_node, huge).expect(msg));
+        // push the root node
+        self.l.push(LeafNode::new());
+        self
+    }
+}
+
+#[test]
+fn test_btree_options() {
+    let mut t = BTree::<u64, u64>::with_options(BTreeOptions::new().capacity(100000));
+    let (i, l) = (t.i.as_ptr(), t.l.as_ptr());
+    for k in 0..100000 {
+        t.insert(&k, &k);
+    }
+    // the buffers are never reallocated
+    assert_eq!((i, l), (t.i.as_ptr(), t.l.as_ptr()));
+
+    // the increasing keys fill the leaves when the left one keeps almost all of the entries
+    let mut t = BTree::<u64, u64>::with_options(BTreeOptions::new().split_ratio(1.0).interpolation_search(true));
+    assert!(t.interpolation_search());
+    for i in 0..100000 {
+        t.insert(&i, &i);
+    }
+    assert_eq!(t.leaf_ids().len(), 100000usize.div_ceil(NODE_DEG - 1));
+    for i in 0..100000 {
+        assert_eq!(t.lookup(&i), Some(&i));
+    }
+    // the nearly empty leaves left by the splits are rebalanced by the removals
+    for i in (0..100000).step_by(2) {
+        assert_eq!(t.remove(&i), Some(i));
+    }
+    assert!(t.iter().map(|(k, _)| *k).eq((1..100000).step_by(2)));
+
+    #[cfg(all(feature = "std", unix))]
+    {
+        let mut t = BTree::<u64, u64>::with_options(BTreeOptions::new().arena(Arena::HugePages));
+        assert!(t.huge_pages() > crate::hugepage::HugePages::Regular);
+        t.insert(&1, &2);
+        assert_eq!(t.lookup(&1), Some(&2));
+    }
+}
@@ -0,0 +1,200 @@
+use alloc::vec::Vec;
+use core::ops::RangeBounds;
+
+use crate::range::Range;
+use crate::BTree;
+
+/// OverflowBTree is a B+Tree for large or variable-size values, e.g. `Vec<u8>`. The leaves only hold the handles of
+/// the values, and the values are stored out of line in a slab, so the nodes stay small and `T` needs not be `Copy`.
+///
+/// The slots of the removed values are reused by the next insertions. A value stays in its slot until it is removed,
+/// so a `Handle` of it keeps working across the other insertions and removals, which split and merge the nodes.
+pub struct OverflowBTree<K, T> {
+    t: BTree<K, usize>,
+    slots: Vec<Slot<T>>,
+    free: Vec<usize>, // the ids of the empty slots
+}
+
+struct Slot<T> {
+    // counts the values stored in the slot, so the handles of the removed values are told apart
+    generation: u64,
+    value: Option<T>,
+}
+
+/// A stable reference to a value of an `OverflowBTree`, which is revalidated on every access. It is invalidated when
+/// the value is removed, but not when it is updated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handle {
+    slot: usize,
+    generation: u64,
+}
+
+/// An iterator over the entries of an `OverflowBTree` within a range of keys.
+pub struct OverflowRange<'a, K, T> {
+    range: Range<'a, K, usize>,
+    slots: &'a [Slot<T>],
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, T> Iterator for OverflowRange<'a, K, T> {
+    type Item = (&'a K, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, h) = self.range.next()?;
+        Some((k, self.slots[*h].value.as_ref().unwrap()))
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, T> Default for OverflowBTree<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, T> OverflowBTree<K, T> {
+    pub fn new() -> Self {
+        OverflowBTree {
+            t: BTree::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&T> {
+        self.t.lookup(k).map(|&h| self.slots[h].value.as_ref().unwrap())
+    }
+
+    /// Returns the value of `k`, which can be updated in place.
+    pub fn lookup_mut(&mut self, k: &K) -> Option<&mut T> {
+        let h = *self.t.lookup(k)?;
+        self.slots[h].value.as_mut()
+    }
+
+    /// Returns the handle of the value of `k`.
+    pub fn handle(&self, k: &K) -> Option<Handle> {
+        let slot = *self.t.lookup(k)?;
+        Some(Handle {
+            slot,
+            generation: self.slots[slot].generation,
+        })
+    }
+
+    /// Returns the value of the handle, None if the value is removed.
+    pub fn get(&self, h: Handle) -> Option<&T> {
+        let s = &self.slots[h.slot];
+        if s.generation == h.generation {
+            s.value.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value of the handle mutably, None if the value is removed.
+    pub fn get_mut(&mut self, h: Handle) -> Option<&mut T> {
+        let s = &mut self.slots[h.slot];
+        if s.generation == h.generation {
+            s.value.as_mut()
+        } else {
+            None
+        }
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value. Updating keeps the handle.
+    pub fn insert(&mut self, k: &K, v: T) -> Option<T> {
+        if let Some(&h) = self.t.lookup(k) {
+            return self.slots[h].value.replace(v);
+        }
+        let h = match self.free.pop() {
+            Some(h) => {
+                let s = &mut self.slots[h];
+                s.generation += 1;
+                s.value = Some(v);
+                h
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(v),
+                });
+                self.slots.len() - 1
+            }
+        };
+        self.t.insert(k, &h);
+        None
+    }
+
+    /// Removes `k`, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<T> {
+        let h = self.t.remove(k)?;
+        self.free.push(h);
+        self.slots[h].value.take()
+    }
+
+    /// Returns an iterator over the entries whose keys are in the `range`.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> OverflowRange<'_, K, T> {
+        OverflowRange {
+            range: self.t.range(range),
+            slots: &self.slots,
+        }
+    }
+}
+
+#[test]
+fn test_overflow() {
+    use alloc::vec;
+
+    let mut t = OverflowBTree::<u32, Vec<u8>>::new();
+    for i in 0..1000u32 {
+        assert_eq!(t.insert(&i, vec![i as u8; i as usize]), None);
+    }
+    assert_eq!(t.len(), 1000);
+    assert_eq!(t.lookup(&10), Some(&vec![10; 10]));
+    assert_eq!(t.insert(&10, vec![1]), Some(vec![10; 10]));
+    t.lookup_mut(&10).unwrap().push(2);
+    assert_eq!(t.lookup(&10), Some(&vec![1, 2]));
+
+    for i in 0..500 {
+        assert_eq!(t.remove(&i).map(|v| v.len()), Some(if i == 10 { 2 } else { i as usize }));
+    }
+    assert_eq!(t.remove(&0), None);
+    // the freed slots are reused
+    for i in 1000..1500u32 {
+        t.insert(&i, vec![0; 3]);
+    }
+    assert_eq!(t.slots.len(), 1000);
+    assert_eq!(t.range(998..1001).map(|(k, v)| (*k, v.len())).collect::<Vec<_>>(), [(998, 998), (999, 999), (1000, 3)]);
+}
+
+#[test]
+fn test_overflow_handle() {
+    let mut t = OverflowBTree::<u32, u64>::new();
+    t.insert(&0, 42);
+    let h = t.handle(&0).unwrap();
+    assert_eq!(t.handle(&1), None);
+
+    // the handle survives the splits and merges
+    for i in 1..10000 {
+        t.insert(&i, i as u64);
+    }
+    *t.get_mut(h).unwrap() += 1;
+    for i in 1..10000 {
+        t.remove(&i);
+    }
+    t.insert(&0, 7);
+    assert_eq!(t.get(h), Some(&7));
+
+    // the slot is reused by another key, but the handle does not see its value
+    t.remove(&0);
+    assert_eq!(t.get(h), None);
+    t.insert(&5, 5);
+    assert_eq!(t.handle(&5).unwrap().slot, h.slot);
+    assert_eq!(t.get(h), None);
+    assert_eq!(t.get_mut(h), None);
+}
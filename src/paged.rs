@@ -0,0 +1,1290 @@
+use std::cmp::Ordering;
+use std::fs::OpenOptions;
+use std::io;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+use crate::batch::WriteBatch;
+use crate::mmap::{bytes_of, from_bytes, Pod};
+use crate::pager::{PageHeader, Pager, MIN_POOL_SIZE, PAGE_HEADER_SIZE};
+use crate::wal::Record;
+
+pub use crate::pager::{CorruptedPage, PageCipher, PoolStats};
+use crate::{lower_bound, InternalNode, LeafNode, NodeIndex, NODE_DEG};
+
+const KIND_META: u32 = 0;
+const KIND_LEAF: u32 = 1;
+const KIND_INTERNAL: u32 = 2;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"BTREEPG2");
+
+/// The number of leaves a range scan reads ahead by default.
+const DEFAULT_READ_AHEAD: usize = 8;
+
+/// The content of the page 0.
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+struct PagedMeta {
+    magic: u64,
+    page_size: u64,
+    internal_size: u64,
+    leaf_size: u64,
+    root_is_leaf: u64,
+    root: u64,
+}
+
+/// Returns the page size for the nodes of `K` and `V`, which is the smallest multiple of 4kB holding any node.
+fn page_size<K, V>() -> usize {
+    let node_size = *[size_of::<InternalNode<K>>(), size_of::<LeafNode<K, V>>(), size_of::<PagedMeta>()]
+        .iter()
+        .max()
+        .unwrap();
+    (PAGE_HEADER_SIZE + node_size).div_ceil(4096) * 4096
+}
+
+/// PagedBTree is a B+Tree stored in a file, one node per page. Only the pages cached in the buffer pool stay in the
+/// memory, so the tree can be much larger than the memory.
+///
+/// The pages are the in-memory nodes as they are, `NodeIndex` of the paged tree holds page ids instead of the indexes
+/// to the node buffers. Removing keys does not merge the nodes.
+///
+/// Every insertion and removal is appended to the write-ahead log `<path>.wal` before it is applied, and the dirty pages
+/// reach the data file only at checkpoints, so that a crash never leaves a half split tree behind. The operations after
+/// the last checkpoint are replayed when the tree is opened again, splits included. The log is not synced on every
+/// operation though, `flush` makes a checkpoint when the operations must survive a power failure.
+///
+/// Every page carries a checksum, a page read from the file with a wrong checksum fails the operation with an
+/// `io::Error` wrapping `CorruptedPage`. The pages and the log can also be encrypted, see `open_encrypted`.
+pub struct PagedBTree<K, V> {
+    pager: Pager,
+    root: NodeIndex,
+    read_ahead: usize,
+    _marker: PhantomData<(K, V)>,
+}
+
+/// PagedRange is an iterator over the entries of a `PagedBTree` within a range of keys, from the smallest key to the
+/// largest. Reading a page may fail, the scan ends after yielding the error.
+pub struct PagedRange<'a, K, V> {
+    t: &'a mut PagedBTree<K, V>,
+    // the start of the range, taken by the first `next`
+    start: Option<Bound<K>>,
+    end: Bound<K>,
+    // the internal nodes from the root to the current leaf, as (page, the index of the son on the path)
+    stack: Vec<(usize, usize)>,
+    // the pinned frame of the current leaf, None if the scan ends
+    leaf: Option<usize>,
+    pos: usize,
+    // the sons of the father node of the current leaf before the index are read ahead, as (father page, index)
+    ahead: (usize, usize),
+}
+
+impl<K, V> Drop for PagedBTree<K, V> {
+    fn drop(&mut self) {
+        // there is no way to report the error here, call `flush` explicitly to check it
+        let _ = self.pager.checkpoint();
+    }
+}
+
+impl<K: Pod + PartialOrd + Default, V: Pod + Default> PagedBTree<K, V> {
+    /// Opens the tree stored in the file `path`, creating an empty one if the file does not exist.
+    /// The buffer pool caches at most `pool_size` pages, which must be at least 4.
+    ///
+    /// # Safety
+    ///
+    /// The file must be created by this function with the same `K` and `V`, and must not be modified by anyone else
+    /// while the tree is open.
+    pub unsafe fn open<P: AsRef<Path>>(path: P, pool_size: usize) -> io::Result<Self> {
+        Self::open_with(path.as_ref(), pool_size, None)
+    }
+
+    /// The same as `open`, but the pages and the log are encrypted by `cipher`. A tree must always be opened with the
+    /// same key, and a tree created by `open` can not be encrypted later.
+    ///
+    /// # Safety
+    ///
+    /// See `open`.
+    pub unsafe fn open_encrypted<P: AsRef<Path>>(
+        path: P,
+        pool_size: usize,
+        cipher: Box<dyn PageCipher>,
+    ) -> io::Result<Self> {
+        Self::open_with(path.as_ref(), pool_size, Some(cipher))
+    }
+
+    unsafe fn open_with(path: &Path, pool_size: usize, cipher: Option<Box<dyn PageCipher>>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut wal_path = path.as_os_str().to_owned();
+        wal_path.push(".wal");
+        let (pager, pending) = Pager::open(file, wal_path, page_size::<K, V>(), pool_size, cipher)?;
+        let mut t = PagedBTree {
+            pager,
+            root: NodeIndex::Leaf(1),
+            read_ahead: DEFAULT_READ_AHEAD,
+            _marker: PhantomData,
+        };
+
+        if t.pager.page_cnt() == 0 {
+            let (_, meta) = t.alloc_node(KIND_META);
+            let (_, root) = t.alloc_node(KIND_LEAF);
+            *t.node_mut(root) = LeafNode::<K, V>::new();
+            t.pager.unpin(root);
+            t.pager.unpin(meta);
+            t.write_meta()?;
+        } else {
+            let frame = t.pager.pin(0)?;
+            let meta: &PagedMeta = t.node(frame);
+            let mut expected = t.meta();
+            expected.root_is_leaf = meta.root_is_leaf;
+            expected.root = meta.root;
+            let valid = meta == &expected;
+            t.root = if meta.root_is_leaf == 1 {
+                NodeIndex::Leaf(meta.root as usize)
+            } else {
+                NodeIndex::Internal(meta.root as usize)
+            };
+            t.pager.unpin(frame);
+            if !valid {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the tree is not created by this version or with these key and value types",
+                ));
+            }
+        }
+
+        // replay the operations after the last checkpoint, the log keeps them until the next checkpoint
+        for r in pending {
+            t.replay(r)?;
+        }
+        t.pager.checkpoint()?;
+        Ok(t)
+    }
+
+    fn replay(&mut self, r: Record) -> io::Result<()> {
+        match r {
+            Record::Insert(kv) if kv.len() == size_of::<K>() + size_of::<V>() => {
+                let (k, v) = kv.split_at(size_of::<K>());
+                self.apply_insert(&from_bytes(k), &from_bytes(v))?;
+            }
+            Record::Remove(k) if k.len() == size_of::<K>() => {
+                self.apply_remove(&from_bytes(&k))?;
+            }
+            Record::Batch(records) => {
+                for r in records {
+                    self.replay(r)?;
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the log record is not an insertion or a removal of these key and value types",
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn meta(&self) -> PagedMeta {
+        let (root_is_leaf, root) = match self.root {
+            NodeIndex::Leaf(id) => (1, id),
+            NodeIndex::Internal(id) => (0, id),
+        };
+        PagedMeta {
+            magic: MAGIC,
+            page_size: page_size::<K, V>() as u64,
+            internal_size: size_of::<InternalNode<K>>() as u64,
+            leaf_size: size_of::<LeafNode<K, V>>() as u64,
+            root_is_leaf,
+            root: root as u64,
+        }
+    }
+
+    fn write_meta(&mut self) -> io::Result<()> {
+        let meta = self.meta();
+        let frame = self.pager.pin(0)?;
+        *self.node_mut(frame) = meta;
+        self.pager.unpin(frame);
+        Ok(())
+    }
+
+    /// Returns the node (or the meta data) in the pinned page.
+    fn node<T>(&self, frame: usize) -> &T {
+        let data = &self.pager.data(frame)[PAGE_HEADER_SIZE..];
+        debug_assert!(size_of::<T>() <= data.len());
+        // pages are aligned to 16 bytes, and every bit pattern of the node is valid, see `open`
+        unsafe { &*(data.as_ptr() as *const T) }
+    }
+
+    /// Returns the mutable node (or the meta data) in the pinned page.
+    fn node_mut<T>(&mut self, frame: usize) -> &mut T {
+        let data = &mut self.pager.data_mut(frame)[PAGE_HEADER_SIZE..];
+        debug_assert!(size_of::<T>() <= data.len());
+        unsafe { &mut *(data.as_mut_ptr() as *mut T) }
+    }
+
+    /// Allocates a page for a node of `kind`, and returns the page id and the pinned frame.
+    fn alloc_node(&mut self, kind: u32) -> (usize, usize) {
+        let (page, frame) = self.pager.alloc();
+        let data = self.pager.data_mut(frame);
+        let mut header = PageHeader::read(data);
+        header.kind = kind;
+        header.write(data);
+        (page as usize, frame)
+    }
+
+    /// Makes the new root, which must be the internal node. `first` is the first child of the new root.
+    /// Returns the pinned frame of the new root.
+    fn make_new_root(&mut self, first: NodeIndex) -> io::Result<usize> {
+        let (page, frame) = self.alloc_node(KIND_INTERNAL);
+        *self.node_mut(frame) = InternalNode::<K>::new(first);
+        self.root = NodeIndex::Internal(page);
+        self.write_meta()?;
+        Ok(frame)
+    }
+
+    /// Makes a checkpoint if the buffer pool is full of dirty pages. It must be called between operations, when the
+    /// tree is consistent.
+    fn maybe_checkpoint(&mut self) -> io::Result<()> {
+        if self.pager.over_budget() {
+            self.pager.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Inserts the key value pair, and returns the old value if the key already exists.
+    pub fn insert(&mut self, k: &K, v: &V) -> io::Result<Option<V>> {
+        let mut kv = bytes_of(k).to_vec();
+        kv.extend_from_slice(bytes_of(v));
+        self.pager.log(&Record::Insert(kv))?;
+        let ret = self.apply_insert(k, v)?;
+        self.maybe_checkpoint()?;
+        Ok(ret)
+    }
+
+    /// Removes the key, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> io::Result<Option<V>> {
+        self.pager.log(&Record::Remove(bytes_of(k).to_vec()))?;
+        let ret = self.apply_remove(k)?;
+        self.maybe_checkpoint()?;
+        Ok(ret)
+    }
+
+    /// Applies the operations of the batch atomically: they are logged as one record, so either all or none of them
+    /// are replayed after a crash.
+    pub fn apply_batch(&mut self, batch: &WriteBatch<K, V>) -> io::Result<()> {
+        let ops = batch.sorted();
+        let records = ops
+            .iter()
+            .map(|(k, v)| match v {
+                Some(v) => {
+                    let mut kv = bytes_of(k).to_vec();
+                    kv.extend_from_slice(bytes_of(v));
+                    Record::Insert(kv)
+                }
+                None => Record::Remove(bytes_of(k).to_vec()),
+            })
+            .collect();
+        self.pager.log(&Record::Batch(records))?;
+        for (k, v) in ops.iter() {
+            match v {
+                Some(v) => self.apply_insert(k, v)?,
+                None => self.apply_remove(k)?,
+            };
+        }
+        self.maybe_checkpoint()
+    }
+
+    /// Ingests the entries sorted by the keys, e.g. read from an export far larger than the memory, merging them into
+    /// the tree. Returns the number of ingested entries.
+    ///
+    /// The entries are not logged: the keys beyond the tree are appended to the rightmost leaf, filling the leaves up,
+    /// the others are inserted as `insert` does, and the dirty pages are checkpointed whenever they fill the buffer
+    /// pool, so the memory stays bounded by the pool. After a crash the tree holds the entries up to the last
+    /// checkpoint, i.e. a prefix of the input, and the ingestion can resume after its largest key.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if the keys are not strictly increasing, the entries before are ingested.
+    pub fn ingest<I: IntoIterator<Item = (K, V)>>(&mut self, entries: I) -> io::Result<usize> {
+        // the logged operations must reach the data file before the unlogged ones
+        self.pager.checkpoint()?;
+        let mut n = 0;
+        let mut last: Option<K> = None;
+        for (k, v) in entries {
+            if let Some(l) = last {
+                if l.partial_cmp(&k) != Some(Ordering::Less) {
+                    self.pager.checkpoint()?;
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "the keys are not sorted"));
+                }
+            }
+            if !self.apply_append(&k, &v)? {
+                self.apply_insert(&k, &v)?;
+            }
+            last = Some(k);
+            n += 1;
+            self.maybe_checkpoint()?;
+        }
+        self.pager.checkpoint()?;
+        Ok(n)
+    }
+
+    /// Appends the key value pair to the rightmost leaf without logging it, if `k` is greater than all of the keys
+    /// there. A full node on the rightmost path keeps all of its entries, and the new entry starts the right node.
+    /// Returns false if `k` can not be appended.
+    fn apply_append(&mut self, k: &K, v: &V) -> io::Result<bool> {
+        // the pinned internal nodes on the rightmost path, as (page, frame)
+        let mut path = Vec::new();
+        let mut cur = self.root;
+        while let NodeIndex::Internal(page) = cur {
+            let frame = self.pager.pin(page as u64)?;
+            let node = self.node::<InternalNode<K>>(frame);
+            cur = node.sons[node.cnt - 1];
+            path.push((page, frame));
+        }
+        let page = match cur {
+            NodeIndex::Leaf(page) => page,
+            NodeIndex::Internal(_) => unreachable!(),
+        };
+        let frame = self.pager.pin(page as u64)?;
+
+        let leaf = self.node::<LeafNode<K, V>>(frame);
+        // an empty leaf other than the root does not tell the largest key
+        let appendable = match leaf.cnt {
+            0 => path.is_empty(),
+            cnt => leaf.keys[cnt - 1].partial_cmp(k) == Some(Ordering::Less),
+        };
+        if !appendable || !leaf.full() {
+            if appendable {
+                let leaf = self.node_mut::<LeafNode<K, V>>(frame);
+                leaf.keys[leaf.cnt] = *k;
+                leaf.values[leaf.cnt] = *v;
+                leaf.cnt += 1;
+            }
+            self.pager.unpin(frame);
+            for (_, frame) in path {
+                self.pager.unpin(frame);
+            }
+            return Ok(appendable);
+        }
+
+        // the max key of the full leaf is also the max key of every full node above it
+        let left_max = leaf.keys[leaf.cnt - 1];
+        self.pager.unpin(frame);
+        let (right_page, right_frame) = self.alloc_node(KIND_LEAF);
+        let right = self.node_mut::<LeafNode<K, V>>(right_frame);
+        *right = LeafNode::new();
+        right.keys[0] = *k;
+        right.values[0] = *v;
+        right.cnt = 1;
+        self.pager.unpin(right_frame);
+
+        let mut node = NodeIndex::Leaf(page);
+        let mut right = NodeIndex::Leaf(right_page);
+        loop {
+            match path.pop() {
+                Some((_, fa)) if !self.node::<InternalNode<K>>(fa).full() => {
+                    let fa_node = self.node_mut::<InternalNode<K>>(fa);
+                    let pos = fa_node.cnt;
+                    fa_node.insert(pos, &left_max, right);
+                    self.pager.unpin(fa);
+                    break;
+                }
+                Some((fa_page, fa)) => {
+                    self.pager.unpin(fa);
+                    let (right_page, right_frame) = self.alloc_node(KIND_INTERNAL);
+                    *self.node_mut(right_frame) = InternalNode::<K>::new(right);
+                    self.pager.unpin(right_frame);
+                    node = NodeIndex::Internal(fa_page);
+                    right = NodeIndex::Internal(right_page);
+                }
+                None => {
+                    let root = self.make_new_root(node)?;
+                    self.node_mut::<InternalNode<K>>(root).insert(1, &left_max, right);
+                    self.pager.unpin(root);
+                    break;
+                }
+            }
+        }
+        for (_, frame) in path {
+            self.pager.unpin(frame);
+        }
+        Ok(true)
+    }
+
+    /// Inserts the key value pair without logging it.
+    /// It mirrors `BTree::insert`, except that it pins the father and the current node while walking down.
+    fn apply_insert(&mut self, k: &K, v: &V) -> io::Result<Option<V>> {
+        let mut cur = self.root;
+        let mut father: Option<usize> = None; // the pinned frame of the father node of the current node
+        let mut father_son_index: usize = 0; // the current node `father_son_index`-th son of the father node
+        loop {
+            match cur {
+                NodeIndex::Internal(page) => {
+                    let mut frame = self.pager.pin(page as u64)?;
+                    if self.node::<InternalNode<K>>(frame).full() {
+                        let (left_max, right) = self.node_mut::<InternalNode<K>>(frame).split();
+                        let (right_page, right_frame) = self.alloc_node(KIND_INTERNAL);
+                        *self.node_mut(right_frame) = right;
+
+                        // make a new root node if the current node is the root
+                        if father.is_none() {
+                            father = Some(self.make_new_root(NodeIndex::Internal(page))?);
+                            father_son_index = 0;
+                        }
+
+                        // insert the right to the father node
+                        let fa = self.node_mut::<InternalNode<K>>(father.unwrap());
+                        fa.insert(father_son_index + 1, &left_max, NodeIndex::Internal(right_page));
+
+                        // insert to the right node
+                        if &left_max < k {
+                            self.pager.unpin(frame);
+                            frame = right_frame;
+                        } else {
+                            self.pager.unpin(right_frame);
+                        }
+                    }
+
+                    if let Some(fa) = father {
+                        self.pager.unpin(fa);
+                    }
+                    father = Some(frame);
+                    let tmp = self.node::<InternalNode<K>>(frame).lookup(k);
+                    father_son_index = tmp.0;
+                    cur = tmp.1;
+                }
+                NodeIndex::Leaf(page) => {
+                    let mut frame = self.pager.pin(page as u64)?;
+                    if self.node::<LeafNode<K, V>>(frame).full() {
+                        // split
+                        let (left_max, right) = self.node_mut::<LeafNode<K, V>>(frame).split_at(NODE_DEG / 2);
+                        let (right_page, right_frame) = self.alloc_node(KIND_LEAF);
+                        *self.node_mut(right_frame) = right;
+
+                        // make a new root node if the current node is the root
+                        if father.is_none() {
+                            father = Some(self.make_new_root(NodeIndex::Leaf(page))?);
+                            father_son_index = 0;
+                        }
+
+                        // insert the right to the father node
+                        let fa = self.node_mut::<InternalNode<K>>(father.unwrap());
+                        fa.insert(father_son_index + 1, &left_max, NodeIndex::Leaf(right_page));
+
+                        // insert to the right node
+                        if &left_max < k {
+                            self.pager.unpin(frame);
+                            frame = right_frame;
+                        } else {
+                            self.pager.unpin(right_frame);
+                        }
+                    }
+
+                    if let Some(fa) = father {
+                        self.pager.unpin(fa);
+                    }
+                    let ret = self.node_mut::<LeafNode<K, V>>(frame).insert(k, v);
+                    self.pager.unpin(frame);
+                    return Ok(ret);
+                }
+            }
+        }
+    }
+
+    /// Removes the key without logging it. The leaf is left as it is even if it becomes empty.
+    fn apply_remove(&mut self, k: &K) -> io::Result<Option<V>> {
+        let mut cur = self.root;
+        loop {
+            match cur {
+                NodeIndex::Internal(page) => {
+                    let frame = self.pager.pin(page as u64)?;
+                    cur = self.node::<InternalNode<K>>(frame).lookup(k).1;
+                    self.pager.unpin(frame);
+                }
+                NodeIndex::Leaf(page) => {
+                    let frame = self.pager.pin(page as u64)?;
+                    let mut ret = None;
+                    // avoid dirtying the page if the key does not exist
+                    if self.node::<LeafNode<K, V>>(frame).lookup(k).is_some() {
+                        ret = self.node_mut::<LeafNode<K, V>>(frame).remove(k);
+                    }
+                    self.pager.unpin(frame);
+                    return Ok(ret);
+                }
+            }
+        }
+    }
+
+    pub fn lookup(&mut self, k: &K) -> io::Result<Option<V>> {
+        let mut cur = self.root;
+        loop {
+            match cur {
+                NodeIndex::Internal(page) => {
+                    let frame = self.pager.pin(page as u64)?;
+                    cur = self.node::<InternalNode<K>>(frame).lookup(k).1;
+                    self.pager.unpin(frame);
+                }
+                NodeIndex::Leaf(page) => {
+                    let frame = self.pager.pin(page as u64)?;
+                    let ret = self.node::<LeafNode<K, V>>(frame).lookup(k).copied();
+                    self.pager.unpin(frame);
+                    return Ok(ret);
+                }
+            }
+        }
+    }
+
+    /// Returns an iterator over the entries within the range of keys, from the smallest key to the largest.
+    ///
+    /// When the scan enters a leaf, the next leaves under the same father node are read ahead in the background, so a
+    /// long scan is bound by the bandwidth of the disk rather than the latency of every page. See `set_read_ahead`.
+    pub fn range<R: RangeBounds<K>>(&mut self, range: R) -> PagedRange<'_, K, V> {
+        PagedRange {
+            t: self,
+            start: Some(range.start_bound().cloned()),
+            end: range.end_bound().cloned(),
+            stack: Vec::new(),
+            leaf: None,
+            pos: 0,
+            ahead: (0, 0),
+        }
+    }
+
+    /// Sets the number of leaves a range scan reads ahead of the current one, 0 disables reading ahead.
+    pub fn set_read_ahead(&mut self, leaves: usize) {
+        self.read_ahead = leaves;
+    }
+
+    /// Loads the pages of the nodes holding the keys in the `range` into the buffer pool, and returns the number of
+    /// them, so that the first requests to a freshly opened tree do not wait for the disk.
+    ///
+    /// The pages are loaded level by level from the root, the upper levels on the paths of every lookup first, and
+    /// every level is read ahead as a whole. It only fills the free frames of the pool, and stops at the first page
+    /// which does not fit, so the pages loaded last do not evict the ones loaded first.
+    pub fn warmup<R: RangeBounds<K>>(&mut self, range: R) -> io::Result<usize> {
+        let (start, end) = (range.start_bound(), range.end_bound());
+        let mut free = self.pager.free_frames();
+        let mut loaded = 0;
+        let mut level = vec![self.root];
+        while !level.is_empty() {
+            let pages: Vec<u64> = level
+                .iter()
+                .map(|&node| match node {
+                    NodeIndex::Internal(page) | NodeIndex::Leaf(page) => page as u64,
+                })
+                .collect();
+            self.pager.read_ahead(&pages[..free.min(pages.len())]);
+            let mut next = Vec::new();
+            for (&node, &page) in level.iter().zip(pages.iter()) {
+                if !self.pager.cached(page) {
+                    if free == 0 {
+                        return Ok(loaded);
+                    }
+                    free -= 1;
+                }
+                let frame = self.pager.pin(page)?;
+                if let NodeIndex::Internal(_) = node {
+                    let n = self.node::<InternalNode<K>>(frame);
+                    next.extend_from_slice(&n.sons[n.son_span(start, end)]);
+                }
+                self.pager.unpin(frame);
+                loaded += 1;
+            }
+            level = next;
+        }
+        Ok(loaded)
+    }
+
+    /// Makes a checkpoint, which writes all dirty pages back to the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.pager.checkpoint()
+    }
+
+    /// Returns the number of pages a buffer pool of `bytes` can cache, at least 4, the smallest pool `open` accepts.
+    /// The pages of the tree are the smallest multiple of 4kB holding a node.
+    pub fn pool_size_for_bytes(bytes: usize) -> usize {
+        (bytes / page_size::<K, V>()).max(MIN_POOL_SIZE)
+    }
+
+    /// Changes the number of pages the buffer pool caches, the sizes below 4 are raised to 4. A smaller pool is shrunk
+    /// at the next checkpoint, which happens as soon as the dirty pages outgrow the pool.
+    pub fn set_pool_size(&mut self, pool_size: usize) {
+        self.pager.set_pool_size(pool_size);
+    }
+
+    /// Returns the statistics of the buffer pool since the tree is opened.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pager.stats()
+    }
+
+    /// Returns the dirty pages as a fraction of the buffer pool size.
+    pub fn dirty_ratio(&self) -> f64 {
+        self.pager.dirty_ratio()
+    }
+}
+
+impl<K: Pod + PartialOrd + Default, V: Pod + Default> PagedRange<'_, K, V> {
+    /// Returns whether a key greater than `k` can be in the range.
+    fn continues_after(&self, k: &K) -> bool {
+        match &self.end {
+            Bound::Included(e) | Bound::Excluded(e) => k < e,
+            Bound::Unbounded => true,
+        }
+    }
+
+    /// Descends from `cur` to the leaf of the first key after `start`, and pins the leaf.
+    fn descend(&mut self, mut cur: NodeIndex, start: Bound<&K>) -> io::Result<()> {
+        loop {
+            match cur {
+                NodeIndex::Internal(page) => {
+                    let frame = self.t.pager.pin(page as u64)?;
+                    let node = self.t.node::<InternalNode<K>>(frame);
+                    let (i, son) = match start {
+                        Bound::Included(k) | Bound::Excluded(k) => node.lookup(k),
+                        Bound::Unbounded => (0, node.sons[0]),
+                    };
+                    self.t.pager.unpin(frame);
+                    self.stack.push((page, i));
+                    cur = son;
+                }
+                NodeIndex::Leaf(page) => {
+                    self.read_ahead()?;
+                    let frame = self.t.pager.pin(page as u64)?;
+                    let l = self.t.node::<LeafNode<K, V>>(frame);
+                    let keys = &l.keys[0..l.cnt];
+                    self.pos = match start {
+                        Bound::Included(k) => lower_bound(keys, k),
+                        Bound::Excluded(k) => {
+                            let pos = lower_bound(keys, k);
+                            pos + (pos < keys.len() && &keys[pos] == k) as usize
+                        }
+                        Bound::Unbounded => 0,
+                    };
+                    self.leaf = Some(frame);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Reads ahead the leaves after the current one under the same father node, up to the end of the range.
+    fn read_ahead(&mut self) -> io::Result<()> {
+        let (page, i) = match self.stack.last() {
+            Some(&last) if self.t.read_ahead > 0 => last,
+            _ => return Ok(()),
+        };
+        // the page 0 holds the meta data, so it is never a father
+        if self.ahead.0 != page {
+            self.ahead = (page, 0);
+        }
+        let frame = self.t.pager.pin(page as u64)?;
+        let node = self.t.node::<InternalNode<K>>(frame);
+        let mut pages = Vec::new();
+        for j in self.ahead.1.max(i + 1)..node.cnt.min(i + 1 + self.t.read_ahead) {
+            // the keys in `sons[j]` are greater than `keys[j - 1]`
+            match node.sons[j] {
+                NodeIndex::Leaf(son) if self.continues_after(&node.keys[j - 1]) => pages.push(son as u64),
+                _ => break,
+            }
+        }
+        self.t.pager.unpin(frame);
+        self.ahead.1 = i + 1 + self.t.read_ahead;
+        self.t.pager.read_ahead(&pages);
+        Ok(())
+    }
+
+    /// Moves to the leaf after the current one, the scan ends if it is the last leaf in the range.
+    fn next_leaf(&mut self) -> io::Result<()> {
+        while let Some((page, i)) = self.stack.pop() {
+            let frame = self.t.pager.pin(page as u64)?;
+            let node = self.t.node::<InternalNode<K>>(frame);
+            // the keys in `sons[i + 1]` are greater than `keys[i]`
+            let next = if i + 1 < node.cnt && self.continues_after(&node.keys[i]) {
+                Some(node.sons[i + 1])
+            } else {
+                None
+            };
+            self.t.pager.unpin(frame);
+            if let Some(son) = next {
+                self.stack.push((page, i + 1));
+                return self.descend(son, Bound::Unbounded);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: Pod + PartialOrd + Default, V: Pod + Default> Iterator for PagedRange<'_, K, V> {
+    type Item = io::Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take() {
+            let root = self.t.root;
+            if let Err(e) = self.descend(root, start.as_ref()) {
+                return Some(Err(e));
+            }
+        }
+        loop {
+            let frame = self.leaf?;
+            let l = self.t.node::<LeafNode<K, V>>(frame);
+            if self.pos < l.cnt {
+                let (k, v) = (l.keys[self.pos], l.values[self.pos]);
+                let in_range = match &self.end {
+                    Bound::Included(e) => &k <= e,
+                    Bound::Excluded(e) => &k < e,
+                    Bound::Unbounded => true,
+                };
+                if !in_range {
+                    self.t.pager.unpin(frame);
+                    self.leaf = None;
+                    return None;
+                }
+                self.pos += 1;
+                return Some(Ok((k, v)));
+            }
+            self.t.pager.unpin(frame);
+            self.leaf = None;
+            if let Err(e) = self.next_leaf() {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl<K, V> Drop for PagedRange<'_, K, V> {
+    fn drop(&mut self) {
+        if let Some(frame) = self.leaf {
+            self.t.pager.unpin(frame);
+        }
+    }
+}
+
+#[test]
+fn test_paged_btree_range() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-range-{}", std::process::id()));
+    remove_test_files(&path);
+
+    let n = 20000u64;
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 16) }.unwrap();
+        for i in 0..n {
+            t.insert(&(i * 7919 % n * 2), &i).unwrap();
+        }
+        // empty some leaves, which are not merged
+        for k in 2000..4000 {
+            t.remove(&(k * 2)).unwrap();
+        }
+    }
+
+    let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 16) }.unwrap();
+    let keys = |r: PagedRange<u64, u64>| r.map(|e| e.unwrap().0).collect::<Vec<_>>();
+    let expected: Vec<u64> = (0..n).filter(|k| !(2000..4000).contains(k)).map(|k| k * 2).collect();
+    assert_eq!(keys(t.range(..)), expected);
+    assert!(t.pool_stats().read_aheads > 0);
+    assert_eq!(keys(t.range(10..=20)), [10, 12, 14, 16, 18, 20]);
+    assert_eq!(keys(t.range((Bound::Excluded(10), Bound::Excluded(20)))), [12, 14, 16, 18]);
+    assert_eq!(keys(t.range(3999..8001)), [8000]);
+    assert_eq!(keys(t.range(2 * n - 3..)), [2 * n - 2]);
+    assert_eq!(keys(t.range(2 * n..)), []);
+    for (i, e) in t.range(100..).take(5).enumerate() {
+        let (k, v) = e.unwrap();
+        assert_eq!((k, v * 7919 % n * 2), (100 + 2 * i as u64, k));
+    }
+
+    // the results do not depend on reading ahead
+    let read_aheads = t.pool_stats().read_aheads;
+    t.set_read_ahead(0);
+    assert_eq!(keys(t.range(..)), expected);
+    assert_eq!(t.pool_stats().read_aheads, read_aheads);
+
+    // a dropped scan leaves nothing pinned, the tree is still usable
+    t.insert(&1, &1).unwrap();
+    assert_eq!(keys(t.range(..4)), [0, 1, 2]);
+    drop(t);
+    remove_test_files(&path);
+}
+
+#[test]
+fn test_paged_btree_pool_stats() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-pool-{}", std::process::id()));
+    remove_test_files(&path);
+
+    let pool_size = PagedBTree::<u64, u64>::pool_size_for_bytes(64 << 10);
+    assert_eq!(pool_size, (64 << 10) / page_size::<u64, u64>());
+    let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, pool_size) }.unwrap();
+    for i in 0..20000 {
+        t.insert(&(i * 7919 % 20000), &i).unwrap();
+    }
+    t.flush().unwrap();
+    let s = t.pool_stats();
+    assert_eq!((s.pool_size, s.frames), (pool_size, pool_size));
+    assert!(s.bytes() <= 64 << 10);
+    assert!(s.misses > 0 && s.evictions > 0 && s.hit_rate() > 0.0 && s.hit_rate() < 1.0);
+
+    // a larger pool holds the whole tree, so the lookups stop missing
+    t.set_pool_size(4000);
+    for i in 0..20000 {
+        t.lookup(&i).unwrap();
+    }
+    let misses = t.pool_stats().misses;
+    for i in 0..20000 {
+        t.lookup(&i).unwrap();
+    }
+    assert_eq!(t.pool_stats().misses, misses);
+
+    t.set_pool_size(4);
+    t.flush().unwrap();
+    assert_eq!(t.pool_stats().frames, 4);
+
+    // the smallest pool is 4 pages
+    t.set_pool_size(0);
+    t.flush().unwrap();
+    assert_eq!((t.pool_stats().pool_size, t.pool_stats().frames), (4, 4));
+    assert!(t.lookup(&7).unwrap().is_some());
+    drop(t);
+    assert_eq!(PagedBTree::<u64, u64>::pool_size_for_bytes(0), 4);
+    let pool_size = PagedBTree::<u64, u64>::pool_size_for_bytes(1);
+    let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, pool_size) }.unwrap();
+    assert_eq!(t.range(..).count(), 20000);
+    drop(t);
+    remove_test_files(&path);
+}
+
+#[cfg(test)]
+fn remove_test_files(path: &Path) {
+    let _ = std::fs::remove_file(path);
+    let mut wal_path = path.as_os_str().to_owned();
+    wal_path.push(".wal");
+    let _ = std::fs::remove_file(wal_path);
+}
+
+#[test]
+fn test_paged_btree_warmup() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-warmup-{}", std::process::id()));
+    remove_test_files(&path);
+
+    let n = 20000u64;
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 16) }.unwrap();
+        for i in 0..n {
+            t.insert(&i, &i).unwrap();
+        }
+    }
+
+    // the warmed up range is served from the pool
+    let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 128) }.unwrap();
+    let loaded = t.warmup(1000..1400).unwrap();
+    let misses = t.pool_stats().misses;
+    assert!(loaded > 400 / NODE_DEG && loaded < 64);
+    for k in 1000..1400 {
+        assert_eq!(t.lookup(&k).unwrap(), Some(k));
+    }
+    assert_eq!(t.pool_stats().misses, misses);
+
+    // the whole tree does not fit in the pool, the internal nodes are loaded first
+    assert!(t.warmup(..).unwrap() > loaded);
+    let s = t.pool_stats();
+    assert_eq!((s.frames, s.evictions), (128, 0));
+    let misses = s.misses;
+    assert_eq!(t.lookup(&(n - 1)).unwrap(), Some(n - 1));
+    assert_eq!(t.pool_stats().misses, misses + 1);
+    drop(t);
+    remove_test_files(&path);
+}
+
+#[test]
+fn test_paged_btree() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-{}", std::process::id()));
+    remove_test_files(&path);
+
+    // a tiny buffer pool makes sure the pages are evicted and read back
+    let n = 50000u64;
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 8) }.unwrap();
+        for i in 0..n {
+            assert_eq!(t.insert(&(i * 7919 % n), &i).unwrap(), None);
+        }
+        assert_eq!(t.insert(&0, &42).unwrap(), Some(0));
+        for i in 0..n {
+            assert_eq!(t.lookup(&(i * 7919 % n)).unwrap(), Some(if i == 0 { 42 } else { i }));
+        }
+        assert_eq!(t.lookup(&n).unwrap(), None);
+        assert_eq!(t.remove(&0).unwrap(), Some(42));
+        assert_eq!(t.remove(&0).unwrap(), None);
+        assert_eq!(t.lookup(&0).unwrap(), None);
+    }
+
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 16) }.unwrap();
+        assert_eq!(t.lookup(&0).unwrap(), None);
+        for i in 1..n {
+            assert_eq!(t.lookup(&(i * 7919 % n)).unwrap(), Some(i));
+        }
+    }
+
+    // the types do not match
+    assert!(unsafe { PagedBTree::<u64, u32>::open(&path, 16) }.is_err());
+    remove_test_files(&path);
+}
+
+#[test]
+fn test_paged_btree_ingest() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-ingest-{}", std::process::id()));
+    remove_test_files(&path);
+
+    let n = 200000u64;
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 16) }.unwrap();
+        assert_eq!(t.ingest((0..n).map(|i| (i * 2, i))).unwrap(), n as usize);
+        // the leaves are filled up, and only a few of them stay in the memory
+        let leaves = (n as usize).div_ceil(NODE_DEG);
+        assert!(t.pager.page_cnt() < (leaves + leaves / 16 + 8) as u64);
+        assert!(t.pool_stats().frames <= 32);
+
+        // merge the odd keys, and the keys beyond the tree
+        assert_eq!(t.ingest((n..n + n / 2).map(|i| (i * 2 - n * 2 + 1, i))).unwrap(), n as usize / 2);
+        assert_eq!(t.ingest((n * 2..n * 3).map(|i| (i, i))).unwrap(), n as usize);
+        let e = t.ingest([(n * 4, 0), (n * 3, 0)].iter().copied()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(t.lookup(&(n * 4)).unwrap(), Some(0));
+    }
+
+    let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 16) }.unwrap();
+    let expected = (0..n)
+        .map(|i| (i * 2, i))
+        .chain((n..n + n / 2).map(|i| (i * 2 - n * 2 + 1, i)))
+        .chain((n * 2..n * 3).map(|i| (i, i)))
+        .chain(std::iter::once((n * 4, 0)));
+    let mut expected: Vec<_> = expected.collect();
+    expected.sort_unstable();
+    expected.dedup_by_key(|e| e.0);
+    assert!(t.range(..).map(|e| e.unwrap()).eq(expected.into_iter()));
+    drop(t);
+    remove_test_files(&path);
+}
+
+#[test]
+fn test_paged_btree_crash_recovery() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-crash-{}", std::process::id()));
+    remove_test_files(&path);
+
+    let n = 20000u64;
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 8) }.unwrap();
+        for i in 0..n {
+            t.insert(&i, &(i * 2)).unwrap();
+        }
+        for i in 0..n / 2 {
+            t.remove(&(i * 2)).unwrap();
+        }
+        // crash without the final checkpoint
+        std::mem::forget(t);
+    }
+
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 8) }.unwrap();
+        for i in 0..n {
+            let expected = if i % 2 == 0 { None } else { Some(i * 2) };
+            assert_eq!(t.lookup(&i).unwrap(), expected);
+        }
+    }
+    remove_test_files(&path);
+}
+
+#[test]
+fn test_paged_btree_batch() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-batch-{}", std::process::id()));
+    remove_test_files(&path);
+
+    let mut b = WriteBatch::new();
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 1024) }.unwrap();
+        for i in 0..1000 {
+            b.insert(&i, &i);
+        }
+        t.apply_batch(&b).unwrap();
+        std::mem::forget(t);
+    }
+
+    // the batch is replayed as a whole
+    let mut wal_path = path.as_os_str().to_owned();
+    wal_path.push(".wal");
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 1024) }.unwrap();
+        for i in 0..1000 {
+            assert_eq!(t.lookup(&i).unwrap(), Some(i));
+        }
+        b.clear();
+        for i in 0..1000 {
+            b.remove(&(i * 2));
+            b.insert(&(i + 1000), &i);
+        }
+        t.apply_batch(&b).unwrap();
+        std::mem::forget(t);
+    }
+
+    // a torn batch is discarded as a whole
+    let wal = std::fs::OpenOptions::new().write(true).open(&wal_path).unwrap();
+    wal.set_len(wal.metadata().unwrap().len() - 1).unwrap();
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 1024) }.unwrap();
+        for i in 0..2000 {
+            assert_eq!(t.lookup(&i).unwrap(), if i < 1000 { Some(i) } else { None });
+        }
+    }
+    remove_test_files(&path);
+}
+
+#[test]
+fn test_paged_btree_malformed_record() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-malformed-{}", std::process::id()));
+    let mut wal_path = path.as_os_str().to_owned();
+    wal_path.push(".wal");
+
+    for r in [Record::Insert(vec![1, 2, 3]), Record::Remove(vec![0; 16]), Record::Batch(vec![Record::Remove(vec![])])] {
+        remove_test_files(&path);
+        {
+            let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 8) }.unwrap();
+            t.insert(&1, &1).unwrap();
+            t.pager.log(&r).unwrap();
+            std::mem::forget(t);
+        }
+        let e = unsafe { PagedBTree::<u64, u64>::open(&path, 8) }.err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+    remove_test_files(&path);
+}
+
+#[test]
+fn test_paged_btree_checksum() {
+    use std::os::unix::fs::FileExt;
+
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-checksum-{}", std::process::id()));
+    remove_test_files(&path);
+
+    let n = 10000u64;
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 8) }.unwrap();
+        for i in 0..n {
+            t.insert(&i, &i).unwrap();
+        }
+    }
+
+    // flip a bit in the payload of the page 5
+    let file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    let offset = 5 * page_size::<u64, u64>() as u64 + PAGE_HEADER_SIZE as u64 + 1;
+    let mut b = [0u8];
+    file.read_exact_at(&mut b, offset).unwrap();
+    file.write_all_at(&[b[0] ^ 4], offset).unwrap();
+
+    let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 8) }.unwrap();
+    let err = (0..n).find_map(|i| t.lookup(&i).err()).unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.get_ref().unwrap().downcast_ref::<CorruptedPage>(), Some(&CorruptedPage { page: 5 }));
+    drop(t);
+    remove_test_files(&path);
+}
+
+#[test]
+fn test_paged_btree_try() {
+    use crate::fallible::BTreeError;
+    use std::os::unix::fs::FileExt;
+
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-try-{}", std::process::id()));
+    remove_test_files(&path);
+
+    let n = 10000u64;
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 8) }.unwrap();
+        for i in 0..n {
+            assert_eq!(t.try_insert(&i, &i).unwrap(), None);
+        }
+        assert_eq!(t.try_lookup(&10).unwrap(), Some(10));
+        assert_eq!(t.try_range(10..20).map(|e| e.unwrap().0).sum::<u64>(), 145);
+    }
+
+    // flip a bit in the payload of the page 5
+    let file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    let offset = 5 * page_size::<u64, u64>() as u64 + PAGE_HEADER_SIZE as u64 + 1;
+    let mut b = [0u8];
+    file.read_exact_at(&mut b, offset).unwrap();
+    file.write_all_at(&[b[0] ^ 4], offset).unwrap();
+
+    let mut t = unsafe { PagedBTree::<u64, u64>::open(&path, 8) }.unwrap();
+    let err = (0..n).find_map(|i| t.try_lookup(&i).err()).unwrap();
+    assert!(matches!(err, BTreeError::Corrupted { page: Some(5) }));
+    let err = t.try_range(..).find_map(|e| e.err()).unwrap();
+    assert_eq!(err.to_string(), "the page 5 is corrupted");
+    drop(t);
+    remove_test_files(&path);
+}
+
+/// A toy cipher for the tests: XOR with a keystream, and a keyed sum as the tag.
+#[cfg(test)]
+struct XorCipher(u64);
+
+#[cfg(test)]
+impl XorCipher {
+    fn apply(&self, nonce: u64, data: &mut [u8]) {
+        let mut x = self.0 ^ nonce.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        for b in data.iter_mut() {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            *b ^= x as u8;
+        }
+    }
+
+    fn tag(&self, nonce: u64, data: &[u8]) -> [u8; 16] {
+        let crc = crate::crc32c::update(crate::crc32c::update(0, &(self.0 ^ nonce).to_le_bytes()), data);
+        let mut tag = [0u8; 16];
+        tag[..4].copy_from_slice(&crc.to_le_bytes());
+        tag
+    }
+}
+
+#[cfg(test)]
+impl PageCipher for XorCipher {
+    fn encrypt(&self, nonce: u64, data: &mut [u8]) -> [u8; 16] {
+        self.apply(nonce, data);
+        self.tag(nonce, data)
+    }
+
+    fn decrypt(&self, nonce: u64, data: &mut [u8], tag: &[u8; 16]) -> bool {
+        if &self.tag(nonce, data) != tag {
+            return false;
+        }
+        self.apply(nonce, data);
+        true
+    }
+}
+
+#[test]
+fn test_paged_btree_encryption() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-crypt-{}", std::process::id()));
+    remove_test_files(&path);
+    let mut wal_path = path.as_os_str().to_owned();
+    wal_path.push(".wal");
+    let secret = 0x5ec2_e75e_c2e7_5ec2u64;
+    let contains_secret = |bytes: Vec<u8>| bytes.windows(8).any(|w| w == secret.to_le_bytes());
+
+    let n = 10000u64;
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open_encrypted(&path, 8, Box::new(XorCipher(42))) }.unwrap();
+        for i in 0..n {
+            t.insert(&i, &(secret + i % 2)).unwrap();
+        }
+        t.flush().unwrap();
+        // the operations after the checkpoint are only in the log
+        for i in 0..10 {
+            t.insert(&(n + i), &secret).unwrap();
+        }
+        assert!(!contains_secret(std::fs::read(&wal_path).unwrap()));
+        std::mem::forget(t);
+    }
+    assert!(!contains_secret(std::fs::read(&path).unwrap()));
+
+    assert!(unsafe { PagedBTree::<u64, u64>::open(&path, 8) }.is_err());
+    assert!(unsafe { PagedBTree::<u64, u64>::open_encrypted(&path, 8, Box::new(XorCipher(7))) }.is_err());
+    {
+        let mut t = unsafe { PagedBTree::<u64, u64>::open_encrypted(&path, 8, Box::new(XorCipher(42))) }.unwrap();
+        for i in 0..n {
+            assert_eq!(t.lookup(&i).unwrap(), Some(secret + i % 2));
+        }
+        for i in 0..10 {
+            assert_eq!(t.lookup(&(n + i)).unwrap(), Some(secret));
+        }
+    }
+    assert!(!contains_secret(std::fs::read(&path).unwrap()));
+    remove_test_files(&path);
+}
+
+#[test]
+fn test_paged_btree_nonce_reuse() {
+    use std::sync::{Arc, Mutex};
+
+    // records the nonces used for the encryption
+    struct Recording(XorCipher, Arc<Mutex<Vec<u64>>>);
+    impl PageCipher for Recording {
+        fn encrypt(&self, nonce: u64, data: &mut [u8]) -> [u8; 16] {
+            self.1.lock().unwrap().push(nonce);
+            self.0.encrypt(nonce, data)
+        }
+
+        fn decrypt(&self, nonce: u64, data: &mut [u8], tag: &[u8; 16]) -> bool {
+            self.0.decrypt(nonce, data, tag)
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-nonce-{}", std::process::id()));
+    remove_test_files(&path);
+    let mut wal_path = path.as_os_str().to_owned();
+    wal_path.push(".wal");
+    let nonces = Arc::new(Mutex::new(Vec::new()));
+    let open = || {
+        let cipher = Box::new(Recording(XorCipher(42), nonces.clone()));
+        unsafe { PagedBTree::<u64, u64>::open_encrypted(&path, 8, cipher) }.unwrap()
+    };
+
+    for round in 0..3 {
+        let mut t = open();
+        for i in 0..1000 {
+            t.insert(&i, &(i + round)).unwrap();
+        }
+        if round == 0 {
+            t.flush().unwrap();
+        }
+        for i in 0..10 {
+            t.insert(&(i * 100), &round).unwrap();
+        }
+        std::mem::forget(t);
+        // the last record is torn, and its nonce is lost with it
+        let wal = std::fs::OpenOptions::new().write(true).open(&wal_path).unwrap();
+        wal.set_len(wal.metadata().unwrap().len() - 1).unwrap();
+    }
+    let mut t = open();
+    assert_eq!(t.lookup(&800).unwrap(), Some(2));
+    assert_eq!(t.lookup(&900).unwrap(), Some(902));
+    t.flush().unwrap();
+
+    let mut nonces = nonces.lock().unwrap().clone();
+    let cnt = nonces.len();
+    nonces.sort_unstable();
+    nonces.dedup();
+    assert_eq!(nonces.len(), cnt);
+    drop(t);
+    remove_test_files(&path);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_paged_btree_compression() {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = std::env::temp_dir().join(format!("btree-rs-test-paged-lz4-{}", std::process::id()));
+    remove_test_files(&path);
+
+    // the leaves span multiple blocks, and the repeated values compress well
+    let n = 5000u64;
+    {
+        let mut t = unsafe { PagedBTree::<u64, [u64; 32]>::open(&path, 8) }.unwrap();
+        for i in 0..n {
+            t.insert(&i, &[i; 32]).unwrap();
+        }
+    }
+    let meta = std::fs::metadata(&path).unwrap();
+    assert_eq!(meta.len() % page_size::<u64, [u64; 32]>() as u64, 0);
+    assert!(meta.blocks() * 512 < meta.len());
+
+    {
+        let mut t = unsafe { PagedBTree::<u64, [u64; 32]>::open(&path, 8) }.unwrap();
+        for i in 0..n {
+            assert_eq!(t.lookup(&i).unwrap(), Some([i; 32]));
+        }
+    }
+    remove_test_files(&path);
+}
@@ -0,0 +1,643 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use crate::crc32c;
+use crate::lz4;
+use crate::wal::{Record, Wal};
+
+pub(crate) type PageId = u64;
+
+/// The smallest buffer pool, which holds the pages pinned by a split.
+pub(crate) const MIN_POOL_SIZE: usize = 4;
+
+/// The number of the nonces reserved in the log at once, see `Pager::take_nonce`.
+const NONCE_BLOCK: u64 = 1024;
+
+/// The size of the header at the beginning of every page.
+pub(crate) const PAGE_HEADER_SIZE: usize = 64;
+
+/// The header of a page. The rest of the page holds a node, or the meta data of the tree for the page 0.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct PageHeader {
+    pub(crate) kind: u32,
+    // how the page is stored in the data file, pages in the buffer pool are always CODEC_NONE
+    codec: u32,
+    // the size of the compressed payload
+    len: u32,
+    // the CRC-32C of the page as stored in the data file, see `page_checksum`
+    checksum: u32,
+    // the nonce of the encrypted payload, 0 if the page is not encrypted
+    nonce: u64,
+    tag: [u8; 16],
+}
+
+impl PageHeader {
+    pub(crate) fn read(page: &[u8]) -> Self {
+        assert!(page.len() >= PAGE_HEADER_SIZE);
+        unsafe { ptr::read_unaligned(page.as_ptr() as *const PageHeader) }
+    }
+
+    pub(crate) fn write(self, page: &mut [u8]) {
+        assert!(page.len() >= PAGE_HEADER_SIZE);
+        unsafe { ptr::write_unaligned(page.as_mut_ptr() as *mut PageHeader, self) }
+    }
+}
+
+/// Returns the checksum of a page as stored in the data file, i.e. the header with the checksum as 0 and the payload.
+/// The bytes of the header after `PageHeader` are not covered.
+fn page_checksum(stored: &[u8]) -> u32 {
+    let mut header = [0u8; PAGE_HEADER_SIZE];
+    let mut h = PageHeader::read(stored);
+    h.checksum = 0;
+    h.write(&mut header);
+    crc32c::update(crc32c::update(0, &header), &stored[PAGE_HEADER_SIZE..])
+}
+
+/// Sets the checksum of the page.
+fn seal(stored: &mut [u8]) {
+    let mut header = PageHeader::read(stored);
+    header.checksum = page_checksum(stored);
+    header.write(stored);
+}
+
+/// The error of a page whose checksum does not match its content, e.g. by bit rot. It is wrapped in an `io::Error` of
+/// `ErrorKind::InvalidData`.
+#[derive(Debug, PartialEq)]
+pub struct CorruptedPage {
+    pub page: u64,
+}
+
+impl fmt::Display for CorruptedPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the checksum of page {} does not match", self.page)
+    }
+}
+
+impl Error for CorruptedPage {}
+
+/// PageCipher is an authenticated cipher, e.g. AES-GCM, encrypting the pages and the log of a `PagedBTree`. The key is
+/// held by the implementation.
+///
+/// The nonces count up from 1 in a file, skipping some after a crash, so a key must not be shared by multiple files. A
+/// cipher is `Send`, so that a tree can be flushed by a background thread.
+pub trait PageCipher: Send {
+    /// Encrypts `data` in place, and returns the authentication tag.
+    fn encrypt(&self, nonce: u64, data: &mut [u8]) -> [u8; 16];
+    /// Decrypts `data` in place, returns false if the tag does not authenticate it.
+    fn decrypt(&self, nonce: u64, data: &mut [u8], tag: &[u8; 16]) -> bool;
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+const CODEC_NONE: u32 = 0;
+const CODEC_LZ4: u32 = 1;
+
+/// Compressed pages only save space if whole blocks of the file are freed.
+#[cfg(feature = "compression")]
+const BLOCK_SIZE: usize = 4096;
+
+/// Frees the range of the file, while keeping the file size.
+/// It is best effort, the space is simply not freed if the file system does not support it.
+#[cfg(feature = "compression")]
+fn punch_hole(file: &File, offset: u64, len: u64) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::fallocate(
+            std::os::unix::io::AsRawFd::as_raw_fd(file),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            len as libc::off_t,
+        );
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (file, offset, len);
+}
+
+/// Asks the kernel to read the range of the file into the page cache in the background.
+/// It is best effort, nothing happens if the platform does not support it.
+fn advise_will_need(file: &File, offset: u64, len: u64) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::posix_fadvise(
+            std::os::unix::io::AsRawFd::as_raw_fd(file),
+            offset as libc::off_t,
+            len as libc::off_t,
+            libc::POSIX_FADV_WILLNEED,
+        );
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (file, offset, len);
+}
+
+/// Splits the payload of an encrypted log record into the nonce, the tag and the encrypted bytes.
+fn split_sealed(mut payload: Vec<u8>) -> io::Result<(u64, [u8; 16], Vec<u8>)> {
+    if payload.len() < 24 {
+        return Err(invalid_data("the encrypted log record is truncated"));
+    }
+    let data = payload.split_off(24);
+    let mut nonce = [0u8; 8];
+    nonce.copy_from_slice(&payload[..8]);
+    let mut tag = [0u8; 16];
+    tag.copy_from_slice(&payload[8..]);
+    Ok((u64::from_le_bytes(nonce), tag, data))
+}
+
+/// The statistics of the buffer pool.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PoolStats {
+    /// The pages found in the pool.
+    pub hits: u64,
+    /// The pages read from the file.
+    pub misses: u64,
+    /// The pages evicted to make room for others.
+    pub evictions: u64,
+    /// The pages read ahead by the range scans.
+    pub read_aheads: u64,
+    /// The frames in the pool now, which exceeds `pool_size` when the pool grows for the dirty pages.
+    pub frames: usize,
+    pub pool_size: usize,
+    pub page_size: usize,
+}
+
+impl PoolStats {
+    /// Returns the fraction of the page accesses served by the pool, 1 if there were none.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            1.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+
+    /// Returns the memory held by the pages of the pool.
+    pub fn bytes(&self) -> usize {
+        self.frames * self.page_size
+    }
+}
+
+/// A frame of the buffer pool, caching one page.
+struct Frame {
+    page: Option<PageId>,
+    pins: usize,
+    dirty: bool,
+    referenced: bool, // the reference bit of the clock eviction
+    // u128 makes the page aligned to 16 bytes, so that nodes can be read from the page in place
+    buf: Box<[u128]>,
+}
+
+impl Frame {
+    fn new(page_size: usize) -> Self {
+        Frame {
+            page: None,
+            pins: 0,
+            dirty: false,
+            referenced: false,
+            buf: vec![0u128; page_size / 16].into_boxed_slice(),
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.buf.as_ptr() as *const u8, self.buf.len() * 16) }
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.buf.as_mut_ptr() as *mut u8, self.buf.len() * 16) }
+    }
+}
+
+/// Pager splits a file into fixed-size pages, and caches them in a buffer pool of `frames`.
+///
+/// A page must be pinned before being accessed, and unpinned after. The pinned pages are never evicted.
+///
+/// The data file only changes at checkpoints (no-steal), so it always holds the tree as of the last checkpoint.
+/// A checkpoint first logs the images of all dirty pages to the write-ahead log, then writes them to the data file.
+/// If the buffer pool runs out of clean pages, it grows beyond `pool_size` until the next checkpoint.
+///
+/// With the `compression` feature, pages are compressed with LZ4 when they are written to the data file. A compressed
+/// page still occupies its slot of `page_size` bytes, but the blocks after the compressed data are punched out of the
+/// file, so it saves space when a page spans multiple blocks. Compressed pages can be read without the feature.
+///
+/// With a cipher, the payloads of the pages and the records of the log are encrypted, the headers of the pages are not.
+/// The page 0 is written last at every checkpoint, so its nonce is the largest one in the data file. The nonces used
+/// after the checkpoint are reserved in blocks in the log before they are used, so a nonce of a record torn off the log
+/// by a crash is never used again.
+pub(crate) struct Pager {
+    file: File,
+    wal: Wal,
+    page_size: usize,
+    page_cnt: u64,
+    pool_size: usize,
+    frames: Vec<Frame>,
+    page_table: HashMap<PageId, usize>, // page id -> frame id
+    hand: usize,                        // the clock hand
+    cipher: Option<Box<dyn PageCipher>>,
+    next_nonce: u64,
+    // the last nonce reserved in the log, see `take_nonce`
+    reserved_nonce: u64,
+    stats: PoolStats,
+}
+
+impl Pager {
+    /// Opens the pager on `file` with a buffer pool of `pool_size` pages.
+    ///
+    /// It recovers the data file from the log at `wal_path`, and returns the logged operations which are not in the
+    /// data file yet. The caller should replay them, and make a checkpoint.
+    pub(crate) fn open<P: AsRef<Path>>(
+        file: File,
+        wal_path: P,
+        page_size: usize,
+        pool_size: usize,
+        cipher: Option<Box<dyn PageCipher>>,
+    ) -> io::Result<(Self, Vec<Record>)> {
+        assert!(page_size.is_multiple_of(16));
+        assert!(pool_size >= MIN_POOL_SIZE, "the buffer pool is too small to split nodes");
+
+        let (wal, records) = Wal::open(wal_path)?;
+        let mut max_nonce = 0;
+        let mut plain = Vec::with_capacity(records.len());
+        for r in records {
+            plain.push(match r {
+                Record::Encrypted(payload) => {
+                    let c = cipher.as_ref().ok_or_else(|| invalid_data("the log is encrypted, but no cipher is given"))?;
+                    let (nonce, tag, mut data) = split_sealed(payload)?;
+                    if !c.decrypt(nonce, &mut data, &tag) {
+                        return Err(invalid_data("the log cannot be decrypted, the key may be wrong"));
+                    }
+                    max_nonce = max_nonce.max(nonce);
+                    Record::from_bytes(&data)?
+                }
+                Record::Page(page, image) => {
+                    max_nonce = max_nonce.max(PageHeader::read(&image).nonce);
+                    Record::Page(page, image)
+                }
+                Record::Nonces(last) => {
+                    max_nonce = max_nonce.max(last);
+                    Record::Nonces(last)
+                }
+                r => r,
+            });
+        }
+
+        // Redo the last committed checkpoint, since it may be interrupted before all pages reached the data file.
+        // The operations logged before the checkpoint are in its pages, the ones after it are returned for replaying.
+        let commit = plain.iter().rposition(|r| *r == Record::Commit);
+        let mut pending = Vec::new();
+        let mut images = Vec::new();
+        for (i, r) in plain.into_iter().enumerate() {
+            match r {
+                Record::Page(page, image) if commit.is_some_and(|c| i < c) => images.push((page, image)),
+                Record::Insert(_) | Record::Remove(_) | Record::Batch(_) if commit.is_none_or(|c| i > c) => {
+                    pending.push(r)
+                }
+                _ => {}
+            }
+        }
+
+        let page_cnt = file.metadata()?.len() / page_size as u64;
+        let mut pager = Pager {
+            file,
+            wal,
+            page_size,
+            page_cnt,
+            pool_size,
+            frames: (0..pool_size).map(|_| Frame::new(page_size)).collect(),
+            page_table: HashMap::new(),
+            hand: 0,
+            cipher,
+            next_nonce: 0,
+            reserved_nonce: 0,
+            stats: PoolStats::default(),
+        };
+        // the images are as stored in the data file, so they are written as they are
+        for (page, image) in images.iter() {
+            pager.write_image(*page, image)?;
+        }
+        if let Some(last) = images.iter().map(|(page, _)| page + 1).max() {
+            // a compressed page at the end does not fill its slot
+            let len = last * page_size as u64;
+            if pager.file.metadata()?.len() < len {
+                pager.file.set_len(len)?;
+            }
+            pager.file.sync_all()?;
+            pager.page_cnt = pager.file.metadata()?.len() / page_size as u64;
+        }
+        if pager.page_cnt > 0 {
+            let mut header = [0u8; PAGE_HEADER_SIZE];
+            pager.file.read_exact_at(&mut header, 0)?;
+            max_nonce = max_nonce.max(PageHeader::read(&header).nonce);
+        }
+        pager.next_nonce = max_nonce + 1;
+        Ok((pager, pending))
+    }
+
+    /// Returns the number of pages in the file, including the ones which are not written back yet.
+    pub(crate) fn page_cnt(&self) -> u64 {
+        self.page_cnt
+    }
+
+    /// Returns the number of the frames holding no page, including the ones not added yet.
+    pub(crate) fn free_frames(&self) -> usize {
+        let empty = self.frames.iter().filter(|f| f.page.is_none()).count();
+        empty + self.pool_size.saturating_sub(self.frames.len())
+    }
+
+    /// Returns whether the `page` is in the buffer pool.
+    pub(crate) fn cached(&self, page: PageId) -> bool {
+        self.page_table.contains_key(&page)
+    }
+
+    /// Finds a frame to hold a new page. Only the clean pages can be evicted, if there are none, adds a new frame.
+    fn victim(&mut self) -> usize {
+        // a pool enlarged by `set_pool_size` fills its new frames first
+        if self.frames.len() < self.pool_size {
+            self.frames.push(Frame::new(self.page_size));
+            return self.frames.len() - 1;
+        }
+        // every frame is visited at most twice: once for clearing the reference bit, once for evicting
+        for _ in 0..2 * self.frames.len() {
+            let id = self.hand;
+            self.hand = (self.hand + 1) % self.frames.len();
+
+            let f = &mut self.frames[id];
+            if f.pins > 0 || f.dirty {
+                continue;
+            }
+            if f.referenced {
+                f.referenced = false;
+                continue;
+            }
+            if let Some(page) = f.page.take() {
+                self.page_table.remove(&page);
+                self.stats.evictions += 1;
+            }
+            return id;
+        }
+        self.frames.push(Frame::new(self.page_size));
+        self.frames.len() - 1
+    }
+
+    /// Pins the `page`, and returns the frame holding it.
+    pub(crate) fn pin(&mut self, page: PageId) -> io::Result<usize> {
+        assert!(page < self.page_cnt);
+        let id = match self.page_table.get(&page) {
+            Some(&id) => {
+                self.stats.hits += 1;
+                id
+            }
+            None => {
+                self.stats.misses += 1;
+                let id = self.victim();
+                self.read_page(page, id)?;
+                self.frames[id].page = Some(page);
+                self.page_table.insert(page, id);
+                id
+            }
+        };
+        let f = &mut self.frames[id];
+        f.pins += 1;
+        f.referenced = true;
+        Ok(id)
+    }
+
+    /// Starts reading the `pages` from the data file in the background, so that pinning them later does not wait for
+    /// the disk. The pages already in the buffer pool are skipped.
+    pub(crate) fn read_ahead(&mut self, pages: &[PageId]) {
+        for &page in pages {
+            if page < self.page_cnt && !self.page_table.contains_key(&page) {
+                self.stats.read_aheads += 1;
+                advise_will_need(&self.file, page * self.page_size as u64, self.page_size as u64);
+            }
+        }
+    }
+
+    /// Allocates a zeroed page at the end of the file, and pins it.
+    /// Returns the page id and the frame holding it.
+    pub(crate) fn alloc(&mut self) -> (PageId, usize) {
+        let id = self.victim();
+        let page = self.page_cnt;
+        self.page_cnt += 1;
+
+        let f = &mut self.frames[id];
+        for x in f.buf.iter_mut() {
+            *x = 0;
+        }
+        f.page = Some(page);
+        f.pins = 1;
+        f.dirty = true;
+        f.referenced = true;
+        self.page_table.insert(page, id);
+        (page, id)
+    }
+
+    /// Reads the `page` from the data file into the frame, and decompresses it if necessary.
+    fn read_page(&mut self, page: PageId, frame: usize) -> io::Result<()> {
+        let buf = self.frames[frame].data_mut();
+        self.file.read_exact_at(buf, page * self.page_size as u64)?;
+
+        let mut header = PageHeader::read(buf);
+        let end = match header.codec {
+            CODEC_LZ4 => PAGE_HEADER_SIZE + header.len as usize,
+            _ => buf.len(),
+        };
+        if end > buf.len() || page_checksum(&buf[..end]) != header.checksum {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, CorruptedPage { page }));
+        }
+
+        if header.nonce != 0 {
+            let c = self.cipher.as_ref().ok_or_else(|| invalid_data("the page is encrypted, but no cipher is given"))?;
+            if !c.decrypt(header.nonce, &mut buf[PAGE_HEADER_SIZE..end], &header.tag) {
+                return Err(invalid_data("the page cannot be decrypted, the key may be wrong"));
+            }
+        }
+
+        match header.codec {
+            CODEC_NONE => {}
+            CODEC_LZ4 => {
+                let compressed = buf[PAGE_HEADER_SIZE..end].to_vec();
+                lz4::decompress(&compressed, &mut buf[PAGE_HEADER_SIZE..])
+                    .ok_or_else(|| invalid_data("the compressed page is corrupted"))?;
+            }
+            _ => return Err(invalid_data("unknown page codec")),
+        }
+        header.codec = CODEC_NONE;
+        header.len = 0;
+        header.nonce = 0;
+        header.tag = [0; 16];
+        header.write(buf);
+        Ok(())
+    }
+
+    /// Takes the next nonce. Once the reserved nonces run out, the next block of them is reserved by logging its last
+    /// nonce, which is synced before any nonce of the block is used.
+    fn take_nonce(&mut self) -> io::Result<u64> {
+        if self.next_nonce > self.reserved_nonce {
+            self.reserved_nonce = self.next_nonce + NONCE_BLOCK - 1;
+            self.wal.append(&Record::Nonces(self.reserved_nonce))?;
+            self.wal.sync()?;
+        }
+        self.next_nonce += 1;
+        Ok(self.next_nonce - 1)
+    }
+
+    /// Returns the page in the frame as stored in the data file. It is compressed if the `compression` feature is
+    /// enabled and it saves blocks, then encrypted if there is a cipher, and sealed with the checksum.
+    fn stored_image(&mut self, frame: usize) -> io::Result<Vec<u8>> {
+        let mut image = self.frames[frame].data().to_vec();
+        let mut header = PageHeader::read(&image);
+
+        #[cfg(feature = "compression")]
+        {
+            let compressed = lz4::compress(&image[PAGE_HEADER_SIZE..]);
+            let used = (PAGE_HEADER_SIZE + compressed.len()).div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+            if used < self.page_size {
+                image.truncate(PAGE_HEADER_SIZE);
+                image.extend_from_slice(&compressed);
+                header.codec = CODEC_LZ4;
+                header.len = compressed.len() as u32;
+            }
+        }
+
+        if self.cipher.is_some() {
+            let nonce = self.take_nonce()?;
+            header.tag = self.cipher.as_ref().unwrap().encrypt(nonce, &mut image[PAGE_HEADER_SIZE..]);
+            header.nonce = nonce;
+        }
+        header.write(&mut image);
+        seal(&mut image);
+        Ok(image)
+    }
+
+    /// Writes the image returned by `stored_image` to the data file.
+    fn write_image(&self, page: PageId, image: &[u8]) -> io::Result<()> {
+        let offset = page * self.page_size as u64;
+        self.file.write_all_at(image, offset)?;
+        // the blocks after a compressed page are not used
+        #[cfg(feature = "compression")]
+        {
+            let used = image.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+            if used < self.page_size {
+                punch_hole(&self.file, offset + used as u64, (self.page_size - used) as u64);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn unpin(&mut self, frame: usize) {
+        let f = &mut self.frames[frame];
+        assert!(f.pins > 0);
+        f.pins -= 1;
+    }
+
+    /// Returns the content of a pinned page.
+    pub(crate) fn data(&self, frame: usize) -> &[u8] {
+        let f = &self.frames[frame];
+        debug_assert!(f.pins > 0);
+        f.data()
+    }
+
+    /// Returns the mutable content of a pinned page, and marks the page dirty.
+    pub(crate) fn data_mut(&mut self, frame: usize) -> &mut [u8] {
+        let f = &mut self.frames[frame];
+        debug_assert!(f.pins > 0);
+        f.dirty = true;
+        f.data_mut()
+    }
+
+    /// Appends the record to the write-ahead log.
+    pub(crate) fn log(&mut self, record: &Record) -> io::Result<()> {
+        if self.cipher.is_none() {
+            return self.wal.append(record);
+        }
+        let nonce = self.take_nonce()?;
+        let mut data = record.to_bytes();
+        let tag = self.cipher.as_ref().unwrap().encrypt(nonce, &mut data);
+        let mut payload = nonce.to_le_bytes().to_vec();
+        payload.extend_from_slice(&tag);
+        payload.extend_from_slice(&data);
+        self.wal.append(&Record::Encrypted(payload))
+    }
+
+    /// Returns true if the buffer pool grows beyond its size, which means it is time for a checkpoint.
+    pub(crate) fn over_budget(&self) -> bool {
+        self.frames.len() > self.pool_size
+    }
+
+    pub(crate) fn stats(&self) -> PoolStats {
+        PoolStats {
+            frames: self.frames.len(),
+            pool_size: self.pool_size,
+            page_size: self.page_size,
+            ..self.stats
+        }
+    }
+
+    /// Changes the size of the buffer pool, at least `MIN_POOL_SIZE`. A larger pool grows as pages are read, a smaller
+    /// one shrinks at the next checkpoint.
+    pub(crate) fn set_pool_size(&mut self, pool_size: usize) {
+        self.pool_size = pool_size.max(MIN_POOL_SIZE);
+    }
+
+    /// Returns the dirty pages as a fraction of the buffer pool size, which exceeds 1 when the pool has grown.
+    pub(crate) fn dirty_ratio(&self) -> f64 {
+        let dirty = self.frames.iter().filter(|f| f.dirty).count();
+        dirty as f64 / self.pool_size.max(1) as f64
+    }
+
+    /// Writes all dirty pages to the data file, and clears the log. No page may be pinned.
+    pub(crate) fn checkpoint(&mut self) -> io::Result<()> {
+        if self.cipher.is_some() && self.page_cnt > 0 {
+            let frame = self.pin(0)?;
+            self.data_mut(frame);
+            self.unpin(frame);
+        }
+        let mut dirty: Vec<usize> = (0..self.frames.len()).filter(|&id| self.frames[id].dirty).collect();
+        // the page 0 goes last, see `Pager`
+        dirty.sort_by_key(|&id| self.frames[id].page == Some(0));
+        let mut images = Vec::with_capacity(dirty.len());
+        for &id in dirty.iter() {
+            debug_assert!(self.frames[id].pins == 0);
+            let page = self.frames[id].page.unwrap();
+            let image = self.stored_image(id)?;
+            self.wal.append(&Record::Page(page, image.clone()))?;
+            images.push((page, image));
+        }
+        self.wal.append(&Record::Commit)?;
+        self.wal.sync()?;
+
+        for (&id, (page, image)) in dirty.iter().zip(images.iter()) {
+            self.write_image(*page, image)?;
+            self.frames[id].dirty = false;
+        }
+
+        // compressed pages at the end do not fill their slots
+        let len = self.page_cnt * self.page_size as u64;
+        if self.file.metadata()?.len() < len {
+            self.file.set_len(len)?;
+        }
+        self.file.sync_all()?;
+        self.wal.truncate()?;
+        // the reservations are gone with the log, the nonces used so far are bounded by the one of the page 0
+        self.reserved_nonce = 0;
+
+        // shrink the buffer pool back to its size
+        for f in self.frames.drain(self.pool_size.min(self.frames.len())..) {
+            if let Some(page) = f.page {
+                self.page_table.remove(&page);
+                self.stats.evictions += 1;
+            }
+        }
+        self.hand %= self.frames.len().max(1);
+        Ok(())
+    }
+}
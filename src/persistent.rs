@@ -0,0 +1,438 @@
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::join::Delta;
+use crate::{lower_bound, NODE_DEG};
+
+#[derive(Clone)]
+enum Node<K, V> {
+    Leaf { keys: Vec<K>, values: Vec<V> },
+    // the maximum key in `sons[i]` is `keys[i]`, as in `InternalNode`
+    Internal { keys: Vec<K>, sons: Vec<Rc<Node<K, V>>> },
+}
+
+impl<K, V> Node<K, V> {
+    /// Returns the height of the subtree, 0 for a leaf.
+    fn height(&self) -> usize {
+        let (mut cur, mut h) = (self, 0);
+        while let Node::Internal { sons, .. } = cur {
+            cur = &sons[0];
+            h += 1;
+        }
+        h
+    }
+
+    /// Returns the first key of the leftmost leaf, which is None if the leaf is emptied by the removals.
+    fn first_key(&self) -> Option<&K> {
+        match self {
+            Node::Leaf { keys, .. } => keys.first(),
+            Node::Internal { sons, .. } => sons[0].first_key(),
+        }
+    }
+}
+
+/// PersistentBTree is a B+Tree whose clones share the nodes, so cloning it takes O(1) however large it is.
+///
+/// The nodes are reference counted, and a mutation copies only the nodes on its path which are shared with another
+/// clone, so near-identical versions of a tree cost little more than one of them. Removing keys does not merge the
+/// nodes.
+pub struct PersistentBTree<K, V> {
+    root: Rc<Node<K, V>>,
+    len: usize,
+}
+
+impl<K, V> Clone for PersistentBTree<K, V> {
+    /// Returns a copy sharing all nodes with this tree.
+    fn clone(&self) -> Self {
+        PersistentBTree {
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for PersistentBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The maximum key of a split node, and its new right sibling.
+type Split<K, V> = Option<(K, Rc<Node<K, V>>)>;
+
+/// Inserts the key value pair into the subtree, and returns the old value, and the split if the node splits.
+fn insert<K: PartialOrd + Copy, V: Copy>(node: &mut Rc<Node<K, V>>, k: &K, v: &V) -> (Option<V>, Split<K, V>) {
+    match Rc::make_mut(node) {
+        Node::Leaf { keys, values } => {
+            let pos = lower_bound(keys, k);
+            if pos < keys.len() && &keys[pos] == k {
+                return (Some(core::mem::replace(&mut values[pos], *v)), None);
+            }
+            keys.insert(pos, *k);
+            values.insert(pos, *v);
+            if keys.len() <= NODE_DEG {
+                return (None, None);
+            }
+            let right = Node::Leaf {
+                keys: keys.split_off(NODE_DEG / 2),
+                values: values.split_off(NODE_DEG / 2),
+            };
+            (None, Some((keys[NODE_DEG / 2 - 1], Rc::new(right))))
+        }
+        Node::Internal { keys, sons } => {
+            let i = lower_bound(keys, k);
+            let (old, split) = insert(&mut sons[i], k, v);
+            let (left_max, right) = match split {
+                Some(split) => split,
+                None => return (old, None),
+            };
+            keys.insert(i, left_max);
+            sons.insert(i + 1, right);
+            if sons.len() <= NODE_DEG {
+                return (old, None);
+            }
+            let right_sons = sons.split_off(NODE_DEG / 2);
+            let right_keys = keys.split_off(NODE_DEG / 2);
+            let left_max = keys.pop().unwrap();
+            (old, Some((left_max, Rc::new(Node::Internal { keys: right_keys, sons: right_sons }))))
+        }
+    }
+}
+
+/// Removes `k` from the subtree, which must hold it.
+fn remove<K: PartialOrd + Copy, V: Copy>(node: &mut Rc<Node<K, V>>, k: &K) -> V {
+    match Rc::make_mut(node) {
+        Node::Leaf { keys, values } => {
+            let pos = lower_bound(keys, k);
+            keys.remove(pos);
+            values.remove(pos)
+        }
+        Node::Internal { keys, sons } => remove(&mut sons[lower_bound(keys, k)], k),
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> PersistentBTree<K, V> {
+    pub fn new() -> Self {
+        PersistentBTree {
+            root: Rc::new(Node::Leaf {
+                keys: Vec::new(),
+                values: Vec::new(),
+            }),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        let mut cur = &self.root;
+        loop {
+            match &**cur {
+                Node::Internal { keys, sons } => cur = &sons[lower_bound(keys, k)],
+                Node::Leaf { keys, values } => {
+                    let pos = lower_bound(keys, k);
+                    return (pos < keys.len() && &keys[pos] == k).then(|| &values[pos]);
+                }
+            }
+        }
+    }
+
+    /// Inserts the key value pair, and returns the old value if the key already exists.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        let (old, split) = insert(&mut self.root, k, v);
+        if let Some((left_max, right)) = split {
+            let left = self.root.clone();
+            self.root = Rc::new(Node::Internal {
+                keys: vec![left_max],
+                sons: vec![left, right],
+            });
+        }
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// Removes `k`, and returns its value if it exists. The nodes are not copied if `k` does not exist.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.lookup(k)?;
+        self.len -= 1;
+        Some(remove(&mut self.root, k))
+    }
+
+    /// Returns whether the two trees share the root, which means they are equal.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.root, &other.root)
+    }
+
+    /// Returns an iterator over the entries in the order of the keys.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            stack: vec![(&*self.root, 0)],
+        }
+    }
+
+    /// Returns the entries added, removed and changed from this tree to `newer`, in the order of the keys.
+    ///
+    /// The subtrees shared by both trees are skipped without being visited, so diffing a clone against the tree it is
+    /// cloned from costs about the nodes copied by the writes since, however large the trees are.
+    pub fn diff<'a>(&'a self, newer: &'a PersistentBTree<K, V>) -> Diff<'a, K, V>
+    where
+        V: PartialEq,
+    {
+        Diff {
+            old: vec![Pending::Node(&self.root, self.root.height())],
+            new: vec![Pending::Node(&newer.root, newer.root.height())],
+            leaves: 0,
+        }
+    }
+}
+
+/// An item of a tree not visited by `Diff` yet: a subtree with its height, or an entry.
+enum Pending<'a, K, V> {
+    Node(&'a Rc<Node<K, V>>, usize),
+    Entry(&'a K, &'a V),
+}
+
+impl<'a, K, V> Clone for Pending<'a, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, K, V> Copy for Pending<'a, K, V> {}
+
+/// Replaces the subtree on the top of `stack` by its sons, or by its entries if it is a leaf.
+fn expand<K, V>(stack: &mut Vec<Pending<'_, K, V>>, leaves: &mut usize) {
+    if let Some(Pending::Node(node, h)) = stack.pop() {
+        match &**node {
+            Node::Internal { sons, .. } => stack.extend(sons.iter().rev().map(|son| Pending::Node(son, h - 1))),
+            Node::Leaf { keys, values } => {
+                *leaves += 1;
+                stack.extend(keys.iter().zip(values.iter()).rev().map(|(k, v)| Pending::Entry(k, v)));
+            }
+        }
+    }
+}
+
+/// Diff is an iterator over the differences between two versions of a `PersistentBTree`, see `PersistentBTree::diff`.
+pub struct Diff<'a, K, V> {
+    // the items of the older and the newer tree not visited yet, the next one last
+    old: Vec<Pending<'a, K, V>>,
+    new: Vec<Pending<'a, K, V>>,
+    // the leaves visited, which excludes the ones in the skipped subtrees
+    leaves: usize,
+}
+
+impl<'a, K: PartialOrd + Copy, V: PartialEq + Copy> Iterator for Diff<'a, K, V> {
+    type Item = Delta<'a, K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // The entries before the next items of both trees are all compared. A shared subtree holds the next entries
+            // of both trees if it is the next item of both, otherwise the taller subtree is expanded first, since a
+            // shared subtree has the same height in both trees.
+            match (self.old.last().copied(), self.new.last().copied()) {
+                (None, None) => return None,
+                (Some(Pending::Node(a, _)), Some(Pending::Node(b, _))) if Rc::ptr_eq(a, b) => {
+                    self.old.pop();
+                    self.new.pop();
+                }
+                (Some(Pending::Node(_, ha)), Some(Pending::Node(_, hb))) => {
+                    if ha >= hb {
+                        expand(&mut self.old, &mut self.leaves);
+                    }
+                    if hb >= ha {
+                        expand(&mut self.new, &mut self.leaves);
+                    }
+                }
+                // an entry before the whole subtree on the other side is not in the other tree
+                (Some(Pending::Node(a, _)), Some(Pending::Entry(k, v))) => match a.first_key() {
+                    Some(first) if k < first => {
+                        self.new.pop();
+                        return Some(Delta::Added(k, v));
+                    }
+                    _ => expand(&mut self.old, &mut self.leaves),
+                },
+                (Some(Pending::Entry(k, v)), Some(Pending::Node(b, _))) => match b.first_key() {
+                    Some(first) if k < first => {
+                        self.old.pop();
+                        return Some(Delta::Removed(k, v));
+                    }
+                    _ => expand(&mut self.new, &mut self.leaves),
+                },
+                (Some(Pending::Node(..)), None) => expand(&mut self.old, &mut self.leaves),
+                (None, Some(Pending::Node(..))) => expand(&mut self.new, &mut self.leaves),
+                (Some(Pending::Entry(k, v)), None) => {
+                    self.old.pop();
+                    return Some(Delta::Removed(k, v));
+                }
+                (None, Some(Pending::Entry(k, v))) => {
+                    self.new.pop();
+                    return Some(Delta::Added(k, v));
+                }
+                (Some(Pending::Entry(ka, a)), Some(Pending::Entry(kb, b))) => {
+                    if ka < kb {
+                        self.old.pop();
+                        return Some(Delta::Removed(ka, a));
+                    }
+                    if kb < ka {
+                        self.new.pop();
+                        return Some(Delta::Added(kb, b));
+                    }
+                    self.old.pop();
+                    self.new.pop();
+                    if a != b {
+                        return Some(Delta::Changed(ka, a, b));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Iter is an iterator over the entries of a `PersistentBTree` in the order of the keys.
+pub struct Iter<'a, K, V> {
+    // the nodes from the root to the current leaf, with the index of the next son or entry
+    stack: Vec<(&'a Node<K, V>, usize)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, i) = self.stack.last_mut()?;
+            match node {
+                Node::Leaf { keys, values } if *i < keys.len() => {
+                    *i += 1;
+                    return Some((&keys[*i - 1], &values[*i - 1]));
+                }
+                Node::Internal { sons, .. } if *i < sons.len() => {
+                    *i += 1;
+                    let son = &*sons[*i - 1];
+                    self.stack.push((son, 0));
+                }
+                _ => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_persistent_btree() {
+    use alloc::collections::BTreeMap;
+
+    let mut base = PersistentBTree::<u32, u32>::new();
+    for i in 0..100000 {
+        assert_eq!(base.insert(&(i * 7919 % 100000), &i), None);
+    }
+    assert_eq!(base.len(), 100000);
+
+    // every version diverges from the base by a few writes
+    let mut versions = Vec::new();
+    let mut truths = Vec::new();
+    for v in 0..20u32 {
+        let mut t = base.clone();
+        assert!(t.ptr_eq(&base));
+        let mut truth: BTreeMap<u32, u32> = BTreeMap::new();
+        for j in 0..50 {
+            let k = (v * 1000 + j * 37) % 120000;
+            t.insert(&k, &(v + 1000000));
+            truth.insert(k, v + 1000000);
+        }
+        let old = t.lookup(&(v * 5)).copied();
+        assert_eq!(t.remove(&(v * 5)), old);
+        truth.insert(v * 5, u32::MAX);
+        assert_eq!(t.remove(&200000), None);
+        versions.push(t);
+        truths.push(truth);
+    }
+
+    // the base and the versions do not see the writes of each other
+    for i in 0..100000u32 {
+        assert_eq!(base.lookup(&(i * 7919 % 100000)), Some(&i));
+    }
+    for (t, truth) in versions.iter().zip(truths.iter()) {
+        for (k, v) in truth.iter() {
+            let expected = if *v == u32::MAX { None } else { Some(v) };
+            assert_eq!(t.lookup(k), expected);
+        }
+        assert_eq!(t.iter().count(), t.len());
+    }
+
+    // only the written paths are copied, the rest is shared with the base
+    let shared = |t: &PersistentBTree<u32, u32>| match (&*t.root, &*base.root) {
+        (Node::Internal { sons: a, .. }, Node::Internal { sons: b, .. }) => {
+            a.iter().zip(b.iter()).filter(|(x, y)| Rc::ptr_eq(x, y)).count()
+        }
+        _ => unreachable!(),
+    };
+    assert!(versions.iter().all(|t| shared(t) > 0));
+
+    let keys: Vec<u32> = base.iter().map(|(k, _)| *k).collect();
+    assert_eq!(keys, (0..100000).collect::<Vec<_>>());
+    assert_eq!(PersistentBTree::<u32, u32>::new().iter().next(), None);
+}
+
+#[test]
+fn test_persistent_diff() {
+    use crate::BTree;
+
+    let mut base = PersistentBTree::<u32, u32>::new();
+    let mut plain = BTree::<u32, u32>::new();
+    for i in 0..100000 {
+        base.insert(&(i * 7919 % 100000), &i);
+        plain.insert(&(i * 7919 % 100000), &i);
+    }
+    assert_eq!(base.diff(&base.clone()).next(), None);
+
+    // a few writes copy a few paths, and only these are visited
+    let mut t = base.clone();
+    let mut newer = plain.clone();
+    for (k, v) in [(5, 0), (50000, 1), (99999, 2), (100005, 3)].iter() {
+        t.insert(k, v);
+        newer.insert(k, v);
+    }
+    t.remove(&70000);
+    newer.remove(&70000);
+    let mut d = base.diff(&t);
+    let deltas: Vec<_> = d.by_ref().collect();
+    assert_eq!(deltas, plain.diff(&newer).collect::<Vec<_>>());
+    assert_eq!(deltas.len(), 5);
+    assert!(d.leaves <= 20, "{} leaves are visited", d.leaves);
+    let mut d = t.diff(&base);
+    assert_eq!(d.by_ref().count(), 5);
+    assert!(d.leaves <= 20);
+
+    // many writes, which split and empty the leaves, and trees sharing nothing
+    let mut t = base.clone();
+    let mut newer = plain.clone();
+    for i in 0..30000u32 {
+        let k = i * 104729 % 130000;
+        if i % 3 == 0 {
+            t.remove(&k);
+            newer.remove(&k);
+        } else {
+            t.insert(&k, &i);
+            newer.insert(&k, &i);
+        }
+    }
+    assert!(base.diff(&t).eq(plain.diff(&newer)));
+    assert!(t.diff(&base).eq(newer.diff(&plain)));
+    let mut fresh = PersistentBTree::<u32, u32>::new();
+    for (k, v) in newer.iter() {
+        fresh.insert(k, v);
+    }
+    assert!(base.diff(&fresh).eq(plain.diff(&newer)));
+    assert!(fresh.diff(&PersistentBTree::new()).all(|d| matches!(d, Delta::Removed(..))));
+}
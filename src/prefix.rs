@@ -0,0 +1,110 @@
+use crate::range::Range;
+use crate::BTree;
+
+/// PrefixKey is a key type made of bytes or chars, whose keys can be matched by a prefix of type `P`.
+///
+/// The keys starting with a prefix are adjacent in the tree, from the smallest such key to the first key without it.
+pub trait PrefixKey<P>: Sized {
+    /// Returns the smallest key starting with `prefix`, or None if no key can start with it.
+    fn prefix_start(prefix: &P) -> Option<Self>;
+    fn starts_with(&self, prefix: &P) -> bool;
+}
+
+impl<'a> PrefixKey<&'a [u8]> for &'a [u8] {
+    fn prefix_start(prefix: &&'a [u8]) -> Option<Self> {
+        Some(prefix)
+    }
+
+    fn starts_with(&self, prefix: &&'a [u8]) -> bool {
+        <[u8]>::starts_with(self, prefix)
+    }
+}
+
+impl<'a> PrefixKey<&'a str> for &'a str {
+    fn prefix_start(prefix: &&'a str) -> Option<Self> {
+        Some(prefix)
+    }
+
+    fn starts_with(&self, prefix: &&'a str) -> bool {
+        str::starts_with(self, prefix)
+    }
+}
+
+/// Fixed size keys are padded with zeros.
+impl<'p, const N: usize> PrefixKey<&'p [u8]> for [u8; N] {
+    fn prefix_start(prefix: &&'p [u8]) -> Option<Self> {
+        if prefix.len() > N {
+            return None;
+        }
+        let mut k = [0u8; N];
+        k[..prefix.len()].copy_from_slice(prefix);
+        Some(k)
+    }
+
+    fn starts_with(&self, prefix: &&'p [u8]) -> bool {
+        self[..].starts_with(prefix)
+    }
+}
+
+/// An iterator over the entries whose keys start with a prefix.
+pub struct PrefixIter<'a, K, V, P> {
+    range: Option<Range<'a, K, V>>,
+    prefix: P,
+}
+
+impl<'a, K, V, P> Iterator for PrefixIter<'a, K, V, P>
+where
+    K: PrefixKey<P> + PartialOrd + PartialEq + Default + Copy,
+    V: Default + Copy,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.range.as_mut()?.next()?;
+        if !k.starts_with(&self.prefix) {
+            // the rest of the keys are beyond the prefix
+            self.range = None;
+            return None;
+        }
+        Some((k, v))
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Returns an iterator over the entries whose keys start with `prefix`, in the order of the keys.
+    pub fn prefix_iter<P>(&self, prefix: P) -> PrefixIter<'_, K, V, P>
+    where
+        K: PrefixKey<P>,
+    {
+        PrefixIter {
+            range: K::prefix_start(&prefix).map(|start| self.range(start..)),
+            prefix,
+        }
+    }
+}
+
+#[test]
+fn test_prefix_iter() {
+    let mut t = BTree::<&str, u32>::new();
+    let keys = ["user/1/name", "user/12/name", "user/123/age", "user/123/name", "user/2/name", "users", "video/1"];
+    for (i, k) in keys.iter().enumerate() {
+        t.insert(k, &(i as u32));
+    }
+    let prefixed = |p| t.prefix_iter(p).map(|(k, _)| *k).collect::<Vec<_>>();
+    assert_eq!(prefixed("user/123/"), ["user/123/age", "user/123/name"]);
+    assert_eq!(prefixed("user/1"), ["user/1/name", "user/12/name", "user/123/age", "user/123/name"]);
+    assert_eq!(prefixed("user"), &keys[..6]);
+    assert_eq!(prefixed(""), keys);
+    assert!(prefixed("user/3").is_empty());
+    assert!(prefixed("zzz").is_empty());
+
+    let mut t = BTree::<[u8; 4], u32>::new();
+    for i in 0..1000u32 {
+        t.insert(&i.to_be_bytes(), &i);
+    }
+    let values = |p: &[u8]| t.prefix_iter(p).map(|(_, v)| *v).collect::<Vec<_>>();
+    assert_eq!(values(&[0, 0, 1]), (256..512).collect::<Vec<_>>());
+    assert_eq!(values(&[0, 0, 3, 231]), [999]);
+    assert!(values(&[0, 0, 3, 232]).is_empty());
+    assert!(values(&[0, 0, 0, 0, 0]).is_empty());
+}
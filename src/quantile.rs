@@ -0,0 +1,92 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{BTree, NodeIndex};
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Returns at most `parts - 1` keys splitting the tree into `parts` ranges of about the same number of entries,
+    /// the i-th key is the first key of the (i+1)-th range, e.g. for choosing the boundaries of shards.
+    ///
+    /// Only the top levels with enough nodes are visited. The size of a subtree is estimated by the number of sons of
+    /// its root, since the nodes at the same level hold similar numbers of entries. The points are exact for a tree
+    /// small enough to visit the leaves.
+    pub fn split_points(&self, parts: usize) -> Vec<K> {
+        let mut level = vec![self.root];
+        while level.len() < 4 * parts {
+            if let NodeIndex::Leaf(_) = level[0] {
+                break;
+            }
+            level = level
+                .iter()
+                .flat_map(|n| match n {
+                    NodeIndex::Internal(id) => &self.i[*id].sons[0..self.i[*id].cnt],
+                    NodeIndex::Leaf(_) => unreachable!(),
+                })
+                .copied()
+                .collect();
+        }
+
+        let weight = |n: &NodeIndex| match n {
+            NodeIndex::Internal(id) => self.i[*id].cnt,
+            NodeIndex::Leaf(id) => self.l[*id].cnt,
+        };
+        let total: usize = level.iter().map(weight).sum();
+        let mut points = Vec::new();
+        let mut acc = 0;
+        // returns true if the unit starting at `acc` begins the next range
+        let starts_part = |acc: usize, found: usize| found + 1 < parts && (found + 1) * total <= acc * parts;
+        for n in level.iter() {
+            match *n {
+                NodeIndex::Internal(mut id) => {
+                    if starts_part(acc, points.len()) {
+                        // the first key of the subtree
+                        let leaf = loop {
+                            match self.i[id].sons[0] {
+                                NodeIndex::Internal(son) => id = son,
+                                NodeIndex::Leaf(leaf) => break leaf,
+                            }
+                        };
+                        points.push(self.l[leaf].keys[0]);
+                    }
+                    acc += weight(n);
+                }
+                NodeIndex::Leaf(id) => {
+                    let l = &self.l[id];
+                    for k in l.keys[0..l.cnt].iter() {
+                        if starts_part(acc, points.len()) {
+                            points.push(*k);
+                        }
+                        acc += 1;
+                    }
+                }
+            }
+        }
+        points
+    }
+}
+
+#[test]
+fn test_split_points() {
+    let n = 100000u32;
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..n {
+        let k = i * 7919 % n;
+        t.insert(&k, &k);
+    }
+    let points = t.split_points(10);
+    assert_eq!(points.len(), 9);
+    for (i, p) in points.iter().enumerate() {
+        let ideal = (i as u32 + 1) * n / 10;
+        assert!(ideal - n / 40 < *p && *p < ideal + n / 40, "{} is far from {}", p, ideal);
+    }
+    assert!(t.split_points(1).is_empty());
+
+    // a single leaf is split exactly
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..12 {
+        t.insert(&i, &i);
+    }
+    assert_eq!(t.split_points(4), [3, 6, 9]);
+    assert_eq!(t.split_points(20).len(), 11);
+    assert!(BTree::<u32, u32>::new().split_points(4).is_empty());
+}
@@ -0,0 +1,459 @@
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::ops::{Bound, RangeBounds};
+
+use crate::cursor::{Nodes, Path};
+use crate::{BTree, InternalNode, LeafNode, NodeIndex};
+
+/// Range is an iterator over the entries of a tree within a range of keys, from the smallest key to the largest.
+pub struct Range<'a, K, V> {
+    t: &'a BTree<K, V>,
+    path: Path,
+    end: Bound<K>,
+}
+
+/// Iter is a `Range` knowing the number of the entries left, so it reports the exact length.
+pub struct Iter<'a, K, V> {
+    range: Range<'a, K, V>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    /// Wraps `range`, which yields exactly `len` entries.
+    pub(crate) fn new(range: Range<'a, K, V>, len: usize) -> Self {
+        Iter { range, remaining: len }
+    }
+}
+
+/// Chunks is an iterator over the entries of a tree within a range of keys, which yields the entries of one leaf at a
+/// time as the slices of the keys and the values.
+pub struct Chunks<'a, K, V> {
+    t: &'a BTree<K, V>,
+    path: Path,
+    end: Bound<K>,
+    // the end of the range is reached
+    done: bool,
+}
+
+/// IntoIter is a consuming iterator over the entries of a tree in order, yielding them by value.
+pub struct IntoIter<K, V> {
+    t: BTree<K, V>,
+    path: Path,
+    remaining: usize,
+}
+
+/// IntoKeys is a consuming iterator over the keys of a tree in order.
+pub struct IntoKeys<K, V> {
+    inner: IntoIter<K, V>,
+}
+
+/// IntoValues is a consuming iterator over the values of a tree in the order of the keys.
+pub struct IntoValues<K, V> {
+    inner: IntoIter<K, V>,
+}
+
+/// RangeMut is the same as `Range`, but yields mutable references to the values.
+pub struct RangeMut<'a, K, V> {
+    nodes: RawNodes<'a, K, V>,
+    path: Path,
+    end: Bound<K>,
+}
+
+/// Page is a page of the entries in the order of the keys, returned by `BTree::page`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<K, V> {
+    pub entries: Vec<(K, V)>,
+    /// The continuation token passed to `BTree::page` for the next page, which is the last key of this page. None if
+    /// there are no more entries.
+    pub next: Option<K>,
+}
+
+/// A view of the tree for `RangeMut`. The leaves are accessed by raw pointers, and only the keys are referenced,
+/// so walking to the next entry does not alias the values lent out before.
+struct RawNodes<'a, K, V> {
+    i: &'a [InternalNode<K>],
+    l: *mut LeafNode<K, V>,
+    root: NodeIndex,
+    _marker: PhantomData<&'a mut LeafNode<K, V>>,
+}
+
+impl<'a, K, V> Nodes for RawNodes<'a, K, V> {
+    type K = K;
+
+    fn root(&self) -> NodeIndex {
+        self.root
+    }
+
+    fn internal(&self, id: usize) -> &InternalNode<K> {
+        &self.i[id]
+    }
+
+    fn leaf_keys(&self, id: usize) -> &[K] {
+        unsafe {
+            let l = self.l.add(id);
+            let keys = &(*l).keys;
+            &keys[0..(*l).cnt]
+        }
+    }
+}
+
+/// Returns true if `k` is not beyond the `end` bound.
+fn before_end<K: PartialOrd>(k: &K, end: &Bound<K>) -> bool {
+    match end {
+        Bound::Included(e) => k <= e,
+        Bound::Excluded(e) => k < e,
+        Bound::Unbounded => true,
+    }
+}
+
+/// Points the path to the gap before the first key in the range.
+fn seek_start<T: Nodes>(path: &mut Path, t: &T, start: Bound<&T::K>)
+where
+    T::K: PartialOrd + Copy + Default,
+{
+    match start {
+        Bound::Included(k) => path.seek(t, k),
+        Bound::Excluded(k) => {
+            path.seek(t, k);
+            if let Some((leaf, pos)) = path.peek(t) {
+                if &t.leaf_keys(leaf)[pos] == k {
+                    path.next(t);
+                }
+            }
+        }
+        Bound::Unbounded => path.seek_first(t),
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Returns an iterator over the entries whose keys are in the `range`. An empty or reversed range yields nothing.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        let mut path = Path::new();
+        seek_start(&mut path, self, range.start_bound());
+        Range {
+            t: self,
+            path,
+            end: range.end_bound().cloned(),
+        }
+    }
+
+    /// Returns an iterator over all entries, which knows its length from the entry count of the tree.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter::new(self.range(..), self.len)
+    }
+
+    /// Returns an iterator over the entries whose keys are in the `range`, yielding the keys and the values of every leaf
+    /// as slices, which suits the batched or vectorized processing.
+    pub fn chunks<R: RangeBounds<K>>(&self, range: R) -> Chunks<'_, K, V> {
+        let mut path = Path::new();
+        seek_start(&mut path, self, range.start_bound());
+        Chunks {
+            t: self,
+            path,
+            end: range.end_bound().cloned(),
+            done: false,
+        }
+    }
+
+    /// Returns a consuming iterator over the keys.
+    pub fn into_keys(self) -> IntoKeys<K, V> {
+        IntoKeys { inner: self.into_iter() }
+    }
+
+    /// Returns a consuming iterator over the values.
+    pub fn into_values(self) -> IntoValues<K, V> {
+        IntoValues { inner: self.into_iter() }
+    }
+
+    /// Returns the page of at most `n` entries whose keys are greater than the token `after`, or the first page if it is
+    /// None. The token is just the last key seen, so it stays valid while the tree changes between the pages.
+    ///
+    /// Panics if `n` is 0.
+    pub fn page(&self, after: Option<&K>, n: usize) -> Page<K, V> {
+        assert!(n > 0, "a page holds at least one entry");
+        let start = after.map_or(Bound::Unbounded, |k| Bound::Excluded(*k));
+        let mut range = self.range((start, Bound::Unbounded));
+        let entries: Vec<(K, V)> = range.by_ref().take(n).map(|(k, v)| (*k, *v)).collect();
+        let next = match range.next() {
+            Some(_) => entries.last().map(|(k, _)| *k),
+            None => None,
+        };
+        Page { entries, next }
+    }
+
+    /// Returns an iterator over the entries whose keys are in the `range`, the values can be updated in place.
+    pub fn range_mut<R: RangeBounds<K>>(&mut self, range: R) -> RangeMut<'_, K, V> {
+        let nodes = RawNodes {
+            i: &self.i[..],
+            l: self.l.as_mut_ptr(),
+            root: self.root,
+            _marker: PhantomData,
+        };
+        let mut path = Path::new();
+        seek_start(&mut path, &nodes, range.start_bound());
+        RangeMut {
+            nodes,
+            path,
+            end: range.end_bound().cloned(),
+        }
+    }
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (leaf, pos) = self.path.peek(self.t)?;
+        let l = &self.t.l[leaf];
+        if !before_end(&l.keys[pos], &self.end) {
+            return None;
+        }
+        self.path.next(self.t);
+        Some((&l.keys[pos], &l.values[pos]))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // the range is not counted, but it holds no more entries than the tree
+        (0, Some(self.t.len))
+    }
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ret = self.range.next()?;
+        self.remaining -= 1;
+        Some(ret)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Iterator for Chunks<'a, K, V> {
+    type Item = (&'a [K], &'a [V]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let (leaf, start, end) = self.path.next_chunk(self.t)?;
+        let l = &self.t.l[leaf];
+        let n = l.keys[start..end].partition_point(|k| before_end(k, &self.end));
+        if start + n < end {
+            self.done = true;
+        }
+        if n == 0 {
+            return None;
+        }
+        Some((&l.keys[start..start + n], &l.values[start..start + n]))
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> IntoIterator for BTree<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        let mut path = Path::new();
+        path.seek_first(&self);
+        IntoIter {
+            remaining: self.len,
+            t: self,
+            path,
+        }
+    }
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> IntoIterator for &'a BTree<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (leaf, pos) = self.path.next(&self.t)?;
+        self.remaining -= 1;
+        let l = &self.t.l[leaf];
+        Some((l.keys[pos], l.values[pos]))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Iterator for IntoKeys<K, V> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> ExactSizeIterator for IntoKeys<K, V> {}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Iterator for IntoValues<K, V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> ExactSizeIterator for IntoValues<K, V> {}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Iterator for RangeMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (leaf, pos) = self.path.peek(&self.nodes)?;
+        if !before_end(&self.nodes.leaf_keys(leaf)[pos], &self.end) {
+            return None;
+        }
+        self.path.next(&self.nodes);
+        // every entry is yielded at most once, so the mutable references never alias
+        unsafe {
+            let l = self.nodes.l.add(leaf);
+            Some((&(*l).keys[pos], &mut (*l).values[pos]))
+        }
+    }
+}
+
+#[test]
+fn test_range() {
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..10000 {
+        t.insert(&(i * 2), &i);
+    }
+
+    let keys = |r: Range<u32, u32>| r.map(|(k, _)| *k).collect::<Vec<_>>();
+    assert_eq!(keys(t.range(100..106)), [100, 102, 104]);
+    assert_eq!(keys(t.range(100..=106)), [100, 102, 104, 106]);
+    assert_eq!(keys(t.range(99..105)), [100, 102, 104]);
+    assert_eq!(keys(t.range((Bound::Excluded(100), Bound::Included(104)))), [102, 104]);
+    assert_eq!(keys(t.range(19995..)), [19996, 19998]);
+    assert_eq!(t.range(..).count(), 10000);
+    assert_eq!(t.range(..10).count(), 5);
+    assert_eq!(t.range(5..5).count(), 0);
+    assert_eq!(t.range((Bound::Included(6), Bound::Excluded(4))).count(), 0);
+    assert_eq!(t.range(20000..).count(), 0);
+    assert_eq!(t.range(..10).size_hint(), (0, Some(10000)));
+
+    let mut it = t.iter();
+    assert_eq!(it.len(), 10000);
+    it.nth(99);
+    assert_eq!(it.len(), 9900);
+    assert_eq!(it.count(), 9900);
+    assert_eq!(BTree::<u32, u32>::new().iter().len(), 0);
+
+    // reprice a band, the entries around it are untouched
+    for (k, v) in t.range_mut(1000..2000) {
+        *v = *k + 1;
+    }
+    assert_eq!(t.lookup(&998), Some(&499));
+    assert_eq!(t.lookup(&1000), Some(&1001));
+    assert_eq!(t.lookup(&1998), Some(&1999));
+    assert_eq!(t.lookup(&2000), Some(&1000));
+
+    // the references live as long as the iterator
+    let mut values: Vec<&mut u32> = t.range_mut((Bound::Excluded(0), Bound::Excluded(6))).map(|(_, v)| v).collect();
+    assert_eq!(values.len(), 2);
+    *values[0] = 42;
+    *values[1] = 43;
+    assert_eq!(t.range(..=4).map(|(_, v)| *v).collect::<Vec<_>>(), [0, 42, 43]);
+}
+
+#[test]
+fn test_into_iter() {
+    let mut t = BTree::<u32, u64>::new();
+    for i in 0..10000 {
+        t.insert(&(i * 7919 % 10000), &(i as u64));
+    }
+    let mut n = 0;
+    for (k, v) in &t {
+        assert_eq!(t.lookup(k), Some(v));
+        n += 1;
+    }
+    assert_eq!(n, 10000);
+
+    let entries: Vec<(u32, u64)> = t.range(..).map(|(k, v)| (*k, *v)).collect();
+    let mut it = BTree::from_sorted_vec(entries.clone()).into_iter();
+    assert_eq!(it.len(), 10000);
+    assert_eq!(it.next(), Some(entries[0]));
+    assert_eq!(it.len(), 9999);
+    assert!(it.eq(entries[1..].iter().copied()));
+
+    let values: Vec<u64> = entries.iter().map(|(_, v)| *v).collect();
+    assert_eq!(BTree::from_sorted_vec(entries).into_values().collect::<Vec<_>>(), values);
+    assert!(t.into_keys().eq(0..10000));
+    assert_eq!(BTree::<u32, u32>::new().into_iter().next(), None);
+}
+
+#[test]
+fn test_chunks() {
+    let mut t = BTree::<u32, u64>::new();
+    for i in 0..10000 {
+        t.insert(&(i * 2), &(i as u64));
+    }
+    let total: u64 = t.chunks(..).map(|(_, vs)| vs.iter().sum::<u64>()).sum();
+    assert_eq!(total, (0..10000).sum());
+    assert!(t.chunks(..).all(|(ks, vs)| !ks.is_empty() && ks.len() == vs.len()));
+
+    for (a, b) in [(0, 20000), (99, 1001), (500, 502), (19998, 30000), (5, 5)].iter() {
+        let chunked: Vec<u32> = t.chunks(*a..*b).flat_map(|(ks, _)| ks.iter().copied()).collect();
+        let expected: Vec<u32> = t.range(*a..*b).map(|(k, _)| *k).collect();
+        assert_eq!(chunked, expected);
+    }
+    assert_eq!(t.chunks(30000..).next(), None);
+    assert_eq!(BTree::<u32, u32>::new().chunks(..).next(), None);
+}
+
+#[test]
+fn test_page() {
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..1000 {
+        t.insert(&(i * 2), &i);
+    }
+
+    let mut seen = Vec::new();
+    let mut token = None;
+    loop {
+        let page = t.page(token.as_ref(), 64);
+        assert!(page.entries.len() <= 64);
+        seen.extend(page.entries.iter().map(|(k, _)| *k));
+        token = page.next;
+        if token.is_none() {
+            break;
+        }
+        // the changes before the token between the pages are not seen, even if the token itself is removed
+        t.insert(&(token.unwrap() - 1), &0);
+        t.remove(&token.unwrap());
+    }
+    assert_eq!(seen, (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+
+    // exactly one page
+    assert_eq!(t.page(Some(&1996), 2), Page { entries: vec![(1998, 999)], next: None });
+    assert_eq!(t.page(Some(&1997), 1).next, None);
+    assert_eq!(BTree::<u32, u32>::new().page(None, 10), Page { entries: vec![], next: None });
+}
@@ -0,0 +1,108 @@
+use crate::float::{F32Key, F64Key};
+use crate::BTree;
+
+/// Numeric keys, which are mapped to numbers for the interpolation search. The mapping only guides the search, it
+/// should follow the order of the keys, but a lossy one is still correct.
+pub trait Numeric {
+    fn to_f64(&self) -> f64;
+}
+
+macro_rules! numeric {
+    ($($t:ty),*) => {
+        $(
+            impl Numeric for $t {
+                fn to_f64(&self) -> f64 {
+                    *self as f64
+                }
+            }
+        )*
+    };
+}
+
+numeric!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+impl Numeric for F64Key {
+    fn to_f64(&self) -> f64 {
+        self.0
+    }
+}
+
+impl Numeric for F32Key {
+    fn to_f64(&self) -> f64 {
+        self.0 as f64
+    }
+}
+
+/// The same as `lower_bound`, but it probes the position interpolated from the first and the last keys, then walks to
+/// the answer. It takes a few probes for the uniformly distributed keys, and a linear walk in the worst case.
+pub(crate) fn interpolation_search<T: PartialOrd>(a: &[T], val: &T, f: fn(&T) -> f64) -> usize {
+    if a.is_empty() || val <= &a[0] {
+        return 0;
+    }
+    let last = a.len() - 1;
+    if &a[last] < val {
+        return a.len();
+    }
+
+    // now a[0] < val <= a[last], so the answer is in [1, last]
+    let (lo, hi) = (f(&a[0]), f(&a[last]));
+    let guess = (f(val) - lo) / (hi - lo) * last as f64;
+    // NaN is converted to 0
+    let mut i = (guess as usize).clamp(1, last);
+    if &a[i] < val {
+        while &a[i] < val {
+            i += 1;
+        }
+    } else {
+        while &a[i - 1] >= val {
+            i -= 1;
+        }
+    }
+    i
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy + Numeric, V: Default + Copy> BTree<K, V> {
+    /// Enables or disables the interpolation search within the nodes for the point lookups, instead of the binary
+    /// search. It takes fewer probes if the keys are roughly uniformly distributed, and more otherwise.
+    pub fn set_interpolation_search(&mut self, enabled: bool) {
+        self.interpolate = if enabled { Some(K::to_f64) } else { None };
+    }
+}
+
+impl<K, V> BTree<K, V> {
+    pub fn interpolation_search(&self) -> bool {
+        self.interpolate.is_some()
+    }
+}
+
+#[test]
+fn test_interpolation_search() {
+    use crate::lower_bound;
+
+    let uniform: [u32; 8] = [0, 10, 20, 30, 40, 50, 60, 70];
+    let skewed: [u32; 8] = [0, 1, 2, 3, 4, 5, 6, 1000000];
+    let dups: [u32; 6] = [1, 1, 5, 5, 5, 9];
+    for a in [&uniform[..], &skewed[..], &dups[..], &[7], &[]] {
+        for val in (0..80).chain([999999, 1000000, 1000001, u32::MAX]) {
+            assert_eq!(interpolation_search(a, &val, u32::to_f64), lower_bound(a, &val), "{:?} {}", a, val);
+        }
+    }
+    let floats = [f64::NEG_INFINITY, -1.0, 0.0, 2.5, f64::INFINITY];
+    for val in [f64::NEG_INFINITY, -2.0, 0.0, 1.0, 3.0, f64::INFINITY] {
+        assert_eq!(interpolation_search(&floats, &val, f64::to_f64), lower_bound(&floats, &val));
+    }
+
+    let mut t = BTree::<u64, u64>::new();
+    t.set_interpolation_search(true);
+    assert!(t.interpolation_search());
+    for i in 0..10000 {
+        t.insert(&(i * i), &i);
+    }
+    for i in 0..10000 {
+        assert_eq!(t.lookup(&(i * i)), Some(&i));
+        assert_eq!(t.lookup(&(i * i + 1)), if i == 0 { Some(&1) } else { None });
+    }
+    t.set_interpolation_search(false);
+    assert!(!t.interpolation_search());
+    assert_eq!(t.lookup(&(99 * 99)), Some(&99));
+}
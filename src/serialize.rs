@@ -0,0 +1,392 @@
+use std::cmp::Ordering;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::RangeBounds;
+
+use crate::mmap::{from_bytes, slice_bytes, slice_bytes_mut, Pod};
+use crate::mvcc::{MvccBTree, Snapshot};
+use crate::paged::PagedBTree;
+use crate::{BTree, LeafNode, NODE_DEG};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"BTREESR1");
+const EXPORT_MAGIC: u64 = u64::from_le_bytes(*b"BTREEEX1");
+
+// The format is a header followed by the leaves from the leftmost to the rightmost one. All integers are little-endian
+// u64s, and keys and values are stored as their bytes:
+//
+//     | magic | key size | value size | leaf count | leaf 0 | leaf 1 | ... |
+//
+// where each leaf is a run of its keys followed by a run of its values:
+//
+//     | cnt | keys[0..cnt] | values[0..cnt] |
+//
+// Loading copies the runs into leaves as they are, and rebuilds the internal nodes on top of them.
+//
+// An export of a range of keys is streamed without knowing the number of the entries in advance, so it is a header
+// followed by runs of any sizes, and an empty run marks the end:
+//
+//     | magic | key size | value size | run 0 | run 1 | ... | 0 |
+//
+// where every run is laid out as a leaf above, and the keys are strictly increasing across the runs.
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Reads `cnt` items into `out`. The count comes from the input, so the items are read in bounded pieces rather than
+/// allocated at once.
+fn read_run<R: Read, T: Pod + Default>(r: &mut R, cnt: usize, out: &mut Vec<T>) -> io::Result<()> {
+    let mut left = cnt;
+    while left > 0 {
+        let n = left.min(NODE_DEG);
+        let start = out.len();
+        out.resize(start + n, T::default());
+        r.read_exact(slice_bytes_mut(&mut out[start..]))?;
+        left -= n;
+    }
+    Ok(())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads the header of an export, and checks that it holds the entries of `K` and `V`.
+fn read_export_header<R: Read, K, V>(r: &mut R) -> io::Result<()> {
+    let mut header = [0u8; 24];
+    r.read_exact(&mut header)?;
+    let field = |i: usize| u64::from_le(from_bytes(&header[i * 8..]));
+    if field(0) != EXPORT_MAGIC {
+        return Err(invalid("not an exported range"));
+    }
+    if field(1) != size_of::<K>() as u64 || field(2) != size_of::<V>() as u64 {
+        return Err(invalid("the sizes of the keys or the values do not match"));
+    }
+    Ok(())
+}
+
+/// ExportWriter streams the entries of a range of keys in the export format, which is read back by
+/// `BTree::import_from`.
+///
+/// `BTree::export_range` and `MvccBTree::export_range` export a range at once. Writing the batches of
+/// `MvccBTree::scan_next` directly exports a pinned snapshot of a tree shared behind a lock, without holding the lock
+/// for the whole export.
+pub struct ExportWriter<K, V, W: Write> {
+    w: W,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K: Pod, V: Pod, W: Write> ExportWriter<K, V, W> {
+    /// Writes the header of the export into `w`.
+    pub fn new(mut w: W) -> io::Result<Self> {
+        for x in [EXPORT_MAGIC, size_of::<K>() as u64, size_of::<V>() as u64].iter() {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        Ok(ExportWriter { w, _marker: PhantomData })
+    }
+
+    /// Writes the entries, whose keys must be greater than the ones written before, and sorted.
+    pub fn write(&mut self, keys: &[K], values: &[V]) -> io::Result<()> {
+        assert_eq!(keys.len(), values.len());
+        if keys.is_empty() {
+            return Ok(());
+        }
+        self.w.write_all(&(keys.len() as u64).to_le_bytes())?;
+        self.w.write_all(slice_bytes(keys))?;
+        self.w.write_all(slice_bytes(values))
+    }
+
+    /// Writes the entries as `write` does.
+    pub fn write_entries(&mut self, entries: &[(K, V)]) -> io::Result<()> {
+        let (keys, values): (Vec<K>, Vec<V>) = entries.iter().copied().unzip();
+        self.write(&keys, &values)
+    }
+
+    /// Ends the export, and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.w.write_all(&0u64.to_le_bytes())?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+impl<K: Pod + PartialOrd + PartialEq + Default, V: Pod + Default> MvccBTree<K, V> {
+    /// Writes the entries whose keys are in the `range` as seen by `snapshot` into `w`, in the format read back by
+    /// `BTree::import_from`.
+    pub fn export_range<R: RangeBounds<K>, W: Write>(&self, snapshot: &Snapshot, range: R, w: W) -> io::Result<W> {
+        let mut scan = snapshot.scan(range);
+        let mut export = ExportWriter::new(w)?;
+        loop {
+            let batch = self.scan_next(&mut scan, NODE_DEG);
+            if batch.is_empty() {
+                return export.finish();
+            }
+            export.write_entries(&batch)?;
+        }
+    }
+}
+
+impl<K: Pod + PartialOrd + PartialEq + Default, V: Pod + Default> BTree<K, V> {
+    /// Writes the entries whose keys are in the `range` into `w`, in the format read back by `import_from`.
+    pub fn export_range<R: RangeBounds<K>, W: Write>(&self, range: R, w: W) -> io::Result<W> {
+        let mut export = ExportWriter::new(w)?;
+        for (keys, values) in self.chunks(range) {
+            export.write(keys, values)?;
+        }
+        export.finish()
+    }
+
+    /// Reads the entries written by `export_range` or `ExportWriter`, and bulk loads them into a new tree.
+    pub fn import_from<R: Read>(mut r: R) -> io::Result<Self> {
+        read_export_header::<R, K, V>(&mut r)?;
+        let mut entries: Vec<(K, V)> = Vec::new();
+        let (mut keys, mut values) = (Vec::new(), Vec::new());
+        loop {
+            let cnt = read_u64(&mut r)? as usize;
+            if cnt == 0 {
+                break;
+            }
+            keys.clear();
+            values.clear();
+            read_run(&mut r, cnt, &mut keys)?;
+            read_run(&mut r, cnt, &mut values)?;
+            entries.extend(keys.iter().copied().zip(values.iter().copied()));
+        }
+        if !entries.windows(2).all(|w| w[0].0.partial_cmp(&w[1].0) == Some(Ordering::Less)) {
+            return Err(invalid("the keys are not sorted"));
+        }
+        Ok(BTree::from_sorted_vec(entries))
+    }
+
+    /// Writes the whole tree into `w` in a compact binary format, which is read back by `deserialize_from`.
+    pub fn serialize_into<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let leaves = self.leaf_ids();
+        for x in [MAGIC, size_of::<K>() as u64, size_of::<V>() as u64, leaves.len() as u64].iter() {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        for id in leaves {
+            let l = &self.l[id];
+            w.write_all(&(l.cnt as u64).to_le_bytes())?;
+            w.write_all(slice_bytes(&l.keys[0..l.cnt]))?;
+            w.write_all(slice_bytes(&l.values[0..l.cnt]))?;
+        }
+        w.flush()
+    }
+
+    /// Reads a tree written by `serialize_into`.
+    pub fn deserialize_from<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut header = [0u8; 32];
+        r.read_exact(&mut header)?;
+        let field = |i: usize| u64::from_le(from_bytes(&header[i * 8..]));
+        if field(0) != MAGIC {
+            return Err(invalid("not a serialized tree"));
+        }
+        if field(1) != size_of::<K>() as u64 || field(2) != size_of::<V>() as u64 {
+            return Err(invalid("the sizes of the keys or the values do not match"));
+        }
+        let leaf_cnt = field(3) as usize;
+        if leaf_cnt == 0 {
+            return Err(invalid("a tree has at least one leaf"));
+        }
+
+        let mut t = BTree::new();
+        // the count in the header is not trusted to size the buffer, it grows as the leaves are read
+        let mut leaves = Vec::new();
+        let mut last: Option<K> = None;
+        for i in 0..leaf_cnt {
+            let mut l = LeafNode::new();
+            l.cnt = read_u64(&mut r)? as usize;
+            if l.cnt > NODE_DEG || (l.cnt == 0 && leaf_cnt > 1) {
+                return Err(invalid("bad leaf size"));
+            }
+            r.read_exact(slice_bytes_mut(&mut l.keys[0..l.cnt]))?;
+            r.read_exact(slice_bytes_mut(&mut l.values[0..l.cnt]))?;
+
+            // the keys must be strictly increasing, otherwise lookups would be broken
+            for k in l.keys[0..l.cnt].iter() {
+                if last.is_some_and(|last| last.partial_cmp(k) != Some(Ordering::Less)) {
+                    return Err(invalid("the keys are not sorted"));
+                }
+                last = Some(*k);
+            }
+
+            t.len += l.cnt;
+            // the root leaf of the new tree is reused as the leftmost leaf
+            if i == 0 {
+                t.l[0] = l;
+                leaves.push(0);
+            } else {
+                leaves.push(t.alloc_leaf(l));
+            }
+        }
+        t.build_internal_levels(&leaves, NODE_DEG);
+        Ok(t)
+    }
+}
+
+impl<K: Pod + PartialOrd + Default, V: Pod + Default> PagedBTree<K, V> {
+    /// Ingests the entries written by `export_range` or `ExportWriter` as `PagedBTree::ingest` does, reading one run at
+    /// a time, so the memory is bounded by the largest run rather than the export. Returns the number of ingested
+    /// entries.
+    pub fn ingest_from<R: Read>(&mut self, mut r: R) -> io::Result<usize> {
+        read_export_header::<R, K, V>(&mut r)?;
+        let mut err = None;
+        let (mut keys, mut values, mut pos) = (Vec::new(), Vec::new(), 0);
+        let entries = std::iter::from_fn(|| {
+            if pos == keys.len() {
+                keys.clear();
+                values.clear();
+                pos = 0;
+                let run = read_u64(&mut r).and_then(|cnt| {
+                    read_run(&mut r, cnt as usize, &mut keys)?;
+                    read_run(&mut r, cnt as usize, &mut values)
+                });
+                if let Err(e) = run {
+                    err = Some(e);
+                    return None;
+                }
+                // the empty run ends the export
+                if keys.is_empty() {
+                    return None;
+                }
+            }
+            pos += 1;
+            Some((keys[pos - 1], values[pos - 1]))
+        });
+        let n = self.ingest(entries)?;
+        err.map_or(Ok(n), Err)
+    }
+}
+
+#[test]
+fn test_serialize() {
+    let mut t = BTree::<u32, u64>::new();
+    let mut buf = Vec::new();
+    t.serialize_into(&mut buf).unwrap();
+    let empty = BTree::<u32, u64>::deserialize_from(&buf[..]).unwrap();
+    assert_eq!(empty.lookup(&0), None);
+
+    for i in 0..100000u32 {
+        t.insert(&(i.wrapping_mul(2654435761) % 1000003), &(i as u64));
+    }
+    buf.clear();
+    t.serialize_into(&mut buf).unwrap();
+
+    let mut loaded = BTree::<u32, u64>::deserialize_from(&buf[..]).unwrap();
+    for i in 0..100000u32 {
+        let k = i.wrapping_mul(2654435761) % 1000003;
+        assert_eq!(loaded.lookup(&k), t.lookup(&k));
+    }
+    assert_eq!(loaded.lookup(&1000003), None);
+    assert_eq!(loaded.len(), t.len());
+    // the loaded tree is a normal tree
+    assert_eq!(loaded.insert(&1000003, &42), None);
+    assert_eq!(loaded.lookup(&1000003), Some(&42));
+
+    // serializing the loaded tree gives the same bytes
+    let mut again = Vec::new();
+    BTree::<u32, u64>::deserialize_from(&buf[..]).unwrap().serialize_into(&mut again).unwrap();
+    assert_eq!(buf, again);
+
+    // wrong types and corrupted data are rejected
+    assert!(BTree::<u64, u64>::deserialize_from(&buf[..]).is_err());
+    assert!(BTree::<u32, u64>::deserialize_from(&buf[..buf.len() - 1]).is_err());
+    let mut unsorted = buf.clone();
+    unsorted[40..44].copy_from_slice(&u32::MAX.to_ne_bytes());
+    assert!(BTree::<u32, u64>::deserialize_from(&unsorted[..]).is_err());
+    // a corrupted leaf count fails at the end of the data, without allocating for it
+    let mut huge = buf.clone();
+    huge[24..32].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+    assert!(BTree::<u32, u64>::deserialize_from(&huge[..]).is_err());
+}
+
+#[test]
+fn test_export_import() {
+    let mut t = BTree::<u32, u64>::new();
+    for i in 0..10000u32 {
+        t.insert(&(i * 3), &(i as u64));
+    }
+    let buf = t.export_range(300..=3000, Vec::new()).unwrap();
+    let imported = BTree::<u32, u64>::import_from(&buf[..]).unwrap();
+    assert_eq!(imported.len(), 901);
+    assert_eq!(
+        imported.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+        t.range(300..=3000).map(|(k, v)| (*k, *v)).collect::<Vec<_>>()
+    );
+
+    let empty = t.export_range(30000.., Vec::new()).unwrap();
+    assert!(BTree::<u32, u64>::import_from(&empty[..]).unwrap().is_empty());
+
+    // a snapshot is exported as it was pinned, whatever is written after
+    let mut m = MvccBTree::<u32, u64>::new();
+    for i in 0..1000u32 {
+        m.insert(&i, &(i as u64));
+    }
+    let snapshot = m.pin();
+    for i in 0..1000u32 {
+        m.insert(&i, &0);
+    }
+    m.remove(&500);
+    let buf = m.export_range(&snapshot, 100..900, Vec::new()).unwrap();
+    m.unpin(snapshot);
+    let imported = BTree::<u32, u64>::import_from(&buf[..]).unwrap();
+    assert_eq!(imported.len(), 800);
+    assert!((100..900).all(|i| imported.lookup(&i) == Some(&(i as u64))));
+
+    // the runs of a live export are read back as one range
+    let mut export = ExportWriter::<u32, u64, _>::new(Vec::new()).unwrap();
+    export.write_entries(&[(1, 10), (2, 20)]).unwrap();
+    export.write_entries(&[]).unwrap();
+    export.write(&[5], &[50]).unwrap();
+    let buf = export.finish().unwrap();
+    let imported = BTree::<u32, u64>::import_from(&buf[..]).unwrap();
+    assert_eq!(imported.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(), [(1, 10), (2, 20), (5, 50)]);
+
+    // wrong types, truncated or unsorted exports are rejected
+    assert!(BTree::<u64, u64>::import_from(&buf[..]).is_err());
+    assert!(BTree::<u32, u64>::import_from(&buf[..buf.len() - 1]).is_err());
+    let mut export = ExportWriter::<u32, u64, _>::new(Vec::new()).unwrap();
+    export.write(&[2, 1], &[0, 0]).unwrap();
+    assert!(BTree::<u32, u64>::import_from(&export.finish().unwrap()[..]).is_err());
+}
+
+#[test]
+fn test_export_ingest() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-export-ingest-{}", std::process::id()));
+    let remove = || {
+        let _ = std::fs::remove_file(&path);
+        let mut wal = path.as_os_str().to_owned();
+        wal.push(".wal");
+        let _ = std::fs::remove_file(wal);
+    };
+    remove();
+
+    let mut src = BTree::<u32, u64>::new();
+    for i in 0..100000u32 {
+        src.insert(&(i * 3), &(i as u64));
+    }
+    let buf = src.export_range(.., Vec::new()).unwrap();
+    {
+        let mut t = unsafe { PagedBTree::<u32, u64>::open(&path, 16) }.unwrap();
+        t.insert(&1, &1).unwrap();
+        assert_eq!(t.ingest_from(&buf[..]).unwrap(), 100000);
+        // wrong types are rejected, and a truncated export keeps the entries before the end
+        let wrong = BTree::<u64, u64>::new().export_range(.., Vec::new()).unwrap();
+        assert!(t.ingest_from(&wrong[..]).is_err());
+        let mut export = ExportWriter::<u32, u64, _>::new(Vec::new()).unwrap();
+        export.write(&[300000, 300001], &[7, 8]).unwrap();
+        let buf = export.finish().unwrap();
+        assert!(t.ingest_from(&buf[..buf.len() - 1]).is_err());
+    }
+
+    let mut t = unsafe { PagedBTree::<u32, u64>::open(&path, 16) }.unwrap();
+    let expected = src.iter().map(|(k, v)| (*k, *v)).chain([(300000, 7), (300001, 8)].iter().copied());
+    let mut expected: Vec<_> = expected.chain(std::iter::once((1, 1))).collect();
+    expected.sort_unstable();
+    assert!(t.range(..).map(|e| e.unwrap()).eq(expected.into_iter()));
+    drop(t);
+    remove();
+}
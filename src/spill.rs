@@ -0,0 +1,281 @@
+use std::io;
+use std::iter::Peekable;
+use std::mem::size_of;
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+
+use crate::mmap::Pod;
+use crate::paged::{PagedBTree, PagedRange};
+use crate::range::Range;
+use crate::{BTree, InternalNode, LeafNode, NODE_DEG};
+
+/// The buffer pool of the spill file, in pages.
+const SPILL_POOL_SIZE: usize = 64;
+
+/// SpillBTree is a B+Tree which keeps its hot leaves in the memory, and spills the cold ones to a file when the memory
+/// used by the in-memory nodes exceeds a cap, so that a data set slightly larger than the memory degrades gracefully
+/// instead of running out of the memory.
+///
+/// Every key lives either in the memory or in the spill file, a `PagedBTree`. Every access to a leaf in the memory
+/// marks it hot. When the nodes exceed the cap, the least recently accessed leaves are moved to the file together,
+/// until the nodes take 3/4 of the cap. Accessing a spilled key reloads it with the following spilled keys of a leaf,
+/// which likely belong to the same cold subtree.
+///
+/// The spill file is scratch space, it is truncated when the tree is created and removed when the tree is dropped.
+pub struct SpillBTree<K: Pod, V: Pod> {
+    mem: BTree<K, V>,
+    disk: PagedBTree<K, V>,
+    path: PathBuf,
+    cap: usize,
+    // the entries in the spill file
+    spilled: usize,
+    // the tick of the last access to every leaf in the memory, by the leaf id
+    heat: Vec<u64>,
+    tick: u64,
+}
+
+impl<K: Pod, V: Pod> Drop for SpillBTree<K, V> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        let mut wal_path = self.path.as_os_str().to_owned();
+        wal_path.push(".wal");
+        let _ = std::fs::remove_file(wal_path);
+    }
+}
+
+impl<K: Pod + PartialOrd + PartialEq + Default, V: Pod + Default> SpillBTree<K, V> {
+    /// News a tree whose in-memory nodes take at most `cap` bytes, spilling to the file `path`.
+    pub fn new<P: AsRef<Path>>(path: P, cap: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut wal_path = path.as_os_str().to_owned();
+        wal_path.push(".wal");
+        for p in [path.as_os_str(), &wal_path].iter() {
+            match std::fs::remove_file(p) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        // the file is created by `open` itself
+        let disk = unsafe { PagedBTree::open(&path, SPILL_POOL_SIZE) }?;
+        Ok(SpillBTree {
+            mem: BTree::new(),
+            disk,
+            path,
+            cap,
+            spilled: 0,
+            heat: Vec::new(),
+            tick: 0,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.mem.len() + self.spilled
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of the entries in the spill file.
+    pub fn spilled_len(&self) -> usize {
+        self.spilled
+    }
+
+    /// Returns the bytes of the nodes in the memory.
+    pub fn memory_usage(&self) -> usize {
+        self.mem.node_bytes()
+    }
+
+    /// Looks up the value of `k`, reloading it from the spill file if it is spilled.
+    pub fn lookup(&mut self, k: &K) -> io::Result<Option<V>> {
+        if let Some((leaf, pos)) = self.mem.locate(k) {
+            self.touch(leaf);
+            return Ok(Some(self.mem.l[leaf].values[pos]));
+        }
+        if self.spilled == 0 || !self.reload(k)? {
+            return Ok(None);
+        }
+        Ok(self.mem.lookup(k).copied())
+    }
+
+    /// Inserts the key value pair, and returns the old value if the key already exists.
+    pub fn insert(&mut self, k: &K, v: &V) -> io::Result<Option<V>> {
+        let mut old = self.mem.insert(k, v);
+        if old.is_none() && self.spilled > 0 {
+            old = self.disk.remove(k)?;
+            if old.is_some() {
+                self.spilled -= 1;
+            }
+        }
+        if let Some((leaf, _)) = self.mem.locate(k) {
+            self.touch(leaf);
+        }
+        self.maybe_spill()?;
+        Ok(old)
+    }
+
+    /// Removes the key, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> io::Result<Option<V>> {
+        if let Some(v) = self.mem.remove(k) {
+            return Ok(Some(v));
+        }
+        if self.spilled == 0 {
+            return Ok(None);
+        }
+        let old = self.disk.remove(k)?;
+        if old.is_some() {
+            self.spilled -= 1;
+        }
+        Ok(old)
+    }
+
+    /// Returns an iterator over the entries within the range of keys, merging the entries in the memory and the spilled
+    /// ones. It reads the spill file without reloading the entries.
+    pub fn range<R: RangeBounds<K>>(&mut self, range: R) -> SpillRange<'_, K, V> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        SpillRange {
+            mem: self.mem.range(range).peekable(),
+            disk: self.disk.range(range).peekable(),
+            failed: false,
+        }
+    }
+
+    /// Marks the leaf as the most recently accessed one.
+    fn touch(&mut self, leaf: usize) {
+        if self.heat.len() <= leaf {
+            self.heat.resize(leaf + 1, self.tick);
+        }
+        self.tick += 1;
+        self.heat[leaf] = self.tick;
+    }
+
+    /// Moves the spilled entries from `k` on, as many as a leaf holds, back to the memory.
+    /// Returns whether `k` is one of them.
+    fn reload(&mut self, k: &K) -> io::Result<bool> {
+        let mut entries = Vec::new();
+        for e in self.disk.range(*k..).take(NODE_DEG) {
+            entries.push(e?);
+        }
+        if entries.first().map(|e| &e.0) != Some(k) {
+            return Ok(false);
+        }
+        for (k, v) in entries.iter() {
+            self.disk.remove(k)?;
+            self.mem.insert(k, v);
+        }
+        self.spilled -= entries.len();
+        if let Some((leaf, _)) = self.mem.locate(k) {
+            self.touch(leaf);
+        }
+        self.maybe_spill()?;
+        Ok(true)
+    }
+
+    /// Spills the coldest leaves if the nodes in the memory exceed the cap.
+    fn maybe_spill(&mut self) -> io::Result<()> {
+        while self.mem.node_bytes() > self.cap && !self.mem.is_empty() {
+            let mut leaves = self.mem.leaf_ids();
+            let heat = &self.heat;
+            leaves.sort_by_key(|&id| heat.get(id).copied().unwrap_or(0));
+
+            // free a quarter of the cap at once, so that the spills are not triggered by every insertion
+            let excess = self.mem.node_bytes() - self.cap * 3 / 4;
+            let cnt = excess.div_ceil(size_of::<LeafNode<K, V>>()).clamp(1, leaves.len());
+            let mut entries = Vec::new();
+            for &id in leaves[..cnt].iter() {
+                let l = &self.mem.l[id];
+                entries.extend(l.keys[..l.cnt].iter().copied().zip(l.values[..l.cnt].iter().copied()));
+            }
+            entries.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            for (k, _) in entries.iter() {
+                self.mem.remove(k);
+            }
+            self.spilled += entries.len();
+            self.disk.ingest(entries)?;
+        }
+        Ok(())
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Returns the bytes of the nodes in use.
+    fn node_bytes(&self) -> usize {
+        (self.i.len() - self.free_i.len()) * size_of::<InternalNode<K>>()
+            + (self.l.len() - self.free_l.len()) * size_of::<LeafNode<K, V>>()
+    }
+}
+
+/// SpillRange is an iterator over the entries of a `SpillBTree` within a range of keys, from the smallest key to the
+/// largest. Reading the spill file may fail, the scan ends after yielding the error.
+pub struct SpillRange<'a, K: Pod + PartialOrd + Default, V: Pod + Default> {
+    mem: Peekable<Range<'a, K, V>>,
+    disk: Peekable<PagedRange<'a, K, V>>,
+    failed: bool,
+}
+
+impl<K: Pod + PartialOrd + PartialEq + Default, V: Pod + Default> Iterator for SpillRange<'_, K, V> {
+    type Item = io::Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        // every key is either in the memory or in the spill file
+        let from_mem = match (self.mem.peek(), self.disk.peek()) {
+            (Some((m, _)), Some(Ok((d, _)))) => *m < d,
+            (Some(_), Some(Err(_))) => false,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if from_mem {
+            self.mem.next().map(|(k, v)| Ok((*k, *v)))
+        } else {
+            let e = self.disk.next()?;
+            self.failed = e.is_err();
+            Some(e)
+        }
+    }
+}
+
+#[test]
+fn test_spill() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-spill-{}", std::process::id()));
+    let n = 20000u64;
+    let cap = 64 * size_of::<LeafNode<u64, u64>>();
+    let mut t = SpillBTree::<u64, u64>::new(&path, cap).unwrap();
+    for i in 0..n {
+        assert_eq!(t.insert(&(i * 7919 % n), &i).unwrap(), None);
+        assert!(t.memory_usage() <= cap);
+    }
+    assert_eq!(t.len(), n as usize);
+    assert!(t.spilled_len() > n as usize / 2);
+
+    // a hot range stays in the memory while the others are accessed once
+    for round in 0..3 {
+        for i in 0..n / 10 {
+            let k = (round * n / 10 + i) % n;
+            assert_eq!(t.lookup(&(k * 7919 % n)).unwrap(), Some(k));
+            assert_eq!(t.lookup(&(i % 100)).unwrap(), Some((i % 100) * 17679 % n));
+        }
+    }
+    let spilled = t.spilled_len();
+    for k in 0..100 {
+        assert_eq!(t.lookup(&k).unwrap(), Some(k * 17679 % n));
+    }
+    assert_eq!(t.spilled_len(), spilled);
+    assert!(t.memory_usage() <= cap);
+
+    // updates and removals reach the spilled entries too
+    for i in 0..n / 2 {
+        assert_eq!(t.insert(&(i * 2), &0).unwrap(), Some(i * 2 * 17679 % n));
+        assert!(t.remove(&(i * 2 + 1)).unwrap().is_some());
+    }
+    assert_eq!(t.len(), n as usize / 2);
+    assert_eq!(t.lookup(&1).unwrap(), None);
+    assert_eq!(t.remove(&1).unwrap(), None);
+    assert!(t.range(..).map(|e| e.unwrap()).eq((0..n / 2).map(|i| (i * 2, 0))));
+    assert!(t.range(1000..=2000).map(|e| e.unwrap().0).eq((500..=1000).map(|i| i * 2)));
+
+    drop(t);
+    assert!(!path.exists());
+}
@@ -0,0 +1,336 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, Write};
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::ptr::NonNull;
+use std::slice;
+
+use crate::mmap::{map_read_only, slice_bytes, Pod};
+use crate::{lower_bound, NODE_DEG};
+
+// The static tree file consists of the full leaves, the levels of the index above them, and the footer.
+//
+// Every leaf is `NODE_DEG` keys followed by `NODE_DEG` values, the last one is padded with zeros. Every level of the
+// index holds the maximum keys of the nodes of the level below it, the same as the internal nodes of a `BTree`, from
+// the level right above the leaves to the root, which has at most `NODE_DEG` keys. The sizes of all sections follow
+// from the number of the entries.
+const STATIC_MAGIC: u64 = u64::from_le_bytes(*b"BTREEST1");
+
+// the sections of the file are padded to it, so the mapped keys and values are aligned
+const ALIGN: usize = 64;
+
+/// The footer ends the file, all fields are stored as little-endian u64s: the magic, `NODE_DEG`, the size of the key,
+/// the size of the value and the number of the entries.
+const FOOTER_SIZE: usize = 5 * 8;
+
+fn padded(size: usize) -> usize {
+    size.div_ceil(ALIGN) * ALIGN
+}
+
+fn leaf_size<K, V>() -> usize {
+    NODE_DEG * (size_of::<K>() + size_of::<V>())
+}
+
+/// Returns the numbers of the keys of the levels of the index over `len` entries, from the bottom.
+fn level_lens(len: usize) -> Vec<usize> {
+    let mut lens = Vec::new();
+    let mut below = len;
+    while below > NODE_DEG {
+        below = below.div_ceil(NODE_DEG);
+        lens.push(below);
+    }
+    lens
+}
+
+fn write_padding<W: Write>(w: &mut W, size: usize) -> io::Result<()> {
+    w.write_all(&[0u8; ALIGN][..padded(size) - size])
+}
+
+/// StaticBTreeBuilder writes a static tree file from the entries sorted by the keys, which is opened by
+/// `StaticBTree::open`.
+///
+/// The leaves are streamed into the writer as they fill up, only the maximum keys of the leaves are kept in the memory
+/// until `finish`, so the entries can be many times larger than the memory.
+pub struct StaticBTreeBuilder<K, V, W: Write> {
+    w: W,
+    keys: Vec<K>,
+    values: Vec<V>,
+    maxes: Vec<K>,
+    len: usize,
+}
+
+impl<K: Pod + PartialOrd, V: Pod, W: Write> StaticBTreeBuilder<K, V, W> {
+    pub fn new(w: W) -> Self {
+        assert!(align_of::<K>() <= ALIGN && align_of::<V>() <= ALIGN);
+        StaticBTreeBuilder {
+            w,
+            keys: Vec::with_capacity(NODE_DEG),
+            values: Vec::with_capacity(NODE_DEG),
+            maxes: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Appends an entry, whose key must be greater than the ones pushed before.
+    pub fn push(&mut self, k: &K, v: &V) -> io::Result<()> {
+        if matches!(self.keys.last().or(self.maxes.last()), Some(last) if last >= k) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the keys are not strictly increasing"));
+        }
+        self.keys.push(*k);
+        self.values.push(*v);
+        self.len += 1;
+        if self.keys.len() == NODE_DEG {
+            self.write_leaf()?;
+        }
+        Ok(())
+    }
+
+    fn write_leaf(&mut self) -> io::Result<()> {
+        let empty = NODE_DEG - self.keys.len();
+        self.w.write_all(slice_bytes(&self.keys))?;
+        self.w.write_all(&vec![0u8; empty * size_of::<K>()])?;
+        self.w.write_all(slice_bytes(&self.values))?;
+        self.w.write_all(&vec![0u8; empty * size_of::<V>()])?;
+        self.maxes.push(self.keys[self.keys.len() - 1]);
+        self.keys.clear();
+        self.values.clear();
+        Ok(())
+    }
+
+    /// Writes the rest of the leaves, the index and the footer, and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.keys.is_empty() {
+            self.write_leaf()?;
+        }
+        write_padding(&mut self.w, self.maxes.len() * leaf_size::<K, V>())?;
+
+        let mut level = Vec::new();
+        if self.len > NODE_DEG {
+            level = std::mem::take(&mut self.maxes);
+        }
+        while !level.is_empty() {
+            self.w.write_all(slice_bytes(&level))?;
+            write_padding(&mut self.w, level.len() * size_of::<K>())?;
+            level = if level.len() > NODE_DEG {
+                level.chunks(NODE_DEG).map(|c| c[c.len() - 1]).collect()
+            } else {
+                Vec::new()
+            };
+        }
+
+        let footer = [STATIC_MAGIC, NODE_DEG as u64, size_of::<K>() as u64, size_of::<V>() as u64, self.len as u64];
+        for x in footer.iter() {
+            self.w.write_all(&x.to_le_bytes())?;
+        }
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+/// StaticBTree is a read-only tree served directly from a mapped static tree file.
+///
+/// Opening maps the file without reading it, so it is instant regardless of the size, and the pages are loaded by the
+/// lookups on demand and shared with the page cache.
+pub struct StaticBTree<K, V> {
+    ptr: NonNull<u8>,
+    size: usize,
+    len: usize,
+    // the offsets and the lengths of the levels of the index, from the bottom
+    levels: Vec<(usize, usize)>,
+    _marker: PhantomData<(K, V)>,
+}
+
+// The mapping is read-only, so it is shared just like a `&[u8]`.
+unsafe impl<K: Sync, V: Sync> Send for StaticBTree<K, V> {}
+unsafe impl<K: Sync, V: Sync> Sync for StaticBTree<K, V> {}
+
+impl<K: Pod + PartialOrd, V: Pod> StaticBTree<K, V> {
+    /// Writes a static tree file at `path` from the entries sorted by the keys.
+    pub fn build<P: AsRef<Path>, I: IntoIterator<Item = (K, V)>>(path: P, entries: I) -> io::Result<()> {
+        let mut builder = StaticBTreeBuilder::new(BufWriter::new(File::create(path)?));
+        for (k, v) in entries {
+            builder.push(&k, &v)?;
+        }
+        builder.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()
+    }
+
+    /// Maps the static tree file at `path` read-only.
+    ///
+    /// # Safety
+    ///
+    /// The file must be written by `StaticBTreeBuilder` with the same `K` and `V`, and must not be modified while the
+    /// tree is open.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut f = File::open(path)?;
+        let size = f.metadata()?.len() as usize;
+        if size < FOOTER_SIZE {
+            return Err(invalid("the file is too short"));
+        }
+        let mut footer = [0u8; FOOTER_SIZE];
+        f.seek(io::SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        f.read_exact(&mut footer)?;
+        let field = |i: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&footer[i * 8..i * 8 + 8]);
+            u64::from_le_bytes(b)
+        };
+        if [field(0), field(1), field(2), field(3)]
+            != [STATIC_MAGIC, NODE_DEG as u64, size_of::<K>() as u64, size_of::<V>() as u64]
+        {
+            return Err(invalid("the file is not a static tree with these key and value types"));
+        }
+
+        let len = field(4) as usize;
+        let mut offset = padded(len.div_ceil(NODE_DEG) * leaf_size::<K, V>());
+        let mut levels = Vec::new();
+        for l in level_lens(len) {
+            levels.push((offset, l));
+            offset += padded(l * size_of::<K>());
+        }
+        if offset + FOOTER_SIZE != size {
+            return Err(invalid("the size of the file does not match the number of the entries"));
+        }
+
+        let ptr = map_read_only(&f, size)?;
+        Ok(StaticBTree { ptr, size, len, levels, _marker: PhantomData })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `len` elements at `offset` of the mapping.
+    fn slice<T>(&self, offset: usize, len: usize) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr().add(offset) as *const T, len) }
+    }
+
+    fn leaf_keys(&self, leaf: usize) -> &[K] {
+        let cnt = NODE_DEG.min(self.len - leaf * NODE_DEG);
+        self.slice(leaf * leaf_size::<K, V>(), cnt)
+    }
+
+    fn entry(&self, i: usize) -> (&K, &V) {
+        let (leaf, pos) = (i / NODE_DEG, i % NODE_DEG);
+        let offset = leaf * leaf_size::<K, V>();
+        let values = self.slice::<V>(offset + NODE_DEG * size_of::<K>(), pos + 1);
+        (&self.leaf_keys(leaf)[pos], &values[pos])
+    }
+
+    /// Returns the number of the keys less than `k`.
+    fn rank(&self, k: &K) -> usize {
+        if self.len == 0 {
+            return 0;
+        }
+        // the first node whose maximum key is not less than `k` holds the answer
+        let mut start = 0;
+        for &(offset, len) in self.levels.iter().rev() {
+            let node = &self.slice::<K>(offset, len)[start..len.min(start + NODE_DEG)];
+            let i = lower_bound(node, k);
+            if i == node.len() {
+                return self.len;
+            }
+            start = (start + i) * NODE_DEG;
+        }
+        start + lower_bound(self.leaf_keys(start / NODE_DEG), k)
+    }
+
+    /// Returns the stored key equal to `k` with its value.
+    pub fn get_key_value(&self, k: &K) -> Option<(&K, &V)> {
+        let i = self.rank(k);
+        if i < self.len && self.entry(i).0 == k {
+            Some(self.entry(i))
+        } else {
+            None
+        }
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.get_key_value(k).map(|(_, v)| v)
+    }
+
+    /// Returns an iterator over the entries in the order of the keys.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> + ExactSizeIterator + '_ {
+        (0..self.len).map(move |i| self.entry(i))
+    }
+
+    /// Returns an iterator over the entries whose keys are in the `range`.
+    pub fn range<R: RangeBounds<K>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = (&K, &V)> + ExactSizeIterator + '_ {
+        let after = |k: &K| {
+            let i = self.rank(k);
+            i + (i < self.len && self.entry(i).0 == k) as usize
+        };
+        let start = match range.start_bound() {
+            Bound::Included(s) => self.rank(s),
+            Bound::Excluded(s) => after(s),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(e) => after(e),
+            Bound::Excluded(e) => self.rank(e),
+            Bound::Unbounded => self.len,
+        };
+        (start..end.max(start)).map(move |i| self.entry(i))
+    }
+}
+
+impl<K, V> Drop for StaticBTree<K, V> {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, self.size) };
+    }
+}
+
+#[test]
+fn test_static_btree() {
+    use crate::BTree;
+
+    let path = std::env::temp_dir().join(format!("btree-rs-test-static-{}", std::process::id()));
+
+    for n in [0u64, 1, 32, 33, 1024, 1025, 40000] {
+        StaticBTree::build(&path, (0..n).map(|i| (i * 2, i as u32))).unwrap();
+        let t = unsafe { StaticBTree::<u64, u32>::open(&path) }.unwrap();
+        assert_eq!((t.len(), t.is_empty()), (n as usize, n == 0));
+        for k in 0..2 * n + 2 {
+            let expected = if k % 2 == 0 && k < 2 * n { Some((k / 2) as u32) } else { None };
+            assert_eq!(t.lookup(&k).copied(), expected);
+        }
+        assert!(t.iter().map(|(k, v)| (*k, *v)).eq((0..n).map(|i| (i * 2, i as u32))));
+    }
+
+    let mut bt = BTree::<u64, u64>::new();
+    for i in 0..100000u64 {
+        bt.insert(&(i * 2654435761 % 1000003), &i);
+    }
+    let mut builder = StaticBTreeBuilder::new(BufWriter::new(File::create(&path).unwrap()));
+    for (k, v) in bt.iter() {
+        builder.push(k, v).unwrap();
+    }
+    // the keys must be increasing
+    assert!(builder.push(&0, &0).is_err());
+    builder.finish().unwrap();
+
+    let t = unsafe { StaticBTree::<u64, u64>::open(&path) }.unwrap();
+    for k in 0..1000003 {
+        assert_eq!(t.lookup(&k), bt.lookup(&k));
+    }
+    assert!(t.iter().eq(bt.iter()));
+    assert!(t.range(1000..=5000).eq(bt.range(1000..=5000)));
+    let r = (Bound::Excluded(5000), Bound::Excluded(9000));
+    assert!(t.range(r).eq(bt.range(r)));
+    assert_eq!(t.iter().next_back(), bt.iter().last());
+
+    // the types do not match
+    assert!(unsafe { StaticBTree::<u32, u64>::open(&path) }.is_err());
+    assert!(unsafe { StaticBTree::<u64, u32>::open(&path) }.is_err());
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,149 @@
+use alloc::vec::Vec;
+use core::mem::take;
+
+use crate::BTree;
+
+/// TombstoneBTree is a B+Tree whose removals only mark the entries dead in their leaves, so a removal never splits,
+/// merges or moves entries between the nodes, and costs no more than a lookup.
+///
+/// The dead entries are swept by the compaction later: `compact_step` sweeps a bounded number of entries at a time,
+/// from an idle loop or a background thread taking the lock for one step, and `compact` rewrites the whole tree into
+/// full leaves at once.
+pub struct TombstoneBTree<K, V> {
+    t: BTree<K, Option<V>>,
+    // the number of the dead entries
+    dead: usize,
+    // the key `compact_step` goes on from, None at the beginning of a pass
+    resume: Option<K>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for TombstoneBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> TombstoneBTree<K, V> {
+    pub fn new() -> Self {
+        TombstoneBTree {
+            t: BTree::new(),
+            dead: 0,
+            resume: None,
+        }
+    }
+
+    /// Returns the number of the live entries.
+    pub fn len(&self) -> usize {
+        self.t.len() - self.dead
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of the dead entries waiting for the compaction.
+    pub fn dead(&self) -> usize {
+        self.dead
+    }
+
+    /// Inserts or updates the key value pair, reusing the dead entry of `k` if there is, and returns the old value.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        let old = self.t.insert(k, &Some(*v))?;
+        if old.is_none() {
+            self.dead -= 1;
+        }
+        old
+    }
+
+    /// Marks `k` dead, and returns its value if it exists.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let [v] = self.t.get_many_mut(&[k])?;
+        let old = v.take()?;
+        self.dead += 1;
+        Some(old)
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k)?.as_ref()
+    }
+
+    /// Returns an iterator over the live entries in the order of the keys.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.t.iter().filter_map(|(k, v)| v.as_ref().map(|v| (k, v)))
+    }
+
+    /// Visits at most `budget` entries from where the last step stopped, and removes the dead ones from the tree.
+    /// Returns true if the step reaches the end, and the next step starts over.
+    pub fn compact_step(&mut self, budget: usize) -> bool {
+        let mut c = self.t.cursor_mut();
+        match &self.resume {
+            Some(k) => c.seek(k),
+            None => c.seek_first(),
+        }
+        for _ in 0..budget {
+            match c.peek() {
+                None => break,
+                Some((_, None)) => {
+                    c.remove_next();
+                    self.dead -= 1;
+                }
+                Some(_) => {
+                    c.next();
+                }
+            }
+        }
+        self.resume = c.peek().map(|(k, _)| *k);
+        self.resume.is_none()
+    }
+
+    /// Removes all dead entries, and rebuilds the tree from the live ones, whose leaves are full.
+    pub fn compact(&mut self) {
+        let mut entries: Vec<(K, Option<V>)> = take(&mut self.t).into_sorted_vec();
+        entries.retain(|(_, v)| v.is_some());
+        self.t = BTree::from_sorted_vec(entries);
+        self.dead = 0;
+        self.resume = None;
+    }
+}
+
+#[test]
+fn test_tombstone() {
+    let mut t = TombstoneBTree::<u32, u32>::new();
+    for i in 0..10000 {
+        t.insert(&i, &i);
+    }
+    let nodes = (t.t.i.len(), t.t.l.len(), t.t.leaf_ids().len());
+    for i in (0..10000).step_by(2) {
+        assert_eq!(t.remove(&i), Some(i));
+    }
+    assert_eq!(t.remove(&0), None);
+    assert_eq!(t.remove(&10000), None);
+    // the removals do not change the nodes
+    assert_eq!(nodes, (t.t.i.len(), t.t.l.len(), t.t.leaf_ids().len()));
+    assert_eq!((t.len(), t.dead()), (5000, 5000));
+    assert_eq!(t.lookup(&2), None);
+    assert_eq!(t.lookup(&3), Some(&3));
+    assert!(t.iter().map(|(k, _)| *k).eq((1..10000).step_by(2)));
+
+    // a dead entry comes back to life
+    assert_eq!(t.insert(&2, &42), None);
+    assert_eq!(t.insert(&2, &43), Some(42));
+    assert_eq!((t.len(), t.dead()), (5001, 4999));
+
+    let mut steps = 1;
+    while !t.compact_step(100) {
+        steps += 1;
+        // the entries inserted during a pass are kept
+        t.insert(&(10000 + steps), &0);
+    }
+    assert!(steps > 50);
+    assert_eq!((t.t.len(), t.dead()), (t.len(), 0));
+    assert_eq!(t.lookup(&2), Some(&43));
+
+    for i in (1..10000).step_by(4) {
+        t.remove(&i);
+    }
+    t.compact();
+    assert_eq!((t.t.len(), t.dead()), (t.len(), 0));
+    assert!(t.iter().map(|(k, _)| *k).eq(core::iter::once(2).chain((3..10000).step_by(4)).chain(10002..=10000 + steps)));
+}
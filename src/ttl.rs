@@ -0,0 +1,118 @@
+use crate::BTree;
+
+/// The expiration time of the entries which never expire.
+const NEVER: u64 = u64::MAX;
+
+/// TtlBTree is a B+Tree whose entries may have an expiration time. An entry expiring at `at` is absent for the
+/// lookups at any time not less than `at`, and stays in the tree until it is swept by `expire_before`.
+///
+/// The time is any u64 clock chosen by the caller, e.g. the seconds since the epoch. The expiring entries are also
+/// indexed by their expiration times, so a sweep only visits the expired entries.
+pub struct TtlBTree<K, V> {
+    t: BTree<K, (V, u64)>,
+    // (expiration time, key) of the entries which expire
+    expiry: BTree<(u64, K), ()>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for TtlBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> TtlBTree<K, V> {
+    pub fn new() -> Self {
+        TtlBTree {
+            t: BTree::new(),
+            expiry: BTree::new(),
+        }
+    }
+
+    /// Inserts or updates the key value pair which never expires, and returns the old value even if it is expired.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        self.insert_until(k, v, NEVER)
+    }
+
+    /// Inserts or updates the key value pair which expires at `at`, and returns the old value even if it is expired.
+    pub fn insert_until(&mut self, k: &K, v: &V, at: u64) -> Option<V> {
+        let old = self.t.insert(k, &(*v, at));
+        if let Some((_, old_at)) = old {
+            if old_at != NEVER {
+                self.expiry.remove(&(old_at, *k));
+            }
+        }
+        if at != NEVER {
+            self.expiry.insert(&(at, *k), &());
+        }
+        old.map(|(v, _)| v)
+    }
+
+    /// Returns the value of `k` if it is not expired at `now`.
+    pub fn lookup(&self, k: &K, now: u64) -> Option<&V> {
+        match self.t.lookup(k) {
+            Some((v, at)) if now < *at => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the expiration time of `k`, None if `k` does not exist or never expires.
+    pub fn expires_at(&self, k: &K) -> Option<u64> {
+        self.t.lookup(k).map(|(_, at)| *at).filter(|at| *at != NEVER)
+    }
+
+    /// Removes `k`, and returns its value if it exists, even if it is expired.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let (v, at) = self.t.remove(k)?;
+        if at != NEVER {
+            self.expiry.remove(&(at, *k));
+        }
+        Some(v)
+    }
+
+    /// Removes the entries expired at `now`, i.e. expiring not later than `now`, and returns the number of them.
+    pub fn expire_before(&mut self, now: u64) -> usize {
+        let mut c = self.expiry.cursor_mut();
+        let mut cnt = 0;
+        while let Some(((at, k), _)) = c.peek() {
+            if *at > now {
+                break;
+            }
+            let k = *k;
+            c.remove_next();
+            self.t.remove(&k);
+            cnt += 1;
+        }
+        cnt
+    }
+}
+
+#[test]
+fn test_ttl() {
+    let mut t = TtlBTree::<u32, u32>::new();
+    for i in 0..10000 {
+        // the keys expire at 0, 10, 20, ... 90, or never
+        if i % 11 == 10 {
+            t.insert(&i, &i);
+        } else {
+            t.insert_until(&i, &i, (i % 11 * 10) as u64);
+        }
+    }
+    assert_eq!(t.lookup(&1, 5), Some(&1));
+    assert_eq!(t.lookup(&1, 10), None);
+    assert_eq!(t.lookup(&0, 0), None);
+    assert_eq!(t.lookup(&10, u64::MAX - 1), Some(&10));
+    assert_eq!(t.expires_at(&10), None);
+    assert_eq!(t.expires_at(&12), Some(10));
+
+    // the expired entries are still there until swept
+    assert_eq!(t.insert_until(&1, &42, 100), Some(1));
+    assert_eq!(t.lookup(&1, 50), Some(&42));
+    let expired = (0..10000).filter(|i| i % 11 != 10 && i % 11 * 10 <= 50).count();
+    assert_eq!(t.expire_before(50), expired - 1);
+    assert_eq!(t.expire_before(50), 0);
+    for i in 0..10000u32 {
+        let alive = i == 1 || i % 11 == 10 || i % 11 * 10 > 50;
+        assert_eq!(t.remove(&i).is_some(), alive);
+    }
+    assert_eq!(t.expire_before(u64::MAX), 0);
+}
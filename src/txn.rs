@@ -0,0 +1,105 @@
+use alloc::vec::Vec;
+
+use crate::BTree;
+
+/// Txn is a group of mutations of a tree which is committed or rolled back as a whole. The mutations are applied to
+/// the tree at once, and the old values are recorded in an undo log.
+///
+/// The transaction borrows the tree mutably, so it is the only writer and reader until it ends. Dropping it without
+/// committing rolls it back.
+pub struct Txn<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> {
+    t: &'a mut BTree<K, V>,
+    // the old values of the written keys, None if the key did not exist
+    undo: Vec<(K, Option<V>)>,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Begins a transaction.
+    pub fn begin(&mut self) -> Txn<'_, K, V> {
+        Txn {
+            t: self,
+            undo: Vec::new(),
+        }
+    }
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Txn<'a, K, V> {
+    /// Returns the value of `k`, including the writes of the transaction.
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k)
+    }
+
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        let old = self.t.insert(k, v);
+        self.undo.push((*k, old));
+        old
+    }
+
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let old = self.t.remove(k);
+        if old.is_some() {
+            self.undo.push((*k, old));
+        }
+        old
+    }
+
+    /// Keeps the mutations.
+    pub fn commit(mut self) {
+        self.undo.clear();
+    }
+
+    /// Reverts the mutations, from the last one to the first one.
+    pub fn rollback(self) {}
+}
+
+impl<'a, K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Drop for Txn<'a, K, V> {
+    fn drop(&mut self) {
+        while let Some((k, old)) = self.undo.pop() {
+            match old {
+                Some(v) => self.t.insert(&k, &v),
+                None => self.t.remove(&k),
+            };
+        }
+    }
+}
+
+#[test]
+fn test_txn() {
+    let mut t = BTree::<u32, u32>::new();
+    for i in 0..1000 {
+        t.insert(&i, &i);
+    }
+
+    // move 10 from every key to the next one, until a value is not enough
+    let mut txn = t.begin();
+    for i in (0..1000).rev() {
+        let v = *txn.lookup(&i).unwrap();
+        if v < 10 {
+            break;
+        }
+        txn.insert(&i, &(v - 10));
+        txn.insert(&(i + 1), &(txn.lookup(&(i + 1)).unwrap_or(&0) + 10));
+        txn.remove(&(i + 2000));
+    }
+    txn.insert(&5000, &1);
+    txn.remove(&500);
+    txn.rollback();
+    assert!(t.range(..).map(|(k, v)| (*k, *v)).eq((0..1000).map(|i| (i, i))));
+
+    let mut txn = t.begin();
+    txn.remove(&0);
+    txn.insert(&1000, &1000);
+    txn.commit();
+    assert_eq!(t.lookup(&0), None);
+    assert_eq!(t.lookup(&1000), Some(&1000));
+
+    // dropping rolls back
+    {
+        let mut txn = t.begin();
+        for i in 0..1001 {
+            txn.remove(&i);
+        }
+        assert_eq!(txn.lookup(&1), None);
+    }
+    assert_eq!(t.range(..).count(), 1000);
+}
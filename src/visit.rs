@@ -0,0 +1,83 @@
+use core::ops::{ControlFlow, RangeBounds};
+
+use crate::BTree;
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Calls `f` with the entries whose keys are in the `range`, in the order of the keys.
+    ///
+    /// The entries of every leaf are visited by a plain loop over its slices, which saves the per-entry bookkeeping of
+    /// `range` and is faster when `f` does little work.
+    pub fn for_each_in_range<R: RangeBounds<K>, F: FnMut(&K, &V)>(&self, range: R, mut f: F) {
+        for (keys, values) in self.chunks(range) {
+            for (k, v) in keys.iter().zip(values.iter()) {
+                f(k, v);
+            }
+        }
+    }
+
+    /// Folds the entries whose keys are in the `range` into `init` by `f`, in the order of the keys, and stops at the
+    /// first `Break` of `f`, which is returned. Returns the folded value in `Continue` if `f` never breaks.
+    ///
+    /// It visits the leaves like `for_each_in_range`, e.g. finding the first entry after a key violating a condition
+    /// stops there without an iterator left half consumed.
+    pub fn try_fold_range<R, B, C, F>(&self, range: R, init: C, mut f: F) -> ControlFlow<B, C>
+    where
+        R: RangeBounds<K>,
+        F: FnMut(C, &K, &V) -> ControlFlow<B, C>,
+    {
+        let mut acc = init;
+        for (keys, values) in self.chunks(range) {
+            for (k, v) in keys.iter().zip(values.iter()) {
+                acc = f(acc, k, v)?;
+            }
+        }
+        ControlFlow::Continue(acc)
+    }
+}
+
+#[test]
+fn test_for_each_in_range() {
+    let mut t = BTree::<u64, u64>::new();
+    let mut visited = alloc::vec::Vec::new();
+    t.for_each_in_range(.., |k, v| visited.push((*k, *v)));
+    assert!(visited.is_empty());
+
+    for i in 0..10000 {
+        t.insert(&(i * 7919 % 10007), &i);
+    }
+    for (start, end) in [(0, 10007), (100, 200), (5000, 5001), (300, 300), (10000, 20000)].iter() {
+        visited.clear();
+        t.for_each_in_range(start..end, |k, v| visited.push((*k, *v)));
+        assert!(visited.iter().map(|(k, v)| (k, v)).eq(t.range(start..end)));
+    }
+    let mut sum = 0;
+    t.for_each_in_range(..=100, |k, _| sum += k);
+    assert_eq!(sum, t.range(..=100).map(|(k, _)| k).sum());
+}
+
+#[test]
+fn test_try_fold_range() {
+    let mut t = BTree::<u64, u64>::new();
+    assert_eq!(
+        t.try_fold_range(.., 0, |n, _, _| ControlFlow::<(), _>::Continue(n + 1)),
+        ControlFlow::Continue(0)
+    );
+    for i in 0..10000 {
+        t.insert(&i, &(i % 1000));
+    }
+    let sum = t.try_fold_range(10..20, 0, |sum, k, _| ControlFlow::<(), _>::Continue(sum + k));
+    assert_eq!(sum, ControlFlow::Continue(145));
+
+    // the first entry after 2500 whose value is 0
+    let mut visited = 0;
+    let found = t.try_fold_range(2500.., (), |_, k, v| {
+        visited += 1;
+        if *v == 0 {
+            ControlFlow::Break(*k)
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    assert_eq!(found, ControlFlow::Break(3000));
+    assert_eq!(visited, 501);
+}
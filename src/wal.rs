@@ -0,0 +1,346 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+
+use crate::crc32c;
+
+/// A record of the write-ahead log.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Record {
+    /// Inserts the key value pair, the payload is the bytes of the key followed by the bytes of the value.
+    Insert(Vec<u8>),
+    /// Removes the key, the payload is the bytes of the key.
+    Remove(Vec<u8>),
+    /// The insertions and removals applied together, the payload is the encoded records.
+    Batch(Vec<Record>),
+    /// An encrypted record, the payload is the nonce, the tag, and the encrypted bytes of the record.
+    Encrypted(Vec<u8>),
+    /// The image of a page written by a checkpoint.
+    Page(u64, Vec<u8>),
+    /// Marks the end of a checkpoint. The pages before it are safe to be written to the data file.
+    Commit,
+    /// Reserves the nonces up to this one, it is logged before any of them is used.
+    Nonces(u64),
+}
+
+const INSERT: u8 = 1;
+const REMOVE: u8 = 2;
+const PAGE: u8 = 3;
+const COMMIT: u8 = 4;
+const BATCH: u8 = 5;
+const ENCRYPTED: u8 = 6;
+const NONCES: u8 = 7;
+
+/// The size of the frame header of a record in the log: the kind byte, the little-endian u64 length, and the
+/// little-endian CRC-32C of the kind, the length and the payload.
+const FRAME_HEADER_SIZE: usize = 13;
+
+/// Wal is a redo log. Every record is framed by a kind byte, a little-endian u64 length and a CRC-32C, followed by the
+/// payload.
+pub(crate) struct Wal {
+    file: File,
+}
+
+impl Wal {
+    /// Opens the log at `path`, and returns the complete records in it. A record torn by a crash is discarded, which is
+    /// the first one whose length exceeds the file, whose checksum does not match, or which can not be decoded, and
+    /// the log is truncated before it.
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> io::Result<(Self, Vec<Record>)> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let file_len = file.metadata()?.len();
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        let mut r = BufReader::new(&file);
+        loop {
+            let mut head = [0u8; FRAME_HEADER_SIZE];
+            if read_full(&mut r, &mut head)? < head.len() {
+                break;
+            }
+            let mut len = [0u8; 8];
+            len.copy_from_slice(&head[1..9]);
+            let len = u64::from_le_bytes(len);
+            // a garbage length must not allocate beyond the file
+            if len > file_len - valid_len - FRAME_HEADER_SIZE as u64 {
+                break;
+            }
+            let mut payload = vec![0u8; len as usize];
+            if read_full(&mut r, &mut payload)? < payload.len() {
+                break;
+            }
+            let mut crc = [0u8; 4];
+            crc.copy_from_slice(&head[9..]);
+            if u32::from_le_bytes(crc) != crc32c::update(crc32c::update(0, &head[..9]), &payload) {
+                break;
+            }
+            match decode(head[0], payload) {
+                Ok(record) => records.push(record),
+                Err(_) => break,
+            }
+            valid_len += FRAME_HEADER_SIZE as u64 + len;
+        }
+
+        // new records must not be appended after the torn one
+        if file_len > valid_len {
+            file.set_len(valid_len)?;
+        }
+        Ok((Wal { file }, records))
+    }
+
+    pub(crate) fn append(&mut self, record: &Record) -> io::Result<()> {
+        let mut bytes = record.to_bytes();
+        let crc = crc32c::update(crc32c::update(0, &bytes[..9]), &bytes[9..]);
+        bytes.splice(9..9, crc.to_le_bytes().iter().copied());
+        self.file.write_all(&bytes)
+    }
+
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Discards all records.
+    pub(crate) fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()
+    }
+}
+
+impl Record {
+    /// Returns the record as it is in the log.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode(self, &mut buf);
+        buf
+    }
+
+    /// Decodes the record returned by `to_bytes`.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 9 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the log record is truncated"));
+        }
+        decode(bytes[0], bytes[9..].to_vec())
+    }
+}
+
+fn encode(record: &Record, buf: &mut Vec<u8>) {
+    let (kind, head, payload): (u8, &[u8], &[u8]) = match record {
+        Record::Insert(p) => (INSERT, &[], p),
+        Record::Remove(p) => (REMOVE, &[], p),
+        Record::Page(page, p) => (PAGE, &page.to_le_bytes(), p),
+        Record::Commit => (COMMIT, &[], &[]),
+        Record::Encrypted(p) => (ENCRYPTED, &[], p),
+        Record::Nonces(last) => (NONCES, &last.to_le_bytes(), &[]),
+        Record::Batch(records) => {
+            let mut p = Vec::new();
+            for r in records.iter() {
+                encode(r, &mut p);
+            }
+            buf.push(BATCH);
+            buf.extend_from_slice(&(p.len() as u64).to_le_bytes());
+            buf.extend_from_slice(&p);
+            return;
+        }
+    };
+    buf.push(kind);
+    buf.extend_from_slice(&((head.len() + payload.len()) as u64).to_le_bytes());
+    buf.extend_from_slice(head);
+    buf.extend_from_slice(payload);
+}
+
+fn decode(kind: u8, mut payload: Vec<u8>) -> io::Result<Record> {
+    Ok(match kind {
+        INSERT => Record::Insert(payload),
+        REMOVE => Record::Remove(payload),
+        PAGE => {
+            if payload.len() < 8 {
+                return Err(invalid_record());
+            }
+            let mut page = [0u8; 8];
+            page.copy_from_slice(&payload[..8]);
+            Record::Page(u64::from_le_bytes(page), payload.split_off(8))
+        }
+        COMMIT => Record::Commit,
+        ENCRYPTED => Record::Encrypted(payload),
+        NONCES => {
+            if payload.len() != 8 {
+                return Err(invalid_record());
+            }
+            let mut last = [0u8; 8];
+            last.copy_from_slice(&payload);
+            Record::Nonces(u64::from_le_bytes(last))
+        }
+        BATCH => {
+            // the records in a batch are complete, as the batch is, and are only the insertions and the removals
+            let mut records = Vec::new();
+            let mut rest = &payload[..];
+            while !rest.is_empty() {
+                if rest.len() < 9 || (rest[0] != INSERT && rest[0] != REMOVE) {
+                    return Err(invalid_record());
+                }
+                let mut len = [0u8; 8];
+                len.copy_from_slice(&rest[1..9]);
+                let len = u64::from_le_bytes(len);
+                if len > (rest.len() - 9) as u64 {
+                    return Err(invalid_record());
+                }
+                let end = 9 + len as usize;
+                records.push(decode(rest[0], rest[9..end].to_vec())?);
+                rest = &rest[end..];
+            }
+            Record::Batch(records)
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown log record")),
+    })
+}
+
+fn invalid_record() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "the log record is malformed")
+}
+
+/// Reads until `buf` is full or the end of the file, returns the number of bytes read.
+fn read_full<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+#[test]
+fn test_wal_torn_tail() {
+    let path = std::env::temp_dir().join(format!("btree-rs-test-wal-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let records = vec![
+        Record::Insert(vec![1, 2, 3]),
+        Record::Remove(vec![4]),
+        Record::Page(7, vec![5; 100]),
+        Record::Batch(vec![Record::Insert(vec![8, 9]), Record::Remove(vec![10])]),
+        Record::Encrypted(vec![11; 30]),
+        Record::Nonces(12),
+        Record::Commit,
+    ];
+    {
+        let (mut wal, old) = Wal::open(&path).unwrap();
+        assert!(old.is_empty());
+        for r in records.iter() {
+            wal.append(r).unwrap();
+        }
+        wal.append(&Record::Insert(vec![6; 10])).unwrap();
+    }
+
+    // tear the last record
+    let len = std::fs::metadata(&path).unwrap().len();
+    OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 1).unwrap();
+    {
+        let (mut wal, old) = Wal::open(&path).unwrap();
+        assert_eq!(old, records);
+        wal.truncate().unwrap();
+    }
+    assert!(Wal::open(&path).unwrap().1.is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_wal_garbage_tail() {
+    use std::io::Seek;
+
+    let path = std::env::temp_dir().join(format!("btree-rs-test-wal-garbage-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let records = vec![Record::Insert(vec![1, 2, 3]), Record::Page(7, vec![5; 100]), Record::Commit];
+    {
+        let (mut wal, _) = Wal::open(&path).unwrap();
+        for r in records.iter() {
+            wal.append(r).unwrap();
+        }
+    }
+    let len = std::fs::metadata(&path).unwrap().len();
+
+    let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+    let mut garbage = |n: usize| {
+        (0..n)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect::<Vec<u8>>()
+    };
+    let tails = vec![
+        vec![0u8; 4096],
+        garbage(5),
+        garbage(4096),
+        // a huge length
+        [&[INSERT][..], &[0xff; 8][..], &[0; 4][..]].concat(),
+        // a valid frame of an unknown kind
+        {
+            let mut b = vec![42u8];
+            b.extend_from_slice(&0u64.to_le_bytes());
+            b.extend_from_slice(&crc32c::update(0, &b).to_le_bytes());
+            b
+        },
+    ];
+    for tail in tails {
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(io::SeekFrom::End(0)).unwrap();
+        file.write_all(&tail).unwrap();
+        drop(file);
+
+        let (mut wal, old) = Wal::open(&path).unwrap();
+        assert_eq!(old, records);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        // the records appended after the torn tail are recovered
+        wal.append(&Record::Remove(vec![4])).unwrap();
+        drop(wal);
+        let (_, old) = Wal::open(&path).unwrap();
+        assert_eq!(old.last(), Some(&Record::Remove(vec![4])));
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len).unwrap();
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_wal_malformed_record() {
+    let batch = Record::Batch(vec![Record::Insert(vec![1, 2]), Record::Remove(vec![3])]).to_bytes();
+    assert!(Record::from_bytes(&batch).is_ok());
+    // the last nested record, of 10 bytes, cut short, so its length exceeds the batch
+    for cut in 1..10 {
+        let mut b = batch[..batch.len() - cut].to_vec();
+        let len = (b.len() - 9) as u64;
+        b[1..9].copy_from_slice(&len.to_le_bytes());
+        assert_eq!(Record::from_bytes(&b).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+    // a batch holds only the insertions and the removals
+    for r in [Record::Commit, Record::Page(1, vec![2]), Record::Encrypted(vec![3]), Record::Batch(vec![])] {
+        let b = Record::Batch(vec![Record::Insert(vec![1]), r]).to_bytes();
+        assert_eq!(Record::from_bytes(&b).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+    let page = [PAGE, 3, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3];
+    assert_eq!(Record::from_bytes(&page).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    // the log stops at the malformed record
+    let path = std::env::temp_dir().join(format!("btree-rs-test-wal-malformed-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let (mut wal, _) = Wal::open(&path).unwrap();
+        wal.append(&Record::Commit).unwrap();
+        let mut b = batch.clone();
+        b.truncate(b.len() - 1);
+        let len = (b.len() - 9) as u64;
+        b[1..9].copy_from_slice(&len.to_le_bytes());
+        let crc = crc32c::update(0, &b);
+        b.splice(9..9, crc.to_le_bytes().iter().copied());
+        wal.file.write_all(&b).unwrap();
+        wal.append(&Record::Commit).unwrap();
+    }
+    assert_eq!(Wal::open(&path).unwrap().1, vec![Record::Commit]);
+    std::fs::remove_file(&path).unwrap();
+}
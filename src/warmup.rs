@@ -0,0 +1,84 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ops::{Bound, RangeBounds};
+
+use crate::{BTree, InternalNode, NodeIndex};
+
+/// The smallest page size of the operating systems, reading a byte of every page faults in the whole node.
+const OS_PAGE_SIZE: usize = 4096;
+
+/// Reads a byte of every page the node spans, so that none of them faults at the next access.
+fn touch<T>(node: &T) {
+    let p = node as *const T as *const u8;
+    let len = size_of::<T>();
+    for off in (0..len).step_by(OS_PAGE_SIZE).chain(core::iter::once(len - 1)) {
+        // a volatile read is never optimized out
+        unsafe { core::ptr::read_volatile(p.add(off)) };
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy> InternalNode<K> {
+    /// Returns the indexes of the sons which may hold the keys between `start` and `end`.
+    pub(crate) fn son_span(&self, start: Bound<&K>, end: Bound<&K>) -> core::ops::Range<usize> {
+        let first = match start {
+            Bound::Included(k) | Bound::Excluded(k) => self.lookup(k).0,
+            Bound::Unbounded => 0,
+        };
+        let last = match end {
+            Bound::Included(k) | Bound::Excluded(k) => self.lookup(k).0,
+            Bound::Unbounded => self.cnt - 1,
+        };
+        first..last.max(first) + 1
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> BTree<K, V> {
+    /// Touches all nodes holding the keys in the `range`, and returns the number of them. The nodes paged out or not
+    /// yet read from a mapped file are faulted in, so that the first requests to a freshly opened or built tree do not
+    /// wait for them.
+    ///
+    /// The nodes are touched level by level from the root, the upper levels on the paths of every lookup first.
+    pub fn warmup<R: RangeBounds<K>>(&self, range: R) -> usize {
+        let (start, end) = (range.start_bound(), range.end_bound());
+        let mut touched = 0;
+        let mut level = vec![self.root];
+        while !level.is_empty() {
+            let mut next = Vec::new();
+            for &node in level.iter() {
+                touched += 1;
+                match node {
+                    NodeIndex::Internal(id) => {
+                        let n = &self.i[id];
+                        touch(n);
+                        next.extend_from_slice(&n.sons[n.son_span(start, end)]);
+                    }
+                    NodeIndex::Leaf(id) => touch(&self.l[id]),
+                }
+            }
+            level = next;
+        }
+        touched
+    }
+}
+
+#[test]
+fn test_warmup() {
+    let mut t = BTree::<u64, u64>::new();
+    assert_eq!(t.warmup(..), 1);
+    let n = 100000u64;
+    for i in 0..n {
+        t.insert(&i, &i);
+    }
+    let nodes = (t.i.len() - t.free_i.len()) + (t.l.len() - t.free_l.len());
+    assert_eq!(t.warmup(..), nodes);
+
+    // only the paths to the leaves of the range
+    let leaves = t.leaf_ids().into_iter().filter(|&id| {
+        let l = &t.l[id];
+        l.keys[0] <= 2000 && l.keys[l.cnt - 1] >= 1000
+    });
+    let touched = t.warmup(1000..=2000);
+    assert!(touched > leaves.count() && touched < nodes / 10);
+    assert!(t.warmup(n..) < 10);
+}
@@ -0,0 +1,144 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::{Bound, RangeBounds};
+
+use crate::BTree;
+
+/// A mutation seen by the watchers.
+#[derive(Debug, PartialEq)]
+pub enum Event<'a, K, V> {
+    Inserted(&'a K, &'a V),
+    /// The key with the old value and the new value.
+    Updated(&'a K, &'a V, &'a V),
+    Removed(&'a K, &'a V),
+}
+
+impl<'a, K, V> Event<'a, K, V> {
+    pub fn key(&self) -> &'a K {
+        match self {
+            Event::Inserted(k, _) | Event::Updated(k, _, _) | Event::Removed(k, _) => k,
+        }
+    }
+}
+
+/// The id of a watcher, for `unwatch`.
+pub type WatchId = u64;
+
+type Callback<K, V> = Box<dyn FnMut(&Event<K, V>)>;
+
+struct Watcher<K, V> {
+    id: WatchId,
+    range: (Bound<K>, Bound<K>),
+    f: Callback<K, V>,
+}
+
+/// WatchedBTree is a B+Tree notifying the watchers of the mutations in their ranges of keys.
+///
+/// A watcher is a callback invoked after every mutation of a key in its range, which may also send the events to a
+/// channel. The watchers are checked one by one, so it suits a moderate number of them.
+pub struct WatchedBTree<K, V> {
+    t: BTree<K, V>,
+    watchers: Vec<Watcher<K, V>>,
+    next_id: WatchId,
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> Default for WatchedBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PartialOrd + PartialEq + Default + Copy, V: Default + Copy> WatchedBTree<K, V> {
+    pub fn new() -> Self {
+        WatchedBTree {
+            t: BTree::new(),
+            watchers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Registers `f` to be called on the mutations of the keys in `range`, and returns the id of the watcher.
+    pub fn watch<R: RangeBounds<K>, F: FnMut(&Event<K, V>) + 'static>(&mut self, range: R, f: F) -> WatchId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watchers.push(Watcher {
+            id,
+            range: (range.start_bound().cloned(), range.end_bound().cloned()),
+            f: Box::new(f),
+        });
+        id
+    }
+
+    /// Removes the watcher, returns false if it does not exist.
+    pub fn unwatch(&mut self, id: WatchId) -> bool {
+        let len = self.watchers.len();
+        self.watchers.retain(|w| w.id != id);
+        self.watchers.len() < len
+    }
+
+    fn notify(&mut self, e: &Event<K, V>) {
+        for w in self.watchers.iter_mut().filter(|w| w.range.contains(e.key())) {
+            (w.f)(e);
+        }
+    }
+
+    pub fn lookup(&self, k: &K) -> Option<&V> {
+        self.t.lookup(k)
+    }
+
+    /// Inserts or updates the key value pair, and returns the old value.
+    pub fn insert(&mut self, k: &K, v: &V) -> Option<V> {
+        let old = self.t.insert(k, v);
+        match old.as_ref() {
+            Some(old) => self.notify(&Event::Updated(k, old, v)),
+            None => self.notify(&Event::Inserted(k, v)),
+        }
+        old
+    }
+
+    /// Removes `k`, and returns its value if it exists. Nothing is notified if `k` does not exist.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let old = self.t.remove(k)?;
+        self.notify(&Event::Removed(k, &old));
+        Some(old)
+    }
+}
+
+#[test]
+fn test_watch() {
+    use std::sync::mpsc::channel;
+
+    let mut t = WatchedBTree::<u32, u32>::new();
+    let (tx, rx) = channel();
+    let id = t.watch(100..200, move |e| {
+        let owned = match e {
+            Event::Inserted(k, v) => (**k, None, Some(**v)),
+            Event::Updated(k, old, new) => (**k, Some(**old), Some(**new)),
+            Event::Removed(k, v) => (**k, Some(**v), None),
+        };
+        tx.send(owned).unwrap();
+    });
+    let (tx_all, rx_all) = channel();
+    t.watch(.., move |e| tx_all.send(*e.key()).unwrap());
+
+    for i in 0..300 {
+        t.insert(&i, &i);
+    }
+    t.insert(&150, &0);
+    t.remove(&199);
+    t.remove(&200);
+    t.remove(&1000);
+
+    let events: Vec<_> = rx.try_iter().collect();
+    assert_eq!(events.len(), 102);
+    assert_eq!(events[0], (100, None, Some(100)));
+    assert_eq!(events[100], (150, Some(150), Some(0)));
+    assert_eq!(events[101], (199, Some(199), None));
+    assert_eq!(rx_all.try_iter().count(), 303);
+
+    assert!(t.unwatch(id));
+    assert!(!t.unwatch(id));
+    t.insert(&150, &1);
+    assert_eq!(rx.try_iter().count(), 0);
+    assert_eq!(rx_all.try_iter().collect::<Vec<_>>(), [150]);
+}